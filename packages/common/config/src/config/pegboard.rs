@@ -1,4 +1,4 @@
-use std::net::IpAddr;
+use std::{collections::HashMap, net::IpAddr, time::Duration};
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -13,6 +13,195 @@ pub struct Pegboard {
 	pub lan_host: Option<String>,
 	/// The port on which the ws service listens.
 	pub port: Option<u16>,
+	/// Addresses or CIDR ranges of proxies in front of the ws service. `Forwarded` and `X-Forwarded-For`
+	/// headers are only read from connections originating from these addresses.
+	pub trusted_proxies: Option<Vec<String>>,
+	/// Size of the buffer each connection reads incoming frames into. Allocated per connection, so memory
+	/// use grows with `receive_buffer_size * connections`. Larger buffers need fewer reads for runners
+	/// sending many large KV requests. Defaults to 128 KiB.
+	pub receive_buffer_size: Option<usize>,
+	/// Largest message a runner can send. A connection can buffer up to this much while receiving a
+	/// message, so it bounds the worst case memory use per connection. Defaults to 64 MiB.
+	pub max_message_size: Option<usize>,
+	/// Enables TCP keepalive on runner sockets so dead peers (i.e. behind NATs or load balancers that
+	/// silently drop idle connections) are detected by the OS and the connection is closed.
+	///
+	/// Complements runner pings: a runner that stops pinging becomes ineligible for allocation after
+	/// 10s and is considered lost after 2 minutes, but its socket stays open until a read or write
+	/// fails. Keepalive probes are only sent once the socket has been idle for `idle_ms`, so it should
+	/// be longer than the runner ping interval (1s for the TypeScript runner) for healthy connections
+	/// to never send probes, and `idle_ms + interval_ms * count` should be shorter than the lost
+	/// threshold to close dead connections before their runner is considered lost. Disabled if not
+	/// set.
+	pub tcp_keepalive: Option<TcpKeepalive>,
+	/// Max new connections allowed per client address within `connection_rate_limit_period_ms`.
+	///
+	/// Unset by default (no rate limiting).
+	pub connection_rate_limit: Option<u64>,
+	/// Period over which `connection_rate_limit` is counted.
+	pub connection_rate_limit_period_ms: Option<u64>,
+	/// Whether new runner connections are rejected regardless of the maintenance mode set via the
+	/// `/runner-ws/maintenance-mode` api-peer endpoint, which is persisted across restarts.
+	pub maintenance_mode: Option<bool>,
+	/// How long rejected runners should wait before reconnecting during maintenance mode.
+	pub maintenance_retry_after_ms: Option<u64>,
+	/// Longest time ping updates can be paused for via the `/runner-ws/ping-updates-paused` api-peer
+	/// endpoint. Ineligible runners are not pinged during a pause, so this must be lower than the runner
+	/// lost threshold (2 minutes) for them not to be considered lost because of a pause. Defaults to 60s.
+	pub max_ping_updates_pause_ms: Option<i64>,
+	/// How long to wait after a runner disconnects before making it ineligible for allocation. If the
+	/// runner reconnects within this period, it stays eligible.
+	///
+	/// Defaults to 0 (runners are made ineligible immediately).
+	pub disconnect_grace_period_ms: Option<u64>,
+	/// Subtracts the estimated clock skew of a runner from its reported ping. Defaults to false.
+	pub correct_rtt_for_clock_skew: Option<bool>,
+	/// How pings with an RTT below `suspicious_rtt_floor_ms` are handled. These usually come from runners
+	/// whose clock is ahead of the server's. Defaults to `ignore`.
+	pub suspicious_rtt_policy: Option<SuspiciousRttPolicy>,
+	/// RTT below which a ping is considered suspicious. Defaults to 1ms.
+	pub suspicious_rtt_floor_ms: Option<u64>,
+	/// Logs sensitive fields (i.e. runner keys and raw packets) without redaction. Only enable in
+	/// development. Defaults to false.
+	pub unredacted_logs: Option<bool>,
+	/// Sends full error details (i.e. database errors) to runners in KV error responses and close frames.
+	/// Only enable in development and test environments, production should keep the default since
+	/// details can leak internals. Runners always receive stable error codes. Defaults to false.
+	pub detailed_errors: Option<bool>,
+	/// Number of recent packets to keep per connection for debugging. Captured packets are logged when a
+	/// connection closes with an error. Defaults to 0 (disabled).
+	pub packet_capture_size: Option<usize>,
+	/// Average KV request latency above which runners are asked to slow down. Disabled if not set.
+	pub kv_throttle_latency_ms: Option<u64>,
+	/// Number of in-flight KV requests per instance above which runners are asked to slow down. Disabled
+	/// if not set.
+	pub kv_throttle_in_flight: Option<u64>,
+	/// Average KV request latency above which KV is considered saturated. While saturated, new
+	/// connections and KV requests of non-system runners are rejected with a retry hint instead of
+	/// being accepted and timing out. Should be higher than `kv_throttle_latency_ms`. Disabled if not
+	/// set.
+	pub kv_shed_latency_ms: Option<u64>,
+	/// Number of in-flight KV requests per instance above which KV is considered saturated, see
+	/// `kv_shed_latency_ms`. Disabled if not set.
+	pub kv_shed_in_flight: Option<u64>,
+	/// Retry hint sent with connections and KV requests rejected while KV is saturated. Defaults to 1s.
+	pub kv_shed_retry_after_ms: Option<u64>,
+	/// Sheds new connections while the CPU or memory usage of the host (or container) is above the
+	/// configured thresholds, and optionally KV requests of non-system runners. Protects an overloaded
+	/// instance independently of connection caps. Only supported on Linux. Disabled if not set.
+	pub resource_pressure: Option<ResourcePressure>,
+	/// Max bytes of KV response data (keys and values of gets and lists) buffered across all connections
+	/// of this instance. Reads are rejected with `kv_budget_exhausted` while the budget is used up.
+	/// Unlimited if not set.
+	pub kv_response_budget_bytes: Option<usize>,
+	/// Max KV operations in flight per namespace on this instance, across all of its runners.
+	/// Requests above the limit are rejected with the retryable `kv_namespace_busy` error. Unlimited
+	/// if not set.
+	pub max_kv_in_flight_per_namespace: Option<usize>,
+	/// Max KV operations executed at once on this instance. Operations above the limit are queued and
+	/// scheduled with weighted fair queuing across namespaces, so a namespace flooding KV requests
+	/// cannot starve the others. Namespaces get throughput in proportion to the priority class of the
+	/// requesting runners (best effort 1, normal 4, system 16). Unlimited if not set.
+	pub max_kv_concurrency: Option<usize>,
+	/// Number of KV keys tracked to detect hot keys (keys with many accesses or transaction conflicts).
+	/// The hottest keys are reported in metrics and contended keys are logged. Defaults to 256. Set to
+	/// 0 to disable.
+	pub kv_hot_key_capacity: Option<usize>,
+	/// Half-life of the access and conflict counts of tracked keys. Defaults to 10s.
+	pub kv_hot_key_half_life_ms: Option<u64>,
+	/// Decayed access count above which concurrent gets of the exact same keys are coalesced into a
+	/// single read. A coalesced get returns the values as of when the in-flight read started, so it may
+	/// not observe writes committed in the meantime (i.e. by another connection of the same actor).
+	/// Reads following a write on the same connection are unaffected since each connection processes
+	/// one KV request at a time. Disabled if not set.
+	pub kv_hot_key_coalesce_threshold: Option<f64>,
+	/// Number of pending handshakes above which clients that have not sent their init packet within
+	/// `silent_client_timeout_ms` are closed early and counted against their rate limit. Disabled if not
+	/// set.
+	pub handshake_pressure_threshold: Option<usize>,
+	/// How long a client can stay silent after connecting while handshakes are under pressure. Defaults
+	/// to 1s.
+	pub silent_client_timeout_ms: Option<u64>,
+	/// Max websocket upgrades and init handshakes in progress at once. Connections hold a slot from
+	/// before the upgrade until their handshake completes. Unlimited if not set.
+	pub max_pending_upgrades: Option<usize>,
+	/// How long a new connection waits for a slot while `max_pending_upgrades` is reached before it is
+	/// rejected with a 503, before the websocket upgrade. Higher values absorb bursts, 0 sheds
+	/// immediately. Defaults to 1s.
+	pub upgrade_queue_timeout_ms: Option<u64>,
+	/// How long a connection holding an upgrade slot has to complete its websocket upgrade before it
+	/// is closed and the slot is released. Defaults to 5s.
+	pub upgrade_timeout_ms: Option<u64>,
+	/// How often metrics snapshots are pushed to runners that opted in during the init handshake.
+	/// Defaults to 10s.
+	pub metrics_snapshot_interval_ms: Option<u64>,
+	/// Stable identity of this instance. Runners are sent the identity of the instance that last served
+	/// them as a hint for which instance to reconnect to (i.e. for sticky load balancing). Hints are
+	/// disabled if not set.
+	pub instance_id: Option<String>,
+	/// What happens when a runner connects while another connection for the same runner is open.
+	/// Can be overridden per namespace. Defaults to `last_writer_wins`.
+	pub duplicate_connection_policy: Option<DuplicateConnectionPolicy>,
+	/// Number of restarts of a background thread within `thread_restart_window_ms` above which the
+	/// instance reports itself unhealthy on `GET /health`. Defaults to 5.
+	pub thread_restart_threshold: Option<usize>,
+	/// Window over which background thread restarts are counted. Defaults to 60s.
+	pub thread_restart_window_ms: Option<u64>,
+	/// Age of the messages processed by the msg thread above which a subscription is considered lagging
+	/// (i.e. its backlog is growing). The instance reports itself degraded while any subscription lags.
+	/// Defaults to 5s.
+	pub msg_lag_threshold_ms: Option<u64>,
+	/// How often the database is probed. The instance reports itself not ready on `GET /health` until a
+	/// probe succeeds and while probes fail. Defaults to 10s.
+	pub db_probe_interval_ms: Option<u64>,
+	/// How long a database probe can take before it counts as failed. Defaults to 5s.
+	pub db_probe_timeout_ms: Option<u64>,
+	/// Number of recently disconnected runners to remember, used to annotate reconnects with the previous
+	/// disconnect reason. Defaults to 10,000. Set to 0 to disable.
+	pub recent_disconnects_capacity: Option<usize>,
+	/// Maximum number of keys in a single KV get, put or delete request. Sent to runners in the init
+	/// packet. Cannot exceed the KV store's limit of 128. Defaults to 128.
+	pub max_kv_keys_per_request: Option<usize>,
+	/// Largest KV key in bytes runners can send. Sent to runners in the init packet. Cannot exceed the
+	/// KV store's limit of 2 KiB. Defaults to 2 KiB.
+	pub max_kv_key_size: Option<usize>,
+	/// Largest KV value in bytes runners can write. Sent to runners in the init packet. Cannot exceed
+	/// the KV store's limit of 128 KiB. Defaults to 128 KiB.
+	pub max_kv_value_size: Option<usize>,
+	/// Export the connection registry to the database on shutdown so a replacement instance with the
+	/// same `instance_id` can pre-warm its caches before runners reconnect (i.e. during rolling
+	/// restarts). Requires `instance_id`. Defaults to false.
+	pub export_connections_on_shutdown: Option<bool>,
+	/// How long the export on shutdown can take before it is abandoned. Defaults to 5s.
+	pub connection_export_timeout_ms: Option<u64>,
+	/// How long resolving the namespace of a connecting runner can take before the connection is closed.
+	/// Defaults to 5s.
+	pub namespace_resolve_timeout_ms: Option<u64>,
+	/// Consecutive namespace resolution timeouts after which connections fail fast without resolving
+	/// the namespace for `namespace_resolve_breaker_cooldown_ms`. Defaults to 5.
+	pub namespace_resolve_breaker_threshold: Option<usize>,
+	/// Defaults to 10s.
+	pub namespace_resolve_breaker_cooldown_ms: Option<u64>,
+	/// Compresses KV values at rest. Transparent to runners, values written before compression was
+	/// enabled are still read correctly. Disabled if not set.
+	pub kv_compression: Option<KvCompression>,
+	/// Publishes connection lifecycle events (`RunnerConnectionEvent`) for external consumers.
+	/// Defaults to false.
+	pub publish_connection_events: Option<bool>,
+	/// Lowest runner version (the `version` sent in the init packet) allowed to connect, i.e. to block
+	/// known-buggy releases. Any version is allowed if not set.
+	pub min_runner_version: Option<u32>,
+	/// Highest runner version allowed to connect. Any version is allowed if not set.
+	pub max_runner_version: Option<u32>,
+	/// Max runner workflows dispatched per second by this instance. Connections that need a new
+	/// workflow wait in a queue instead of flooding the workflow engine during reconnection storms.
+	/// Unlimited if not set.
+	pub workflow_dispatch_rate: Option<u32>,
+	/// Number of connections that can wait for a workflow dispatch. Connections beyond this are
+	/// rejected with a retry hint. Defaults to 1000.
+	pub workflow_dispatch_queue_size: Option<usize>,
+	/// Per-namespace overrides, keyed by namespace name.
+	pub namespaces: Option<HashMap<String, PegboardNamespace>>,
 }
 
 impl Pegboard {
@@ -31,4 +220,455 @@ impl Pegboard {
 		self.port
 			.unwrap_or(crate::defaults::ports::PEGBOARD_RUNNER_WS)
 	}
+
+	pub fn trusted_proxies(&self) -> &[String] {
+		self.trusted_proxies.as_deref().unwrap_or_default()
+	}
+
+	/// Returns the number of allowed connections per period, if enabled.
+	pub fn connection_rate_limit(&self) -> Option<(u64, Duration)> {
+		self.connection_rate_limit.map(|requests| {
+			(
+				requests,
+				Duration::from_millis(self.connection_rate_limit_period_ms.unwrap_or(60_000)),
+			)
+		})
+	}
+
+	pub fn receive_buffer_size(&self) -> usize {
+		self.receive_buffer_size.unwrap_or(128 * 1024)
+	}
+
+	pub fn max_message_size(&self) -> usize {
+		self.max_message_size.unwrap_or(64 * 1024 * 1024)
+	}
+
+	pub fn tcp_keepalive(&self) -> Option<TcpKeepalive> {
+		self.tcp_keepalive
+	}
+
+	pub fn maintenance_mode(&self) -> bool {
+		self.maintenance_mode.unwrap_or_default()
+	}
+
+	pub fn maintenance_retry_after_ms(&self) -> u64 {
+		self.maintenance_retry_after_ms.unwrap_or(30_000)
+	}
+
+	pub fn max_ping_updates_pause_ms(&self) -> i64 {
+		self.max_ping_updates_pause_ms.unwrap_or(60_000)
+	}
+
+	pub fn disconnect_grace_period(&self) -> Duration {
+		Duration::from_millis(self.disconnect_grace_period_ms.unwrap_or_default())
+	}
+
+	pub fn correct_rtt_for_clock_skew(&self) -> bool {
+		self.correct_rtt_for_clock_skew.unwrap_or_default()
+	}
+
+	pub fn suspicious_rtt_policy(&self) -> SuspiciousRttPolicy {
+		self.suspicious_rtt_policy.unwrap_or_default()
+	}
+
+	pub fn suspicious_rtt_floor_ms(&self) -> u64 {
+		self.suspicious_rtt_floor_ms.unwrap_or(1)
+	}
+
+	pub fn unredacted_logs(&self) -> bool {
+		self.unredacted_logs.unwrap_or_default()
+	}
+
+	pub fn detailed_errors(&self) -> bool {
+		self.detailed_errors.unwrap_or_default()
+	}
+
+	/// Packet capture size for the given namespace, taking namespace overrides into account.
+	pub fn packet_capture_size(&self, namespace_name: &str) -> usize {
+		self.namespace(namespace_name)
+			.and_then(|ns| ns.packet_capture_size)
+			.or(self.packet_capture_size)
+			.unwrap_or_default()
+	}
+
+	pub fn kv_throttle_latency(&self) -> Option<Duration> {
+		self.kv_throttle_latency_ms.map(Duration::from_millis)
+	}
+
+	pub fn kv_throttle_in_flight(&self) -> Option<u64> {
+		self.kv_throttle_in_flight
+	}
+
+	pub fn kv_shed_latency(&self) -> Option<Duration> {
+		self.kv_shed_latency_ms.map(Duration::from_millis)
+	}
+
+	pub fn kv_shed_in_flight(&self) -> Option<u64> {
+		self.kv_shed_in_flight
+	}
+
+	pub fn kv_shed_retry_after_ms(&self) -> u64 {
+		self.kv_shed_retry_after_ms.unwrap_or(1_000)
+	}
+
+	pub fn resource_pressure(&self) -> Option<ResourcePressure> {
+		self.resource_pressure
+	}
+
+	pub fn kv_response_budget(&self) -> Option<usize> {
+		self.kv_response_budget_bytes
+	}
+
+	pub fn max_kv_in_flight_per_namespace(&self) -> Option<usize> {
+		self.max_kv_in_flight_per_namespace
+	}
+
+	pub fn max_kv_concurrency(&self) -> Option<usize> {
+		self.max_kv_concurrency.filter(|limit| *limit != 0)
+	}
+
+	pub fn max_pending_upgrades(&self) -> Option<usize> {
+		self.max_pending_upgrades
+	}
+
+	pub fn upgrade_queue_timeout(&self) -> Duration {
+		Duration::from_millis(self.upgrade_queue_timeout_ms.unwrap_or(1_000))
+	}
+
+	pub fn upgrade_timeout(&self) -> Duration {
+		Duration::from_millis(self.upgrade_timeout_ms.unwrap_or(5_000))
+	}
+
+	pub fn kv_hot_key_capacity(&self) -> usize {
+		self.kv_hot_key_capacity.unwrap_or(256)
+	}
+
+	pub fn kv_hot_key_half_life(&self) -> Duration {
+		Duration::from_millis(self.kv_hot_key_half_life_ms.unwrap_or(10_000).max(1))
+	}
+
+	pub fn kv_hot_key_coalesce_threshold(&self) -> Option<f64> {
+		self.kv_hot_key_coalesce_threshold
+	}
+
+	/// Returns the pending handshake threshold and silent client timeout, if enabled.
+	pub fn silent_client_close(&self) -> Option<(usize, Duration)> {
+		self.handshake_pressure_threshold.map(|threshold| {
+			(
+				threshold,
+				Duration::from_millis(self.silent_client_timeout_ms.unwrap_or(1_000)),
+			)
+		})
+	}
+
+	pub fn metrics_snapshot_interval(&self) -> Duration {
+		Duration::from_millis(self.metrics_snapshot_interval_ms.unwrap_or(10_000))
+	}
+
+	pub fn duplicate_connection_policy(&self, namespace: &str) -> DuplicateConnectionPolicy {
+		self.namespace(namespace)
+			.and_then(|ns| ns.duplicate_connection_policy)
+			.or(self.duplicate_connection_policy)
+			.unwrap_or_default()
+	}
+
+	/// Returns the number of allowed background thread restarts per window.
+	pub fn thread_restart_threshold(&self) -> (usize, Duration) {
+		(
+			self.thread_restart_threshold.unwrap_or(5),
+			Duration::from_millis(self.thread_restart_window_ms.unwrap_or(60_000)),
+		)
+	}
+
+	pub fn msg_lag_threshold_ms(&self) -> u64 {
+		self.msg_lag_threshold_ms.unwrap_or(5_000)
+	}
+
+	/// Returns the interval and timeout of database probes.
+	pub fn db_probe(&self) -> (Duration, Duration) {
+		(
+			Duration::from_millis(self.db_probe_interval_ms.unwrap_or(10_000)),
+			Duration::from_millis(self.db_probe_timeout_ms.unwrap_or(5_000)),
+		)
+	}
+
+	pub fn recent_disconnects_capacity(&self) -> usize {
+		self.recent_disconnects_capacity.unwrap_or(10_000)
+	}
+
+	pub fn max_kv_keys_per_request(&self) -> usize {
+		self.max_kv_keys_per_request.unwrap_or(128)
+	}
+
+	pub fn max_kv_key_size(&self, namespace: &str) -> usize {
+		self.namespace(namespace)
+			.and_then(|ns| ns.max_kv_key_size)
+			.or(self.max_kv_key_size)
+			.unwrap_or(2 * 1024)
+	}
+
+	pub fn max_kv_value_size(&self, namespace: &str) -> usize {
+		self.namespace(namespace)
+			.and_then(|ns| ns.max_kv_value_size)
+			.or(self.max_kv_value_size)
+			.unwrap_or(128 * 1024)
+	}
+
+	pub fn export_connections_on_shutdown(&self) -> bool {
+		self.export_connections_on_shutdown.unwrap_or_default()
+	}
+
+	pub fn connection_export_timeout(&self) -> Duration {
+		Duration::from_millis(self.connection_export_timeout_ms.unwrap_or(5_000))
+	}
+
+	pub fn namespace_resolve_timeout(&self) -> Duration {
+		Duration::from_millis(self.namespace_resolve_timeout_ms.unwrap_or(5_000))
+	}
+
+	/// Returns the number of consecutive timeouts that open the breaker and how long it stays open.
+	pub fn namespace_resolve_breaker(&self) -> (usize, Duration) {
+		(
+			self.namespace_resolve_breaker_threshold.unwrap_or(5),
+			Duration::from_millis(self.namespace_resolve_breaker_cooldown_ms.unwrap_or(10_000)),
+		)
+	}
+
+	pub fn publish_connection_events(&self) -> bool {
+		self.publish_connection_events.unwrap_or_default()
+	}
+
+	/// KV compression for the given namespace, `None` if disabled.
+	pub fn kv_compression(&self, namespace: &str) -> Option<KvCompression> {
+		self.namespace(namespace)
+			.and_then(|ns| ns.kv_compression)
+			.or(self.kv_compression)
+			.filter(|compression| compression.algorithm != KvCompressionAlgorithm::None)
+	}
+
+	/// Returns the interval between workflow dispatches and the dispatch queue size, if throttling is
+	/// enabled.
+	pub fn workflow_dispatch_throttle(&self) -> Option<(Duration, usize)> {
+		self.workflow_dispatch_rate
+			.filter(|rate| *rate > 0)
+			.map(|rate| {
+				(
+					Duration::from_secs(1) / rate,
+					self.workflow_dispatch_queue_size.unwrap_or(1_000),
+				)
+			})
+	}
+
+	pub fn namespace(&self, name: &str) -> Option<&PegboardNamespace> {
+		self.namespaces.as_ref().and_then(|x| x.get(name))
+	}
+
+	/// Whether the runner version is within the allowed range. Namespace overrides replace the global
+	/// bounds individually.
+	pub fn is_runner_version_allowed(&self, namespace: &str, version: u32) -> bool {
+		let ns = self.namespace(namespace);
+		let min = ns
+			.and_then(|ns| ns.min_runner_version)
+			.or(self.min_runner_version);
+		let max = ns
+			.and_then(|ns| ns.max_runner_version)
+			.or(self.max_runner_version);
+
+		min.map_or(true, |min| version >= min) && max.map_or(true, |max| version <= max)
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct KvCompression {
+	pub algorithm: KvCompressionAlgorithm,
+	/// Values smaller than this are stored uncompressed. Defaults to 1 KiB.
+	pub min_size: Option<usize>,
+	/// Values without a content type whose sampled entropy (in bits per byte, 0 to 8) is above this are
+	/// stored uncompressed. Values with a content type are only skipped if the content type is already
+	/// compressed (i.e. images). Defaults to 7.5.
+	pub max_entropy: Option<f64>,
+}
+
+impl KvCompression {
+	pub fn min_size(&self) -> usize {
+		self.min_size.unwrap_or(1024)
+	}
+
+	pub fn max_entropy(&self) -> f64 {
+		self.max_entropy.unwrap_or(7.5)
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TcpKeepalive {
+	/// Time without traffic before the first probe is sent. Defaults to 15s.
+	pub idle_ms: Option<u64>,
+	/// Time between unanswered probes. Defaults to 5s.
+	pub interval_ms: Option<u64>,
+	/// Unanswered probes after which the connection is closed. Defaults to 3. Ignored on Windows, which
+	/// uses a fixed count.
+	pub count: Option<u32>,
+}
+
+impl TcpKeepalive {
+	pub fn idle(&self) -> Duration {
+		Duration::from_millis(self.idle_ms.unwrap_or(15_000))
+	}
+
+	pub fn interval(&self) -> Duration {
+		Duration::from_millis(self.interval_ms.unwrap_or(5_000))
+	}
+
+	pub fn count(&self) -> u32 {
+		self.count.unwrap_or(3)
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ResourcePressure {
+	/// Fraction of CPU time in use (0 to 1) above which new work is shed. Not checked if not set.
+	pub cpu_threshold: Option<f64>,
+	/// Fraction of memory in use (0 to 1) above which new work is shed. Uses the cgroup memory limit if
+	/// set, total system memory otherwise. Not checked if not set.
+	pub memory_threshold: Option<f64>,
+	/// Also rejects KV requests of non-system runners while shedding. Defaults to false.
+	pub shed_kv: Option<bool>,
+	/// Retry hint sent with rejected connections and KV requests. Defaults to 1s.
+	pub retry_after_ms: Option<u64>,
+	/// How often resource usage is sampled. Defaults to 1s.
+	pub sample_interval_ms: Option<u64>,
+}
+
+impl ResourcePressure {
+	pub fn shed_kv(&self) -> bool {
+		self.shed_kv.unwrap_or_default()
+	}
+
+	pub fn retry_after_ms(&self) -> u64 {
+		self.retry_after_ms.unwrap_or(1_000)
+	}
+
+	pub fn sample_interval(&self) -> Duration {
+		Duration::from_millis(self.sample_interval_ms.unwrap_or(1_000))
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum KvCompressionAlgorithm {
+	/// Disables compression, used to opt a namespace out of `pegboard.kv_compression`.
+	None,
+	Gzip,
+	Zstd,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum DuplicateConnectionPolicy {
+	/// The new connection replaces the existing one, which is closed.
+	#[default]
+	LastWriterWins,
+	/// The new connection is rejected and the existing one is kept. Avoids connections flapping between
+	/// duplicate runner processes using the same key.
+	FirstWriterWins,
+	/// Same as `LastWriterWins`, but replacements are logged as errors. For namespaces where duplicate
+	/// runner keys indicate a bug.
+	AlertAndReplace,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum SuspiciousRttPolicy {
+	/// Stores the floor instead of the sample.
+	Clamp,
+	/// Drops the sample and keeps the last RTT of the runner.
+	#[default]
+	Ignore,
+	/// Drops the sample and flags the runner's clock as unreliable. Its RTT is no longer written to the
+	/// alloc index for the rest of the connection.
+	Exclude,
+}
+
+/// Runner ws settings that apply to a single namespace.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PegboardNamespace {
+	/// KV key prefixes that runners can read but not write or delete. Used for server-managed state. Only
+	/// applies to the actor's flat key space, not collections.
+	pub kv_read_only_prefixes: Option<Vec<String>>,
+	/// Runner names that are allowed to connect. Any name is allowed if not set.
+	pub allowed_runner_names: Option<Vec<String>>,
+	/// Overrides `pegboard.packet_capture_size` for this namespace.
+	pub packet_capture_size: Option<usize>,
+	/// Lowest runner protocol version allowed to connect. Set together with `max_protocol_version` to pin
+	/// a namespace to a single version.
+	pub min_protocol_version: Option<u16>,
+	/// Highest runner protocol version allowed to connect.
+	pub max_protocol_version: Option<u16>,
+	/// Overrides `pegboard.min_runner_version` for this namespace.
+	pub min_runner_version: Option<u32>,
+	/// Overrides `pegboard.max_runner_version` for this namespace.
+	pub max_runner_version: Option<u32>,
+	/// KV operations runners are allowed to perform. All operations are allowed if not set.
+	pub allowed_kv_operations: Option<Vec<KvOperation>>,
+	/// Overrides `allowed_kv_operations` for specific runner names.
+	pub runner_allowed_kv_operations: Option<HashMap<String, Vec<KvOperation>>>,
+	/// Whether runners can use KV. If disabled, all KV requests are rejected without touching the
+	/// database. Defaults to true.
+	pub kv_enabled: Option<bool>,
+	/// Overrides `pegboard.kv_compression` for this namespace.
+	pub kv_compression: Option<KvCompression>,
+	/// Overrides `pegboard.duplicate_connection_policy` for this namespace.
+	pub duplicate_connection_policy: Option<DuplicateConnectionPolicy>,
+	/// Overrides `pegboard.max_kv_key_size` for this namespace.
+	pub max_kv_key_size: Option<usize>,
+	/// Overrides `pegboard.max_kv_value_size` for this namespace.
+	pub max_kv_value_size: Option<usize>,
+}
+
+impl PegboardNamespace {
+	pub fn kv_read_only_prefixes(&self) -> &[String] {
+		self.kv_read_only_prefixes.as_deref().unwrap_or_default()
+	}
+
+	pub fn is_protocol_version_allowed(&self, protocol_version: u16) -> bool {
+		self.min_protocol_version
+			.map_or(true, |min| protocol_version >= min)
+			&& self
+				.max_protocol_version
+				.map_or(true, |max| protocol_version <= max)
+	}
+
+	/// KV operations the given runner is allowed to perform, or `None` if all are allowed.
+	pub fn allowed_kv_operations(&self, runner_name: &str) -> Option<&[KvOperation]> {
+		self.runner_allowed_kv_operations
+			.as_ref()
+			.and_then(|x| x.get(runner_name))
+			.or(self.allowed_kv_operations.as_ref())
+			.map(Vec::as_slice)
+	}
+
+	pub fn kv_enabled(&self) -> bool {
+		self.kv_enabled.unwrap_or(true)
+	}
+
+	pub fn is_runner_name_allowed(&self, name: &str) -> bool {
+		self.allowed_runner_names
+			.as_ref()
+			.map_or(true, |names| names.iter().any(|x| x == name))
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum KvOperation {
+	Get,
+	List,
+	Put,
+	Delete,
+	/// Deletes all of an actor's KV data.
+	Drop,
 }
@@ -336,3 +336,18 @@ fn test_enum_variant_key_too_large_metadata() {
 	assert_eq!(metadata["max_size"], 256);
 	assert_eq!(metadata["key_preview"], "very_long_key_name...");
 }
+
+#[test]
+fn test_enum_schemas() {
+	init_test();
+	let schemas = TestEnumError::schemas();
+
+	assert_eq!(
+		schemas.iter().map(|schema| schema.code).collect::<Vec<_>>(),
+		["not_found", "input_too_large", "key_too_large"]
+	);
+	assert!(schemas.iter().all(|schema| schema.group == "test"));
+	assert_eq!(schemas[0].default_message, "The resource does not exist.");
+	assert!(schemas[0].meta_type.is_none());
+	assert!(schemas[1].meta_type.is_some());
+}
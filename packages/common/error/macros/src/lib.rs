@@ -260,6 +260,7 @@ fn derive_enum_error(input: DeriveInput, data_enum: &syn::DataEnum) -> TokenStre
 	};
 
 	let mut variant_matches = Vec::new();
+	let mut variant_schemas = Vec::new();
 
 	// Process each variant
 	for variant in &data_enum.variants {
@@ -296,6 +297,21 @@ fn derive_enum_error(input: DeriveInput, data_enum: &syn::DataEnum) -> TokenStre
 			);
 		}
 
+		let meta_type = if matches!(variant.fields, Fields::Unit) {
+			quote! { None }
+		} else {
+			quote! { Some(stringify!(#enum_name::#variant_name)) }
+		};
+		variant_schemas.push(quote! {
+			&RivetErrorSchema {
+				group: #group,
+				code: #code,
+				default_message: #description,
+				meta_type: #meta_type,
+				_macro_marker: MacroMarker { _private: () },
+			}
+		});
+
 		// Handle variants with fields
 		match &variant.fields {
 			Fields::Named(fields) => {
@@ -492,6 +508,16 @@ fn derive_enum_error(input: DeriveInput, data_enum: &syn::DataEnum) -> TokenStre
 					#(#variant_matches),*
 				}
 			}
+
+			/// Schemas of all variants, in declaration order.
+			#[allow(dead_code)]
+			#vis fn schemas() -> &'static [&'static ::rivet_error::RivetErrorSchema] {
+				use ::rivet_error::{RivetErrorSchema, MacroMarker};
+
+				static SCHEMAS: &[&RivetErrorSchema] = &[#(#variant_schemas),*];
+
+				SCHEMAS
+			}
 		}
 	};
 
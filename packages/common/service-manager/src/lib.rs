@@ -33,7 +33,11 @@ impl Service {
 /// Defines the type of the service. Used for filtering service types to run.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ServiceKind {
+	/// Services that accept traffic from outside of the cluster (i.e. runner connections). The public API
+	/// router itself is served through guard, which applies the public middleware (auth, rate limiting,
+	/// CORS).
 	ApiPublic,
+	/// Internal API used for communication between peers in the cluster. Must not be exposed publicly.
 	ApiPeer,
 	Standalone,
 	Singleton,
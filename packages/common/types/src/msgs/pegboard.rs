@@ -2,3 +2,58 @@ use gas::prelude::*;
 
 #[message("pegboard_bump_serverless_autoscaler")]
 pub struct BumpServerlessAutoscaler {}
+
+/// Published after maintenance mode changed (see `pegboard::ops::runner::set_ws_maintenance_mode`) so
+/// runner ws instances reload it. While enabled, new runner connections are rejected but existing
+/// connections are kept alive.
+#[message("pegboard_runner_ws_set_maintenance_mode")]
+pub struct SetRunnerWsMaintenanceMode {}
+
+/// Pauses or resumes ping updates on all runner ws instances. While paused, runners keep their current
+/// eligibility: eligible runners are still pinged and ineligible runners are not made eligible again.
+/// Connections are kept alive.
+#[message("pegboard_runner_ws_set_ping_updates_paused")]
+pub struct SetRunnerWsPingUpdatesPaused {
+	pub paused: bool,
+	/// How long to pause for, bounded by `pegboard.max_ping_updates_pause_ms` (which is also the default).
+	pub duration_ms: Option<i64>,
+}
+
+/// Published after draining a namespace started or stopped (see
+/// `pegboard::ops::runner::set_ws_namespace_draining`) so runner ws instances reload it. While
+/// draining, new connections of the namespace are rejected and existing ones are closed with a hint
+/// to reconnect elsewhere. Other namespaces are unaffected.
+#[message("pegboard_runner_ws_set_namespace_draining")]
+pub struct SetRunnerWsNamespaceDraining {
+	pub namespace_id: Id,
+}
+
+/// Connection lifecycle event published by runner ws instances if `pegboard.publish_connection_events`
+/// is enabled. Tagged with `namespace_id` and `runner_id` so consumers can subscribe to a subset.
+#[message("pegboard_runner_connection_event")]
+pub struct RunnerConnectionEvent {
+	pub runner_id: Id,
+	pub namespace_id: Id,
+	pub kind: RunnerConnectionEventKind,
+	/// Why the connection was closed. Set for `Disconnect`, `Evict` and `Error` events.
+	pub reason: Option<String>,
+	pub ts: i64,
+	/// When the publishing instance started. Changes with every restart, so disconnects caused by a
+	/// deploy share the boot epoch of the replaced instance.
+	pub boot_epoch: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunnerConnectionEventKind {
+	/// The runner's init packet was accepted and its workflow dispatched.
+	Init,
+	/// The connection was registered and is receiving messages.
+	Connect,
+	/// The connection ended, `reason` is one of `normal`, `replaced`, `evicted`, `timeout` or `error`.
+	Disconnect,
+	/// The runner workflow closed the connection, `reason` is the eviction reason.
+	Evict,
+	/// Processing the runner's messages failed, `reason` is the error code (i.e. `ws.invalid_packet`).
+	Error,
+}
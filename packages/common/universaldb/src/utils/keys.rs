@@ -122,4 +122,15 @@ define_keys! {
 	(94, SERVERLESS, "serverless"),
 	(95, DESIRED_SLOTS, "desired_slots"),
 	(96, BY_VARIANT, "by_variant"),
+	(97, STATUS, "status"),
+	(98, LAST_LOAD, "last_load"),
+	(99, QUARANTINE, "quarantine"),
+	(100, LAST_INSTANCE, "last_instance"),
+	(101, CONTENT_TYPE, "content_type"),
+	(102, CONNECTION_EXPORT, "connection_export"),
+	(103, COMPRESSION, "compression"),
+	(104, PROTOCOL_VERSION, "protocol_version"),
+	(105, PREPROVISION_EXPIRE_TS, "preprovision_expire_ts"),
+	(106, MAINTENANCE, "maintenance"),
+	(107, DRAIN, "drain"),
 }
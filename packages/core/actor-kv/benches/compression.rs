@@ -0,0 +1,44 @@
+//! Benchmarks the CPU cost of compressing KV values at rest (see `pegboard.kv_compression`).
+//! Compression runs on every put and decompression on every get and list of a compressed value.
+//!
+//! Run with `cargo bench -p pegboard-actor-kv`.
+
+use divan::{Bencher, counter::BytesCount};
+use pegboard_actor_kv::CompressionAlgorithm;
+
+const ALGORITHMS: [CompressionAlgorithm; 2] =
+	[CompressionAlgorithm::Gzip, CompressionAlgorithm::Zstd];
+/// Value sizes up to the KV value size limit.
+const SIZES: [usize; 3] = [1024, 16 * 1024, 128 * 1024];
+
+fn main() {
+	divan::main();
+}
+
+/// Semi-structured value, similar to serialized actor state.
+fn value(size: usize) -> Vec<u8> {
+	(0..)
+		.flat_map(|i: u32| {
+			format!(r#"{{"id":{i},"name":"actor-{}","state":"idle"}},"#, i % 7).into_bytes()
+		})
+		.take(size)
+		.collect()
+}
+
+#[divan::bench(args = ALGORITHMS, consts = SIZES)]
+fn compress<const SIZE: usize>(bencher: Bencher, algorithm: CompressionAlgorithm) {
+	let value = value(SIZE);
+
+	bencher
+		.counter(BytesCount::new(SIZE))
+		.bench_local(|| algorithm.compress(divan::black_box(&value)).unwrap());
+}
+
+#[divan::bench(args = ALGORITHMS, consts = SIZES)]
+fn decompress<const SIZE: usize>(bencher: Bencher, algorithm: CompressionAlgorithm) {
+	let compressed = algorithm.compress(&value(SIZE)).unwrap();
+
+	bencher
+		.counter(BytesCount::new(SIZE))
+		.bench_local(|| algorithm.decompress(divan::black_box(&compressed), SIZE).unwrap());
+}
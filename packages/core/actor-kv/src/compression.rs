@@ -0,0 +1,245 @@
+use std::io::{Read, Write};
+
+use anyhow::*;
+
+/// Compression of values at rest. Values are compressed in `put` and decompressed in `get` and
+/// `list`, transparent to runners.
+#[derive(Debug, Clone, Copy)]
+pub struct Compression {
+	pub algorithm: CompressionAlgorithm,
+	/// Values smaller than this are stored uncompressed.
+	pub min_size: usize,
+	/// Values without a content type whose sampled entropy (in bits per byte) is above this are stored
+	/// uncompressed.
+	pub max_entropy: f64,
+}
+
+impl Compression {
+	/// Returns why compressing the value should not be attempted, if it is likely incompressible. Uses
+	/// the declared content type if set and falls back to the entropy of a sample of the value.
+	pub(crate) fn skip_reason(
+		&self,
+		value: &[u8],
+		content_type: Option<&str>,
+	) -> Option<SkipReason> {
+		// Not compressed either way
+		if value.len() < self.min_size {
+			return None;
+		}
+
+		if let Some(content_type) = content_type {
+			return is_compressed_content_type(content_type).then_some(SkipReason::ContentType);
+		}
+
+		(sample_entropy(value) > self.max_entropy).then_some(SkipReason::Entropy)
+	}
+
+	/// Returns the compressed value if compression applies and is worth it.
+	pub(crate) fn compress(&self, value: &[u8]) -> Result<Option<Vec<u8>>> {
+		if value.len() < self.min_size {
+			return Ok(None);
+		}
+
+		let compressed = self.algorithm.compress(value)?;
+
+		// Incompressible values (i.e. already compressed) are stored as is
+		if compressed.len() >= value.len() {
+			return Ok(None);
+		}
+
+		Ok(Some(compressed))
+	}
+}
+
+/// Why compressing a value was skipped, see `Compression::skip_reason`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+	/// The declared content type is already compressed (i.e. images, archives).
+	ContentType,
+	/// The value has no content type and looks like random data.
+	Entropy,
+}
+
+impl SkipReason {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			SkipReason::ContentType => "content_type",
+			SkipReason::Entropy => "entropy",
+		}
+	}
+}
+
+/// Stored with each compressed entry. Entries stored without one are uncompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+	Gzip,
+	Zstd,
+}
+
+impl CompressionAlgorithm {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			CompressionAlgorithm::Gzip => "gzip",
+			CompressionAlgorithm::Zstd => "zstd",
+		}
+	}
+
+	pub fn parse(v: &str) -> Result<Self> {
+		match v {
+			"gzip" => Ok(CompressionAlgorithm::Gzip),
+			"zstd" => Ok(CompressionAlgorithm::Zstd),
+			_ => bail!("unknown compression algorithm `{v}`"),
+		}
+	}
+
+	pub fn compress(&self, value: &[u8]) -> Result<Vec<u8>> {
+		match self {
+			CompressionAlgorithm::Gzip => {
+				let mut encoder =
+					flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+				encoder.write_all(value)?;
+
+				encoder.finish().map_err(Into::into)
+			}
+			CompressionAlgorithm::Zstd => {
+				zstd::bulk::compress(value, zstd::DEFAULT_COMPRESSION_LEVEL).map_err(Into::into)
+			}
+		}
+	}
+
+	/// Decompresses a value, failing if it decompresses to more than `max_size` bytes.
+	pub fn decompress(&self, value: &[u8], max_size: usize) -> Result<Vec<u8>> {
+		match self {
+			CompressionAlgorithm::Gzip => {
+				let mut buf = Vec::new();
+				flate2::read::GzDecoder::new(value)
+					.take(max_size as u64 + 1)
+					.read_to_end(&mut buf)?;
+				ensure!(buf.len() <= max_size, "decompressed value too large");
+
+				Ok(buf)
+			}
+			CompressionAlgorithm::Zstd => {
+				zstd::bulk::decompress(value, max_size).map_err(Into::into)
+			}
+		}
+	}
+}
+
+/// Number of leading bytes the entropy of a value is estimated from.
+const ENTROPY_SAMPLE_SIZE: usize = 4096;
+
+/// Whether values of the content type are already compressed, ignoring parameters (i.e. `; charset=`).
+fn is_compressed_content_type(content_type: &str) -> bool {
+	let mime = content_type
+		.split(';')
+		.next()
+		.unwrap_or_default()
+		.trim()
+		.to_ascii_lowercase();
+	let Some((kind, subtype)) = mime.split_once('/') else {
+		return false;
+	};
+
+	match kind {
+		"image" => !matches!(subtype, "svg+xml" | "bmp" | "x-ms-bmp" | "tiff"),
+		"audio" => !matches!(subtype, "wav" | "x-wav" | "wave"),
+		"video" => true,
+		"font" => matches!(subtype, "woff" | "woff2"),
+		"application" => {
+			matches!(
+				subtype,
+				"zip"
+					| "gzip" | "x-gzip"
+					| "zstd" | "x-bzip2"
+					| "x-xz" | "x-7z-compressed"
+					| "vnd.rar" | "x-rar-compressed"
+			) || subtype.ends_with("+zip")
+				|| subtype.ends_with("+gzip")
+				|| subtype.ends_with("+zstd")
+		}
+		_ => false,
+	}
+}
+
+/// Shannon entropy of the leading bytes of the value, in bits per byte. Compressed and encrypted data
+/// is close to 8.
+fn sample_entropy(value: &[u8]) -> f64 {
+	let sample = &value[..value.len().min(ENTROPY_SAMPLE_SIZE)];
+	if sample.is_empty() {
+		return 0.0;
+	}
+
+	let mut counts = [0u32; 256];
+	for byte in sample {
+		counts[*byte as usize] += 1;
+	}
+
+	let len = sample.len() as f64;
+	counts
+		.iter()
+		.filter(|count| **count > 0)
+		.map(|count| {
+			let p = *count as f64 / len;
+			-p * p.log2()
+		})
+		.sum()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn roundtrip() {
+		let value = b"actor state".repeat(1000);
+
+		for algorithm in [CompressionAlgorithm::Gzip, CompressionAlgorithm::Zstd] {
+			let compression = Compression {
+				algorithm,
+				min_size: 1024,
+				max_entropy: 7.5,
+			};
+
+			let compressed = compression.compress(&value).unwrap().unwrap();
+			assert!(compressed.len() < value.len());
+			assert_eq!(algorithm.decompress(&compressed, value.len()).unwrap(), value);
+			assert!(algorithm.decompress(&compressed, value.len() - 1).is_err());
+
+			// Below the threshold
+			assert!(compression.compress(&value[..1023]).unwrap().is_none());
+		}
+	}
+
+	#[test]
+	fn skips_incompressible_values() {
+		let compression = Compression {
+			algorithm: CompressionAlgorithm::Zstd,
+			min_size: 1024,
+			max_entropy: 7.5,
+		};
+		let text = b"actor state".repeat(1000);
+		// Evenly spread bytes, similar to compressed data
+		let random = (0..8192u32)
+			.map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+			.collect::<Vec<_>>();
+
+		// Declared content types take precedence over the heuristic
+		assert_eq!(
+			compression.skip_reason(&text, Some("image/png")),
+			Some(SkipReason::ContentType)
+		);
+		assert_eq!(
+			compression.skip_reason(&text, Some("Application/GZIP; foo=bar")),
+			Some(SkipReason::ContentType)
+		);
+		assert_eq!(compression.skip_reason(&random, Some("text/plain")), None);
+		assert_eq!(compression.skip_reason(&text, Some("image/svg+xml")), None);
+
+		assert_eq!(compression.skip_reason(&text, None), None);
+		assert_eq!(compression.skip_reason(&random, None), Some(SkipReason::Entropy));
+
+		// Below the threshold
+		assert_eq!(compression.skip_reason(&random[..1023], None), None);
+	}
+}
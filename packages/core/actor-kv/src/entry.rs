@@ -4,12 +4,15 @@ use anyhow::*;
 use universaldb::prelude::*;
 
 use rivet_runner_protocol as rp;
+use serde::{Deserialize, Serialize};
 
-use crate::key::KeyWrapper;
+use crate::{MAX_VALUE_SIZE, TxStats, compression::CompressionAlgorithm, key::KeyWrapper};
 
 pub struct EntryBuilder {
 	pub key: KeyWrapper,
-	metadata: Option<rp::KvMetadata>,
+	metadata: Option<EntryMetadata>,
+	content_type: Option<String>,
+	compression: Option<CompressionAlgorithm>,
 	value: Vec<u8>,
 	next_idx: usize,
 }
@@ -19,12 +22,14 @@ impl EntryBuilder {
 		EntryBuilder {
 			key,
 			metadata: None,
+			content_type: None,
+			compression: None,
 			value: Vec::new(),
 			next_idx: 0,
 		}
 	}
 
-	pub fn append_metadata(&mut self, metadata: rp::KvMetadata) {
+	pub fn append_metadata(&mut self, metadata: EntryMetadata) {
 		// We ignore setting the metadata again because it means the same key was given twice in the
 		// input keys for `get`. We don't perform automatic deduplication.
 		if self.metadata.is_none() {
@@ -32,6 +37,18 @@ impl EntryBuilder {
 		}
 	}
 
+	pub fn append_content_type(&mut self, content_type: String) {
+		if self.content_type.is_none() {
+			self.content_type = Some(content_type);
+		}
+	}
+
+	pub fn append_compression(&mut self, compression: CompressionAlgorithm) {
+		if self.compression.is_none() {
+			self.compression = Some(compression);
+		}
+	}
+
 	pub fn append_chunk(&mut self, idx: usize, chunk: &[u8]) {
 		if idx >= self.next_idx {
 			self.value.extend(chunk);
@@ -39,13 +56,28 @@ impl EntryBuilder {
 		}
 	}
 
-	pub fn build(self) -> Result<(rp::KvKey, rp::KvValue, rp::KvMetadata)> {
+	pub fn build(self, stats: &TxStats) -> Result<(rp::KvKey, rp::KvValue, rp::KvMetadata)> {
 		ensure!(!self.value.is_empty(), "empty value at key");
 
+		let metadata = self.metadata.context("no metadata for key")?;
+
+		let value = if let Some(compression) = self.compression {
+			let value = compression.decompress(&self.value, MAX_VALUE_SIZE)?;
+			stats.record_read_compression(value.len(), self.value.len());
+
+			value
+		} else {
+			self.value
+		};
+
 		Ok((
 			self.key.0,
-			self.value,
-			self.metadata.context("no metadata for key")?,
+			value,
+			rp::KvMetadata {
+				version: metadata.version,
+				create_ts: metadata.create_ts,
+				content_type: self.content_type,
+			},
 		))
 	}
 }
@@ -99,6 +131,14 @@ impl<'de> TupleUnpack<'de> for EntryValueChunkKey {
 	}
 }
 
+/// Stored metadata of an entry. Kept separate from `rp::KvMetadata` so that fields added to the
+/// protocol do not change the stored format.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EntryMetadata {
+	pub version: Vec<u8>,
+	pub create_ts: i64,
+}
+
 #[derive(Debug)]
 pub struct EntryMetadataKey {
 	pub key: KeyWrapper,
@@ -111,7 +151,7 @@ impl EntryMetadataKey {
 }
 
 impl FormalKey for EntryMetadataKey {
-	type Value = rp::KvMetadata;
+	type Value = EntryMetadata;
 
 	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
 		serde_bare::from_slice(raw).map_err(Into::into)
@@ -145,3 +185,99 @@ impl<'de> TupleUnpack<'de> for EntryMetadataKey {
 		Ok((input, v))
 	}
 }
+
+/// Optional content type of the value, stored as given by the client.
+#[derive(Debug)]
+pub struct EntryContentTypeKey {
+	pub key: KeyWrapper,
+}
+
+impl EntryContentTypeKey {
+	pub fn new(key: KeyWrapper) -> Self {
+		EntryContentTypeKey { key }
+	}
+}
+
+impl FormalKey for EntryContentTypeKey {
+	type Value = String;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		String::from_utf8(raw.to_vec()).map_err(Into::into)
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		Ok(value.into_bytes())
+	}
+}
+
+impl TuplePack for EntryContentTypeKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (&self.key, CONTENT_TYPE);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for EntryContentTypeKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (key, data)) = <(KeyWrapper, usize)>::unpack(input, tuple_depth)?;
+		if data != CONTENT_TYPE {
+			return Err(PackError::Message("expected CONTENT_TYPE data".into()));
+		}
+
+		let v = EntryContentTypeKey { key };
+
+		Ok((input, v))
+	}
+}
+
+/// Algorithm the value is compressed with. Not set for uncompressed values.
+#[derive(Debug)]
+pub struct EntryCompressionKey {
+	pub key: KeyWrapper,
+}
+
+impl EntryCompressionKey {
+	pub fn new(key: KeyWrapper) -> Self {
+		EntryCompressionKey { key }
+	}
+}
+
+impl FormalKey for EntryCompressionKey {
+	type Value = CompressionAlgorithm;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		CompressionAlgorithm::parse(std::str::from_utf8(raw)?)
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		Ok(value.as_str().as_bytes().to_vec())
+	}
+}
+
+impl TuplePack for EntryCompressionKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (&self.key, COMPRESSION);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for EntryCompressionKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (key, data)) = <(KeyWrapper, usize)>::unpack(input, tuple_depth)?;
+		if data != COMPRESSION {
+			return Err(PackError::Message("expected COMPRESSION data".into()));
+		}
+
+		let v = EntryCompressionKey { key };
+
+		Ok((input, v))
+	}
+}
@@ -7,8 +7,10 @@ use universaldb::tuple::{
 pub struct KeyWrapper(pub rp::KvKey);
 
 impl KeyWrapper {
+	/// Size of the key once packed. Null bytes are escaped when packing, so they count twice. Otherwise
+	/// keys made of null bytes could pack to twice the size limit.
 	pub fn tuple_len(key: &rp::KvKey) -> usize {
-		key.len() + 2
+		key.len() + key.iter().filter(|b| **b == 0).count() + 2
 	}
 }
 
@@ -65,3 +67,63 @@ impl TuplePack for ListKeyWrapper {
 		Ok(offset)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use universaldb::utils::Subspace;
+
+	use super::*;
+
+	/// Keys that look like parts of the internal tuple encoding.
+	fn adversarial_keys() -> Vec<rp::KvKey> {
+		vec![
+			vec![],
+			vec![0x00],
+			vec![0x00, 0xff],
+			vec![0xff, 0x00],
+			vec![b'a', 0x00],
+			vec![b'a', 0x00, 0x00],
+			vec![
+				universaldb::utils::codes::NESTED,
+				0x01,
+				b'a',
+				0x00,
+				universaldb::utils::codes::NIL,
+			],
+			vec![0xff; 16],
+		]
+	}
+
+	#[test]
+	fn adversarial_keys_round_trip() {
+		let subspace = Subspace::new(&("kv",));
+
+		for key in adversarial_keys() {
+			let packed = subspace.pack(&KeyWrapper(key.clone()));
+			let unpacked = subspace.unpack::<KeyWrapper>(&packed).unwrap();
+			assert_eq!(unpacked.0, key);
+			assert_eq!(
+				packed.len() - subspace.bytes().len(),
+				KeyWrapper::tuple_len(&key) + 2
+			);
+		}
+	}
+
+	#[test]
+	fn key_ranges_do_not_contain_other_keys() {
+		let subspace = Subspace::new(&("kv",));
+		let keys = adversarial_keys();
+
+		for key in &keys {
+			let (start, end) = subspace.subspace(&KeyWrapper(key.clone())).range();
+
+			for other in keys.iter().filter(|other| *other != key) {
+				let packed = subspace.pack(&KeyWrapper(other.clone()));
+				assert!(
+					!(start <= packed && packed < end),
+					"range of {key:?} contains {other:?}"
+				);
+			}
+		}
+	}
+}
@@ -1,29 +1,207 @@
 use std::result::Result::{Err, Ok};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use anyhow::*;
-use entry::{EntryBaseKey, EntryBuilder, EntryMetadataKey, EntryValueChunkKey};
+use entry::{
+	EntryBaseKey, EntryBuilder, EntryCompressionKey, EntryContentTypeKey, EntryMetadata,
+	EntryMetadataKey, EntryValueChunkKey,
+};
 use futures_util::{StreamExt, TryStreamExt};
 use key::{KeyWrapper, ListKeyWrapper};
 use rivet_runner_protocol as rp;
 use rivet_util_id::Id;
 use universaldb::prelude::*;
-use universaldb::tuple::Subspace;
-use utils::{validate_entries, validate_keys};
+use universaldb::tuple::{Bytes, Subspace};
+use utils::{validate_collection, validate_content_types, validate_entries, validate_keys};
 
+mod compression;
 mod entry;
 mod key;
 mod utils;
 
+pub use compression::{Compression, CompressionAlgorithm, SkipReason};
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const MAX_KEY_SIZE: usize = 2 * 1024;
-const MAX_VALUE_SIZE: usize = 128 * 1024;
-const MAX_KEYS: usize = 128;
+pub const MAX_KEY_SIZE: usize = 2 * 1024;
+pub const MAX_VALUE_SIZE: usize = 128 * 1024;
+/// Maximum number of keys in a single get, put or delete.
+pub const MAX_KEYS: usize = 128;
 const MAX_PUT_PAYLOAD_SIZE: usize = 976 * 1024;
 const MAX_STORAGE_SIZE: usize = 1024 * 1024 * 1024; // 1 GiB
+const MAX_COLLECTION_NAME_SIZE: usize = 64;
+const MAX_PREFIX_SIZE: usize = 64;
+const MAX_CONTENT_TYPE_SIZE: usize = 255;
 const VALUE_CHUNK_SIZE: usize = 10_000; // 10 KB, not KiB, see https://apple.github.io/foundationdb/blob.html
 
-fn subspace(actor_id: Id) -> universaldb::utils::Subspace {
-	pegboard::keys::actor_kv_subspace().subspace(&actor_id)
+/// Error caused by the request itself (i.e. invalid keys or exceeding the actor's storage limit). Retrying
+/// the same request fails again, see `is_retryable`.
+#[derive(Debug)]
+pub struct InvalidRequest(anyhow::Error);
+
+impl std::fmt::Display for InvalidRequest {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl std::error::Error for InvalidRequest {}
+
+fn invalid_request(err: anyhow::Error) -> anyhow::Error {
+	InvalidRequest(err).into()
+}
+
+/// Whether a failed KV operation can succeed if the same request is retried. Errors caused by the request
+/// are permanent, database errors are classified by `DatabaseError::is_retryable` and any other error
+/// (i.e. the database being unreachable or timing out) is assumed to be transient.
+pub fn is_retryable(err: &anyhow::Error) -> bool {
+	if err.is::<InvalidRequest>() {
+		return false;
+	}
+
+	err.chain()
+		.find_map(|err| err.downcast_ref::<universaldb::error::DatabaseError>())
+		.is_none_or(|err| err.is_retryable())
+}
+
+/// Tracks how many times the transactions of a KV operation were attempted. Transactions are retried
+/// internally by universaldb on conflicts.
+///
+/// Also tracks the size of values before and after compression, only for values that go through
+/// compression (values written while compression is enabled and compressed values read).
+#[derive(Debug, Default)]
+pub struct TxStats {
+	attempts: AtomicUsize,
+	read_uncompressed: AtomicU64,
+	read_compressed: AtomicU64,
+	written_uncompressed: AtomicU64,
+	written_compressed: AtomicU64,
+	skipped_content_type: AtomicU64,
+	skipped_entropy: AtomicU64,
+}
+
+impl TxStats {
+	fn attempt(&self) {
+		self.attempts.fetch_add(1, Ordering::Relaxed);
+
+		// Values read by a previous attempt were discarded
+		self.read_uncompressed.store(0, Ordering::Relaxed);
+		self.read_compressed.store(0, Ordering::Relaxed);
+	}
+
+	fn record_read_compression(&self, uncompressed: usize, compressed: usize) {
+		self.read_uncompressed
+			.fetch_add(uncompressed as u64, Ordering::Relaxed);
+		self.read_compressed
+			.fetch_add(compressed as u64, Ordering::Relaxed);
+	}
+
+	fn record_write_compression(&self, uncompressed: usize, compressed: usize) {
+		self.written_uncompressed
+			.fetch_add(uncompressed as u64, Ordering::Relaxed);
+		self.written_compressed
+			.fetch_add(compressed as u64, Ordering::Relaxed);
+	}
+
+	fn record_compression_skipped(&self, reason: SkipReason) {
+		let counter = match reason {
+			SkipReason::ContentType => &self.skipped_content_type,
+			SkipReason::Entropy => &self.skipped_entropy,
+		};
+		counter.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Size of the compressed values read, as `(uncompressed, compressed)` bytes.
+	pub fn read_compression(&self) -> (u64, u64) {
+		(
+			self.read_uncompressed.load(Ordering::Relaxed),
+			self.read_compressed.load(Ordering::Relaxed),
+		)
+	}
+
+	/// Size of the values written while compression was enabled, as `(uncompressed, compressed)` bytes.
+	/// Values that were not worth compressing count with the same size for both.
+	pub fn write_compression(&self) -> (u64, u64) {
+		(
+			self.written_uncompressed.load(Ordering::Relaxed),
+			self.written_compressed.load(Ordering::Relaxed),
+		)
+	}
+
+	/// Number of values written uncompressed because they looked incompressible, by reason.
+	pub fn compression_skipped(&self) -> [(SkipReason, u64); 2] {
+		[
+			(SkipReason::ContentType, self.skipped_content_type.load(Ordering::Relaxed)),
+			(SkipReason::Entropy, self.skipped_entropy.load(Ordering::Relaxed)),
+		]
+	}
+
+	/// Number of times a transaction was retried after the first attempt.
+	pub fn retries(&self) -> usize {
+		self.attempts.load(Ordering::Relaxed).saturating_sub(1)
+	}
+}
+
+/// Where in an actor's KV store an operation applies.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Scope<'a> {
+	/// Isolates all data of a connection (i.e. test runners sharing a database with production runners).
+	pub prefix: Option<&'a str>,
+	pub collection: Option<&'a str>,
+}
+
+impl Scope<'_> {
+	fn validate(&self) -> Result<()> {
+		if let Some(prefix) = self.prefix {
+			validate_prefix(prefix).map_err(invalid_request)?;
+		}
+
+		validate_collection(self.collection).map_err(invalid_request)
+	}
+}
+
+/// Validates a connection-scoped KV prefix.
+pub fn validate_prefix(prefix: &str) -> Result<()> {
+	ensure!(!prefix.is_empty(), "kv prefix cannot be empty");
+	ensure!(
+		prefix.len() <= MAX_PREFIX_SIZE,
+		"kv prefix is too long (max 64 bytes)"
+	);
+	ensure!(
+		prefix
+			.bytes()
+			.all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'),
+		"kv prefix can only contain alphanumeric characters, `-` and `_`"
+	);
+
+	Ok(())
+}
+
+/// Prefixes are stored as a bytes element and collections as a string element within the actor's subspace.
+/// Keys are always packed as nested tuples so entries in the actor's flat key space never overlap with
+/// prefixes or collections.
+fn subspace(actor_id: Id, scope: Scope) -> universaldb::utils::Subspace {
+	let mut subspace = pegboard::keys::actor_kv_subspace().subspace(&actor_id);
+
+	if let Some(prefix) = scope.prefix {
+		subspace = subspace.subspace(&Bytes::from(prefix.as_bytes()));
+	}
+
+	if let Some(collection) = scope.collection {
+		subspace = subspace.subspace(&collection);
+	}
+
+	subspace
+}
+
+/// Range of all entries directly in the given subspace, excluding prefixes and collections.
+fn entries_range(subspace: &Subspace) -> (Vec<u8>, Vec<u8>) {
+	let mut start = subspace.bytes().to_vec();
+	start.push(universaldb::utils::codes::NESTED);
+
+	let mut end = subspace.bytes().to_vec();
+	end.push(universaldb::utils::codes::NESTED + 1);
+
+	(start, end)
 }
 
 /// Returns estimated size of the given subspace.
@@ -41,20 +219,29 @@ pub async fn get_subspace_size(db: &universaldb::Database, subspace: &Subspace)
 pub async fn get(
 	db: &universaldb::Database,
 	actor_id: Id,
+	scope: Scope<'_>,
 	keys: Vec<rp::KvKey>,
+	stats: &TxStats,
 ) -> Result<(Vec<rp::KvKey>, Vec<rp::KvValue>, Vec<rp::KvMetadata>)> {
-	validate_keys(&keys)?;
+	scope.validate()?;
+	validate_keys(&keys).map_err(invalid_request)?;
+
+	let subspace = subspace(actor_id, scope);
 
 	db.run(|tx| {
+		stats.attempt();
+
 		let keys = keys.clone();
+		let subspace = subspace.clone();
+
 		async move {
-			let tx = tx.with_subspace(subspace(actor_id));
+			let tx = tx.with_subspace(subspace.clone());
 
 			let size_estimate = keys.len().min(1024);
 
 			let mut stream = futures_util::stream::iter(keys)
 				.map(|key| {
-					let key_subspace = subspace(actor_id).subspace(&KeyWrapper(key));
+					let key_subspace = subspace.subspace(&KeyWrapper(key));
 
 					// Get all sub keys in the key subspace
 					tx.get_ranges_keyvalues(
@@ -84,7 +271,7 @@ pub async fn get(
 				let current_entry = if let Some(inner) = &mut current_entry {
 					if inner.key != key {
 						let (key, value, meta) =
-							std::mem::replace(inner, EntryBuilder::new(key)).build()?;
+							std::mem::replace(inner, EntryBuilder::new(key)).build(stats)?;
 
 						keys.push(key);
 						values.push(value);
@@ -104,13 +291,23 @@ pub async fn get(
 					let value = metadata_key.deserialize(entry.value())?;
 
 					current_entry.append_metadata(value);
+				} else if let Ok(content_type_key) =
+					tx.unpack::<EntryContentTypeKey>(&entry.key())
+				{
+					let value = content_type_key.deserialize(entry.value())?;
+
+					current_entry.append_content_type(value);
+				} else if let Ok(compression_key) = tx.unpack::<EntryCompressionKey>(&entry.key()) {
+					let value = compression_key.deserialize(entry.value())?;
+
+					current_entry.append_compression(value);
 				} else {
 					bail!("unexpected sub key");
 				}
 			}
 
 			if let Some(inner) = current_entry {
-				let (key, value, meta) = inner.build()?;
+				let (key, value, meta) = inner.build(stats)?;
 
 				keys.push(key);
 				values.push(value);
@@ -128,17 +325,22 @@ pub async fn get(
 pub async fn list(
 	db: &universaldb::Database,
 	actor_id: Id,
+	scope: Scope<'_>,
 	query: rp::KvListQuery,
 	reverse: bool,
 	limit: Option<usize>,
+	stats: &TxStats,
 ) -> Result<(Vec<rp::KvKey>, Vec<rp::KvValue>, Vec<rp::KvMetadata>)> {
-	utils::validate_list_query(&query)?;
+	scope.validate()?;
+	utils::validate_list_query(&query).map_err(invalid_request)?;
 
 	let limit = limit.unwrap_or(16384);
-	let subspace = subspace(actor_id);
+	let subspace = subspace(actor_id, scope);
 	let list_range = list_query_range(query, &subspace);
 
 	db.run(|tx| {
+		stats.attempt();
+
 		let list_range = list_range.clone();
 		let subspace = subspace.clone();
 
@@ -169,7 +371,7 @@ pub async fn list(
 				let curr = if let Some(inner) = &mut current_entry {
 					if inner.key != key {
 						let (key, value, meta) =
-							std::mem::replace(inner, EntryBuilder::new(key)).build()?;
+							std::mem::replace(inner, EntryBuilder::new(key)).build(stats)?;
 
 						keys.push(key);
 						values.push(value);
@@ -194,13 +396,23 @@ pub async fn list(
 					let value = metadata_key.deserialize(entry.value())?;
 
 					curr.append_metadata(value);
+				} else if let Ok(content_type_key) =
+					tx.unpack::<EntryContentTypeKey>(&entry.key())
+				{
+					let value = content_type_key.deserialize(entry.value())?;
+
+					curr.append_content_type(value);
+				} else if let Ok(compression_key) = tx.unpack::<EntryCompressionKey>(&entry.key()) {
+					let value = compression_key.deserialize(entry.value())?;
+
+					curr.append_compression(value);
 				} else {
 					bail!("unexpected sub key");
 				}
 			}
 
 			if let Some(inner) = current_entry {
-				let (key, value, meta) = inner.build()?;
+				let (key, value, meta) = inner.build(stats)?;
 
 				keys.push(key);
 				values.push(value);
@@ -218,25 +430,60 @@ pub async fn list(
 pub async fn put(
 	db: &universaldb::Database,
 	actor_id: Id,
+	scope: Scope<'_>,
 	keys: Vec<rp::KvKey>,
 	values: Vec<rp::KvValue>,
+	content_types: Option<Vec<Option<String>>>,
+	compression: Option<Compression>,
+	stats: &TxStats,
 ) -> Result<()> {
-	let subspace = subspace(actor_id);
-	let total_size = get_subspace_size(&db, &subspace).await? as usize;
+	scope.validate()?;
+
+	// Storage limit applies to the actor as a whole, including all prefixes and collections
+	let total_size = get_subspace_size(&db, &subspace(actor_id, Scope::default())).await? as usize;
+	let subspace = subspace(actor_id, scope);
+
+	validate_entries(&keys, &values, total_size).map_err(invalid_request)?;
+	validate_content_types(&keys, content_types.as_deref()).map_err(invalid_request)?;
+
+	// Entries without a content type are stored as opaque values
+	let content_types = content_types.unwrap_or_else(|| vec![None; keys.len()]);
+
+	// Compressed once outside of the transaction since it may be retried
+	let values = values
+		.into_iter()
+		.zip(&content_types)
+		.map(|(value, content_type)| {
+			if let Some(compression) = compression {
+				if let Some(reason) = compression.skip_reason(&value, content_type.as_deref()) {
+					stats.record_compression_skipped(reason);
+				} else if let Some(compressed) = compression.compress(&value)? {
+					stats.record_write_compression(value.len(), compressed.len());
+
+					return Ok((compressed, Some(compression.algorithm)));
+				}
 
-	validate_entries(&keys, &values, total_size)?;
+				stats.record_write_compression(value.len(), value.len());
+			}
+
+			Ok((value, None))
+		})
+		.collect::<Result<Vec<_>>>()?;
 
 	db.run(|tx| {
+		stats.attempt();
+
 		// TODO: Costly clone
 		let keys = keys.clone();
 		let values = values.clone();
+		let content_types = content_types.clone();
 		let subspace = subspace.clone();
 
 		async move {
 			let tx = tx.with_subspace(subspace.clone());
 
-			futures_util::stream::iter(keys.into_iter().zip(values.into_iter()))
-				.map(|(key, value)| {
+			futures_util::stream::iter(keys.into_iter().zip(values).zip(content_types))
+				.map(|((key, (value, compression)), content_type)| {
 					let tx = tx.clone();
 					let key = KeyWrapper(key.clone());
 					let subspace = subspace.clone();
@@ -248,12 +495,20 @@ pub async fn put(
 						// Set metadata
 						tx.write(
 							&EntryMetadataKey::new(key.clone()),
-							rp::KvMetadata {
+							EntryMetadata {
 								version: VERSION.as_bytes().to_vec(),
 								create_ts: utils::now(),
 							},
 						)?;
 
+						if let Some(content_type) = content_type {
+							tx.write(&EntryContentTypeKey::new(key.clone()), content_type)?;
+						}
+
+						if let Some(compression) = compression {
+							tx.write(&EntryCompressionKey::new(key.clone()), compression)?;
+						}
+
 						// Set key data in chunks
 						for start in (0..value.len()).step_by(VALUE_CHUNK_SIZE) {
 							let idx = start / VALUE_CHUNK_SIZE;
@@ -278,14 +533,27 @@ pub async fn put(
 }
 
 /// Deletes keys from the KV store. Cannot be undone.
-pub async fn delete(db: &universaldb::Database, actor_id: Id, keys: Vec<rp::KvKey>) -> Result<()> {
-	validate_keys(&keys)?;
+pub async fn delete(
+	db: &universaldb::Database,
+	actor_id: Id,
+	scope: Scope<'_>,
+	keys: Vec<rp::KvKey>,
+	stats: &TxStats,
+) -> Result<()> {
+	scope.validate()?;
+	validate_keys(&keys).map_err(invalid_request)?;
+
+	let subspace = subspace(actor_id, scope);
 
 	db.run(|tx| {
+		stats.attempt();
+
 		let keys = keys.clone();
+		let subspace = subspace.clone();
+
 		async move {
 			for key in keys {
-				let key_subspace = subspace(actor_id).subspace(&KeyWrapper(key));
+				let key_subspace = subspace.subspace(&KeyWrapper(key));
 
 				tx.clear_subspace_range(&key_subspace);
 			}
@@ -297,11 +565,28 @@ pub async fn delete(db: &universaldb::Database, actor_id: Id, keys: Vec<rp::KvKe
 	.map_err(Into::into)
 }
 
-/// Deletes all keys from the KV store. Cannot be undone.
-pub async fn delete_all(db: &universaldb::Database, actor_id: Id) -> Result<()> {
-	db.run(|tx| async move {
-		tx.clear_subspace_range(&subspace(actor_id));
-		Ok(())
+/// Deletes all keys within the given scope from the KV store. Without a collection all keys of the scope
+/// are deleted, including its collections. Without a prefix or collection all keys of the actor are
+/// deleted. Cannot be undone.
+pub async fn delete_all(
+	db: &universaldb::Database,
+	actor_id: Id,
+	scope: Scope<'_>,
+	stats: &TxStats,
+) -> Result<()> {
+	scope.validate()?;
+
+	let subspace = subspace(actor_id, scope);
+
+	db.run(|tx| {
+		stats.attempt();
+
+		let subspace = subspace.clone();
+
+		async move {
+			tx.clear_subspace_range(&subspace);
+			Ok(())
+		}
 	})
 	.await
 	.map_err(Into::into)
@@ -309,7 +594,7 @@ pub async fn delete_all(db: &universaldb::Database, actor_id: Id) -> Result<()>
 
 fn list_query_range(query: rp::KvListQuery, subspace: &Subspace) -> (Vec<u8>, Vec<u8>) {
 	match query {
-		rp::KvListQuery::KvListAllQuery => subspace.range(),
+		rp::KvListQuery::KvListAllQuery => entries_range(subspace),
 		rp::KvListQuery::KvListRangeQuery(range) => (
 			subspace.subspace(&ListKeyWrapper(range.start)).range().0,
 			if range.exclusive {
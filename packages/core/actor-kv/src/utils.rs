@@ -4,7 +4,8 @@ use anyhow::*;
 use rivet_runner_protocol as rp;
 
 use crate::{
-	MAX_KEY_SIZE, MAX_KEYS, MAX_PUT_PAYLOAD_SIZE, MAX_STORAGE_SIZE, MAX_VALUE_SIZE, key::KeyWrapper,
+	MAX_COLLECTION_NAME_SIZE, MAX_CONTENT_TYPE_SIZE, MAX_KEY_SIZE, MAX_KEYS, MAX_PUT_PAYLOAD_SIZE,
+	MAX_STORAGE_SIZE, MAX_VALUE_SIZE, key::KeyWrapper,
 };
 
 pub fn now() -> i64 {
@@ -16,6 +17,18 @@ pub fn now() -> i64 {
 		.expect("now doesn't fit in i64")
 }
 
+pub fn validate_collection(collection: Option<&str>) -> Result<()> {
+	if let Some(collection) = collection {
+		ensure!(!collection.is_empty(), "collection name cannot be empty");
+		ensure!(
+			collection.len() <= MAX_COLLECTION_NAME_SIZE,
+			"collection name is too long (max 64 bytes)"
+		);
+	}
+
+	Ok(())
+}
+
 pub fn validate_list_query(query: &rp::KvListQuery) -> Result<()> {
 	match query {
 		rp::KvListQuery::KvListAllQuery => {}
@@ -28,6 +41,9 @@ pub fn validate_list_query(query: &rp::KvListQuery) -> Result<()> {
 				KeyWrapper::tuple_len(&range.end) <= MAX_KEY_SIZE,
 				"end key is too long (max 2048 bytes)"
 			);
+			// Packed keys sort like the raw bytes, an inverted range would be rejected by the database
+			// instead
+			ensure!(range.start <= range.end, "start key is after end key");
 		}
 		rp::KvListQuery::KvListPrefixQuery(prefix) => {
 			ensure!(
@@ -99,3 +115,27 @@ pub fn validate_entries(
 
 	Ok(())
 }
+
+pub fn validate_content_types(
+	keys: &[rp::KvKey],
+	content_types: Option<&[Option<String>]>,
+) -> Result<()> {
+	let Some(content_types) = content_types else {
+		return Ok(());
+	};
+
+	ensure!(
+		keys.len() == content_types.len(),
+		"Keys list length != content types list length"
+	);
+
+	for content_type in content_types.iter().flatten() {
+		ensure!(!content_type.is_empty(), "content type cannot be empty");
+		ensure!(
+			content_type.len() <= MAX_CONTENT_TYPE_SIZE,
+			"content type is too long (max 255 bytes)"
+		);
+	}
+
+	Ok(())
+}
@@ -42,3 +42,88 @@ pub async fn bump_serverless_autoscaler(
 
 	Ok(BumpServerlessAutoscalerResponse {})
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct SetRunnerWsMaintenanceModeRequest {
+	pub enabled: bool,
+	pub retry_after_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct SetRunnerWsMaintenanceModeResponse {}
+
+pub async fn set_runner_ws_maintenance_mode(
+	ctx: ApiCtx,
+	_path: (),
+	_query: (),
+	body: SetRunnerWsMaintenanceModeRequest,
+) -> Result<SetRunnerWsMaintenanceModeResponse> {
+	ctx.op(pegboard::ops::runner::set_ws_maintenance_mode::Input {
+		enabled: body.enabled,
+		retry_after_ms: body.retry_after_ms,
+	})
+	.await?;
+
+	ctx.msg(rivet_types::msgs::pegboard::SetRunnerWsMaintenanceMode {})
+		.send()
+		.await?;
+
+	Ok(SetRunnerWsMaintenanceModeResponse {})
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetRunnerWsPingUpdatesPausedRequest {
+	pub paused: bool,
+	pub duration_ms: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SetRunnerWsPingUpdatesPausedResponse {}
+
+pub async fn set_runner_ws_ping_updates_paused(
+	ctx: ApiCtx,
+	_path: (),
+	_query: (),
+	body: SetRunnerWsPingUpdatesPausedRequest,
+) -> Result<SetRunnerWsPingUpdatesPausedResponse> {
+	ctx.msg(rivet_types::msgs::pegboard::SetRunnerWsPingUpdatesPaused {
+		paused: body.paused,
+		duration_ms: body.duration_ms,
+	})
+	.send()
+	.await?;
+
+	Ok(SetRunnerWsPingUpdatesPausedResponse {})
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetRunnerWsNamespaceDrainingRequest {
+	pub namespace_id: Id,
+	pub draining: bool,
+	pub retry_after_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct SetRunnerWsNamespaceDrainingResponse {}
+
+pub async fn set_runner_ws_namespace_draining(
+	ctx: ApiCtx,
+	_path: (),
+	_query: (),
+	body: SetRunnerWsNamespaceDrainingRequest,
+) -> Result<SetRunnerWsNamespaceDrainingResponse> {
+	ctx.op(pegboard::ops::runner::set_ws_namespace_draining::Input {
+		namespace_id: body.namespace_id,
+		draining: body.draining,
+		retry_after_ms: body.retry_after_ms,
+	})
+	.await?;
+
+	ctx.msg(rivet_types::msgs::pegboard::SetRunnerWsNamespaceDraining {
+		namespace_id: body.namespace_id,
+	})
+	.send()
+	.await?;
+
+	Ok(SetRunnerWsNamespaceDrainingResponse {})
+}
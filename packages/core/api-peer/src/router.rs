@@ -41,6 +41,18 @@ pub async fn router(
 				"/bump-serverless-autoscaler",
 				post(internal::bump_serverless_autoscaler),
 			)
+			.route(
+				"/runner-ws/maintenance-mode",
+				post(internal::set_runner_ws_maintenance_mode),
+			)
+			.route(
+				"/runner-ws/ping-updates-paused",
+				post(internal::set_runner_ws_ping_updates_paused),
+			)
+			.route(
+				"/runner-ws/namespace-draining",
+				post(internal::set_runner_ws_namespace_draining),
+			)
 	})
 	.await
 }
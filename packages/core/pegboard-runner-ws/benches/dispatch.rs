@@ -0,0 +1,222 @@
+//! Benchmarks the dispatch hot path of the msg thread: looking up the connection of a `ToWs` message,
+//! converting and serializing it (mirrors `Connection::send`) and writing it to the socket. The socket
+//! is replaced with a no-op sink so only the server side cost is measured.
+//!
+//! The `broadcast_*` benches compare sending the same message to every connection by serializing it per
+//! connection against serializing it once per protocol version and only prepending each connection's
+//! sequence number.
+//!
+//! Run with `cargo bench -p pegboard-runner-ws`.
+
+use std::{
+	collections::{HashMap, hash_map::Entry},
+	sync::{
+		Arc,
+		atomic::{AtomicU64, Ordering},
+	},
+};
+
+use anyhow::Result;
+use divan::{Bencher, counter::ItemsCount};
+use futures_util::{
+	SinkExt,
+	sink::{Drain, drain},
+};
+use gas::prelude::Id;
+use rivet_runner_protocol::{self as rp, PROTOCOL_VERSION, protocol, versioned};
+use tokio::sync::{Mutex, RwLock};
+use tokio_tungstenite::tungstenite::Message;
+use versioned_data_util::OwnedVersionedData;
+
+/// Messages dispatched per iteration.
+const MESSAGES: usize = 1000;
+
+fn main() {
+	divan::main();
+}
+
+struct FakeConnection {
+	protocol_version: u16,
+	last_seq: AtomicU64,
+	tx: Mutex<Drain<Message>>,
+}
+
+type Connections = RwLock<HashMap<Id, Arc<FakeConnection>>>;
+
+/// Dispatches messages round robin over all connections. `INPUT_SIZE` is the size of the actor input in
+/// each start actor command, which dominates the message size.
+#[divan::bench(consts = [0, 1024, 64 * 1024], args = [1, 100, 10_000])]
+fn dispatch<const INPUT_SIZE: usize>(bencher: Bencher, connection_count: usize) {
+	let rt = tokio::runtime::Builder::new_current_thread()
+		.build()
+		.expect("failed building runtime");
+
+	let conns: Connections = RwLock::new(
+		(0..connection_count)
+			.map(|_| {
+				(
+					Id::new_v1(1),
+					Arc::new(FakeConnection {
+						protocol_version: PROTOCOL_VERSION,
+						last_seq: AtomicU64::new(0),
+						tx: Mutex::new(drain()),
+					}),
+				)
+			})
+			.collect(),
+	);
+	let runner_ids = rt.block_on(conns.read()).keys().copied().collect::<Vec<_>>();
+
+	bencher
+		.counter(ItemsCount::new(MESSAGES))
+		.with_inputs(|| {
+			(0..MESSAGES)
+				.map(|i| (runner_ids[i % runner_ids.len()], start_actor(i, INPUT_SIZE)))
+				.collect::<Vec<_>>()
+		})
+		.bench_local_values(|messages| {
+			rt.block_on(async {
+				for (runner_id, inner) in messages {
+					dispatch_one(&conns, runner_id, inner)
+						.await
+						.expect("failed dispatching");
+				}
+			})
+		});
+}
+
+/// Same steps as the `ToWs` branch of `msg_thread_inner`.
+async fn dispatch_one(conns: &Connections, runner_id: Id, inner: protocol::ToClient) -> Result<()> {
+	let conns = conns.read().await;
+	let Some(conn) = conns.get(&runner_id) else {
+		return Ok(());
+	};
+
+	let message: rp::ToClient = inner.try_into()?;
+
+	let mut tx = conn.tx.lock().await;
+	let seq = conn.last_seq.fetch_add(1, Ordering::AcqRel) + 1;
+	let buf = versioned::ToClient::latest(rp::ToClientPacket { seq, message })
+		.serialize(conn.protocol_version)?;
+	tx.send(Message::Binary(buf.into())).await?;
+
+	Ok(())
+}
+
+/// Sends one message to all connections, serializing it for each connection like `Connection::send`.
+#[divan::bench(consts = [0, 1024, 64 * 1024], args = [100, 10_000])]
+fn broadcast_per_connection<const INPUT_SIZE: usize>(bencher: Bencher, connection_count: usize) {
+	let rt = tokio::runtime::Builder::new_current_thread()
+		.build()
+		.expect("failed building runtime");
+	let conns = connections(connection_count);
+
+	bencher
+		.counter(ItemsCount::new(connection_count))
+		.with_inputs(|| start_actor(0, INPUT_SIZE))
+		.bench_local_values(|inner| {
+			rt.block_on(async {
+				let message: rp::ToClient = inner.try_into().expect("failed converting");
+
+				for conn in conns.read().await.values() {
+					let mut tx = conn.tx.lock().await;
+					let seq = conn.last_seq.fetch_add(1, Ordering::AcqRel) + 1;
+					let buf = versioned::ToClient::latest(rp::ToClientPacket {
+						seq,
+						message: message.clone(),
+					})
+					.serialize(conn.protocol_version)
+					.expect("failed serializing");
+					tx.send(Message::Binary(buf.into()))
+						.await
+						.expect("failed sending");
+				}
+			})
+		});
+}
+
+/// Sends one message to all connections, serializing it once per distinct protocol version.
+#[divan::bench(consts = [0, 1024, 64 * 1024], args = [100, 10_000])]
+fn broadcast_cached<const INPUT_SIZE: usize>(bencher: Bencher, connection_count: usize) {
+	let rt = tokio::runtime::Builder::new_current_thread()
+		.build()
+		.expect("failed building runtime");
+	let conns = connections(connection_count);
+
+	bencher
+		.counter(ItemsCount::new(connection_count))
+		.with_inputs(|| start_actor(0, INPUT_SIZE))
+		.bench_local_values(|inner| {
+			rt.block_on(async {
+				let message: rp::ToClient = inner.try_into().expect("failed converting");
+				let mut frames = HashMap::<u16, Vec<u8>>::new();
+
+				for conn in conns.read().await.values() {
+					let frame = match frames.entry(conn.protocol_version) {
+						Entry::Occupied(entry) => entry.into_mut(),
+						Entry::Vacant(entry) => entry.insert(
+							serialize_message(&message, conn.protocol_version)
+								.expect("failed serializing"),
+						),
+					};
+
+					let mut tx = conn.tx.lock().await;
+					let seq = conn.last_seq.fetch_add(1, Ordering::AcqRel) + 1;
+					let mut buf = Vec::with_capacity(8 + frame.len());
+					buf.extend_from_slice(&seq.to_le_bytes());
+					buf.extend_from_slice(frame);
+					tx.send(Message::Binary(buf.into()))
+						.await
+						.expect("failed sending");
+				}
+			})
+		});
+}
+
+/// Serializes a message without its packet. A `ToClientPacket` is its sequence number (a fixed size u64)
+/// followed by the message, so the packet of any connection on the same protocol version is the sequence
+/// number prepended to these bytes.
+fn serialize_message(message: &rp::ToClient, protocol_version: u16) -> Result<Vec<u8>> {
+	let mut buf = versioned::ToClient::latest(rp::ToClientPacket {
+		seq: 0,
+		message: message.clone(),
+	})
+	.serialize(protocol_version)?;
+	buf.drain(..8);
+
+	Ok(buf)
+}
+
+fn connections(count: usize) -> Connections {
+	RwLock::new(
+		(0..count)
+			.map(|_| {
+				(
+					Id::new_v1(1),
+					Arc::new(FakeConnection {
+						protocol_version: PROTOCOL_VERSION,
+						last_seq: AtomicU64::new(0),
+						tx: Mutex::new(drain()),
+					}),
+				)
+			})
+			.collect(),
+	)
+}
+
+fn start_actor(index: usize, input_size: usize) -> protocol::ToClient {
+	protocol::ToClient::Commands(vec![protocol::CommandWrapper {
+		index: index as i64,
+		inner: protocol::Command::StartActor {
+			actor_id: Id::new_v1(1),
+			generation: 0,
+			config: Box::new(protocol::ActorConfig {
+				name: "bench".to_string(),
+				key: None,
+				create_ts: 0,
+				// Valid base64 for sizes that are a multiple of 4
+				input: (input_size > 0).then(|| "a".repeat(input_size)),
+			}),
+		},
+	}])
+}
@@ -0,0 +1,201 @@
+use std::net::{IpAddr, SocketAddr};
+
+use gas::prelude::*;
+use hyper::HeaderMap;
+use ipnet::IpNet;
+
+/// Parses the configured trusted proxy list. Entries can either be CIDR ranges or single addresses.
+pub fn parse_trusted_proxies(config: &rivet_config::Config) -> Result<Vec<IpNet>> {
+	config
+		.pegboard()
+		.trusted_proxies()
+		.iter()
+		.map(|x| {
+			x.parse::<IpNet>()
+				.or_else(|_| x.parse::<IpAddr>().map(IpNet::from))
+				.with_context(|| format!("invalid trusted proxy `{x}`"))
+		})
+		.collect()
+}
+
+/// Determines the real address of the client that opened the connection.
+///
+/// Forwarding headers are only read if the socket peer is a trusted proxy. The `Forwarded` header takes
+/// precedence over `X-Forwarded-For`. Hops are walked from right to left (closest to furthest) and the
+/// first address that is not a trusted proxy is returned. Falls back to the socket peer if no usable
+/// header is present or a hop cannot be parsed before reaching an untrusted address.
+pub fn resolve(peer: SocketAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+	let peer_ip = peer.ip().to_canonical();
+
+	if !is_trusted(peer_ip, trusted_proxies) {
+		return peer_ip;
+	}
+
+	let hops = forwarded_hops(headers).or_else(|| x_forwarded_for_hops(headers));
+	let Some(hops) = hops else {
+		return peer_ip;
+	};
+
+	let mut client_ip = None;
+	for hop in hops.into_iter().rev() {
+		// Anything after an unparsable hop was written by an untrusted party. The hops walked so far
+		// are trusted proxies, not the client.
+		let Some(ip) = hop else {
+			return peer_ip;
+		};
+
+		client_ip = Some(ip);
+
+		if !is_trusted(ip, trusted_proxies) {
+			break;
+		}
+	}
+
+	client_ip.unwrap_or(peer_ip)
+}
+
+fn is_trusted(ip: IpAddr, trusted_proxies: &[IpNet]) -> bool {
+	trusted_proxies.iter().any(|net| net.contains(&ip))
+}
+
+/// Reads the `for=` parameter of every element in all `Forwarded` headers (RFC 7239).
+fn forwarded_hops(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+	let mut hops = Vec::new();
+
+	for value in headers.get_all(hyper::header::FORWARDED) {
+		let value = value.to_str().ok()?;
+
+		for element in value.split(',') {
+			let node = element.split(';').find_map(|pair| {
+				let (key, value) = pair.split_once('=')?;
+
+				key.trim()
+					.eq_ignore_ascii_case("for")
+					.then(|| value.trim().trim_matches('"'))
+			});
+
+			if let Some(node) = node {
+				hops.push(parse_node(node));
+			}
+		}
+	}
+
+	(!hops.is_empty()).then_some(hops)
+}
+
+fn x_forwarded_for_hops(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+	let mut hops = Vec::new();
+
+	for value in headers.get_all("x-forwarded-for") {
+		let value = value.to_str().ok()?;
+
+		hops.extend(value.split(',').map(|x| parse_node(x.trim())));
+	}
+
+	(!hops.is_empty()).then_some(hops)
+}
+
+/// Parses a single node which can be a bare IPv4/IPv6 address or an address with a port (IPv6 addresses
+/// with ports are enclosed in brackets).
+fn parse_node(node: &str) -> Option<IpAddr> {
+	if let Ok(ip) = node.parse::<IpAddr>() {
+		return Some(ip.to_canonical());
+	}
+
+	if let Ok(addr) = node.parse::<SocketAddr>() {
+		return Some(addr.ip().to_canonical());
+	}
+
+	// Bracketed IPv6 without a port
+	node.strip_prefix('[')
+		.and_then(|x| x.strip_suffix(']'))
+		.and_then(|x| x.parse::<IpAddr>().ok())
+		.map(|ip| ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		for (k, v) in pairs {
+			headers.append(*k, v.parse().unwrap());
+		}
+		headers
+	}
+
+	fn trusted() -> Vec<IpNet> {
+		vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]
+	}
+
+	#[test]
+	fn untrusted_peer_ignores_headers() {
+		let peer = "203.0.113.7:1234".parse().unwrap();
+		let headers = headers(&[("x-forwarded-for", "198.51.100.1")]);
+
+		assert_eq!(
+			resolve(peer, &headers, &trusted()),
+			"203.0.113.7".parse::<IpAddr>().unwrap()
+		);
+	}
+
+	#[test]
+	fn trusted_peer_without_headers() {
+		let peer = "10.0.0.1:1234".parse().unwrap();
+
+		assert_eq!(
+			resolve(peer, &HeaderMap::new(), &trusted()),
+			"10.0.0.1".parse::<IpAddr>().unwrap()
+		);
+	}
+
+	#[test]
+	fn x_forwarded_for_skips_trusted_hops() {
+		let peer = "10.0.0.1:1234".parse().unwrap();
+		let headers = headers(&[("x-forwarded-for", "1.1.1.1, 198.51.100.1, 10.0.0.2")]);
+
+		// Spoofed leftmost entry is ignored
+		assert_eq!(
+			resolve(peer, &headers, &trusted()),
+			"198.51.100.1".parse::<IpAddr>().unwrap()
+		);
+	}
+
+	#[test]
+	fn forwarded_ipv6() {
+		let peer = "[fd00::1]:1234".parse().unwrap();
+		let headers = headers(&[
+			("forwarded", "for=\"[2001:db8::1]:4711\";proto=https, for=fd00::2"),
+			("x-forwarded-for", "198.51.100.1"),
+		]);
+
+		assert_eq!(
+			resolve(peer, &headers, &trusted()),
+			"2001:db8::1".parse::<IpAddr>().unwrap()
+		);
+	}
+
+	#[test]
+	fn ipv4_mapped_peer() {
+		let peer = "[::ffff:10.0.0.1]:1234".parse().unwrap();
+		let headers = headers(&[("x-forwarded-for", "198.51.100.1")]);
+
+		assert_eq!(
+			resolve(peer, &headers, &trusted()),
+			"198.51.100.1".parse::<IpAddr>().unwrap()
+		);
+	}
+
+	#[test]
+	fn unparsable_hop_falls_back_to_peer() {
+		let peer = "10.0.0.1:1234".parse().unwrap();
+		let headers = headers(&[("x-forwarded-for", "198.51.100.1, unknown, 10.0.0.2")]);
+
+		// Neither the spoofable entry nor the trusted proxy before the unparsable hop
+		assert_eq!(
+			resolve(peer, &headers, &trusted()),
+			"10.0.0.1".parse::<IpAddr>().unwrap()
+		);
+	}
+}
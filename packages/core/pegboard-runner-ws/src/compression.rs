@@ -0,0 +1,73 @@
+use gas::prelude::*;
+
+/// Compression used for the init packet, set with the `init_compression` query parameter.
+#[derive(Debug, Clone, Copy)]
+pub enum InitCompression {
+	/// LZ4 block format with the uncompressed size prepended as a little endian u32.
+	Lz4,
+}
+
+impl InitCompression {
+	pub fn parse(v: &str) -> Result<Self> {
+		match v {
+			"lz4" => Ok(InitCompression::Lz4),
+			_ => bail!("unsupported init compression `{v}`"),
+		}
+	}
+
+	/// Decompresses an init packet of at most `max_size` bytes (i.e. the max message size, which the
+	/// uncompressed packet would have been subject to). Prevents decompression bombs.
+	pub fn decompress(&self, buf: &[u8], max_size: usize) -> Result<Vec<u8>> {
+		match self {
+			InitCompression::Lz4 => {
+				let (size, payload) = buf
+					.split_first_chunk::<4>()
+					.context("compressed payload too short")?;
+				let size = u32::from_le_bytes(*size) as usize;
+
+				// Check the declared size before allocating
+				ensure!(
+					size <= max_size,
+					"decompressed init packet too large ({size} > {max_size} bytes)"
+				);
+
+				let buf = lz4_flex::block::decompress(payload, size)?;
+
+				// Should be enforced by lz4_flex, checked again to be safe
+				ensure!(buf.len() <= max_size, "decompressed init packet too large");
+
+				Ok(buf)
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn lz4_roundtrip() {
+		let data = b"runner init packet".repeat(100);
+		let buf = lz4_flex::block::compress_prepend_size(&data);
+
+		assert_eq!(InitCompression::Lz4.decompress(&buf, 4096).unwrap(), data);
+	}
+
+	#[test]
+	fn lz4_limited_to_max_message_size() {
+		let mut root = rivet_config::config::Root::default();
+		root.pegboard = Some(rivet_config::config::Pegboard {
+			max_message_size: Some(1024),
+			..Default::default()
+		});
+		let config = rivet_config::Config::from_root(root);
+		let max_size = config.pegboard().max_message_size();
+
+		let buf = lz4_flex::block::compress_prepend_size(&vec![0; max_size]);
+		assert!(InitCompression::Lz4.decompress(&buf, max_size).is_ok());
+
+		let buf = lz4_flex::block::compress_prepend_size(&vec![0; max_size + 1]);
+		assert!(InitCompression::Lz4.decompress(&buf, max_size).is_err());
+	}
+}
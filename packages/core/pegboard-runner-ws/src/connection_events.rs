@@ -0,0 +1,76 @@
+use gas::prelude::*;
+use rivet_metrics::KeyValue;
+use rivet_types::msgs::pegboard::{RunnerConnectionEvent, RunnerConnectionEventKind};
+
+use crate::metrics;
+
+/// Publishes connection lifecycle events, see `Pegboard::publish_connection_events`.
+pub struct ConnectionEvents {
+	enabled: bool,
+	boot_epoch: i64,
+}
+
+impl ConnectionEvents {
+	pub fn new(config: &rivet_config::Config, boot_epoch: i64) -> Self {
+		ConnectionEvents {
+			enabled: config.pegboard().publish_connection_events(),
+			boot_epoch,
+		}
+	}
+
+	/// Publishes in the background so the connection is never held up by the pubsub driver. Events are
+	/// best effort, failures are only logged.
+	pub fn publish(
+		&self,
+		ctx: &StandaloneCtx,
+		runner_id: Id,
+		namespace_id: Id,
+		kind: RunnerConnectionEventKind,
+		reason: Option<String>,
+	) {
+		if !self.enabled {
+			return;
+		}
+
+		let ctx = ctx.clone();
+		let event = RunnerConnectionEvent {
+			runner_id,
+			namespace_id,
+			kind,
+			reason,
+			ts: util::timestamp::now(),
+			boot_epoch: self.boot_epoch,
+		};
+
+		tokio::spawn(async move {
+			let res = ctx
+				.msg(event)
+				.tag("namespace_id", namespace_id)
+				.tag("runner_id", runner_id)
+				.send()
+				.await;
+
+			if let Err(err) = &res {
+				tracing::warn!(?runner_id, ?kind, ?err, "failed publishing connection event");
+			}
+
+			metrics::CONNECTION_EVENTS.add(
+				1,
+				&[
+					KeyValue::new("kind", kind_str(kind)),
+					KeyValue::new("result", if res.is_ok() { "ok" } else { "error" }),
+				],
+			);
+		});
+	}
+}
+
+fn kind_str(kind: RunnerConnectionEventKind) -> &'static str {
+	match kind {
+		RunnerConnectionEventKind::Init => "init",
+		RunnerConnectionEventKind::Connect => "connect",
+		RunnerConnectionEventKind::Disconnect => "disconnect",
+		RunnerConnectionEventKind::Evict => "evict",
+		RunnerConnectionEventKind::Error => "error",
+	}
+}
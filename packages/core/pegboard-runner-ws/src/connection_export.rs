@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+
+use gas::prelude::*;
+use pegboard::ops::runner::{export_connections, take_exported_connections};
+use rivet_metrics::KeyValue;
+use tokio::sync::RwLock;
+
+use crate::{Connections, metrics};
+
+/// Exports the connection registry so the instance replacing this one can pre-warm its caches, see
+/// `Pegboard::export_connections_on_shutdown`. Best effort, gives up after the configured timeout so
+/// shutdown is never blocked.
+#[tracing::instrument(skip_all)]
+pub async fn export(ctx: &StandaloneCtx, instance_id: String, conns: &RwLock<Connections>) {
+	let connections = conns
+		.read()
+		.await
+		.iter()
+		.map(|(runner_id, conn)| export_connections::Connection {
+			runner_id: *runner_id,
+			workflow_id: conn.workflow_id,
+			namespace_id: conn.namespace_id,
+			namespace_name: conn.namespace_name.clone(),
+			runner_key: conn.runner_key.clone(),
+		})
+		.collect::<Vec<_>>();
+	let count = connections.len();
+
+	let timeout = ctx.config().pegboard().connection_export_timeout();
+	let res = tokio::time::timeout(
+		timeout,
+		ctx.op(export_connections::Input {
+			instance_id,
+			connections,
+		}),
+	)
+	.await;
+
+	let result = match res {
+		Ok(Ok(())) => {
+			tracing::info!(?count, "exported connections");
+			"ok"
+		}
+		Ok(Err(err)) => {
+			tracing::warn!(?err, "failed exporting connections");
+			"error"
+		}
+		Err(_) => {
+			tracing::warn!(?timeout, "timed out exporting connections");
+			"timeout"
+		}
+	};
+	metrics::CONNECTION_EXPORT.add(count as u64, &[KeyValue::new("result", result)]);
+}
+
+/// Reads the connections exported by the previous instance with the same identity and resolves their
+/// namespaces so the reconnect storm after a restart hits warm caches.
+#[tracing::instrument(skip_all)]
+pub async fn import(ctx: StandaloneCtx, instance_id: String) {
+	let connections = match ctx
+		.op(take_exported_connections::Input { instance_id })
+		.await
+	{
+		Ok(res) => res.connections,
+		Err(err) => {
+			tracing::warn!(?err, "failed reading exported connections");
+			return;
+		}
+	};
+	metrics::CONNECTION_IMPORT.add(connections.len() as u64, &[]);
+
+	if connections.is_empty() {
+		return;
+	}
+
+	let namespace_names = connections
+		.into_iter()
+		.map(|conn| conn.namespace_name)
+		.collect::<HashSet<_>>();
+	tracing::info!(namespaces=?namespace_names.len(), "pre-warming exported connection namespaces");
+
+	let ctx = &ctx;
+	futures_util::future::join_all(namespace_names.into_iter().map(|name| async move {
+		if let Err(err) = ctx
+			.op(namespace::ops::resolve_for_name_global::Input { name })
+			.await
+		{
+			tracing::debug!(?err, "failed pre-warming namespace");
+		}
+	}))
+	.await;
+}
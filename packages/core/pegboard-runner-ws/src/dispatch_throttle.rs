@@ -0,0 +1,70 @@
+use std::{
+	sync::{
+		Mutex,
+		atomic::{AtomicUsize, Ordering},
+	},
+	time::{Duration, Instant},
+};
+
+use crate::metrics;
+
+/// Smooths the rate of runner workflow dispatches, see `Pegboard::workflow_dispatch_rate`.
+///
+/// Connections that need a new workflow reserve the next free dispatch slot and wait for it, so a mass
+/// reconnect is spread out over time instead of hitting the workflow engine all at once. Once the queue
+/// is full, new connections are rejected with a retry hint for when the queue will have drained.
+pub struct DispatchThrottle {
+	limit: Option<(Duration, usize)>,
+	queued: AtomicUsize,
+	next_slot: Mutex<Instant>,
+}
+
+impl DispatchThrottle {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		DispatchThrottle {
+			limit: config.pegboard().workflow_dispatch_throttle(),
+			queued: AtomicUsize::new(0),
+			next_slot: Mutex::new(Instant::now()),
+		}
+	}
+
+	/// Waits until a workflow can be dispatched. Returns the retry hint in ms if the queue is full.
+	pub async fn acquire(&self) -> Result<(), u64> {
+		let Some((interval, queue_size)) = self.limit else {
+			// Throttling disabled
+			return Ok(());
+		};
+
+		let queued = self.queued.fetch_add(1, Ordering::AcqRel) + 1;
+		let _guard = QueueGuard { throttle: self };
+		metrics::WORKFLOW_DISPATCH_QUEUE_DEPTH.record(queued as u64, &[]);
+
+		if queued > queue_size {
+			let retry_after = interval.saturating_mul(queue_size.try_into().unwrap_or(u32::MAX));
+			return Err(retry_after.as_millis().try_into().unwrap_or(u64::MAX));
+		}
+
+		let slot = {
+			let mut next_slot = self.next_slot.lock().expect("poisoned");
+			let slot = (*next_slot).max(Instant::now());
+			*next_slot = slot + interval;
+
+			slot
+		};
+
+		tokio::time::sleep_until(slot.into()).await;
+
+		Ok(())
+	}
+}
+
+struct QueueGuard<'a> {
+	throttle: &'a DispatchThrottle,
+}
+
+impl Drop for QueueGuard<'_> {
+	fn drop(&mut self) {
+		let queued = self.throttle.queued.fetch_sub(1, Ordering::AcqRel) - 1;
+		metrics::WORKFLOW_DISPATCH_QUEUE_DEPTH.record(queued as u64, &[]);
+	}
+}
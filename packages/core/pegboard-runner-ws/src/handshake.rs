@@ -0,0 +1,54 @@
+use std::{
+	sync::atomic::{AtomicUsize, Ordering},
+	time::Duration,
+};
+
+use crate::metrics;
+
+/// Tracks connections that have not completed their handshake yet.
+///
+/// Once the number of pending handshakes crosses the configured threshold, clients that have not sent
+/// their init packet within the silent client timeout are closed early instead of holding on to their
+/// handshake until the full init timeout.
+pub struct Handshakes {
+	pending: AtomicUsize,
+	silent_client_close: Option<(usize, Duration)>,
+}
+
+impl Handshakes {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		Handshakes {
+			pending: AtomicUsize::new(0),
+			silent_client_close: config.pegboard().silent_client_close(),
+		}
+	}
+
+	/// Tracks a handshake until the returned guard is dropped.
+	pub fn start(&self) -> HandshakeGuard<'_> {
+		let pending = self.pending.fetch_add(1, Ordering::Relaxed) + 1;
+		metrics::HANDSHAKE_PENDING.record(pending as u64, &[]);
+
+		HandshakeGuard { handshakes: self }
+	}
+
+	/// Returns the silent client timeout, if enabled.
+	pub fn silent_client_timeout(&self) -> Option<Duration> {
+		self.silent_client_close.map(|(_, timeout)| timeout)
+	}
+
+	pub fn is_under_pressure(&self) -> bool {
+		self.silent_client_close
+			.is_some_and(|(threshold, _)| self.pending.load(Ordering::Relaxed) > threshold)
+	}
+}
+
+pub struct HandshakeGuard<'a> {
+	handshakes: &'a Handshakes,
+}
+
+impl Drop for HandshakeGuard<'_> {
+	fn drop(&mut self) {
+		let pending = self.handshakes.pending.fetch_sub(1, Ordering::Relaxed) - 1;
+		metrics::HANDSHAKE_PENDING.record(pending as u64, &[]);
+	}
+}
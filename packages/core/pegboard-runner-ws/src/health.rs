@@ -0,0 +1,410 @@
+use std::{
+	collections::{HashMap, HashSet, VecDeque},
+	sync::{
+		Arc, Mutex,
+		atomic::{AtomicBool, AtomicI64, Ordering},
+	},
+	time::{Duration, Instant},
+};
+
+use gas::prelude::*;
+use rivet_metrics::KeyValue;
+use tokio::{io::AsyncWriteExt, net::TcpStream, sync::RwLock};
+
+use crate::{Connections, metrics};
+
+/// Start of the request line of a readiness probe. Probes are served on the same port as the websocket.
+const PROBE_REQUEST_PREFIX: &[u8] = b"GET /health ";
+/// How long a connection has to send the start of its request line before it is handled as a
+/// websocket handshake instead, which has its own timeout.
+const PROBE_PEEK_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long to wait between peeks while only part of the request line arrived.
+const PROBE_PEEK_INTERVAL: Duration = Duration::from_millis(10);
+/// How often the conns lock watchdog checks the lock.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(10);
+/// How long acquiring the conns lock can take before it is considered wedged.
+const WEDGED_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tracks restarts of the background threads. The instance is reported unhealthy while any thread
+/// restarted more than the configured threshold within the window, so orchestration can recycle an
+/// instance whose background loops are thrashing.
+pub struct Health {
+	restart_threshold: usize,
+	restart_window: Duration,
+	restarts: Mutex<HashMap<&'static str, VecDeque<Instant>>>,
+	/// Set by the watchdog while the conns lock cannot be acquired.
+	conns_lock_wedged: AtomicBool,
+	/// Since when the msg thread has no subscription to workflow messages, 0 while subscribed. Commands
+	/// cannot be delivered to runners in the meantime.
+	msg_delivery_down_since_ts: AtomicI64,
+	msg_lag_threshold_ms: i64,
+	/// Msg thread subscriptions whose last message was older than `msg_lag_threshold_ms`. Commands are
+	/// delivered late in the meantime.
+	lagging_subscriptions: Mutex<HashSet<&'static str>>,
+	/// Set while the last database probe succeeded, see `db_probe_thread`.
+	db_reachable: AtomicBool,
+}
+
+impl Health {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		let (restart_threshold, restart_window) = config.pegboard().thread_restart_threshold();
+
+		Health {
+			restart_threshold,
+			restart_window,
+			restarts: Mutex::new(HashMap::new()),
+			conns_lock_wedged: AtomicBool::new(false),
+			msg_delivery_down_since_ts: AtomicI64::new(util::timestamp::now()),
+			msg_lag_threshold_ms: config
+				.pegboard()
+				.msg_lag_threshold_ms()
+				.try_into()
+				.unwrap_or(i64::MAX),
+			lagging_subscriptions: Mutex::new(HashSet::new()),
+			// Not ready until the first probe succeeds
+			db_reachable: AtomicBool::new(false),
+		}
+	}
+
+	/// Called once the msg thread subscribed to workflow messages.
+	pub fn set_msg_delivery_up(&self) {
+		let down_since_ts = self.msg_delivery_down_since_ts.swap(0, Ordering::AcqRel);
+		metrics::MSG_DELIVERY_DOWN.record(0, &[]);
+
+		if down_since_ts != 0 {
+			let down_ms = util::timestamp::now().saturating_sub(down_since_ts);
+			tracing::info!(%down_ms, "subscribed to workflow messages");
+		}
+	}
+
+	/// Called when the msg thread exits. Returns true if it was subscribed before, false if it failed to
+	/// subscribe (i.e. the pubsub backend is unavailable).
+	pub fn set_msg_delivery_down(&self) -> bool {
+		let was_up = self
+			.msg_delivery_down_since_ts
+			.compare_exchange(0, util::timestamp::now(), Ordering::AcqRel, Ordering::Acquire)
+			.is_ok();
+		metrics::MSG_DELIVERY_DOWN.record(1, &[]);
+
+		if was_up {
+			tracing::error!(
+				"workflow message subscription lost, commands cannot be delivered to runners"
+			);
+		}
+
+		was_up
+	}
+
+	/// Records the age of a message when the msg thread processes it. Messages the thread has not
+	/// processed yet are buffered by the pubsub backend, so a growing age means a growing backlog.
+	pub fn record_msg_lag(&self, subscription: &'static str, msg_ts: i64) {
+		let lag_ms = util::timestamp::now().saturating_sub(msg_ts).max(0);
+		let attrs = [KeyValue::new("subscription", subscription)];
+		metrics::MSG_THREAD_LAG.record(lag_ms as f64 / 1000.0, &attrs);
+		metrics::MSG_THREAD_CURRENT_LAG.record(lag_ms as f64 / 1000.0, &attrs);
+
+		let mut lagging_subscriptions = self.lagging_subscriptions.lock().expect("poisoned");
+		if lag_ms > self.msg_lag_threshold_ms {
+			if lagging_subscriptions.insert(subscription) {
+				tracing::warn!(
+					%subscription,
+					%lag_ms,
+					"msg thread falling behind, commands are delivered late"
+				);
+			}
+		} else if lagging_subscriptions.remove(subscription) {
+			tracing::info!(%subscription, %lag_ms, "msg thread caught up");
+		}
+	}
+
+	/// Degraded instances keep serving existing connections but cannot deliver commands (or only late).
+	/// Unlike unhealthy instances, they should not be recycled since the cause (i.e. a pubsub outage) is
+	/// usually shared by all instances.
+	pub fn is_degraded(&self) -> bool {
+		self.msg_delivery_down_since_ts.load(Ordering::Acquire) != 0
+			|| !self
+				.lagging_subscriptions
+				.lock()
+				.expect("poisoned")
+				.is_empty()
+	}
+
+	/// Records a restart of the given background thread.
+	pub fn record_restart(&self, thread: &'static str) {
+		metrics::THREAD_RESTARTS.add(1, &[KeyValue::new("thread", thread)]);
+
+		let now = Instant::now();
+		let mut restarts = self.restarts.lock().expect("poisoned");
+		let thread_restarts = restarts.entry(thread).or_default();
+		thread_restarts.push_back(now);
+		Self::prune(thread_restarts, now, self.restart_window);
+
+		if thread_restarts.len() > self.restart_threshold {
+			tracing::error!(
+				%thread,
+				restarts=%thread_restarts.len(),
+				"background thread restarting repeatedly, reporting unhealthy"
+			);
+		}
+	}
+
+	/// Instances that cannot reach the database cannot serve KV requests and should be taken out of
+	/// rotation, but not recycled.
+	pub fn is_ready(&self) -> bool {
+		self.db_reachable.load(Ordering::Relaxed)
+	}
+
+	pub fn is_healthy(&self) -> bool {
+		if self.conns_lock_wedged.load(Ordering::Relaxed) {
+			return false;
+		}
+
+		let now = Instant::now();
+		let mut restarts = self.restarts.lock().expect("poisoned");
+
+		restarts.values_mut().all(|thread_restarts| {
+			Self::prune(thread_restarts, now, self.restart_window);
+			thread_restarts.len() <= self.restart_threshold
+		})
+	}
+
+	fn prune(restarts: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+		while restarts
+			.front()
+			.is_some_and(|ts| now.duration_since(*ts) > window)
+		{
+			restarts.pop_front();
+		}
+	}
+}
+
+/// Periodically checks that the conns lock can be acquired and reports the instance unhealthy while it
+/// cannot, so orchestration can recycle an instance whose connection handling is wedged (i.e. a task
+/// holding the lock is stuck on a socket).
+///
+/// NOTE: The lock is a tokio `RwLock` which does not poison, a panic while holding it releases it.
+#[tracing::instrument(skip_all)]
+pub async fn conns_watchdog_thread(conns: Arc<RwLock<Connections>>, health: &Health) {
+	let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+	loop {
+		interval.tick().await;
+
+		// Waits behind queued writers, so a stuck writer is detected as well
+		let start = Instant::now();
+		let wedged = tokio::time::timeout(WEDGED_LOCK_TIMEOUT, conns.read())
+			.await
+			.is_err();
+		metrics::CONNS_LOCK_WAIT_DURATION.record(start.elapsed().as_secs_f64(), &[]);
+
+		let was_wedged = health.conns_lock_wedged.swap(wedged, Ordering::Relaxed);
+		if wedged && !was_wedged {
+			tracing::error!("conns lock wedged, reporting unhealthy");
+		} else if !wedged && was_wedged {
+			tracing::info!("conns lock recovered");
+		}
+	}
+}
+
+/// Periodically checks that the database is reachable and reports the instance not ready while it is
+/// not, so runners are not accepted by an instance that cannot serve their KV requests.
+#[tracing::instrument(skip_all)]
+pub async fn db_probe_thread(ctx: &StandaloneCtx, health: &Health) {
+	let (probe_interval, probe_timeout) = ctx.config().pegboard().db_probe();
+	let mut interval = tokio::time::interval(probe_interval);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+	loop {
+		interval.tick().await;
+
+		let start = Instant::now();
+		let res = tokio::time::timeout(
+			probe_timeout,
+			ctx.op(pegboard::ops::runner::probe_db::Input {}),
+		)
+		.await;
+		metrics::DB_PROBE_DURATION.record(start.elapsed().as_secs_f64(), &[]);
+
+		let reachable = match res {
+			Ok(Ok(_)) => true,
+			Ok(Err(err)) => {
+				tracing::debug!(?err, "db probe failed");
+				false
+			}
+			Err(_) => {
+				tracing::debug!(?probe_timeout, "db probe timed out");
+				false
+			}
+		};
+		metrics::DB_REACHABLE.record(reachable as u64, &[]);
+
+		let was_reachable = health.db_reachable.swap(reachable, Ordering::Relaxed);
+		if reachable && !was_reachable {
+			tracing::info!("database reachable, reporting ready");
+		} else if !reachable && was_reachable {
+			tracing::error!("database unreachable, reporting not ready");
+		}
+	}
+}
+
+/// Returns true if the incoming connection is a readiness probe instead of a websocket handshake.
+pub async fn is_probe(stream: &TcpStream) -> bool {
+	tokio::time::timeout(PROBE_PEEK_TIMEOUT, peek_probe_prefix(stream))
+		.await
+		.unwrap_or(false)
+}
+
+/// Peeks until the whole prefix arrived or the bytes diverge from it. The request line can arrive
+/// split across several segments.
+async fn peek_probe_prefix(stream: &TcpStream) -> bool {
+	let mut buf = [0; PROBE_REQUEST_PREFIX.len()];
+
+	loop {
+		let n = match stream.peek(&mut buf).await {
+			Ok(0) | Err(_) => return false,
+			Ok(n) => n,
+		};
+
+		if buf[..n] != PROBE_REQUEST_PREFIX[..n] {
+			return false;
+		} else if n == buf.len() {
+			return true;
+		}
+
+		// Peeking returns immediately while any data is buffered
+		tokio::time::sleep(PROBE_PEEK_INTERVAL).await;
+	}
+}
+
+/// Responds to a readiness probe with 200 if healthy and ready, 503 otherwise.
+pub async fn respond(health: &Health, mut stream: TcpStream) -> Result<()> {
+	let (status, body) = if !health.is_healthy() {
+		("503 Service Unavailable", r#"{"status":"unhealthy"}"#)
+	} else if !health.is_ready() {
+		("503 Service Unavailable", r#"{"status":"not_ready"}"#)
+	} else if health.is_degraded() {
+		("200 OK", r#"{"status":"degraded"}"#)
+	} else {
+		("200 OK", r#"{"status":"ok"}"#)
+	};
+
+	let res = format!(
+		"HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+		body.len()
+	);
+	stream.write_all(res.as_bytes()).await?;
+	stream.shutdown().await?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::net::TcpListener;
+
+	use super::*;
+
+	/// Returns the client and server end of a connection.
+	async fn tcp_pair() -> (TcpStream, TcpStream) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let client = TcpStream::connect(listener.local_addr().unwrap())
+			.await
+			.unwrap();
+		let (server, _) = listener.accept().await.unwrap();
+
+		(client, server)
+	}
+
+	/// Returns a healthy and ready instance with msg delivery up.
+	fn health(restart_threshold: usize, msg_lag_threshold_ms: i64) -> Health {
+		Health {
+			restart_threshold,
+			restart_window: Duration::from_secs(60),
+			restarts: Mutex::new(HashMap::new()),
+			conns_lock_wedged: AtomicBool::new(false),
+			msg_delivery_down_since_ts: AtomicI64::new(0),
+			msg_lag_threshold_ms,
+			lagging_subscriptions: Mutex::new(HashSet::new()),
+			db_reachable: AtomicBool::new(true),
+		}
+	}
+
+	#[tokio::test]
+	async fn detects_probe_split_across_segments() {
+		let (mut client, server) = tcp_pair().await;
+
+		client.write_all(b"GET /he").await.unwrap();
+		let (is_probe, _) = tokio::join!(is_probe(&server), async {
+			tokio::time::sleep(Duration::from_millis(50)).await;
+			client.write_all(b"alth HTTP/1.1\r\n").await.unwrap();
+		});
+		assert!(is_probe);
+	}
+
+	#[tokio::test]
+	async fn stops_peeking_once_bytes_diverge() {
+		let (mut client, server) = tcp_pair().await;
+
+		// Websocket handshakes are detected without waiting for the rest of the prefix
+		client.write_all(b"GET /?").await.unwrap();
+		let res = tokio::time::timeout(Duration::from_millis(100), is_probe(&server)).await;
+		assert_eq!(res.ok(), Some(false));
+	}
+
+	#[tokio::test]
+	async fn stalled_client_is_not_a_probe() {
+		let (mut client, server) = tcp_pair().await;
+
+		client.write_all(b"GET /").await.unwrap();
+		assert!(!is_probe(&server).await);
+	}
+
+	#[test]
+	fn unhealthy_after_repeated_restarts() {
+		let health = health(2, 5_000);
+
+		health.record_restart("msg");
+		health.record_restart("msg");
+		health.record_restart("update_ping");
+		assert!(health.is_healthy());
+
+		health.record_restart("msg");
+		assert!(!health.is_healthy());
+	}
+
+	#[test]
+	fn failing_to_subscribe_only_degrades() {
+		let health = health(2, 5_000);
+
+		// Lost an established subscription
+		assert!(health.set_msg_delivery_down());
+		assert!(health.is_degraded());
+
+		// Repeatedly failing to resubscribe
+		assert!(!health.set_msg_delivery_down());
+		assert!(!health.set_msg_delivery_down());
+
+		health.set_msg_delivery_up();
+		assert!(!health.is_degraded());
+		assert!(health.is_healthy());
+	}
+
+	#[test]
+	fn lagging_subscription_degrades() {
+		let health = health(2, 5_000);
+		let now = util::timestamp::now();
+
+		health.record_msg_lag("to_ws", now - 100);
+		assert!(!health.is_degraded());
+
+		health.record_msg_lag("to_ws", now - 10_000);
+		health.record_msg_lag("close_ws", now);
+		assert!(health.is_degraded());
+
+		// Caught up
+		health.record_msg_lag("to_ws", util::timestamp::now());
+		assert!(!health.is_degraded());
+		assert!(health.is_healthy());
+	}
+}
@@ -0,0 +1,385 @@
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use gas::prelude::*;
+use pegboard_actor_kv as kv;
+use rivet_metrics::KeyValue;
+use rivet_runner_protocol::*;
+
+use crate::{metrics, redact};
+
+/// How often the hottest keys are reported.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// Number of hottest keys reported in metrics and logs.
+const REPORT_TOP: usize = 10;
+
+pub type GetResult =
+	std::result::Result<(Vec<KvKey>, Vec<KvValue>, Vec<KvMetadata>), Arc<anyhow::Error>>;
+type SharedGet = Shared<BoxFuture<'static, GetResult>>;
+
+/// Detects KV keys that receive a disproportionate share of the accesses and transaction conflicts on
+/// this instance, see `Pegboard::kv_hot_key_capacity`.
+///
+/// Memory is bounded by tracking at most `capacity` keys (space-saving top-K): once full, a new key
+/// replaces the coldest tracked key and inherits its score. Scores of new keys are overestimated by at
+/// most the evicted score, hot keys are never missed. Scores decay exponentially with the configured
+/// half-life so keys that cooled down are evicted over time.
+///
+/// Optionally coalesces identical gets of hot keys, see `Pegboard::kv_hot_key_coalesce_threshold`.
+pub struct HotKeys {
+	capacity: usize,
+	half_life: Duration,
+	coalesce_threshold: Option<f64>,
+	keys: Mutex<HashMap<KeyId, Score>>,
+	in_flight_gets: Mutex<HashMap<GetId, SharedGet>>,
+}
+
+/// Identifies a key across actors and scopes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct KeyId {
+	actor_id: Id,
+	prefix: Option<String>,
+	collection: Option<String>,
+	key: KvKey,
+}
+
+impl KeyId {
+	fn new(actor_id: Id, scope: kv::Scope, key: KvKey) -> Self {
+		KeyId {
+			actor_id,
+			prefix: scope.prefix.map(ToString::to_string),
+			collection: scope.collection.map(ToString::to_string),
+			key,
+		}
+	}
+}
+
+/// Identifies a get by its keys, only gets of the exact same keys are coalesced.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct GetId {
+	actor_id: Id,
+	prefix: Option<String>,
+	collection: Option<String>,
+	keys: Vec<KvKey>,
+}
+
+#[derive(Clone, Copy)]
+struct Score {
+	/// Decayed number of requests that included the key.
+	accesses: f64,
+	/// Decayed number of transaction retries of requests that included the key.
+	conflicts: f64,
+	updated_at: Instant,
+}
+
+impl Score {
+	fn new(now: Instant) -> Self {
+		Score {
+			accesses: 0.0,
+			conflicts: 0.0,
+			updated_at: now,
+		}
+	}
+
+	fn decay(&mut self, half_life: Duration, now: Instant) {
+		let elapsed = now.saturating_duration_since(self.updated_at);
+		let factor = 0.5f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64());
+
+		self.accesses *= factor;
+		self.conflicts *= factor;
+		self.updated_at = now;
+	}
+
+	fn heat(&self) -> f64 {
+		self.accesses + self.conflicts
+	}
+}
+
+impl HotKeys {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		HotKeys {
+			capacity: config.pegboard().kv_hot_key_capacity(),
+			half_life: config.pegboard().kv_hot_key_half_life(),
+			coalesce_threshold: config.pegboard().kv_hot_key_coalesce_threshold(),
+			keys: Mutex::new(HashMap::new()),
+			in_flight_gets: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Counts an access to each of the keys of a KV request, along with the transaction retries of the
+	/// request.
+	pub fn record(&self, actor_id: Id, scope: kv::Scope, keys: &[KvKey], retries: usize) {
+		if self.capacity == 0 {
+			return;
+		}
+
+		let now = Instant::now();
+		let mut tracked = self.keys.lock().expect("poisoned");
+
+		for key in keys {
+			let id = KeyId::new(actor_id, scope, key.clone());
+
+			let inherited = if !tracked.contains_key(&id) && tracked.len() >= self.capacity {
+				self.evict_coldest(&mut tracked, now)
+			} else {
+				None
+			};
+
+			let score = tracked
+				.entry(id)
+				.or_insert_with(|| inherited.unwrap_or_else(|| Score::new(now)));
+			score.decay(self.half_life, now);
+			score.accesses += 1.0;
+			score.conflicts += retries as f64;
+		}
+	}
+
+	/// Removes the coldest tracked key and returns its score.
+	fn evict_coldest(&self, tracked: &mut HashMap<KeyId, Score>, now: Instant) -> Option<Score> {
+		let coldest = tracked
+			.iter_mut()
+			.map(|(id, score)| {
+				score.decay(self.half_life, now);
+				(id, score.heat())
+			})
+			.min_by(|(_, a), (_, b)| a.total_cmp(b))
+			.map(|(id, _)| id.clone())?;
+
+		tracked.remove(&coldest)
+	}
+
+	/// Whether gets of the given keys should be coalesced, true if coalescing is enabled and all keys
+	/// are hot.
+	pub fn should_coalesce(&self, actor_id: Id, scope: kv::Scope, keys: &[KvKey]) -> bool {
+		let Some(threshold) = self.coalesce_threshold else {
+			return false;
+		};
+		if keys.is_empty() {
+			return false;
+		}
+
+		let now = Instant::now();
+		let mut tracked = self.keys.lock().expect("poisoned");
+
+		keys.iter().all(|key| {
+			tracked
+				.get_mut(&KeyId::new(actor_id, scope, key.clone()))
+				.is_some_and(|score| {
+					score.decay(self.half_life, now);
+					score.accesses >= threshold
+				})
+		})
+	}
+
+	/// Runs the get, or joins an in-flight get of the same keys instead. Returns the result and whether
+	/// it was shared with an in-flight get (in which case `get` was not called).
+	///
+	/// A joined get returns the values as of when the in-flight get read them. Writes committed between
+	/// the start of the in-flight get and the joined get are not observed by the joined get.
+	pub async fn coalesce_get(
+		&self,
+		actor_id: Id,
+		scope: kv::Scope,
+		keys: Vec<KvKey>,
+		get: impl FnOnce(Vec<KvKey>) -> BoxFuture<'static, GetResult>,
+	) -> (GetResult, bool) {
+		let id = GetId {
+			actor_id,
+			prefix: scope.prefix.map(ToString::to_string),
+			collection: scope.collection.map(ToString::to_string),
+			keys,
+		};
+
+		let (fut, joined) = {
+			let mut in_flight = self.in_flight_gets.lock().expect("poisoned");
+
+			// Completed gets are only removed once their leader is dropped
+			if let Some(fut) = in_flight.get(&id).filter(|fut| fut.peek().is_none()) {
+				(fut.clone(), true)
+			} else {
+				let fut = get(id.keys.clone()).shared();
+				in_flight.insert(id.clone(), fut.clone());
+
+				(fut, false)
+			}
+		};
+
+		if joined {
+			metrics::KV_COALESCED_GETS.add(1, &[]);
+
+			return (fut.await, true);
+		}
+
+		// Removes the in-flight get even if the leader is cancelled. Joined gets keep polling it
+		let _guard = InFlightGuard {
+			hot_keys: self,
+			id,
+			fut: fut.clone(),
+		};
+
+		(fut.await, false)
+	}
+
+	/// Returns the tracked keys sorted by heat, hottest first.
+	fn hottest(&self, n: usize) -> Vec<(KeyId, Score)> {
+		let now = Instant::now();
+		let mut keys = self
+			.keys
+			.lock()
+			.expect("poisoned")
+			.iter_mut()
+			.map(|(id, score)| {
+				score.decay(self.half_life, now);
+				(id.clone(), *score)
+			})
+			.collect::<Vec<_>>();
+
+		keys.sort_by(|(_, a), (_, b)| b.heat().total_cmp(&a.heat()));
+		keys.truncate(n);
+
+		keys
+	}
+}
+
+struct InFlightGuard<'a> {
+	hot_keys: &'a HotKeys,
+	id: GetId,
+	fut: SharedGet,
+}
+
+impl Drop for InFlightGuard<'_> {
+	fn drop(&mut self) {
+		let mut in_flight = self.hot_keys.in_flight_gets.lock().expect("poisoned");
+
+		// Might have been replaced by a newer get after completing
+		if in_flight
+			.get(&self.id)
+			.is_some_and(|fut| fut.ptr_eq(&self.fut))
+		{
+			in_flight.remove(&self.id);
+		}
+	}
+}
+
+/// Periodically reports the hottest keys in metrics (by rank, to bound cardinality) and logs. Exits
+/// immediately if hot key detection is disabled.
+#[tracing::instrument(skip_all)]
+pub async fn thread(config: &rivet_config::Config, hot_keys: &HotKeys) {
+	if hot_keys.capacity == 0 {
+		tracing::debug!("kv hot key detection disabled");
+		return;
+	}
+
+	let mut interval = tokio::time::interval(REPORT_INTERVAL);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+	loop {
+		interval.tick().await;
+
+		let hottest = hot_keys.hottest(REPORT_TOP);
+
+		for rank in 0..REPORT_TOP {
+			let score = hottest.get(rank).map(|(_, score)| *score);
+			let attributes = [KeyValue::new("rank", (rank + 1) as i64)];
+
+			metrics::KV_HOT_KEY_ACCESSES.record(
+				score.map_or(0.0, |score| score.accesses),
+				&attributes,
+			);
+			metrics::KV_HOT_KEY_CONFLICTS.record(
+				score.map_or(0.0, |score| score.conflicts),
+				&attributes,
+			);
+		}
+
+		// Only worth an operator's attention if keys are contended
+		let contended = hottest
+			.iter()
+			.filter(|(_, score)| score.conflicts >= 1.0)
+			.collect::<Vec<_>>();
+		for (rank, (id, score)) in contended.iter().enumerate() {
+			tracing::info!(
+				rank = rank + 1,
+				actor_id = ?id.actor_id,
+				prefix = ?id.prefix,
+				collection = ?id.collection,
+				key = %redact::debug(config, &id.key),
+				accesses = score.accesses,
+				conflicts = score.conflicts,
+				"contended kv key"
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hot_keys(capacity: usize) -> HotKeys {
+		HotKeys {
+			capacity,
+			half_life: Duration::from_secs(10),
+			coalesce_threshold: Some(3.0),
+			keys: Mutex::new(HashMap::new()),
+			in_flight_gets: Mutex::new(HashMap::new()),
+		}
+	}
+
+	#[test]
+	fn tracks_bounded_top_keys() {
+		let hot_keys = hot_keys(2);
+		let actor_id = Id::new_v1(1);
+		let scope = kv::Scope::default();
+
+		for _ in 0..5 {
+			hot_keys.record(actor_id, scope, &[vec![1]], 1);
+		}
+		hot_keys.record(actor_id, scope, &[vec![2]], 0);
+		// Replaces the coldest key
+		hot_keys.record(actor_id, scope, &[vec![3]], 0);
+
+		let hottest = hot_keys.hottest(10);
+		assert_eq!(hottest.len(), 2);
+		assert_eq!(hottest[0].0.key, vec![1]);
+		assert!(hottest[0].1.conflicts > 4.0);
+		// Inherits the score of the evicted key
+		assert_eq!(hottest[1].0.key, vec![3]);
+		assert!(hottest[1].1.accesses > 1.5);
+
+		assert!(hot_keys.should_coalesce(actor_id, scope, &[vec![1]]));
+		assert!(!hot_keys.should_coalesce(actor_id, scope, &[vec![1], vec![3]]));
+	}
+
+	#[tokio::test]
+	async fn coalesces_concurrent_gets() {
+		let hot_keys = hot_keys(2);
+		let actor_id = Id::new_v1(1);
+		let scope = kv::Scope::default();
+		let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+
+		let leader = hot_keys.coalesce_get(actor_id, scope, vec![vec![1]], |keys| {
+			async move {
+				let _ = rx.await;
+				Ok((keys, vec![vec![2]], Vec::new()))
+			}
+			.boxed()
+		});
+		let follower = hot_keys.coalesce_get(actor_id, scope, vec![vec![1]], |_| {
+			unreachable!("joins the in-flight get")
+		});
+
+		// Polled in order, the follower joins while the leader waits
+		let (leader, follower, _) = tokio::join!(leader, follower, async {
+			tx.send(()).unwrap();
+		});
+		assert!(!leader.1);
+		assert!(follower.1);
+		assert_eq!(follower.0.unwrap().1, vec![vec![2]]);
+		assert!(hot_keys.in_flight_gets.lock().unwrap().is_empty());
+	}
+}
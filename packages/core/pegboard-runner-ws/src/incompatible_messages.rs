@@ -0,0 +1,123 @@
+use std::{
+	collections::{HashMap, HashSet},
+	sync::Mutex,
+};
+
+use gas::prelude::*;
+use rivet_metrics::KeyValue;
+use rivet_runner_protocol::{protocol, versioned};
+
+use crate::metrics;
+
+/// Maximum number of (protocol version, message type) pairs tracked.
+const MAX_ENTRIES: usize = 256;
+/// Maximum number of distinct runners counted per pair.
+const MAX_RUNNERS_PER_ENTRY: usize = 10_000;
+
+/// Aggregates messages that could not be converted for a runner's protocol version, to report how many
+/// runners on each protocol version cannot receive each message type (i.e. to prioritize client
+/// upgrades).
+#[derive(Default)]
+pub struct IncompatibleMessages {
+	entries: Mutex<HashMap<(u16, &'static str), HashSet<Id>>>,
+}
+
+impl IncompatibleMessages {
+	pub fn record(&self, runner_id: Id, protocol_version: u16, message_type: &'static str) {
+		let attrs = [
+			KeyValue::new("protocol_version", protocol_version.to_string()),
+			KeyValue::new("message_type", message_type),
+		];
+		metrics::INCOMPATIBLE_MESSAGES.add(1, &attrs);
+
+		let mut entries = self.entries.lock().expect("poisoned");
+
+		let key = (protocol_version, message_type);
+		if !entries.contains_key(&key) && entries.len() >= MAX_ENTRIES {
+			tracing::debug!(
+				?protocol_version,
+				%message_type,
+				"too many incompatible message entries, not aggregating"
+			);
+			return;
+		}
+
+		let runners = entries.entry(key).or_default();
+		if runners.len() < MAX_RUNNERS_PER_ENTRY {
+			runners.insert(runner_id);
+		}
+
+		metrics::INCOMPATIBLE_RUNNERS.record(runners.len() as u64, &attrs);
+	}
+}
+
+pub fn message_type(message: &protocol::ToClient) -> &'static str {
+	match message {
+		protocol::ToClient::Init { .. } => "init",
+		protocol::ToClient::Commands(_) => "commands",
+		protocol::ToClient::AckEvents { .. } => "ack_events",
+		protocol::ToClient::ShutdownAck => "shutdown_ack",
+	}
+}
+
+/// How a message that cannot be represented in a runner's protocol version (i.e. after the runner was
+/// rolled back) is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncompatiblePolicy {
+	/// The message is dropped, the runner recovers without it.
+	Strip,
+	/// The connection is closed, dropping the message would leave the runner out of sync with its
+	/// workflow.
+	Reject,
+}
+
+pub fn policy(message: &protocol::ToClient) -> IncompatiblePolicy {
+	match message {
+		// Unacknowledged events are resent on the next connection
+		protocol::ToClient::AckEvents { .. } => IncompatiblePolicy::Strip,
+		// Informational only
+		protocol::ToClient::ShutdownAck => IncompatiblePolicy::Strip,
+		// The runner would never start or stop its actors
+		protocol::ToClient::Init { .. } | protocol::ToClient::Commands(_) => {
+			IncompatiblePolicy::Reject
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn counts_distinct_runners() {
+		let incompatible = IncompatibleMessages::default();
+		let runner_id = Id::new_v1(1);
+
+		incompatible.record(runner_id, 1, "commands");
+		incompatible.record(runner_id, 1, "commands");
+		incompatible.record(Id::new_v1(1), 1, "commands");
+		incompatible.record(runner_id, 1, "init");
+
+		let entries = incompatible.entries.lock().unwrap();
+		assert_eq!(entries[&(1, "commands")].len(), 2);
+		assert_eq!(entries[&(1, "init")].len(), 1);
+	}
+
+	#[test]
+	fn only_recoverable_messages_are_stripped() {
+		let ack = protocol::ToClient::AckEvents { last_event_idx: 0 };
+		assert_eq!(policy(&ack), IncompatiblePolicy::Strip);
+		assert_eq!(policy(&protocol::ToClient::ShutdownAck), IncompatiblePolicy::Strip);
+		assert_eq!(policy(&protocol::ToClient::Commands(Vec::new())), IncompatiblePolicy::Reject);
+	}
+
+	#[test]
+	fn shutdown_ack_is_stripped_for_v1_runners() {
+		let message = protocol::ToClient::ShutdownAck;
+		assert_eq!(policy(&message), IncompatiblePolicy::Strip);
+
+		let message = rivet_runner_protocol::ToClient::try_from(message).unwrap();
+		assert!(!versioned::ToClient::is_representable(&message, 1));
+		assert!(versioned::ToClient::is_representable(&message, 2));
+	}
+}
@@ -0,0 +1,92 @@
+use rivet_runner_protocol::*;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::metrics;
+
+/// Limits the KV response data buffered across all connections of this instance, see
+/// `Pegboard::kv_response_budget`.
+///
+/// Each connection processes one KV request at a time, so a connection holds at most one response worth
+/// of the budget. Responses that do not fit are dropped and the request is rejected with a retryable
+/// error instead of growing memory without bound.
+pub struct KvBudget {
+	limit: usize,
+	semaphore: Option<Semaphore>,
+}
+
+impl KvBudget {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		let limit = config
+			.pegboard()
+			.kv_response_budget()
+			.map(|limit| limit.min(Semaphore::MAX_PERMITS));
+
+		KvBudget {
+			limit: limit.unwrap_or_default(),
+			semaphore: limit.map(Semaphore::new),
+		}
+	}
+
+	/// Whether new KV reads should be rejected before touching the database.
+	pub fn is_exhausted(&self) -> bool {
+		self.semaphore
+			.as_ref()
+			.is_some_and(|semaphore| semaphore.available_permits() == 0)
+	}
+
+	/// Reserves the size of the response until the returned reservation is dropped. Returns `None` if the
+	/// budget is exhausted. Responses larger than the whole budget reserve all of it.
+	pub fn try_reserve(&self, data: &KvResponseData) -> Option<Reservation<'_>> {
+		let permit = if let Some(semaphore) = &self.semaphore {
+			let size = response_size(data)
+				.min(self.limit as u64)
+				.try_into()
+				.unwrap_or(u32::MAX);
+			Some(semaphore.try_acquire_many(size).ok()?)
+		} else {
+			None
+		};
+		let reservation = Reservation {
+			budget: self,
+			permit,
+		};
+		self.record_usage();
+
+		Some(reservation)
+	}
+
+	fn record_usage(&self) {
+		if let Some(semaphore) = &self.semaphore {
+			let used = self.limit - semaphore.available_permits();
+			metrics::KV_RESPONSE_BYTES_IN_FLIGHT.record(used as u64, &[]);
+		}
+	}
+}
+
+pub struct Reservation<'a> {
+	budget: &'a KvBudget,
+	permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for Reservation<'_> {
+	fn drop(&mut self) {
+		if let Some(permit) = self.permit.take() {
+			drop(permit);
+			self.budget.record_usage();
+		}
+	}
+}
+
+/// Size of the keys and values of a response, which dominate its memory use.
+fn response_size(data: &KvResponseData) -> u64 {
+	let (keys, values) = match data {
+		KvResponseData::KvGetResponse(res) => (&res.keys, &res.values),
+		KvResponseData::KvListResponse(res) => (&res.keys, &res.values),
+		_ => return 0,
+	};
+
+	keys.iter()
+		.chain(values.iter())
+		.map(|x| x.len() as u64)
+		.sum()
+}
@@ -0,0 +1,195 @@
+use std::{
+	sync::{
+		Arc,
+		atomic::{AtomicBool, AtomicU64, Ordering},
+	},
+	time::{Duration, Instant},
+};
+
+use gas::prelude::*;
+use rivet_runner_protocol::*;
+use tokio::sync::RwLock;
+
+use crate::{Connections, metrics};
+
+/// How often KV pressure is evaluated and runners are notified of changes.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks KV load on this instance to ask runners to slow down when the KV backend is saturated.
+///
+/// Runners are throttled once the average KV request latency or the number of in-flight KV requests
+/// crosses the configured threshold and resumed once both drop below half of it. Past the higher shed
+/// thresholds, new connections and KV requests are rejected outright (see `Pegboard::kv_shed_latency`).
+pub struct KvPressure {
+	throttle: Thresholds,
+	shed: Thresholds,
+	in_flight: AtomicU64,
+	/// Accumulated since the last check.
+	total_latency_us: AtomicU64,
+	/// Accumulated since the last check.
+	request_count: AtomicU64,
+	overloaded: AtomicBool,
+	saturated: AtomicBool,
+	shed_retry_after_ms: u64,
+}
+
+struct Thresholds {
+	latency: Option<Duration>,
+	in_flight: Option<u64>,
+}
+
+impl Thresholds {
+	fn is_enabled(&self) -> bool {
+		self.latency.is_some() || self.in_flight.is_some()
+	}
+
+	/// Uses a lower threshold for leaving the state than for entering it to prevent flapping.
+	fn exceeded(&self, active: bool, avg_latency: Duration, in_flight: u64) -> bool {
+		let divisor = if active { 2 } else { 1 };
+		let latency_exceeded = self
+			.latency
+			.is_some_and(|threshold| avg_latency > threshold / divisor);
+		let in_flight_exceeded = self
+			.in_flight
+			.is_some_and(|threshold| in_flight > threshold / divisor as u64);
+
+		latency_exceeded || in_flight_exceeded
+	}
+}
+
+impl KvPressure {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		KvPressure {
+			throttle: Thresholds {
+				latency: config.pegboard().kv_throttle_latency(),
+				in_flight: config.pegboard().kv_throttle_in_flight(),
+			},
+			shed: Thresholds {
+				latency: config.pegboard().kv_shed_latency(),
+				in_flight: config.pegboard().kv_shed_in_flight(),
+			},
+			in_flight: AtomicU64::new(0),
+			total_latency_us: AtomicU64::new(0),
+			request_count: AtomicU64::new(0),
+			overloaded: AtomicBool::new(false),
+			saturated: AtomicBool::new(false),
+			shed_retry_after_ms: config.pegboard().kv_shed_retry_after_ms(),
+		}
+	}
+
+	/// Tracks a KV request until the returned guard is dropped.
+	pub fn start_request(&self) -> KvRequestGuard<'_> {
+		self.in_flight.fetch_add(1, Ordering::Relaxed);
+
+		KvRequestGuard {
+			pressure: self,
+			start: Instant::now(),
+		}
+	}
+
+	pub fn is_overloaded(&self) -> bool {
+		self.overloaded.load(Ordering::Acquire)
+	}
+
+	/// Returns the retry after hint (in ms) if KV is saturated and new work should be shed.
+	pub fn shed_retry_after_ms(&self) -> Option<u64> {
+		self.saturated
+			.load(Ordering::Acquire)
+			.then_some(self.shed_retry_after_ms)
+	}
+
+	/// Re-evaluates the pressure state from the requests since the last check.
+	fn update(&self) -> bool {
+		let total_latency_us = self.total_latency_us.swap(0, Ordering::Relaxed);
+		let request_count = self.request_count.swap(0, Ordering::Relaxed);
+		let in_flight = self.in_flight.load(Ordering::Relaxed);
+		metrics::KV_IN_FLIGHT_REQUESTS.record(in_flight, &[]);
+
+		let avg_latency = Duration::from_micros(total_latency_us / request_count.max(1));
+		let was_overloaded = self.is_overloaded();
+		let was_saturated = self.saturated.load(Ordering::Acquire);
+
+		let overloaded = self
+			.throttle
+			.exceeded(was_overloaded, avg_latency, in_flight);
+		if overloaded != was_overloaded {
+			tracing::info!(?overloaded, ?avg_latency, ?in_flight, "kv pressure changed");
+		}
+
+		let saturated = self.shed.exceeded(was_saturated, avg_latency, in_flight);
+		if saturated != was_saturated {
+			tracing::warn!(?saturated, ?avg_latency, ?in_flight, "kv saturation changed");
+		}
+
+		self.overloaded.store(overloaded, Ordering::Release);
+		self.saturated.store(saturated, Ordering::Release);
+
+		overloaded
+	}
+}
+
+pub struct KvRequestGuard<'a> {
+	pressure: &'a KvPressure,
+	start: Instant,
+}
+
+impl Drop for KvRequestGuard<'_> {
+	fn drop(&mut self) {
+		let dt = self.start.elapsed();
+
+		self.pressure.in_flight.fetch_sub(1, Ordering::Relaxed);
+		self.pressure
+			.total_latency_us
+			.fetch_add(dt.as_micros() as u64, Ordering::Relaxed);
+		self.pressure.request_count.fetch_add(1, Ordering::Relaxed);
+
+		metrics::KV_REQUEST_DURATION.record(dt.as_secs_f64(), &[]);
+	}
+}
+
+/// Periodically evaluates KV pressure and sends `KvThrottle`/`KvResume` to runners whose throttle state
+/// is out of date. System connections are never throttled. Exits immediately if both KV throttling and
+/// shedding are disabled.
+#[tracing::instrument(skip_all)]
+pub async fn thread(conns: Arc<RwLock<Connections>>, pressure: &KvPressure) {
+	if !pressure.throttle.is_enabled() && !pressure.shed.is_enabled() {
+		tracing::debug!("kv throttling disabled");
+		return;
+	}
+
+	let mut interval = tokio::time::interval(CHECK_INTERVAL);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+	loop {
+		interval.tick().await;
+
+		let overloaded = pressure.update();
+
+		let conns = conns
+			.read()
+			.await
+			.iter()
+			.filter_map(|(runner_id, conn)| {
+				let throttled = overloaded && conn.priority != protocol::PriorityClass::System;
+
+				(conn.kv_throttled.load(Ordering::Acquire) != throttled)
+					.then(|| (*runner_id, conn.clone(), throttled))
+			})
+			.collect::<Vec<_>>();
+
+		for (runner_id, conn, throttled) in conns {
+			let message = if throttled {
+				ToClient::ToClientKvThrottle
+			} else {
+				ToClient::ToClientKvResume
+			};
+
+			if let Err(err) = conn.send(message).await {
+				tracing::debug!(?runner_id, ?err, "failed sending kv throttle state");
+				continue;
+			}
+
+			conn.kv_throttled.store(throttled, Ordering::Release);
+		}
+	}
+}
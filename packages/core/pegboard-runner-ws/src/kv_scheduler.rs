@@ -0,0 +1,282 @@
+use std::{
+	cmp::Ordering,
+	collections::{BinaryHeap, HashMap},
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use gas::prelude::*;
+use rivet_metrics::KeyValue;
+use rivet_runner_protocol::*;
+use tokio::sync::oneshot;
+
+use crate::metrics;
+
+/// Shares the instance's KV concurrency fairly between namespaces, see `Pegboard::max_kv_concurrency`.
+///
+/// Connections already process their KV requests one at a time, so fairness is enforced between
+/// namespaces: a namespace with many runners flooding KV requests only delays its own operations. Queued
+/// operations are tagged with a virtual finish time (the later of the scheduler's virtual time and the
+/// namespace's last finish time, plus the inverse of the runner's priority weight) and freed slots go to
+/// the lowest tag. Namespaces without a backlog do not accumulate credit for later.
+pub struct KvScheduler {
+	limit: Option<usize>,
+	inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+	running: usize,
+	/// Start tag of the last operation that got a slot.
+	virtual_time: f64,
+	/// Finish tag of the last operation scheduled per namespace. Reset once the scheduler is idle.
+	finish_tags: HashMap<Id, f64>,
+	queue: BinaryHeap<Waiter>,
+	/// Keeps operations with the same tag in FIFO order.
+	next_seq: u64,
+}
+
+struct Waiter {
+	start: f64,
+	finish: f64,
+	seq: u64,
+	tx: oneshot::Sender<()>,
+}
+
+impl PartialEq for Waiter {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Waiter {
+	// Reversed, the heap pops the lowest finish tag first
+	fn cmp(&self, other: &Self) -> Ordering {
+		other
+			.finish
+			.total_cmp(&self.finish)
+			.then_with(|| other.seq.cmp(&self.seq))
+	}
+}
+
+impl KvScheduler {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		KvScheduler {
+			limit: config.pegboard().max_kv_concurrency(),
+			inner: Mutex::new(Inner::default()),
+		}
+	}
+
+	/// Waits for a slot to run a KV operation, held until the returned guard is dropped.
+	pub async fn acquire(&self, namespace_id: Id, priority: protocol::PriorityClass) -> KvSlot<'_> {
+		let Some(limit) = self.limit else {
+			return KvSlot {
+				scheduler: self,
+				held: false,
+				wait: Duration::ZERO,
+			};
+		};
+
+		let start_instant = Instant::now();
+		let rx = {
+			let mut inner = self.inner.lock().expect("poisoned");
+
+			let start = inner
+				.finish_tags
+				.get(&namespace_id)
+				.copied()
+				.unwrap_or_default()
+				.max(inner.virtual_time);
+			let finish = start + 1.0 / weight(priority);
+			inner.finish_tags.insert(namespace_id, finish);
+
+			if inner.running < limit && inner.queue.is_empty() {
+				inner.running += 1;
+				inner.virtual_time = inner.virtual_time.max(start);
+
+				None
+			} else {
+				let (tx, rx) = oneshot::channel();
+				let seq = inner.next_seq;
+				inner.next_seq += 1;
+				inner.queue.push(Waiter {
+					start,
+					finish,
+					seq,
+					tx,
+				});
+				metrics::KV_SCHEDULER_QUEUE_DEPTH.record(inner.queue.len() as u64, &[]);
+
+				Some(rx)
+			}
+		};
+
+		if let Some(rx) = rx {
+			let mut waiting = Waiting {
+				scheduler: self,
+				rx,
+				granted: false,
+			};
+
+			// The sender is only dropped after handing over a slot, see `release`
+			let _ = (&mut waiting.rx).await;
+			waiting.granted = true;
+		}
+
+		let wait = start_instant.elapsed();
+		metrics::KV_SCHEDULER_WAIT_DURATION.record(
+			wait.as_secs_f64(),
+			&[KeyValue::new("priority", priority_str(priority))],
+		);
+
+		KvSlot {
+			scheduler: self,
+			held: true,
+			wait,
+		}
+	}
+
+	/// Hands the slot over to the queued operation with the lowest finish tag.
+	fn release(&self) {
+		let mut inner = self.inner.lock().expect("poisoned");
+
+		while let Some(waiter) = inner.queue.pop() {
+			// Fails if the operation was abandoned while queued
+			if waiter.tx.send(()).is_ok() {
+				inner.virtual_time = inner.virtual_time.max(waiter.start);
+				metrics::KV_SCHEDULER_QUEUE_DEPTH.record(inner.queue.len() as u64, &[]);
+
+				return;
+			}
+		}
+
+		inner.running -= 1;
+		metrics::KV_SCHEDULER_QUEUE_DEPTH.record(0, &[]);
+
+		// Bounds the tags to namespaces with KV activity since the scheduler was last idle
+		if inner.running == 0 {
+			inner.finish_tags.clear();
+			inner.virtual_time = 0.0;
+		}
+	}
+}
+
+/// Share of KV throughput per priority class relative to best effort runners.
+fn weight(priority: protocol::PriorityClass) -> f64 {
+	match priority {
+		protocol::PriorityClass::BestEffort => 1.0,
+		protocol::PriorityClass::Normal => 4.0,
+		protocol::PriorityClass::System => 16.0,
+	}
+}
+
+fn priority_str(priority: protocol::PriorityClass) -> &'static str {
+	match priority {
+		protocol::PriorityClass::BestEffort => "best_effort",
+		protocol::PriorityClass::Normal => "normal",
+		protocol::PriorityClass::System => "system",
+	}
+}
+
+/// Releases a slot that was handed over after the waiting operation was abandoned.
+struct Waiting<'a> {
+	scheduler: &'a KvScheduler,
+	rx: oneshot::Receiver<()>,
+	granted: bool,
+}
+
+impl Drop for Waiting<'_> {
+	fn drop(&mut self) {
+		if self.granted {
+			return;
+		}
+
+		self.rx.close();
+		if self.rx.try_recv().is_ok() {
+			self.scheduler.release();
+		}
+	}
+}
+
+pub struct KvSlot<'a> {
+	scheduler: &'a KvScheduler,
+	/// Not set if the limit is disabled.
+	held: bool,
+	wait: Duration,
+}
+
+impl KvSlot<'_> {
+	/// Time spent queued for the slot.
+	pub fn wait(&self) -> Duration {
+		self.wait
+	}
+}
+
+impl Drop for KvSlot<'_> {
+	fn drop(&mut self) {
+		if self.held {
+			self.scheduler.release();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn flooding_namespace_does_not_starve_others() {
+		let scheduler = KvScheduler {
+			limit: Some(1),
+			inner: Mutex::new(Inner::default()),
+		};
+		let noisy = Id::new_v1(1);
+		let quiet = Id::new_v1(1);
+		let normal = protocol::PriorityClass::Normal;
+
+		let slot = scheduler.acquire(noisy, normal).await;
+
+		// The noisy namespace queues a backlog before the quiet one sends a single request
+		let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+		let waiters = (0..3)
+			.map(|_| (noisy, "noisy"))
+			.chain(std::iter::once((quiet, "quiet")))
+			.map(|(namespace_id, name)| {
+				let scheduler = &scheduler;
+				let order_tx = order_tx.clone();
+
+				async move {
+					let _slot = scheduler.acquire(namespace_id, normal).await;
+					order_tx.send(name).unwrap();
+				}
+			})
+			.collect::<Vec<_>>();
+
+		let waiters = async {
+			// Polled in order so the queue is populated before the slot is released
+			futures_util::future::join_all(waiters).await;
+		};
+		let release = async {
+			tokio::task::yield_now().await;
+			drop(slot);
+		};
+		tokio::join!(waiters, release);
+
+		drop(order_tx);
+		let mut order = Vec::new();
+		while let Some(name) = order_rx.recv().await {
+			order.push(name);
+		}
+
+		// Served before the noisy backlog despite being queued last
+		assert_eq!(order, ["quiet", "noisy", "noisy", "noisy"]);
+	}
+}
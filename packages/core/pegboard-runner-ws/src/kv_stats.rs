@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use pegboard_actor_kv as kv;
+use rivet_runner_protocol::*;
+
+/// KV usage of a single connection, reported to the runner with `ToClientKvStats`.
+#[derive(Default)]
+pub struct KvStats {
+	gets: AtomicU64,
+	lists: AtomicU64,
+	puts: AtomicU64,
+	deletes: AtomicU64,
+	drops: AtomicU64,
+	bytes_read: AtomicU64,
+	bytes_written: AtomicU64,
+	errors: AtomicU64,
+	/// Set if KV compression is enabled for the connection.
+	compression: Option<CompressionStats>,
+}
+
+#[derive(Default)]
+struct CompressionStats {
+	inbound_uncompressed: AtomicU64,
+	inbound_compressed: AtomicU64,
+	outbound_uncompressed: AtomicU64,
+	outbound_compressed: AtomicU64,
+}
+
+impl KvStats {
+	/// Compression is only accounted for if `compression_enabled` is set.
+	pub fn new(compression_enabled: bool) -> Self {
+		KvStats {
+			compression: compression_enabled.then(CompressionStats::default),
+			..Default::default()
+		}
+	}
+
+	/// Counts the request's operation. Rejected requests are counted as well.
+	pub fn record_request(&self, data: &KvRequestData) {
+		match data {
+			KvRequestData::KvGetRequest(_) => {
+				self.gets.fetch_add(1, Ordering::Relaxed);
+			}
+			KvRequestData::KvListRequest(_) => {
+				self.lists.fetch_add(1, Ordering::Relaxed);
+			}
+			KvRequestData::KvPutRequest(body) => {
+				self.puts.fetch_add(1, Ordering::Relaxed);
+				self.bytes_written.fetch_add(
+					entries_size(&body.keys, &body.values),
+					Ordering::Relaxed,
+				);
+			}
+			KvRequestData::KvDeleteRequest(_) => {
+				self.deletes.fetch_add(1, Ordering::Relaxed);
+			}
+			KvRequestData::KvDropRequest => {
+				self.drops.fetch_add(1, Ordering::Relaxed);
+			}
+		}
+	}
+
+	pub fn record_response(&self, data: &KvResponseData) {
+		match data {
+			KvResponseData::KvErrorResponse(_) => {
+				self.errors.fetch_add(1, Ordering::Relaxed);
+			}
+			KvResponseData::KvGetResponse(body) => {
+				self.bytes_read
+					.fetch_add(entries_size(&body.keys, &body.values), Ordering::Relaxed);
+			}
+			KvResponseData::KvListResponse(body) => {
+				self.bytes_read
+					.fetch_add(entries_size(&body.keys, &body.values), Ordering::Relaxed);
+			}
+			KvResponseData::KvPutResponse
+			| KvResponseData::KvDeleteResponse
+			| KvResponseData::KvDropResponse => {}
+		}
+	}
+
+	/// Adds the compression of a KV operation. No-op if compression is disabled for the connection.
+	pub fn record_compression(&self, stats: &kv::TxStats) {
+		let Some(compression) = &self.compression else {
+			return;
+		};
+
+		let (uncompressed, compressed) = stats.write_compression();
+		compression
+			.inbound_uncompressed
+			.fetch_add(uncompressed, Ordering::Relaxed);
+		compression
+			.inbound_compressed
+			.fetch_add(compressed, Ordering::Relaxed);
+
+		let (uncompressed, compressed) = stats.read_compression();
+		compression
+			.outbound_uncompressed
+			.fetch_add(uncompressed, Ordering::Relaxed);
+		compression
+			.outbound_compressed
+			.fetch_add(compressed, Ordering::Relaxed);
+	}
+
+	pub fn snapshot(&self) -> ToClientKvStats {
+		ToClientKvStats {
+			gets: self.gets.load(Ordering::Relaxed),
+			lists: self.lists.load(Ordering::Relaxed),
+			puts: self.puts.load(Ordering::Relaxed),
+			deletes: self.deletes.load(Ordering::Relaxed),
+			drops: self.drops.load(Ordering::Relaxed),
+			bytes_read: self.bytes_read.load(Ordering::Relaxed),
+			bytes_written: self.bytes_written.load(Ordering::Relaxed),
+			errors: self.errors.load(Ordering::Relaxed),
+			compression: self
+				.compression
+				.as_ref()
+				.map(|compression| KvCompressionStats {
+					inbound_uncompressed: compression.inbound_uncompressed.load(Ordering::Relaxed),
+					inbound_compressed: compression.inbound_compressed.load(Ordering::Relaxed),
+					outbound_uncompressed: compression.outbound_uncompressed.load(Ordering::Relaxed),
+					outbound_compressed: compression.outbound_compressed.load(Ordering::Relaxed),
+				}),
+		}
+	}
+}
+
+fn entries_size(keys: &[KvKey], values: &[KvValue]) -> u64 {
+	keys.iter()
+		.chain(values.iter())
+		.map(|x| x.len() as u64)
+		.sum()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn counts_bytes_and_errors() {
+		let stats = KvStats::default();
+
+		stats.record_request(&KvRequestData::KvPutRequest(KvPutRequest {
+			keys: vec![vec![1, 2]],
+			values: vec![vec![3, 4, 5]],
+			content_types: None,
+		}));
+		stats.record_request(&KvRequestData::KvDropRequest);
+		stats.record_response(&KvResponseData::KvErrorResponse(KvErrorResponse {
+			message: "test".to_string(),
+			code: None,
+			retryable: None,
+		}));
+
+		let snapshot = stats.snapshot();
+		assert_eq!(snapshot.puts, 1);
+		assert_eq!(snapshot.drops, 1);
+		assert_eq!(snapshot.bytes_written, 5);
+		assert_eq!(snapshot.bytes_read, 0);
+		assert_eq!(snapshot.errors, 1);
+		// Compression is not accounted for unless enabled
+		assert!(snapshot.compression.is_none());
+	}
+}
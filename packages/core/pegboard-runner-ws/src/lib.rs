@@ -1,38 +1,138 @@
 use std::{
-	collections::HashMap,
-	net::SocketAddr,
+	collections::{HashMap, HashSet},
+	net::{IpAddr, SocketAddr},
+	ops::ControlFlow,
 	sync::{
-		Arc,
-		atomic::{AtomicU32, Ordering},
+		Arc, OnceLock,
+		atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering},
 	},
-	time::Duration,
+	time::{Duration, Instant},
 };
 
 use futures_util::{
-	SinkExt, StreamExt,
+	FutureExt, SinkExt, Stream, StreamExt,
 	stream::{SplitSink, SplitStream},
 };
 use gas::prelude::Id;
 use gas::prelude::*;
-use pegboard::ops::runner::update_alloc_idx::{Action, RunnerEligibility};
+use ipnet::IpNet;
+use opentelemetry::trace::TraceContextExt;
+use pegboard::ops::runner::update_alloc_idx::{Action, RunnerEligibility, RunnerLoad};
+use pegboard::workflows::runner::{DisconnectReason, EvictionReason};
 use pegboard_actor_kv as kv;
+use rivet_config::config::{
+	DuplicateConnectionPolicy, KvCompressionAlgorithm, KvOperation, SuspiciousRttPolicy,
+};
 use rivet_error::*;
+use rivet_metrics::KeyValue;
 use rivet_runner_protocol::*;
+use rivet_types::msgs::pegboard::RunnerConnectionEventKind;
 use serde_json::json;
 use tokio::{
 	net::{TcpListener, TcpStream},
 	sync::{Mutex, RwLock},
+	task::AbortHandle,
 };
 use tokio_tungstenite::{
 	WebSocketStream,
-	tungstenite::protocol::{
-		Message,
-		frame::{CloseFrame, coding::CloseCode},
+	tungstenite::{
+		self,
+		error::ProtocolError,
+		protocol::{
+			Message, WebSocketConfig,
+			frame::{CloseFrame, coding::CloseCode},
+		},
 	},
 };
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use versioned_data_util::OwnedVersionedData;
 
+mod client_addr;
+mod compression;
+mod connection_events;
+mod connection_export;
+mod dispatch_throttle;
+mod handshake;
+mod health;
+mod hot_keys;
+mod incompatible_messages;
+mod kv_budget;
+mod kv_pressure;
+mod kv_scheduler;
+mod kv_stats;
+mod maintenance;
+mod metrics;
+mod metrics_snapshot;
+mod namespace_drain;
+mod namespace_kv_limit;
+mod namespace_resolve;
+mod packet_capture;
+mod rate_limit;
+mod recent_disconnects;
+mod redact;
+mod resource_pressure;
+mod synthetic_load;
+mod upgrade_limit;
+
+use compression::InitCompression;
+use connection_events::ConnectionEvents;
+use dispatch_throttle::DispatchThrottle;
+use handshake::Handshakes;
+use health::Health;
+use hot_keys::HotKeys;
+use incompatible_messages::{IncompatibleMessages, IncompatiblePolicy};
+use kv_budget::KvBudget;
+use kv_pressure::KvPressure;
+use kv_scheduler::KvScheduler;
+use kv_stats::KvStats;
+use maintenance::Maintenance;
+use namespace_drain::NamespaceDrain;
+use namespace_kv_limit::NamespaceKvLimiter;
+use namespace_resolve::NamespaceResolver;
+use packet_capture::PacketCapture;
+use pegboard::ops::runner::get_packet_capture::PacketDirection;
+use rate_limit::SourceRateLimiter;
+use recent_disconnects::RecentDisconnects;
+use resource_pressure::ResourcePressure;
+use synthetic_load::SyntheticLoad;
+use upgrade_limit::UpgradeLimiter;
+
 const UPDATE_PING_INTERVAL: Duration = Duration::from_secs(3);
+const INIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Close code sent when the init packet is not sent in time, distinct from other errors so runners can tell
+/// they were too slow rather than rejected. Mirrors HTTP 408.
+const INIT_TIMEOUT_CLOSE_CODE: u16 = 4008;
+/// Estimated clock skew (in ms) above which a warning is logged.
+const CLOCK_SKEW_WARN_THRESHOLD_MS: i64 = 1000;
+/// `KvErrorResponse` code for requests with more keys than allowed, see
+/// `Pegboard::max_kv_keys_per_request`.
+const KV_KEYS_LIMIT_EXCEEDED_CODE: &str = "kv_keys_limit_exceeded";
+/// `KvErrorResponse` code for requests in namespaces with KV disabled, see
+/// `PegboardNamespace::kv_enabled`.
+const KV_DISABLED_CODE: &str = "kv_disabled";
+/// `KvErrorResponse` code for requests of best effort connections while KV is overloaded.
+const KV_OVERLOADED_CODE: &str = "kv_overloaded";
+/// `KvErrorResponse` code for requests of non-system connections while KV is saturated, see
+/// `Pegboard::kv_shed_latency`.
+const KV_SATURATED_CODE: &str = "kv_saturated";
+/// `KvErrorResponse` code for list requests of runners that did not declare list support, see
+/// `KvCapabilities::list`.
+const KV_LIST_UNSUPPORTED_CODE: &str = "kv_list_unsupported";
+/// `KvErrorResponse` code for reads rejected because the instance-wide KV response budget is exhausted,
+/// see `Pegboard::kv_response_budget`.
+const KV_BUDGET_EXHAUSTED_CODE: &str = "kv_budget_exhausted";
+/// `KvErrorResponse` code for requests of namespaces with too many KV operations in flight, see
+/// `Pegboard::max_kv_in_flight_per_namespace`.
+const KV_NAMESPACE_BUSY_CODE: &str = "kv_namespace_busy";
+/// `KvErrorResponse` code for requests with keys or values larger than allowed for the namespace, see
+/// `Pegboard::max_kv_key_size` and `Pegboard::max_kv_value_size`.
+const KV_SIZE_LIMIT_EXCEEDED_CODE: &str = "kv_size_limit_exceeded";
+/// `KvErrorResponse` code for requests rejected by validation (i.e. malformed actor id or keys).
+const KV_INVALID_REQUEST_CODE: &str = "kv_invalid_request";
+/// `KvErrorResponse` code for KV operations that failed inside the engine.
+const KV_INTERNAL_ERROR_CODE: &str = "kv_internal_error";
 
 #[derive(RivetError, Debug)]
 #[error("ws")]
@@ -42,6 +142,11 @@ enum WsError {
 		"New runner connected, closing old connection."
 	)]
 	NewRunnerConnected,
+	#[error(
+		"runner_already_connected",
+		"Another connection for this runner is already open, rejecting new connection."
+	)]
+	RunnerAlreadyConnected,
 	#[error("connection_closed", "Normal connection close.")]
 	ConnectionClosed,
 	#[error(
@@ -49,17 +154,42 @@ enum WsError {
 		"The websocket has been evicted and should not attempt to reconnect."
 	)]
 	Eviction,
+	#[error(
+		"eviction_decommission",
+		"The server is being decommissioned. Reconnect to another instance."
+	)]
+	EvictionDecommission,
+	#[error(
+		"eviction_rebalance",
+		"The websocket has been moved to balance load. Reconnect to another instance."
+	)]
+	EvictionRebalance,
+	#[error(
+		"eviction_policy_violation",
+		"The websocket has been evicted for violating a policy and should not attempt to reconnect."
+	)]
+	EvictionPolicyViolation,
+	#[error("eviction_manual", "The websocket has been evicted by an operator.")]
+	EvictionManual,
 	#[error(
 		"timed_out_waiting_for_init",
-		"Timed out waiting for the init packet to be sent."
+		"Timed out waiting for the init packet to be sent. Reconnect and send the init packet immediately."
 	)]
-	TimedOutWaitingForInit,
+	TimedOutWaitingForInit { retry_after_ms: u64 },
+	#[error("silent_client", "No init packet was sent while the server is under load.")]
+	SilentClient,
 	#[error(
 		"invalid_initial_packet",
 		"The websocket could not process the initial packet.",
 		"Invalid initial packet: {0}."
 	)]
 	InvalidInitialPacket(&'static str),
+	#[error(
+		"invalid_total_slots",
+		"The runner declared more slots than allowed.",
+		"Runner declared {total_slots} slots, at most {max} are allowed."
+	)]
+	InvalidTotalSlots { total_slots: u32, max: u32 },
 	#[error(
 		"invalid_packet",
 		"The websocket could not process the given packet.",
@@ -68,17 +198,261 @@ enum WsError {
 	InvalidPacket(String),
 	#[error("invalid_url", "The connection URL is invalid.", "Invalid url: {0}")]
 	InvalidUrl(String),
+	#[error(
+		"rate_limited",
+		"Too many connections from this address, try again later."
+	)]
+	RateLimited,
+	#[error(
+		"maintenance_mode",
+		"The server is undergoing maintenance and is not accepting new connections."
+	)]
+	MaintenanceMode { retry_after_ms: u64 },
+	#[error(
+		"kv_saturated",
+		"KV is saturated and the server is not accepting new connections. Retry later."
+	)]
+	KvSaturated { retry_after_ms: u64 },
+	#[error(
+		"resource_pressure",
+		"The server is under CPU or memory pressure and is not accepting new connections. Retry later."
+	)]
+	ResourcePressure { retry_after_ms: u64 },
+	#[error(
+		"namespace_disabled",
+		"The namespace is not active and cannot accept runner connections."
+	)]
+	NamespaceDisabled,
+	#[error("namespace_resolution_timeout", "Timed out resolving the namespace. Retry later.")]
+	NamespaceResolutionTimeout { retry_after_ms: u64 },
+	#[error(
+		"message_not_representable",
+		"A message could not be represented in the runner's protocol version. Upgrade the runner.",
+		"Message `{0}` cannot be represented in the runner's protocol version. Upgrade the runner."
+	)]
+	MessageNotRepresentable(&'static str),
+	#[error(
+		"runner_version_unsupported",
+		"The runner version is not supported. Upgrade the runner to a supported version.",
+		"Runner version {version} is not supported. Upgrade the runner to a supported version."
+	)]
+	RunnerVersionUnsupported { version: u32 },
+	#[error(
+		"runner_name_not_allowed",
+		"The runner name is not allowed in this namespace.",
+		"Runner name `{0}` is not allowed in this namespace."
+	)]
+	RunnerNameNotAllowed(String),
+	#[error(
+		"protocol_version_not_allowed_for_namespace",
+		"The protocol version is not allowed in this namespace.",
+		"Protocol version {0} is not allowed in this namespace."
+	)]
+	ProtocolVersionNotAllowedForNamespace(u16),
+	#[error(
+		"quarantined",
+		"The runner has been temporarily quarantined after repeatedly sending invalid packets."
+	)]
+	Quarantined { retry_after_ms: u64 },
+	#[error(
+		"workflow_dispatch_queue_full",
+		"Too many runners are waiting for their workflow to be created. Retry later."
+	)]
+	WorkflowDispatchQueueFull { retry_after_ms: u64 },
+	#[error(
+		"namespace_draining",
+		"The namespace is being drained from this instance. Reconnect to another instance."
+	)]
+	NamespaceDraining { retry_after_ms: u64 },
+	#[error(
+		"runner_namespace_mismatch",
+		"The runner belongs to a different namespace than the one it connected to."
+	)]
+	RunnerNamespaceMismatch,
+}
+
+/// Errors that runners can receive in close frames, derived from `WsError`. Used to generate and
+/// validate the close frame handling of client SDKs. Unexpected errors are sent as
+/// `core.internal_error`.
+pub fn close_frame_errors() -> &'static [&'static RivetErrorSchema] {
+	WsError::schemas()
 }
 
 struct Connection {
 	workflow_id: Id,
+	namespace_id: Id,
+	namespace_name: String,
+	/// Used to quarantine runners that send invalid packets.
+	runner_key: String,
+	/// Instance the runner should prefer on its next connection attempt, sent with `ToClientInit`.
+	preferred_instance: Option<String>,
 	protocol_version: u16,
+	/// Set if the runner previously connected with a higher protocol version (i.e. after a rollback).
+	downgraded_from: Option<u16>,
 	tx: Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>,
 	last_rtt: AtomicU32,
+	/// Last load score reported by the runner, see `RunnerLoad::score`.
+	last_load: AtomicU32,
+	/// Server receive time minus the runner's timestamp of the last ping. Includes both latency and clock
+	/// skew.
+	last_ping_offset: AtomicI64,
+	/// Estimated clock skew in ms, positive if the runner's clock is behind the server's. Measured by
+	/// sending a ws ping after every runner ping since the server clock alone gives the true round trip.
+	clock_skew: AtomicI64,
+	/// Sequence number of the last packet sent to the runner. Resets with every new connection.
+	last_seq: AtomicU64,
+	/// Last sequence number acknowledged by the runner.
+	last_acked_seq: AtomicU64,
+	/// Cancelled once the connection is closed. Used to abandon in-flight work for this connection.
+	closed: CancellationToken,
+	/// Set if packet capture is enabled for the namespace.
+	packet_capture: Option<PacketCapture>,
+	/// Whether the runner was last sent `KvThrottle` (as opposed to `KvResume`).
+	kv_throttled: AtomicBool,
+	/// Set if the reason for the connection ending is known before the connection errors (i.e. it was
+	/// replaced or evicted). Reported to the runner workflow on close.
+	disconnect_reason: OnceLock<DisconnectReason>,
+	/// Whether the runner opted in to metrics snapshots during the init handshake.
+	metrics_snapshots: bool,
+	/// Prefixes all KV operations of the connection, see `kv::Scope::prefix`.
+	kv_prefix: Option<String>,
+	/// See `PegboardNamespace::kv_enabled`.
+	kv_enabled: bool,
+	/// Compression applied to KV values written by the runner, see `Pegboard::kv_compression`.
+	kv_compression: Option<kv::Compression>,
+	/// Resolved for the namespace when connecting, see `Pegboard::max_kv_key_size` and
+	/// `Pegboard::max_kv_value_size`.
+	kv_size_limits: KvSizeLimits,
+	/// Declared in the init packet, determines how the connection is treated under load.
+	priority: protocol::PriorityClass,
+	/// KV operations the runner is allowed to perform, all are allowed if not set. See
+	/// `PegboardNamespace::allowed_kv_operations`.
+	allowed_kv_operations: Option<Vec<KvOperation>>,
+	/// Declared in the init packet, KV responses are tailored to it (see `tailor_kv_response`).
+	kv_capabilities: protocol::KvCapabilities,
+	/// Declared in the init packet, keeps the runner's RTT out of the alloc index. Pings still keep the
+	/// runner alive.
+	exclude_rtt: bool,
+	/// Set once a ping with a suspicious RTT was received, see `SuspiciousRttPolicy::Exclude`.
+	unreliable_clock: AtomicBool,
+	/// Whether the runner is eligible for allocation, as last reported by `update_alloc_idx`.
+	eligible: AtomicBool,
+	/// Total KV request latency since the last metrics snapshot.
+	kv_latency_us: AtomicU64,
+	/// Number of KV requests since the last metrics snapshot.
+	kv_requests: AtomicU64,
+	/// Total time KV requests spent queued for a slot since the last metrics snapshot, see
+	/// `Pegboard::max_kv_concurrency`.
+	kv_queue_wait_us: AtomicU64,
+	synthetic_load: SyntheticLoad,
+	kv_stats: KvStats,
+}
+
+impl Connection {
+	/// Wraps the message in a packet with the next sequence number and sends it to the runner.
+	async fn send(&self, mut message: ToClient) -> Result<()> {
+		// Runners on an older protocol version cannot parse messages added after it
+		if !versioned::ToClient::is_representable(&message, self.protocol_version) {
+			tracing::debug!(
+				protocol_version=?self.protocol_version,
+				"message not representable in protocol version, not sending"
+			);
+			return Ok(());
+		}
+
+		if let Some(packet_capture) = &self.packet_capture {
+			packet_capture.push(PacketDirection::ToClient, &message);
+		}
+		if let ToClient::ToClientKvResponse(res) = &mut message {
+			self.kv_stats.record_response(&res.data);
+			tailor_kv_response(self.kv_capabilities, &mut res.data);
+		}
+
+		let mut tx = self.tx.lock().await;
+
+		// Assigned while holding the lock so that sequence numbers are sent in order
+		let seq = self.last_seq.fetch_add(1, Ordering::AcqRel) + 1;
+
+		let buf = versioned::ToClient::latest(ToClientPacket { seq, message })
+			.serialize(self.protocol_version)?;
+		tx.send(Message::Binary(buf.into())).await?;
+
+		Ok(())
+	}
+
+	/// Closes a connection whose sink can no longer be written to (i.e. the peer reset the connection).
+	/// The connection's task cleans it up (removing it from `conns` and evicting it) once `closed` is
+	/// cancelled, even if its stream has not ended yet.
+	fn close_broken(&self) {
+		let _ = self.disconnect_reason.set(DisconnectReason::Error);
+		self.closed.cancel();
+	}
 }
 
 type Connections = HashMap<Id, Arc<Connection>>;
 
+/// State shared by all incoming connections.
+struct SharedState {
+	/// Identity of this instance, see `Pegboard::instance_id`. Random if not configured.
+	instance_id: String,
+	/// When this instance started. Attached to connection events to tell disconnects caused by a
+	/// deploy apart from other failures.
+	boot_epoch: i64,
+	trusted_proxies: Vec<IpNet>,
+	rate_limiter: SourceRateLimiter,
+	maintenance: Maintenance,
+	namespace_drain: NamespaceDrain,
+	kv_pressure: KvPressure,
+	resource_pressure: ResourcePressure,
+	kv_budget: KvBudget,
+	namespace_kv_limiter: NamespaceKvLimiter,
+	kv_scheduler: KvScheduler,
+	hot_keys: HotKeys,
+	handshakes: Handshakes,
+	upgrade_limiter: UpgradeLimiter,
+	health: Health,
+	/// Deferred alloc idx evictions of recently disconnected runners, see
+	/// `Pegboard::disconnect_grace_period_ms`.
+	pending_evictions: std::sync::Mutex<HashMap<Id, AbortHandle>>,
+	recent_disconnects: RecentDisconnects,
+	incompatible_messages: IncompatibleMessages,
+	namespace_resolver: NamespaceResolver,
+	connection_events: ConnectionEvents,
+	dispatch_throttle: DispatchThrottle,
+}
+
+impl SharedState {
+	fn new(config: &rivet_config::Config, boot_epoch: i64) -> Result<Self> {
+		Ok(SharedState {
+			instance_id: config
+				.pegboard()
+				.instance_id
+				.clone()
+				.unwrap_or_else(|| Id::new_v1(config.dc_label()).to_string()),
+			boot_epoch,
+			trusted_proxies: client_addr::parse_trusted_proxies(config)?,
+			rate_limiter: SourceRateLimiter::new(config),
+			maintenance: Maintenance::new(config),
+			namespace_drain: NamespaceDrain::new(config),
+			kv_pressure: KvPressure::new(config),
+			resource_pressure: ResourcePressure::new(config),
+			kv_budget: KvBudget::new(config),
+			namespace_kv_limiter: NamespaceKvLimiter::new(config),
+			kv_scheduler: KvScheduler::new(config),
+			hot_keys: HotKeys::new(config),
+			handshakes: Handshakes::new(config),
+			upgrade_limiter: UpgradeLimiter::new(config),
+			health: Health::new(config),
+			pending_evictions: std::sync::Mutex::new(HashMap::new()),
+			recent_disconnects: RecentDisconnects::new(config),
+			incompatible_messages: IncompatibleMessages::default(),
+			namespace_resolver: NamespaceResolver::new(config),
+			connection_events: ConnectionEvents::new(config, boot_epoch),
+			dispatch_throttle: DispatchThrottle::new(config),
+		})
+	}
+}
+
 #[tracing::instrument(skip_all)]
 pub async fn start(config: rivet_config::Config, pools: rivet_pools::Pools) -> Result<()> {
 	let cache = rivet_cache::CacheInner::from_env(&config, pools.clone())?;
@@ -92,7 +466,12 @@ pub async fn start(config: rivet_config::Config, pools: rivet_pools::Pools) -> R
 		Id::new_v1(config.dc_label()),
 	)?;
 
+	let boot_epoch = util::timestamp::now();
+	tracing::info!(?boot_epoch, "runner ws starting");
+	metrics::BOOT_EPOCH.record(boot_epoch.try_into().unwrap_or_default(), &[]);
+
 	let conns: Arc<RwLock<Connections>> = Arc::new(RwLock::new(HashMap::new()));
+	let state = Arc::new(SharedState::new(ctx.config(), boot_epoch)?);
 
 	let host = ctx.config().pegboard().host();
 	let port = ctx.config().pegboard().port();
@@ -106,11 +485,43 @@ pub async fn start(config: rivet_config::Config, pools: rivet_pools::Pools) -> R
 	// If these do exit, then the `handle_connection` task will run indefinitely and never
 	// send/receive anything to runners. Runner workflows will then expire because of their ping,
 	// their workflow will complete, and runners will be unusable unless they reconnect.
-	tokio::join!(
-		socket_thread(&ctx, conns.clone(), listener),
-		msg_thread(&ctx, conns.clone()),
-		update_ping_thread(&ctx, conns.clone()),
-	);
+	let threads = async {
+		tokio::join!(
+			socket_thread(&ctx, conns.clone(), state.clone(), listener),
+			msg_thread(&ctx, conns.clone(), &state),
+			update_ping_thread(&ctx, conns.clone(), &state),
+			maintenance::thread(&ctx, &state.maintenance),
+			namespace_drain::thread(&ctx, conns.clone(), &state.namespace_drain),
+			kv_pressure::thread(conns.clone(), &state.kv_pressure),
+			resource_pressure::thread(&state.resource_pressure),
+			hot_keys::thread(ctx.config(), &state.hot_keys),
+			metrics_snapshot::thread(ctx.config(), conns.clone()),
+			health::conns_watchdog_thread(conns.clone(), &state.health),
+			health::db_probe_thread(&ctx, &state.health),
+		)
+	};
+
+	// Exports are keyed by instance identity, a random identity can't be picked up by a replacement
+	let export_instance_id = ctx
+		.config()
+		.pegboard()
+		.instance_id
+		.clone()
+		.filter(|_| ctx.config().pegboard().export_connections_on_shutdown());
+	if let Some(instance_id) = export_instance_id {
+		tokio::spawn(connection_export::import(ctx.clone(), instance_id.clone()));
+
+		let mut term_signal = util::signal::TermSignal::new()?;
+		tokio::select! {
+			_ = threads => {}
+			_ = term_signal.recv() => {
+				tracing::info!("termination signal received, exporting connections");
+				connection_export::export(&ctx, instance_id, &conns).await;
+			}
+		}
+	} else {
+		threads.await;
+	}
 
 	Ok(())
 }
@@ -119,11 +530,14 @@ pub async fn start(config: rivet_config::Config, pools: rivet_pools::Pools) -> R
 async fn socket_thread(
 	ctx: &StandaloneCtx,
 	conns: Arc<RwLock<Connections>>,
+	state: Arc<SharedState>,
 	listener: TcpListener,
 ) {
 	loop {
 		match listener.accept().await {
-			Ok((stream, addr)) => handle_connection(ctx, conns.clone(), stream, addr).await,
+			Ok((stream, addr)) => {
+				handle_connection(ctx, conns.clone(), state.clone(), stream, addr).await
+			}
 			Err(err) => tracing::error!(?err, "failed to connect websocket"),
 		}
 	}
@@ -133,202 +547,768 @@ async fn socket_thread(
 async fn handle_connection(
 	ctx: &StandaloneCtx,
 	conns: Arc<RwLock<Connections>>,
+	state: Arc<SharedState>,
 	raw_stream: TcpStream,
 	addr: SocketAddr,
 ) {
 	tracing::debug!(?addr, "new connection");
 
-	let ctx = ctx.clone();
+	tokio::spawn(run_connection(ctx.clone(), conns, state, raw_stream, addr));
+}
 
-	tokio::spawn(async move {
-		let (ws_stream, uri) = match setup_stream(raw_stream, addr).await {
-			Ok(x) => x,
-			Err(err) => {
-				tracing::warn!(?addr, ?err, "setup stream failed");
-				return;
+/// Drives a single connection from the upgrade until it is closed and cleaned up. Spawned per connection
+/// by `handle_connection`, tests can await it directly to observe the full connection lifecycle. Only the
+/// deferred alloc idx eviction outlives it, see `Pegboard::disconnect_grace_period_ms`.
+async fn run_connection(
+	ctx: StandaloneCtx,
+	conns: Arc<RwLock<Connections>>,
+	state: Arc<SharedState>,
+	raw_stream: TcpStream,
+	addr: SocketAddr,
+) {
+	if health::is_probe(&raw_stream).await {
+		if let Err(err) = health::respond(&state.health, raw_stream).await {
+			tracing::debug!(?addr, ?err, "failed responding to health probe");
+		}
+
+		return;
+	}
+
+	// Shed before the upgrade, completing it only to close the socket costs more under a flood
+	let upgrade_slot = match state.upgrade_limiter.acquire().await {
+		Ok(slot) => slot,
+		Err(reason) => {
+			tracing::debug!(?addr, %reason, "shedding websocket upgrade");
+
+			if let Err(err) = upgrade_limit::reject(raw_stream).await {
+				tracing::debug!(?addr, ?err, "failed rejecting websocket upgrade");
 			}
-		};
-		let (mut tx, mut rx) = ws_stream.split();
 
-		let url_data = match parse_url(addr, uri) {
-			Ok(x) => x,
-			Err(err) => {
-				tracing::warn!(?addr, ?err, "could not parse runner connection url");
+			return;
+		}
+	};
+
+	// The slot is released on timeout, clients stalling mid-request cannot hold it forever
+	let (ws_stream, uri, headers) = match state
+		.upgrade_limiter
+		.with_upgrade_timeout(setup_stream(&ctx, raw_stream, addr))
+		.await
+	{
+		Ok(x) => x,
+		Err(err) => {
+			tracing::warn!(?addr, ?err, "setup stream failed");
+			return;
+		}
+	};
+	if let Err(err) = set_tcp_keepalive(ctx.config(), ws_stream.get_ref()) {
+		tracing::warn!(?addr, ?err, "failed setting tcp keepalive");
+	}
+	let (mut tx, mut rx) = ws_stream.split();
 
-				let close_frame = err_to_close_frame(WsError::InvalidUrl(err.to_string()).build());
+	// Real address of the client, used for rate limiting and audit logs
+	let client_addr = client_addr::resolve(addr, &headers, &state.trusted_proxies);
 
-				if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
-					tracing::error!(?addr, ?err, "failed closing socket");
-				}
+	// Existing connections are not affected by maintenance mode
+	if let Some(retry_after_ms) = state.maintenance.retry_after_ms(&ctx).await {
+		tracing::debug!(?addr, ?client_addr, "rejecting runner connection, maintenance mode");
+
+		let close_frame = err_to_close_frame(WsError::MaintenanceMode { retry_after_ms }.build());
+
+		if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+			tracing::error!(?addr, ?err, "failed closing socket");
+		}
+
+		return;
+	}
+
+	// Accepting the connection would add KV work (i.e. actor state loads) that would only time out
+	if let Some(retry_after_ms) = state.kv_pressure.shed_retry_after_ms() {
+		tracing::debug!(?addr, ?client_addr, "rejecting runner connection, kv saturated");
+		metrics::KV_SHED_CONNECTIONS.add(1, &[]);
+
+		let close_frame = err_to_close_frame(WsError::KvSaturated { retry_after_ms }.build());
+
+		if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+			tracing::error!(?addr, ?err, "failed closing socket");
+		}
+
+		return;
+	}
+
+	if let Some(retry_after_ms) = state.resource_pressure.shed_retry_after_ms() {
+		tracing::debug!(?addr, ?client_addr, "rejecting runner connection, resource pressure");
+		metrics::RESOURCE_PRESSURE_SHED_CONNECTIONS.add(1, &[]);
+
+		let close_frame = err_to_close_frame(WsError::ResourcePressure { retry_after_ms }.build());
+
+		if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+			tracing::error!(?addr, ?err, "failed closing socket");
+		}
+
+		return;
+	}
+
+	if !state.rate_limiter.try_acquire(client_addr).await {
+		tracing::warn!(?addr, ?client_addr, "runner connection rate limited");
 
-				return;
+		let close_frame = err_to_close_frame(WsError::RateLimited.build());
+
+		if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+			tracing::error!(?addr, ?err, "failed closing socket");
+		}
+
+		return;
+	}
+
+	let url_data = match parse_url(addr, uri) {
+		Ok(x) => x,
+		Err(err) => {
+			tracing::warn!(
+				?addr,
+				?client_addr,
+				?err,
+				"could not parse runner connection url"
+			);
+
+			// NOTE: Parse errors never include query parameter values so they are safe to return
+			let close_frame = err_to_close_frame(WsError::InvalidUrl(err.to_string()).build());
+
+			if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+				tracing::error!(?addr, ?err, "failed closing socket");
 			}
-		};
 
-		let mut tx = Some(tx);
+			return;
+		}
+	};
 
-		let (runner_id, conn) = match build_connection(&ctx, &mut tx, &mut rx, url_data).await {
-			Ok(res) => res,
-			Err(err) => {
-				tracing::warn!(?addr, ?err, "failed to build connection");
+	let handshake = state.handshakes.start();
+	let res = build_connection(&ctx, &state, &conns, &mut rx, client_addr, url_data).await;
+	drop(handshake);
+	drop(upgrade_slot);
 
-				if let Some(mut tx) = tx {
-					let close_frame = err_to_close_frame(err);
+	let (runner_id, conn) = match res {
+		Ok((runner_id, connection)) => (runner_id, Arc::new(connection(tx))),
+		Err(err) => {
+			tracing::warn!(?addr, ?client_addr, ?err, "failed to build connection");
 
-					if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
-						tracing::error!(?addr, ?err, "failed closing socket");
-					}
-				}
+			let close_frame = err_to_detailed_close_frame(ctx.config(), err);
 
-				return;
+			if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+				tracing::error!(?addr, ?err, "failed closing socket");
 			}
-		};
 
-		// Store connection
-		{
-			let mut conns = conns.write().await;
-			if let Some(old_conn) = conns.insert(runner_id, conn.clone()) {
+			return;
+		}
+	};
+
+	tracing::info!(?runner_id, ?client_addr, "runner connected");
+
+	state.connection_events.publish(
+		&ctx,
+		runner_id,
+		conn.namespace_id,
+		RunnerConnectionEventKind::Init,
+		None,
+	);
+
+	// Runner reconnected within the grace period, cancel its eviction
+	if let Some(eviction) = state
+		.pending_evictions
+		.lock()
+		.expect("poisoned")
+		.remove(&runner_id)
+	{
+		tracing::debug!(?runner_id, "runner reconnected, cancelling alloc idx eviction");
+		eviction.abort();
+	}
+
+	// Store connection
+	let policy = ctx
+		.config()
+		.pegboard()
+		.duplicate_connection_policy(&conn.namespace_name);
+	if !register_connection(&conns, runner_id, &conn, policy).await {
+		tracing::warn!(?runner_id, "runner already connected, rejecting new connection");
+		metrics::DUPLICATE_CONNECTION.add(1, &[KeyValue::new("policy", "first_writer_wins")]);
+
+		let close_frame = err_to_close_frame(WsError::RunnerAlreadyConnected.build());
+		let mut tx = conn.tx.lock().await;
+
+		if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+			tracing::error!(?runner_id, ?err, "failed closing socket");
+		}
+
+		return;
+	}
+
+	state.connection_events.publish(
+		&ctx,
+		runner_id,
+		conn.namespace_id,
+		RunnerConnectionEventKind::Connect,
+		None,
+	);
+
+	let res = conn
+		.closed
+		.run_until_cancelled(handle_messages(&ctx, &state, &mut rx, runner_id, &conn))
+		.await;
+	// Set if the runner ended the connection
+	let mut stream_end = None;
+	let err = match res {
+		Some(Err(err)) => {
+			tracing::warn!(
+				?runner_id,
+				?client_addr,
+				?err,
+				"failed processing runner messages"
+			);
+
+			if let Some(packet_capture) = &conn.packet_capture {
 				tracing::warn!(
 					?runner_id,
-					"runner already connected, closing old connection"
+					packets = ?packet_capture.dump(),
+					"recent packets before connection error"
 				);
-
-				let close_frame = err_to_close_frame(WsError::NewRunnerConnected.build());
-				let mut tx = old_conn.tx.lock().await;
-
-				if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
-					tracing::error!(?runner_id, ?err, "failed closing old connection");
-				}
 			}
-		}
 
-		let err = if let Err(err) = handle_messages(&ctx, &mut rx, runner_id, &conn).await {
-			tracing::warn!(?runner_id, ?err, "failed processing runner messages");
+			state.connection_events.publish(
+				&ctx,
+				runner_id,
+				conn.namespace_id,
+				RunnerConnectionEventKind::Error,
+				Some(err_code(&err)),
+			);
 
 			err
-		} else {
-			tracing::info!(?runner_id, "runner connection closed");
+		}
+		Some(Ok(end)) => {
+			tracing::info!(
+				?runner_id,
+				?client_addr,
+				end = end.as_str(),
+				"runner connection closed"
+			);
+			metrics::CONNECTION_ENDS.add(1, &[KeyValue::new("end", end.as_str())]);
+
+			let _ = conn.disconnect_reason.set(end.disconnect_reason());
+			stream_end = Some(end);
 
 			WsError::ConnectionClosed.build()
-		};
+		}
+		// Closed by the server (replaced, evicted or the sink became unusable), the disconnect reason
+		// is already set
+		None => {
+			tracing::info!(?runner_id, ?client_addr, "runner connection closed by server");
 
-		// Clean up
-		{
-			conns.write().await.remove(&runner_id);
+			WsError::ConnectionClosed.build()
 		}
+	};
 
-		// Make runner immediately ineligible when it disconnects
+	// Inform the runner workflow why the connection ended. Evictions are only sent by the workflow once
+	// it has completed so there is nothing to inform.
+	let reason = conn
+		.disconnect_reason
+		.get()
+		.copied()
+		.unwrap_or_else(|| err_to_disconnect_reason(&err));
+	if reason != DisconnectReason::Evicted {
 		if let Err(err) = ctx
-			.op(pegboard::ops::runner::update_alloc_idx::Input {
-				runners: vec![pegboard::ops::runner::update_alloc_idx::Runner {
-					runner_id,
-					action: Action::ClearIdx,
-				}],
-			})
+			.signal(pegboard::workflows::runner::Disconnected { reason })
+			.to_workflow_id(conn.workflow_id)
+			.send()
 			.await
 		{
-			tracing::error!(?runner_id, ?err, "failed evicting runner from alloc idx");
+			tracing::warn!(?runner_id, ?err, "failed sending disconnected signal");
+		}
+	}
+
+	// Clean up. If the connection was already closed by the server, a close frame was either already
+	// sent or cannot be sent.
+	let closed_by_server = conn.closed.is_cancelled();
+	conn.closed.cancel();
+
+	// Only remove this exact connection, it may have been replaced by a newer connection for the same
+	// runner
+	let replaced = {
+		let mut conns = conns.write().await;
+
+		if conns
+			.get(&runner_id)
+			.is_some_and(|current| Arc::ptr_eq(current, &conn))
+		{
+			conns.remove(&runner_id);
+			false
+		} else {
+			true
+		}
+	};
+
+	// The newer connection already completed its handshake, remembering this disconnect would
+	// misattribute the runner's next reconnect
+	if !replaced {
+		state.recent_disconnects.record(runner_id, reason);
+	}
+
+	metrics::DISCONNECTS.add(
+		1,
+		&[KeyValue::new("reason", recent_disconnects::reason_str(reason))],
+	);
+	state.connection_events.publish(
+		&ctx,
+		runner_id,
+		conn.namespace_id,
+		RunnerConnectionEventKind::Disconnect,
+		Some(recent_disconnects::reason_str(reason).to_string()),
+	);
+
+	let grace_period = ctx.config().pegboard().disconnect_grace_period();
+	if replaced {
+		tracing::debug!(?runner_id, "connection was replaced, runner stays eligible");
+	} else if grace_period.is_zero() {
+		// Make runner immediately ineligible when it disconnects
+		evict_from_alloc_idx(&ctx, runner_id).await;
+	} else {
+		// Defer making the runner ineligible in case it reconnects shortly (i.e. network blip)
+		let handle = tokio::spawn({
+			let ctx = ctx.clone();
+			let state = state.clone();
+
+			async move {
+				tokio::time::sleep(grace_period).await;
+
+				state
+					.pending_evictions
+					.lock()
+					.expect("poisoned")
+					.remove(&runner_id);
+
+				tracing::debug!(?runner_id, "runner did not reconnect within grace period");
+
+				evict_from_alloc_idx(&ctx, runner_id).await;
+			}
+		});
+
+		if let Some(old_eviction) = state
+			.pending_evictions
+			.lock()
+			.expect("poisoned")
+			.insert(runner_id, handle.abort_handle())
+		{
+			old_eviction.abort();
 		}
+	}
 
-		let close_frame = err_to_close_frame(err);
+	if !closed_by_server {
+		let close_frame = if let Some(end) = stream_end {
+			stream_end_close_frame(end)
+		} else {
+			err_to_detailed_close_frame(ctx.config(), err)
+		};
 		let mut tx = conn.tx.lock().await;
 		if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
 			tracing::error!(?runner_id, ?err, "failed closing socket");
 		}
-	});
+	}
+}
+
+/// Records the decompressed size of an init packet and which of its optional fields are set, to inform size
+/// limits and track feature adoption. Field values are never recorded.
+fn record_init_packet(
+	protocol_version: u16,
+	init_compression: Option<InitCompression>,
+	size: usize,
+	init: &ToServerInit,
+) {
+	let protocol_version = KeyValue::new("protocol_version", protocol_version as i64);
+
+	metrics::INIT_PACKET_SIZE.record(
+		size as f64,
+		&[
+			protocol_version.clone(),
+			KeyValue::new("compressed", init_compression.is_some()),
+		],
+	);
+
+	let fields = [
+		("last_command_idx", init.last_command_idx.is_some()),
+		("prepopulate_actor_names", init.prepopulate_actor_names.is_some()),
+		("metadata", init.metadata.is_some()),
+		("metrics_snapshots", init.metrics_snapshots.is_some()),
+		("runner_id", init.runner_id.is_some()),
+		("priority", init.priority.is_some()),
+		("kv_capabilities", init.kv_capabilities.is_some()),
+		("exclude_rtt", init.exclude_rtt.is_some()),
+	];
+	for (field, _) in fields.into_iter().filter(|(_, present)| *present) {
+		metrics::INIT_PACKET_FIELDS.add(
+			1,
+			&[protocol_version.clone(), KeyValue::new("field", field)],
+		);
+	}
+}
+
+/// Counts an invalid packet towards the runner key's quarantine. Errors are logged since the connection is
+/// closed regardless.
+async fn record_violation(ctx: &StandaloneCtx, runner_id: Id, conn: &Connection) {
+	match ctx
+		.op(pegboard::ops::runner::record_violation::Input {
+			namespace_id: conn.namespace_id,
+			runner_key: conn.runner_key.clone(),
+		})
+		.await
+	{
+		Ok(res) => {
+			if let Some(quarantined_until_ts) = res.quarantined_until_ts {
+				tracing::warn!(
+					?runner_id,
+					?quarantined_until_ts,
+					"runner quarantined after repeated invalid packets"
+				);
+				metrics::RUNNER_QUARANTINED.add(1, &[]);
+			}
+		}
+		Err(err) => tracing::error!(?runner_id, ?err, "failed recording protocol violation"),
+	}
+}
+
+async fn evict_from_alloc_idx(ctx: &StandaloneCtx, runner_id: Id) {
+	if let Err(err) = ctx
+		.op(pegboard::ops::runner::update_alloc_idx::Input {
+			runners: vec![pegboard::ops::runner::update_alloc_idx::Runner {
+				runner_id,
+				action: Action::ClearIdx,
+			}],
+		})
+		.await
+	{
+		tracing::error!(?runner_id, ?err, "failed evicting runner from alloc idx");
+	}
 }
 
 #[tracing::instrument(skip_all)]
 async fn setup_stream(
+	ctx: &StandaloneCtx,
 	raw_stream: TcpStream,
 	addr: SocketAddr,
-) -> Result<(WebSocketStream<TcpStream>, hyper::Uri)> {
+) -> Result<(WebSocketStream<TcpStream>, hyper::Uri, hyper::HeaderMap)> {
 	let mut uri = None;
-	let ws_stream = tokio_tungstenite::accept_hdr_async(
+	let mut headers = hyper::HeaderMap::new();
+	let ws_stream = tokio_tungstenite::accept_hdr_async_with_config(
 		raw_stream,
 		|req: &tokio_tungstenite::tungstenite::handshake::server::Request, res| {
-			// Bootleg way of reading the uri
+			// Bootleg way of reading the uri and headers
 			uri = Some(req.uri().clone());
+			headers = req.headers().clone();
 
-			tracing::debug!(?addr, ?uri, "handshake");
+			tracing::debug!(
+				?addr,
+				uri = %redact::uri(ctx.config(), req.uri()),
+				"handshake"
+			);
 
 			Ok(res)
 		},
+		Some(ws_config(ctx.config())),
 	)
 	.await?;
 
 	let uri = uri.context("socket has no associated request")?;
 
-	Ok((ws_stream, uri))
+	Ok((ws_stream, uri, headers))
+}
+
+/// See `Pegboard::receive_buffer_size` and `Pegboard::max_message_size`.
+fn ws_config(config: &rivet_config::Config) -> WebSocketConfig {
+	let max_message_size = config.pegboard().max_message_size();
+
+	WebSocketConfig::default()
+		.read_buffer_size(config.pegboard().receive_buffer_size())
+		.max_message_size(Some(max_message_size))
+		// A frame can never be larger than its message, otherwise keep the library default of 16 MiB
+		.max_frame_size(Some(max_message_size.min(16 * 1024 * 1024)))
+}
+
+/// See `Pegboard::tcp_keepalive`.
+fn set_tcp_keepalive(config: &rivet_config::Config, stream: &TcpStream) -> std::io::Result<()> {
+	let Some(keepalive) = config.pegboard().tcp_keepalive() else {
+		return Ok(());
+	};
+
+	let params = socket2::TcpKeepalive::new()
+		.with_time(keepalive.idle())
+		.with_interval(keepalive.interval());
+	#[cfg(not(windows))]
+	let params = params.with_retries(keepalive.count());
+
+	socket2::SockRef::from(stream).set_tcp_keepalive(&params)
 }
 
+/// Runs the handshake of a new connection. Returns the connection without its sink, which the caller
+/// keeps until the handshake succeeded so it can send a close frame if it fails. Attaching the sink is
+/// infallible, so a dispatched workflow is always either connected or informed of the failed handshake.
 #[tracing::instrument(skip_all)]
 async fn build_connection(
 	ctx: &StandaloneCtx,
-	tx: &mut Option<SplitSink<WebSocketStream<TcpStream>, Message>>,
+	state: &SharedState,
+	conns: &RwLock<Connections>,
 	rx: &mut SplitStream<WebSocketStream<TcpStream>>,
+	client_addr: IpAddr,
 	UrlData {
 		protocol_version,
 		namespace,
 		runner_key,
+		init_compression,
+		kv_prefix,
 	}: UrlData,
-) -> Result<(Id, Arc<Connection>)> {
-	let namespace = ctx
-		.op(namespace::ops::resolve_for_name_global::Input { name: namespace })
+) -> Result<(Id, ConnectionBuilder)> {
+	let start = Instant::now();
+
+	let namespace = state
+		.namespace_resolver
+		.resolve(ctx, namespace)
 		.await?
 		.ok_or_else(|| namespace::errors::Namespace::NotFound.build())?;
+	metrics::HANDSHAKE_NAMESPACE_RESOLVE_DURATION.record(start.elapsed().as_secs_f64(), &[]);
+
+	if !namespace.status.is_active() {
+		tracing::debug!(
+			namespace_id=?namespace.namespace_id,
+			status=?namespace.status,
+			"namespace not active"
+		);
+		return Err(WsError::NamespaceDisabled.build());
+	}
+
+	if let Some(retry_after_ms) = state
+		.namespace_drain
+		.retry_after_ms(ctx, namespace.namespace_id)
+		.await
+	{
+		tracing::debug!(namespace_id=?namespace.namespace_id, "namespace draining");
+		return Err(WsError::NamespaceDraining { retry_after_ms }.build());
+	}
+
+	let protocol_version_allowed = ctx
+		.config()
+		.pegboard()
+		.namespace(&namespace.name)
+		.map_or(true, |ns| ns.is_protocol_version_allowed(protocol_version));
+	if !protocol_version_allowed {
+		tracing::debug!(
+			namespace_id=?namespace.namespace_id,
+			?protocol_version,
+			"protocol version not allowed for namespace"
+		);
+		return Err(WsError::ProtocolVersionNotAllowedForNamespace(protocol_version).build());
+	}
+
+	let quarantine_res = ctx
+		.op(pegboard::ops::runner::get_quarantine::Input {
+			namespace_id: namespace.namespace_id,
+			runner_key: runner_key.clone(),
+		})
+		.await?;
+	if let Some(quarantined_until_ts) = quarantine_res.quarantined_until_ts {
+		tracing::debug!(
+			namespace_id=?namespace.namespace_id,
+			?quarantined_until_ts,
+			"runner quarantined"
+		);
+
+		let retry_after_ms = quarantined_until_ts.saturating_sub(util::timestamp::now());
+		return Err(WsError::Quarantined {
+			retry_after_ms: retry_after_ms.try_into().unwrap_or_default(),
+		}
+		.build());
+	}
 
 	tracing::debug!("new runner connection");
 
 	// Receive init packet
-	let (runner_id, workflow_id) = if let Some(msg) =
-		tokio::time::timeout(Duration::from_secs(5), rx.next())
-			.await
-			.map_err(|_| WsError::TimedOutWaitingForInit.build())?
-	{
+	let init_wait_start = Instant::now();
+	let init_msg = if let Some(silent_timeout) = state.handshakes.silent_client_timeout() {
+		match tokio::time::timeout(silent_timeout, rx.next()).await {
+			Ok(msg) => Ok(msg),
+			// Close silent clients early so they can't exhaust handshakes (i.e. slowloris)
+			Err(_) if state.handshakes.is_under_pressure() => {
+				tracing::debug!(?client_addr, "closing silent client");
+				metrics::HANDSHAKE_SILENT_CLIENT_CLOSED.add(1, &[]);
+				state.rate_limiter.penalize(client_addr).await;
+
+				return Err(WsError::SilentClient.build());
+			}
+			Err(_) => {
+				tokio::time::timeout(INIT_TIMEOUT.saturating_sub(silent_timeout), rx.next()).await
+			}
+		}
+	} else {
+		tokio::time::timeout(INIT_TIMEOUT, rx.next()).await
+	}
+	.map_err(|_| WsError::TimedOutWaitingForInit { retry_after_ms: 0 }.build())?;
+	metrics::HANDSHAKE_INIT_WAIT_DURATION.record(init_wait_start.elapsed().as_secs_f64(), &[]);
+
+	// Resolved from the runner name in the init packet
+	let mut allowed_kv_operations = None;
+	let mut priority = protocol::PriorityClass::default();
+	let mut kv_capabilities = protocol::KvCapabilities::default();
+	let mut exclude_rtt = false;
+	// Set once the runner workflow is dispatched
+	let mut dispatched = None;
+
+	let (runner_id, workflow_id, runner_reused, metrics_snapshots) = if let Some(msg) = init_msg {
 		let buf = match msg? {
 			Message::Binary(buf) => buf,
 			Message::Close(_) => return Err(WsError::ConnectionClosed.build()),
 			msg => {
-				tracing::debug!(?msg, "invalid initial message");
+				tracing::debug!(
+					msg = %redact::debug(ctx.config(), &msg),
+					"invalid initial message"
+				);
 				return Err(WsError::InvalidInitialPacket("must be a binary blob").build());
 			}
 		};
 
+		let buf = if let Some(init_compression) = init_compression {
+			init_compression
+				.decompress(&buf, ctx.config().pegboard().max_message_size())
+				.map_err(|err| WsError::InvalidPacket(err.to_string()).build())?
+		} else {
+			buf.to_vec()
+		};
+
 		let packet = versioned::ToServer::deserialize(&buf, protocol_version)
-			.map_err(|err| WsError::InvalidPacket(err.to_string()).build())?
+			.map_err(|err| WsError::InvalidPacket(err.to_string()).build())?;
+		if let ToServer::ToServerInit(init) = &packet {
+			record_init_packet(protocol_version, init_compression, buf.len(), init);
+		}
+		let packet = packet
 			.try_into()
 			.map_err(|err: anyhow::Error| WsError::InvalidPacket(err.to_string()).build())?;
 
-		let (runner_id, workflow_id) = if let protocol::ToServer::Init {
+		let metrics_snapshots = matches!(
+			packet,
+			protocol::ToServer::Init {
+				metrics_snapshots: true,
+				..
+			}
+		);
+
+		let (runner_id, workflow_id, runner_reused) = if let protocol::ToServer::Init {
 			name,
 			version,
 			total_slots,
+			runner_id: requested_runner_id,
+			priority: init_priority,
+			kv_capabilities: init_kv_capabilities,
+			exclude_rtt: init_exclude_rtt,
 			..
 		} = &packet
 		{
-			// Look up existing runner by key
-			let existing_runner = ctx
-				.op(pegboard::ops::runner::get_by_key::Input {
-					namespace_id: namespace.namespace_id,
-					name: name.clone(),
-					key: runner_key.clone(),
-				})
-				.await?;
+			let name_allowed = ctx
+				.config()
+				.pegboard()
+				.namespace(&namespace.name)
+				.map_or(true, |ns| ns.is_runner_name_allowed(name));
+			if !name_allowed {
+				tracing::debug!(namespace_id=?namespace.namespace_id, ?name, "runner name not allowed");
+				return Err(WsError::RunnerNameNotAllowed(name.clone()).build());
+			}
 
-			let runner_id = if let Some(runner) = existing_runner.runner {
-				// IMPORTANT: Before we spawn/get the workflow, we try to update the runner's last ping ts.
-				// This ensures if the workflow is currently checking for expiry that it will not expire
+			validate_total_slots(*total_slots)?;
+
+			if !ctx
+				.config()
+				.pegboard()
+				.is_runner_version_allowed(&namespace.name, *version)
+			{
+				tracing::debug!(
+					namespace_id=?namespace.namespace_id,
+					?version,
+					"runner version unsupported"
+				);
+				metrics::RUNNER_VERSION_UNSUPPORTED.add(1, &[]);
+
+				return Err(WsError::RunnerVersionUnsupported { version: *version }.build());
+			}
+
+			allowed_kv_operations = ctx
+				.config()
+				.pegboard()
+				.namespace(&namespace.name)
+				.and_then(|ns| ns.allowed_kv_operations(name))
+				.map(<[_]>::to_vec);
+			priority = *init_priority;
+			kv_capabilities = *init_kv_capabilities;
+			exclude_rtt = *init_exclude_rtt;
+
+			// Look up existing runner, preferring the runner id requested by the runner. Falls back to the
+			// lookup by key if the requested runner is not owned by this runner or no longer live.
+			let lookup_start = Instant::now();
+			let requested_runner = if let Some(requested_runner_id) = requested_runner_id {
+				get_requested_runner(
+					ctx,
+					namespace.namespace_id,
+					name,
+					&runner_key,
+					*requested_runner_id,
+				)
+				.await?
+			} else {
+				None
+			};
+			let existing_runner = if let Some(runner) = requested_runner {
+				Some(runner)
+			} else {
+				ctx.op(pegboard::ops::runner::get_by_key::Input {
+					namespace_id: namespace.namespace_id,
+					name: name.clone(),
+					key: runner_key.clone(),
+				})
+				.await?
+				.runner
+			};
+			// Lookups are scoped to the namespace, this guards against binding across tenants regardless
+			if let Some(runner) = &existing_runner {
+				ensure_runner_namespace(runner, namespace.namespace_id)?;
+			}
+			// Bind to a workflow created ahead of the connection instead of dispatching one
+			let preprovisioned = if existing_runner.is_none() {
+				ctx.op(pegboard::ops::runner::get_preprovisioned::Input {
+					namespace_id: namespace.namespace_id,
+					name: name.clone(),
+					key: runner_key.clone(),
+				})
+				.await?
+				.runner
+			} else {
+				None
+			};
+			metrics::HANDSHAKE_RUNNER_LOOKUP_DURATION
+				.record(lookup_start.elapsed().as_secs_f64(), &[]);
+
+			let (runner_id, runner_reused) = if let Some(runner) = &preprovisioned {
+				(runner.runner_id, false)
+			} else if let Some(runner) = existing_runner {
+				// IMPORTANT: Before we spawn/get the workflow, we try to update the runner's last ping ts.
+				// This ensures if the workflow is currently checking for expiry that it will not expire
 				// (because we are about to send signals to it) and if it is already expired (but not
 				// completed) we can choose a new runner id.
+				let ping_update_start = Instant::now();
 				let update_ping_res = ctx
 					.op(pegboard::ops::runner::update_alloc_idx::Input {
 						runners: vec![pegboard::ops::runner::update_alloc_idx::Runner {
 							runner_id: runner.runner_id,
-							action: Action::UpdatePing { rtt: 0 },
+							action: Action::UpdatePing {
+								rtt: (!exclude_rtt).then_some(0),
+								load: 0,
+								retain_eligibility: false,
+							},
 						}],
 					})
 					.await?;
+				metrics::HANDSHAKE_PING_UPDATE_DURATION
+					.record(ping_update_start.elapsed().as_secs_f64(), &[]);
 
 				if update_ping_res
 					.notifications
@@ -338,282 +1318,510 @@ async fn build_connection(
 					.unwrap_or_default()
 				{
 					// Runner expired, create a new one
-					Id::new_v1(ctx.config().dc_label())
+					(Id::new_v1(ctx.config().dc_label()), false)
 				} else {
 					// Use existing runner
-					runner.runner_id
+					(runner.runner_id, true)
 				}
 			} else {
 				// No existing runner for this key, create a new one
-				Id::new_v1(ctx.config().dc_label())
+				(Id::new_v1(ctx.config().dc_label()), false)
 			};
 
-			// Spawn a new runner workflow if one doesn't already exist
-			let workflow_id = ctx
-				.workflow(pegboard::workflows::runner::Input {
-					runner_id,
-					namespace_id: namespace.namespace_id,
-					name: name.clone(),
-					key: runner_key.clone(),
-					version: version.clone(),
-					total_slots: *total_slots,
-				})
-				.tag("runner_id", runner_id)
-				.unique()
-				.dispatch()
-				.await?;
+			// Reject before the workflow is signaled so the existing connection is unaffected
+			if ctx
+				.config()
+				.pegboard()
+				.duplicate_connection_policy(&namespace.name)
+				== DuplicateConnectionPolicy::FirstWriterWins
+				&& conns.read().await.contains_key(&runner_id)
+			{
+				tracing::debug!(?runner_id, "runner already connected");
+				metrics::DUPLICATE_CONNECTION
+					.add(1, &[KeyValue::new("policy", "first_writer_wins")]);
+
+				return Err(WsError::RunnerAlreadyConnected.build());
+			}
+
+			let workflow_id = if let Some(runner) = preprovisioned {
+				tracing::debug!(
+					?runner_id,
+					workflow_id=?runner.workflow_id,
+					"binding to pre-provisioned runner"
+				);
+				metrics::HANDSHAKE_PREPROVISIONED.add(1, &[]);
+
+				// Not guarded like dispatched workflows, the workflow keeps waiting for a connection until its
+				// TTL if this handshake fails
+				runner.workflow_id
+			} else {
+				// Smooth out dispatches during reconnection storms
+				if let Err(retry_after_ms) = state.dispatch_throttle.acquire().await {
+					tracing::debug!(?runner_id, ?retry_after_ms, "workflow dispatch queue full");
+					metrics::WORKFLOW_DISPATCH_REJECTED.add(1, &[]);
+
+					return Err(WsError::WorkflowDispatchQueueFull { retry_after_ms }.build());
+				}
 
-			(runner_id, workflow_id)
+				// Spawn a new runner workflow if one doesn't already exist.
+				//
+				// NOTE: `.unique()` resolves to the existing workflow id within the dispatch transaction. If a
+				// concurrent connection for the same runner wins the race and the dispatch fails anyway (i.e.
+				// the transaction conflicted and exhausted its retries), we converge on the workflow it created
+				// instead of failing the connection.
+				let dispatch_start = Instant::now();
+				let dispatch_res = ctx
+					.workflow(pegboard::workflows::runner::Input {
+						runner_id,
+						namespace_id: namespace.namespace_id,
+						name: name.clone(),
+						key: runner_key.clone(),
+						version: version.clone(),
+						total_slots: *total_slots,
+						preprovision_ttl_ms: None,
+					})
+					.tag("runner_id", runner_id)
+					.unique()
+					.dispatch()
+					.await;
+				let workflow_id = match dispatch_res {
+					Ok(workflow_id) => workflow_id,
+					Err(err) => {
+						let existing_workflow_id = ctx
+							.find_workflow::<pegboard::workflows::runner::Workflow>((
+								"runner_id",
+								runner_id,
+							))
+							.await?;
+
+						if let Some(workflow_id) = existing_workflow_id {
+							tracing::debug!(
+								?err,
+								?runner_id,
+								?workflow_id,
+								"runner workflow dispatch conflicted, using existing workflow"
+							);
+							metrics::HANDSHAKE_WORKFLOW_DISPATCH_CONFLICT.add(1, &[]);
+
+							workflow_id
+						} else {
+							// Genuine dispatch error
+							return Err(err);
+						}
+					}
+				};
+				metrics::HANDSHAKE_WORKFLOW_DISPATCH_DURATION
+					.record(dispatch_start.elapsed().as_secs_f64(), &[]);
+				dispatched = Some(DispatchedWorkflowGuard::new(ctx, workflow_id));
+
+				workflow_id
+			};
+
+			(runner_id, workflow_id, runner_reused)
 		} else {
-			tracing::debug!(?packet, "invalid initial packet");
+			tracing::debug!(
+				packet = %redact::debug(ctx.config(), &packet),
+				"invalid initial packet"
+			);
 			return Err(WsError::InvalidInitialPacket("must be `ToServer::Init`").build());
 		};
 
 		// Forward to runner wf
+		let signal_start = Instant::now();
 		ctx.signal(packet)
 			.to_workflow_id(workflow_id)
 			.send()
 			.await?;
+		metrics::HANDSHAKE_SIGNAL_SEND_DURATION.record(signal_start.elapsed().as_secs_f64(), &[]);
 
-		(runner_id, workflow_id)
+		(runner_id, workflow_id, runner_reused, metrics_snapshots)
 	} else {
 		return Err(WsError::ConnectionClosed.build());
 	};
 
-	let tx = tx.take().context("should exist")?;
+	// Advisory, failing to record the protocol version only disables downgrade detection
+	let downgraded_from = match ctx
+		.op(pegboard::ops::runner::record_protocol_version::Input {
+			runner_id,
+			protocol_version,
+		})
+		.await
+	{
+		Ok(res) => res.downgraded_from,
+		Err(err) => {
+			tracing::warn!(?runner_id, ?err, "failed recording runner protocol version");
+			None
+		}
+	};
+	if let Some(downgraded_from) = downgraded_from {
+		tracing::warn!(
+			?runner_id,
+			?downgraded_from,
+			?protocol_version,
+			"runner protocol downgraded"
+		);
+		metrics::PROTOCOL_DOWNGRADES.add(
+			1,
+			&[
+				KeyValue::new("from", downgraded_from.to_string()),
+				KeyValue::new("to", protocol_version.to_string()),
+			],
+		);
+	}
 
-	Ok((
-		runner_id,
-		Arc::new(Connection {
+	// Advisory, failing to determine the preferred instance should not fail the connection
+	let preferred_instance = if let Some(instance_id) = ctx.config().pegboard().instance_id.clone() {
+		match ctx
+			.op(pegboard::ops::runner::claim_instance::Input {
+				namespace_id: namespace.namespace_id,
+				runner_key: runner_key.clone(),
+				instance_id,
+			})
+			.await
+		{
+			Ok(res) => res.preferred_instance_id,
+			Err(err) => {
+				tracing::warn!(?runner_id, ?err, "failed claiming runner instance");
+				None
+			}
+		}
+	} else {
+		None
+	};
+
+	if let Some(prior) = state.recent_disconnects.take(runner_id) {
+		let gap_ms = util::timestamp::now().saturating_sub(prior.disconnect_ts);
+		let prior_reason = recent_disconnects::reason_str(prior.reason);
+
+		tracing::info!(?runner_id, %prior_reason, %gap_ms, "runner reconnected");
+
+		let attrs = [KeyValue::new("prior_reason", prior_reason)];
+		metrics::RECONNECT_CHURN.add(1, &attrs);
+		metrics::RECONNECT_GAP_DURATION.record(gap_ms as f64 / 1000.0, &attrs);
+	}
+
+	metrics::HANDSHAKE_DURATION.record(
+		start.elapsed().as_secs_f64(),
+		&[KeyValue::new("runner_reused", runner_reused.to_string())],
+	);
+
+	if let Some(dispatched) = dispatched {
+		dispatched.disarm();
+	}
+
+	let kv_compression = kv_compression(ctx.config(), &namespace.name);
+	let kv_size_limits = KvSizeLimits::new(ctx.config(), &namespace.name);
+
+	let packet_capture = PacketCapture::new(ctx.config(), &namespace.name);
+	let kv_enabled = ctx
+		.config()
+		.pegboard()
+		.namespace(&namespace.name)
+		.map_or(true, |ns| ns.kv_enabled());
+
+	let connection: ConnectionBuilder = Box::new(move |tx| Connection {
+		workflow_id,
+		packet_capture,
+		namespace_id: namespace.namespace_id,
+		runner_key,
+		preferred_instance,
+		kv_throttled: AtomicBool::new(false),
+		disconnect_reason: OnceLock::new(),
+		metrics_snapshots,
+		kv_prefix,
+		kv_enabled,
+		kv_compression,
+		kv_size_limits,
+		priority,
+		allowed_kv_operations,
+		kv_capabilities,
+		exclude_rtt,
+		unreliable_clock: AtomicBool::new(false),
+		eligible: AtomicBool::new(true),
+		kv_latency_us: AtomicU64::new(0),
+		kv_requests: AtomicU64::new(0),
+		kv_queue_wait_us: AtomicU64::new(0),
+		synthetic_load: SyntheticLoad::default(),
+		kv_stats: KvStats::new(kv_compression.is_some()),
+		namespace_name: namespace.name,
+		protocol_version,
+		downgraded_from,
+		tx: Mutex::new(tx),
+		last_rtt: AtomicU32::new(0),
+		last_load: AtomicU32::new(0),
+		last_ping_offset: AtomicI64::new(0),
+		clock_skew: AtomicI64::new(0),
+		last_seq: AtomicU64::new(0),
+		last_acked_seq: AtomicU64::new(0),
+		closed: CancellationToken::new(),
+	});
+
+	Ok((runner_id, connection))
+}
+
+/// Completes a connection with its sink once the handshake succeeded, see `build_connection`.
+type ConnectionBuilder =
+	Box<dyn FnOnce(SplitSink<WebSocketStream<TcpStream>, Message>) -> Connection + Send>;
+
+/// Informs a dispatched runner workflow if the handshake fails before the connection is established. A
+/// newly dispatched workflow then completes instead of waiting for a runner that never fully connected.
+struct DispatchedWorkflowGuard {
+	ctx: StandaloneCtx,
+	workflow_id: Id,
+	armed: bool,
+}
+
+impl DispatchedWorkflowGuard {
+	fn new(ctx: &StandaloneCtx, workflow_id: Id) -> Self {
+		DispatchedWorkflowGuard {
+			ctx: ctx.clone(),
 			workflow_id,
-			protocol_version,
-			tx: Mutex::new(tx),
-			last_rtt: AtomicU32::new(0),
-		}),
-	))
+			armed: true,
+		}
+	}
+
+	fn disarm(mut self) {
+		self.armed = false;
+	}
+}
+
+impl Drop for DispatchedWorkflowGuard {
+	fn drop(&mut self) {
+		if !self.armed {
+			return;
+		}
+
+		let ctx = self.ctx.clone();
+		let workflow_id = self.workflow_id;
+		tokio::spawn(async move {
+			if let Err(err) = ctx
+				.signal(pegboard::workflows::runner::Disconnected {
+					reason: DisconnectReason::Error,
+				})
+				.to_workflow_id(workflow_id)
+				.send()
+				.await
+			{
+				tracing::warn!(?workflow_id, ?err, "failed informing workflow of failed handshake");
+			}
+		});
+	}
+}
+
+/// Stores the connection, replacing any older connection of the same runner. Returns false if the
+/// connection was rejected because the runner is already connected.
+///
+/// The write lock is only held for the map swap. The old connection is closed in a background task
+/// since a slow socket would otherwise block all connection inserts and removals on this instance.
+async fn register_connection(
+	conns: &RwLock<Connections>,
+	runner_id: Id,
+	conn: &Arc<Connection>,
+	policy: DuplicateConnectionPolicy,
+) -> bool {
+	let old_conn = {
+		let mut conns = conns.write().await;
+
+		// Checked before dispatching the workflow in `build_connection`, this only happens when a
+		// concurrent connection for the same runner finished its handshake first
+		if policy == DuplicateConnectionPolicy::FirstWriterWins && conns.contains_key(&runner_id) {
+			return false;
+		}
+
+		conns.insert(runner_id, conn.clone())
+	};
+
+	if let Some(old_conn) = old_conn {
+		if policy == DuplicateConnectionPolicy::AlertAndReplace {
+			tracing::error!(
+				?runner_id,
+				namespace=%conn.namespace_name,
+				"duplicate runner connection, closing old connection"
+			);
+			metrics::DUPLICATE_CONNECTION.add(1, &[KeyValue::new("policy", "alert_and_replace")]);
+		} else {
+			tracing::warn!(?runner_id, "runner already connected, closing old connection");
+			metrics::DUPLICATE_CONNECTION.add(1, &[KeyValue::new("policy", "last_writer_wins")]);
+		}
+
+		let _ = old_conn.disconnect_reason.set(DisconnectReason::Replaced);
+		old_conn.closed.cancel();
+
+		tokio::spawn(async move {
+			let close_frame = err_to_close_frame(WsError::NewRunnerConnected.build());
+			let mut tx = old_conn.tx.lock().await;
+
+			if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+				tracing::error!(?runner_id, ?err, "failed closing old connection");
+			}
+		});
+	}
+
+	true
 }
 
+/// Processes packets from the runner until the stream ends.
+///
+/// Packets are processed one at a time, strictly in the order they were received across all
+/// packet types (i.e. a KV put followed by events that depend on it, see `ToServer`). Runners rely
+/// on this, so nothing here may defer processing a packet to a background task.
+#[tracing::instrument(name = "runner_connection", skip_all, fields(?runner_id, namespace_id = ?conn.namespace_id))]
 async fn handle_messages(
 	ctx: &StandaloneCtx,
+	state: &SharedState,
 	rx: &mut SplitStream<WebSocketStream<TcpStream>>,
 	runner_id: Id,
 	conn: &Connection,
-) -> Result<()> {
+) -> Result<StreamEnd> {
+	let conn_span_ctx = tracing::Span::current()
+		.context()
+		.span()
+		.span_context()
+		.clone();
+	let mut rx = RunnerRx::new(rx);
+
 	// Receive messages from socket
-	while let Some(msg) = rx.next().await {
-		let buf = match msg? {
-			Message::Binary(buf) => buf,
-			Message::Ping(_) => continue,
-			Message::Close(_) => bail!("socket closed {}", runner_id),
-			msg => {
-				tracing::warn!(?runner_id, ?msg, "unexpected message");
+	loop {
+		let buf = match rx.next().await {
+			Ok(Message::Binary(buf)) => buf,
+			Ok(Message::Ping(_)) => continue,
+			Ok(Message::Pong(buf)) => {
+				handle_pong(runner_id, conn, &buf);
+				continue;
+			}
+			Err(end) => return Ok(end),
+			Ok(msg) => {
+				tracing::warn!(
+					?runner_id,
+					msg = %redact::debug(ctx.config(), &msg),
+					"unexpected message"
+				);
 				continue;
 			}
 		};
 
-		let packet = versioned::ToServer::deserialize(&buf, conn.protocol_version)?;
+		let packet = match versioned::ToServer::deserialize(&buf, conn.protocol_version) {
+			Ok(packet) => packet,
+			Err(err) => {
+				record_violation(ctx, runner_id, conn).await;
+				return Err(err);
+			}
+		};
+
+		if let Some(packet_capture) = &conn.packet_capture {
+			packet_capture.push(PacketDirection::ToServer, &packet);
+		}
 
 		match packet {
 			ToServer::ToServerPing(ping) => {
-				let rtt = util::timestamp::now().saturating_sub(ping.ts).try_into()?;
+				let now = util::timestamp::now();
+				let offset = now.saturating_sub(ping.ts);
+				conn.last_ping_offset.store(offset, Ordering::Relaxed);
 
-				conn.last_rtt.store(rtt, Ordering::Relaxed);
-			}
-			// Process KV request
-			ToServer::ToServerKvRequest(req) => {
-				let actor_id = match Id::parse(&req.actor_id) {
-					Ok(actor_id) => actor_id,
-					Err(err) => {
-						let packet = versioned::ToClient::latest(ToClient::ToClientKvResponse(
-							ToClientKvResponse {
-								request_id: req.request_id,
-								data: KvResponseData::KvErrorResponse(KvErrorResponse {
-									message: err.to_string(),
-								}),
-							},
-						));
+				let rtt = if ctx.config().pegboard().correct_rtt_for_clock_skew() {
+					offset.saturating_sub(conn.clock_skew.load(Ordering::Relaxed))
+				} else {
+					offset
+				};
 
-						let buf = packet.serialize(conn.protocol_version)?;
-						conn.tx
-							.lock()
-							.await
-							.send(Message::Binary(buf.into()))
-							.await?;
+				let policy = ctx.config().pegboard().suspicious_rtt_policy();
+				let floor_ms = ctx.config().pegboard().suspicious_rtt_floor_ms();
+				if let Some(rtt) = sanitize_rtt(policy, floor_ms, rtt) {
+					conn.last_rtt.store(rtt, Ordering::Relaxed);
+				} else {
+					tracing::debug!(?runner_id, ?rtt, ?policy, "dropping suspicious rtt sample");
+				}
+
+				if rtt < floor_ms.try_into().unwrap_or(i64::MAX) {
+					metrics::SUSPICIOUS_RTT_SAMPLES.add(1, &[]);
 
-						continue;
+					if policy == SuspiciousRttPolicy::Exclude
+						&& !conn.unreliable_clock.swap(true, Ordering::Relaxed)
+					{
+						tracing::warn!(
+							?runner_id,
+							?rtt,
+							"runner clock unreliable, excluding its rtt from the alloc index"
+						);
 					}
-				};
+				}
 
-				let actors_res = ctx
-					.op(pegboard::ops::actor::get_runner::Input {
-						actor_ids: vec![actor_id],
+				let load = ping
+					.load
+					.map(|load| RunnerLoad {
+						cpu: load.cpu,
+						memory: load.memory,
+						queue_depth: load.queue_depth,
 					})
-					.await?;
-				let actor_belongs = actors_res
-					.actors
-					.first()
-					.map(|x| x.runner_id == runner_id)
 					.unwrap_or_default();
+				conn.last_load.store(load.score(), Ordering::Relaxed);
 
-				// Verify actor belongs to this runner
-				if !actor_belongs {
-					let packet = versioned::ToClient::latest(ToClient::ToClientKvResponse(
-						ToClientKvResponse {
-							request_id: req.request_id,
-							data: KvResponseData::KvErrorResponse(KvErrorResponse {
-								message: "given actor does not belong to runner".to_string(),
-							}),
-						},
-					));
-
-					let buf = packet.serialize(conn.protocol_version)?;
-					conn.tx
-						.lock()
-						.await
-						.send(Message::Binary(buf.into()))
-						.await?;
-
+				// Measure the true round trip with the server clock to estimate skew, see `handle_pong`
+				conn.tx
+					.lock()
+					.await
+					.send(Message::Ping(now.to_le_bytes().to_vec().into()))
+					.await?;
+			}
+			ToServer::ToServerAckPackets(ack) => {
+				let last_seq = conn.last_seq.load(Ordering::Acquire);
+
+				if ack.last_seq > last_seq {
+					tracing::warn!(
+						?runner_id,
+						ack_seq=?ack.last_seq,
+						?last_seq,
+						"runner acked packet that was never sent"
+					);
 					continue;
 				}
 
-				// TODO: Add queue and bg thread for processing kv ops
-				// Run kv operation
-				match req.data {
-					KvRequestData::KvGetRequest(body) => {
-						let res = kv::get(&*ctx.udb()?, actor_id, body.keys).await;
-
-						let packet = versioned::ToClient::latest(ToClient::ToClientKvResponse(
-							ToClientKvResponse {
-								request_id: req.request_id,
-								data: match res {
-									Ok((keys, values, metadata)) => {
-										KvResponseData::KvGetResponse(KvGetResponse {
-											keys,
-											values,
-											metadata,
-										})
-									}
-									Err(err) => KvResponseData::KvErrorResponse(KvErrorResponse {
-										// TODO: Don't return actual error?
-										message: err.to_string(),
-									}),
-								},
-							},
-						));
-
-						let buf = packet.serialize(conn.protocol_version)?;
-						conn.tx
-							.lock()
-							.await
-							.send(Message::Binary(buf.into()))
-							.await?;
-					}
-					KvRequestData::KvListRequest(body) => {
-						let res = kv::list(
-							&*ctx.udb()?,
-							actor_id,
-							body.query,
-							body.reverse.unwrap_or_default(),
-							body.limit.map(TryInto::try_into).transpose()?,
-						)
-						.await;
-
-						let packet = versioned::ToClient::latest(ToClient::ToClientKvResponse(
-							ToClientKvResponse {
-								request_id: req.request_id,
-								data: match res {
-									Ok((keys, values, metadata)) => {
-										KvResponseData::KvListResponse(KvListResponse {
-											keys,
-											values,
-											metadata,
-										})
-									}
-									Err(err) => KvResponseData::KvErrorResponse(KvErrorResponse {
-										// TODO: Don't return actual error?
-										message: err.to_string(),
-									}),
-								},
-							},
-						));
-
-						let buf = packet.serialize(conn.protocol_version)?;
-						conn.tx
-							.lock()
-							.await
-							.send(Message::Binary(buf.into()))
-							.await?;
-					}
-					KvRequestData::KvPutRequest(body) => {
-						let res = kv::put(&*ctx.udb()?, actor_id, body.keys, body.values).await;
-
-						let packet = versioned::ToClient::latest(ToClient::ToClientKvResponse(
-							ToClientKvResponse {
-								request_id: req.request_id,
-								data: match res {
-									Ok(()) => KvResponseData::KvPutResponse,
-									Err(err) => KvResponseData::KvErrorResponse(KvErrorResponse {
-										// TODO: Don't return actual error?
-										message: err.to_string(),
-									}),
-								},
-							},
-						));
+				conn.last_acked_seq.fetch_max(ack.last_seq, Ordering::AcqRel);
+			}
+			// Process KV request
+			ToServer::ToServerKvRequest(req) => {
+				// Each request gets its own trace linked to the connection span instead of being its child,
+				// otherwise all KV requests of a connection end up in a single long-lived trace
+				let span = tracing::info_span!(
+					parent: None,
+					"kv_request",
+					?runner_id,
+					request_id = req.request_id,
+					actor_id = %req.actor_id,
+					op = kv_operation(&req.data).1,
+					rejected = tracing::field::Empty,
+					queue_us = tracing::field::Empty,
+					db_us = tracing::field::Empty,
+					send_us = tracing::field::Empty,
+					total_us = tracing::field::Empty,
+				);
+				span.add_link(conn_span_ctx.clone());
 
-						let buf = packet.serialize(conn.protocol_version)?;
-						conn.tx
-							.lock()
-							.await
-							.send(Message::Binary(buf.into()))
-							.await?;
-					}
-					KvRequestData::KvDeleteRequest(body) => {
-						let res = kv::delete(&*ctx.udb()?, actor_id, body.keys).await;
-
-						let packet = versioned::ToClient::latest(ToClient::ToClientKvResponse(
-							ToClientKvResponse {
-								request_id: req.request_id,
-								data: match res {
-									Ok(()) => KvResponseData::KvDeleteResponse,
-									Err(err) => KvResponseData::KvErrorResponse(KvErrorResponse {
-										// TODO: Don't return actual error?
-										message: err.to_string(),
-									}),
-								},
-							},
-						));
+				let res = handle_kv_request(ctx, state, &mut rx, runner_id, conn, req)
+					.instrument(span)
+					.await?;
+				// Otherwise the runner ended the stream, which is returned by the next read
+				if res.is_break() && conn.closed.is_cancelled() {
+					break;
+				}
+			}
+			#[cfg(feature = "synthetic-load")]
+			ToServer::ToServerSyntheticLoad(req) => {
+				tracing::info!(?runner_id, skip_writes=?req.skip_writes, "synthetic load enabled");
 
-						let buf = packet.serialize(conn.protocol_version)?;
-						conn.tx
-							.lock()
-							.await
-							.send(Message::Binary(buf.into()))
-							.await?;
-					}
-					KvRequestData::KvDropRequest => {
-						let res = kv::delete_all(&*ctx.udb()?, actor_id).await;
-
-						let packet = versioned::ToClient::latest(ToClient::ToClientKvResponse(
-							ToClientKvResponse {
-								request_id: req.request_id,
-								data: match res {
-									Ok(()) => KvResponseData::KvDropResponse,
-									Err(err) => KvResponseData::KvErrorResponse(KvErrorResponse {
-										// TODO: Don't return actual error?
-										message: err.to_string(),
-									}),
-								},
-							},
-						));
+				conn.synthetic_load.enable(req.skip_writes);
+			}
+			// Must never be accepted in production
+			#[cfg(not(feature = "synthetic-load"))]
+			ToServer::ToServerSyntheticLoad(_) => {
+				record_violation(ctx, runner_id, conn).await;
 
-						let buf = packet.serialize(conn.protocol_version)?;
-						conn.tx
-							.lock()
-							.await
-							.send(Message::Binary(buf.into()))
-							.await?;
-					}
-				}
+				return Err(
+					WsError::InvalidPacket("synthetic load is not supported".to_string()).build(),
+				);
+			}
+			ToServer::ToServerGetKvStats => {
+				conn.send(ToClient::ToClientKvStats(conn.kv_stats.snapshot())).await?;
 			}
 			// Forward to runner wf
 			_ => {
@@ -628,10 +1836,852 @@ async fn handle_messages(
 	bail!("stream closed {runner_id}");
 }
 
+/// Handles a KV request from the runner. Breaks if the connection closed or the runner ended the stream
+/// before the request completed.
+async fn handle_kv_request(
+	ctx: &StandaloneCtx,
+	state: &SharedState,
+	rx: &mut RunnerRx<'_>,
+	runner_id: Id,
+	conn: &Connection,
+	req: ToServerKvRequest,
+) -> Result<ControlFlow<()>> {
+	let received = Instant::now();
+	tracing::debug!(?runner_id, request_id = req.request_id, "kv request received");
+	conn.kv_stats.record_request(&req.data);
+
+	if !conn.kv_enabled {
+		let error = KvErrorResponse {
+			message: "kv is disabled for this namespace".to_string(),
+			code: Some(KV_DISABLED_CODE.to_string()),
+			retryable: Some(false),
+		};
+		reject_kv_request(conn, req.request_id, "kv_disabled", error).await;
+
+		return Ok(ControlFlow::Continue(()));
+	}
+
+	let actor_id = match Id::parse(&req.actor_id) {
+		Ok(actor_id) => actor_id,
+		Err(err) => {
+			let message = if ctx.config().pegboard().detailed_errors() {
+				err.to_string()
+			} else {
+				"invalid actor id".to_string()
+			};
+			let error = KvErrorResponse {
+				message,
+				code: Some(KV_INVALID_REQUEST_CODE.to_string()),
+				retryable: Some(false),
+			};
+			reject_kv_request(conn, req.request_id, "invalid_actor_id", error).await;
+
+			return Ok(ControlFlow::Continue(()));
+		}
+	};
+
+	let actors_res = ctx
+		.op(pegboard::ops::actor::get_runner::Input {
+			actor_ids: vec![actor_id],
+		})
+		.await?;
+	let actor_belongs = actors_res
+		.actors
+		.first()
+		.map(|x| x.runner_id == runner_id)
+		.unwrap_or_default();
+
+	// Verify actor belongs to this runner
+	if !actor_belongs {
+		let error = KvErrorResponse {
+			message: "given actor does not belong to runner".to_string(),
+			code: None,
+			retryable: Some(false),
+		};
+		reject_kv_request(conn, req.request_id, "actor_not_owned", error).await;
+
+		return Ok(ControlFlow::Continue(()));
+	}
+
+	// Reject disallowed operations, writes to server-managed keys and oversized requests before
+	// touching the database
+	let rejection = check_kv_operation_allowed(conn, &req.data)
+		.map(|message| ("operation_not_allowed", message))
+		.or_else(|| {
+			check_kv_read_only(ctx, conn, req.collection.as_deref(), &req.data)
+				.map(|message| ("read_only", message))
+		})
+		.map(|(reason, message)| {
+			(
+				reason,
+				KvErrorResponse {
+					message,
+					code: None,
+					retryable: Some(false),
+				},
+			)
+		})
+		.or_else(|| {
+			check_kv_list_supported(conn, &req.data).map(|error| ("list_unsupported", error))
+		})
+		.or_else(|| {
+			check_kv_keys_limit(ctx, &req.data).map(|error| ("keys_limit_exceeded", error))
+		})
+		.or_else(|| {
+			check_kv_size_limits(conn, &req.data).map(|error| ("size_limit_exceeded", error))
+		})
+		.or_else(|| {
+			check_kv_overloaded(state, conn).map(|error| ("overloaded", error))
+		})
+		.or_else(|| check_kv_saturated(state, conn).map(|error| ("saturated", error)))
+		.or_else(|| {
+			check_resource_pressure(state, conn).map(|error| ("resource_pressure", error))
+		})
+		.or_else(|| {
+			check_kv_budget(state, &req.data).map(|error| ("budget_exhausted", error))
+		});
+	if let Some((reason, error)) = rejection {
+		reject_kv_request(conn, req.request_id, reason, error).await;
+
+		return Ok(ControlFlow::Continue(()));
+	}
+
+	// Checked last so requests rejected by the checks above do not take up the namespace's slots
+	let Some(_namespace_kv_guard) = state.namespace_kv_limiter.try_acquire(conn.namespace_id) else {
+		let error = KvErrorResponse {
+			message: "namespace has too many kv operations in flight, try again later".to_string(),
+			code: Some(KV_NAMESPACE_BUSY_CODE.to_string()),
+			retryable: Some(true),
+		};
+		reject_kv_request(conn, req.request_id, "namespace_busy", error).await;
+
+		return Ok(ControlFlow::Continue(()));
+	};
+
+	let request_id = req.request_id;
+	let synthetic = conn.synthetic_load.is_enabled();
+
+	// Wait for a slot, shared fairly across namespaces. Load tests bypass the queue so they do not take
+	// up the slots of real runners
+	let _kv_slot = if synthetic {
+		None
+	} else {
+		let acquire = state.kv_scheduler.acquire(conn.namespace_id, conn.priority);
+		let res = rx
+			.run_until_ended(conn.closed.run_until_cancelled(acquire))
+			.await;
+		let Some(Some(kv_slot)) = res else {
+			record_kv_stage("total_us", received);
+			tracing::debug!(
+				?runner_id,
+				?request_id,
+				"connection closed, abandoned queued kv request"
+			);
+
+			return Ok(ControlFlow::Break(()));
+		};
+		let queue_us = kv_slot.wait().as_micros() as u64;
+		conn.kv_queue_wait_us.fetch_add(queue_us, Ordering::Relaxed);
+		tracing::Span::current().record("queue_us", queue_us);
+		tracing::debug!(?runner_id, ?request_id, %queue_us, "kv request dequeued");
+
+		Some(kv_slot)
+	};
+
+	// Load tests should not throttle real runners
+	let _kv_request = (!synthetic).then(|| state.kv_pressure.start_request());
+	let kv_op = kv_operation(&req.data).1;
+	let kv_start = Instant::now();
+
+	// Abandon the operation if the connection closes first, the response can't be delivered
+	let res = rx
+		.run_until_ended(conn.closed.run_until_cancelled(run_kv_request(
+			ctx,
+			state,
+			conn,
+			actor_id,
+			request_id,
+			req.collection.as_deref(),
+			req.data,
+		)))
+		.await;
+	match res.flatten() {
+		Some(res) => {
+			conn.kv_latency_us
+				.fetch_add(kv_start.elapsed().as_micros() as u64, Ordering::Relaxed);
+			conn.kv_requests.fetch_add(1, Ordering::Relaxed);
+
+			if synthetic {
+				metrics::SYNTHETIC_KV_REQUEST_DURATION.record(
+					kv_start.elapsed().as_secs_f64(),
+					&[KeyValue::new("op", kv_op)],
+				);
+			}
+
+			res?;
+
+			let total_us = record_kv_stage("total_us", received);
+			tracing::debug!(?runner_id, ?request_id, %total_us, "kv request completed");
+
+			Ok(ControlFlow::Continue(()))
+		}
+		None => {
+			record_kv_stage("total_us", received);
+			tracing::debug!(?runner_id, ?request_id, "connection closed, abandoned kv request");
+
+			Ok(ControlFlow::Break(()))
+		}
+	}
+}
+
+/// Runs a KV operation and sends the response to the runner.
+async fn run_kv_request(
+	ctx: &StandaloneCtx,
+	state: &SharedState,
+	conn: &Connection,
+	actor_id: Id,
+	request_id: u32,
+	collection: Option<&str>,
+	data: KvRequestData,
+) -> Result<()> {
+	if let Some(data) = conn.synthetic_load.skipped_write_response(&data) {
+		send_kv_response(conn, request_id, data).await?;
+
+		return Ok(());
+	}
+
+	let db_start = Instant::now();
+
+	let stats = Arc::new(kv::TxStats::default());
+	let scope = kv::Scope {
+		prefix: conn.kv_prefix.as_deref(),
+		collection,
+	};
+
+	match data {
+		KvRequestData::KvGetRequest(body) => {
+			let requested_keys = body.keys.clone();
+			let udb = ctx.udb()?;
+			let get_stats = stats.clone();
+			let prefix = conn.kv_prefix.clone();
+			let owned_collection = collection.map(ToString::to_string);
+			let get = move |keys| {
+				async move {
+					let scope = kv::Scope {
+						prefix: prefix.as_deref(),
+						collection: owned_collection.as_deref(),
+					};
+
+					kv::get(&*udb, actor_id, scope, keys, &get_stats)
+						.await
+						.map_err(Arc::new)
+				}
+				.boxed()
+			};
+
+			// Stats of a joined get stay empty, its read is accounted for by the in-flight get
+			let res = if state.hot_keys.should_coalesce(actor_id, scope, &body.keys) {
+				state
+					.hot_keys
+					.coalesce_get(actor_id, scope, body.keys, get)
+					.await
+					.0
+			} else {
+				get(body.keys).await
+			};
+			record_kv_retries(actor_id, request_id, "get", &stats);
+			record_kv_stage("db_us", db_start);
+			record_kv_compression(conn, &stats);
+			state
+				.hot_keys
+				.record(actor_id, scope, &requested_keys, stats.retries());
+
+			let data = match res {
+				Ok((keys, values, metadata)) => {
+					let missing_keys = missing_keys(requested_keys, &keys);
+
+					KvResponseData::KvGetResponse(KvGetResponse {
+						keys,
+						values,
+						metadata,
+						missing_keys: Some(missing_keys),
+					})
+				}
+				Err(err) => {
+					KvResponseData::KvErrorResponse(kv_operation_error(ctx.config(), &err))
+				}
+			};
+			send_kv_read_response(state, conn, request_id, data).await?;
+		}
+		KvRequestData::KvListRequest(body) => {
+			let res = kv::list(
+				&*ctx.udb()?,
+				actor_id,
+				scope,
+				body.query,
+				body.reverse.unwrap_or_default(),
+				body.limit.map(TryInto::try_into).transpose()?,
+				&stats,
+			)
+			.await;
+			record_kv_retries(actor_id, request_id, "list", &stats);
+			record_kv_stage("db_us", db_start);
+			record_kv_compression(conn, &stats);
+
+			let data = match res {
+				Ok((keys, values, metadata)) => KvResponseData::KvListResponse(KvListResponse {
+					keys,
+					values,
+					metadata,
+				}),
+				Err(err) => {
+					KvResponseData::KvErrorResponse(kv_operation_error(ctx.config(), &err))
+				}
+			};
+			send_kv_read_response(state, conn, request_id, data).await?;
+		}
+		KvRequestData::KvPutRequest(body) => {
+			let res = kv::put(
+				&*ctx.udb()?,
+				actor_id,
+				scope,
+				body.keys.clone(),
+				body.values,
+				body.content_types,
+				conn.kv_compression,
+				&stats,
+			)
+			.await;
+			record_kv_retries(actor_id, request_id, "put", &stats);
+			record_kv_stage("db_us", db_start);
+			record_kv_compression(conn, &stats);
+			state
+				.hot_keys
+				.record(actor_id, scope, &body.keys, stats.retries());
+
+			let data = match res {
+				Ok(()) => KvResponseData::KvPutResponse,
+				Err(err) => {
+					KvResponseData::KvErrorResponse(kv_operation_error(ctx.config(), &err))
+				}
+			};
+			send_kv_response(conn, request_id, data).await?;
+		}
+		KvRequestData::KvDeleteRequest(body) => {
+			let res = kv::delete(&*ctx.udb()?, actor_id, scope, body.keys.clone(), &stats).await;
+			record_kv_retries(actor_id, request_id, "delete", &stats);
+			record_kv_stage("db_us", db_start);
+			state
+				.hot_keys
+				.record(actor_id, scope, &body.keys, stats.retries());
+
+			let data = match res {
+				Ok(()) => KvResponseData::KvDeleteResponse,
+				Err(err) => {
+					KvResponseData::KvErrorResponse(kv_operation_error(ctx.config(), &err))
+				}
+			};
+			send_kv_response(conn, request_id, data).await?;
+		}
+		KvRequestData::KvDropRequest => {
+			let res = kv::delete_all(&*ctx.udb()?, actor_id, scope, &stats).await;
+			record_kv_retries(actor_id, request_id, "drop", &stats);
+			record_kv_stage("db_us", db_start);
+
+			let data = match res {
+				Ok(()) => KvResponseData::KvDropResponse,
+				Err(err) => {
+					KvResponseData::KvErrorResponse(kv_operation_error(ctx.config(), &err))
+				}
+			};
+			send_kv_response(conn, request_id, data).await?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Builds the response for a failed KV operation. The underlying error is only sent to the runner
+/// with `Pegboard::detailed_errors` since it can expose engine internals.
+fn kv_operation_error(config: &rivet_config::Config, err: &anyhow::Error) -> KvErrorResponse {
+	let (code, message) = if err.is::<kv::InvalidRequest>() {
+		(KV_INVALID_REQUEST_CODE, "invalid kv request")
+	} else {
+		tracing::warn!(?err, "kv operation failed");
+
+		(KV_INTERNAL_ERROR_CODE, "kv operation failed")
+	};
+
+	let message = if config.pegboard().detailed_errors() {
+		err.to_string()
+	} else {
+		message.to_string()
+	};
+
+	KvErrorResponse {
+		message,
+		code: Some(code.to_string()),
+		retryable: Some(kv::is_retryable(err)),
+	}
+}
+
+/// Sends the response of a KV request to the runner.
+async fn send_kv_response(conn: &Connection, request_id: u32, data: KvResponseData) -> Result<()> {
+	let send_start = Instant::now();
+	conn.send(ToClient::ToClientKvResponse(ToClientKvResponse { request_id, data }))
+		.await?;
+
+	let send_us = record_kv_stage("send_us", send_start);
+	tracing::debug!(?request_id, %send_us, "kv response sent");
+
+	Ok(())
+}
+
+/// Records the duration of a stage of the current KV request on its span, in microseconds.
+fn record_kv_stage(field: &'static str, start: Instant) -> u64 {
+	let elapsed_us = start.elapsed().as_micros() as u64;
+	tracing::Span::current().record(field, elapsed_us);
+
+	elapsed_us
+}
+
+/// Returns the requested keys that were not found, in request order.
+fn missing_keys(requested_keys: Vec<KvKey>, found_keys: &[KvKey]) -> Vec<KvKey> {
+	let found_keys = found_keys.iter().collect::<HashSet<_>>();
+
+	requested_keys
+		.into_iter()
+		.filter(|key| !found_keys.contains(key))
+		.collect()
+}
+
+/// Records UDB transaction retries of a KV operation.
+fn record_kv_retries(actor_id: Id, request_id: u32, op: &'static str, stats: &kv::TxStats) {
+	let retries = stats.retries();
+	if retries == 0 {
+		return;
+	}
+
+	tracing::debug!(?actor_id, ?request_id, %op, %retries, "kv transaction retried");
+	metrics::KV_UDB_RETRIES.add(retries as u64, &[KeyValue::new("op", op)]);
+}
+
+/// Records the realized compression ratio of a KV operation. Only accounted for while KV compression is
+/// enabled for the connection.
+fn record_kv_compression(conn: &Connection, stats: &kv::TxStats) {
+	if conn.kv_compression.is_none() {
+		return;
+	}
+
+	conn.kv_stats.record_compression(stats);
+
+	for (reason, count) in stats.compression_skipped() {
+		if count > 0 {
+			metrics::KV_COMPRESSION_SKIPPED.add(count, &[KeyValue::new("reason", reason.as_str())]);
+		}
+	}
+
+	// Inbound are values written by the runner, outbound are values read by the runner
+	for (direction, (uncompressed, compressed)) in [
+		("inbound", stats.write_compression()),
+		("outbound", stats.read_compression()),
+	] {
+		if uncompressed == 0 {
+			continue;
+		}
+
+		metrics::KV_COMPRESSION_BYTES.add(
+			uncompressed,
+			&[
+				KeyValue::new("direction", direction),
+				KeyValue::new("stage", "uncompressed"),
+			],
+		);
+		metrics::KV_COMPRESSION_BYTES.add(
+			compressed,
+			&[
+				KeyValue::new("direction", direction),
+				KeyValue::new("stage", "compressed"),
+			],
+		);
+		metrics::KV_COMPRESSION_RATIO.record(
+			compressed as f64 / uncompressed as f64,
+			&[KeyValue::new("direction", direction)],
+		);
+	}
+}
+
+fn kv_operation(data: &KvRequestData) -> (KvOperation, &'static str) {
+	match data {
+		KvRequestData::KvGetRequest(_) => (KvOperation::Get, "get"),
+		KvRequestData::KvListRequest(_) => (KvOperation::List, "list"),
+		KvRequestData::KvPutRequest(_) => (KvOperation::Put, "put"),
+		KvRequestData::KvDeleteRequest(_) => (KvOperation::Delete, "delete"),
+		KvRequestData::KvDropRequest => (KvOperation::Drop, "drop"),
+	}
+}
+
+/// Returns an error message if the connection is not allowed to perform the given KV operation.
+fn check_kv_operation_allowed(conn: &Connection, data: &KvRequestData) -> Option<String> {
+	let allowed_kv_operations = conn.allowed_kv_operations.as_ref()?;
+
+	let (operation, name) = kv_operation(data);
+
+	(!allowed_kv_operations.contains(&operation))
+		.then(|| format!("permission denied, kv operation `{name}` is not allowed"))
+}
+
+/// Returns an error if the request is a list and the runner did not declare list support.
+fn check_kv_list_supported(conn: &Connection, data: &KvRequestData) -> Option<KvErrorResponse> {
+	if !matches!(data, KvRequestData::KvListRequest(_)) || conn.kv_capabilities.list {
+		return None;
+	}
+
+	Some(KvErrorResponse {
+		message: "kv list is not supported by this runner".to_string(),
+		code: Some(KV_LIST_UNSUPPORTED_CODE.to_string()),
+		retryable: Some(false),
+	})
+}
+
+/// Strips fields of a KV response the runner cannot parse, see `KvCapabilities`.
+fn tailor_kv_response(capabilities: protocol::KvCapabilities, data: &mut KvResponseData) {
+	match data {
+		KvResponseData::KvGetResponse(res) => {
+			if !capabilities.metadata {
+				res.metadata.clear();
+			}
+			if !capabilities.missing_keys {
+				res.missing_keys = None;
+			}
+		}
+		KvResponseData::KvListResponse(res) => {
+			if !capabilities.metadata {
+				res.metadata.clear();
+			}
+		}
+		_ => {}
+	}
+}
+
+/// Responds to a KV request rejected because of a client error (i.e. an invalid actor id or a
+/// permission error). Failing to send the response means the socket is broken, which is unrelated to
+/// the rejected request, so the connection is closed as broken instead of failing with the request's
+/// error.
+async fn reject_kv_request(
+	conn: &Connection,
+	request_id: u32,
+	reason: &'static str,
+	error: KvErrorResponse,
+) {
+	tracing::Span::current().record("rejected", reason);
+	tracing::debug!(?request_id, %reason, "kv request rejected");
+
+	let res = conn
+		.send(ToClient::ToClientKvResponse(ToClientKvResponse {
+			request_id,
+			data: KvResponseData::KvErrorResponse(error),
+		}))
+		.await;
+
+	metrics::KV_REQUEST_REJECTED.add(
+		1,
+		&[
+			KeyValue::new("reason", reason),
+			KeyValue::new("response_sent", res.is_ok()),
+		],
+	);
+
+	if let Err(err) = res {
+		tracing::warn!(
+			?request_id,
+			%reason,
+			?err,
+			"failed sending kv rejection, closing connection"
+		);
+		conn.close_broken();
+	}
+}
+
+/// Maximum number of keys in a single KV get, put or delete request. Never exceeds the KV store's own
+/// limit.
+fn max_kv_keys_per_request(config: &rivet_config::Config) -> usize {
+	config.pegboard().max_kv_keys_per_request().min(kv::MAX_KEYS)
+}
+
+/// Largest KV key and value allowed for a namespace. Never exceeds the KV store's own limits.
+#[derive(Clone, Copy)]
+struct KvSizeLimits {
+	max_key_size: usize,
+	max_value_size: usize,
+}
+
+impl KvSizeLimits {
+	fn new(config: &rivet_config::Config, namespace: &str) -> Self {
+		KvSizeLimits {
+			max_key_size: config
+				.pegboard()
+				.max_kv_key_size(namespace)
+				.min(kv::MAX_KEY_SIZE),
+			max_value_size: config
+				.pegboard()
+				.max_kv_value_size(namespace)
+				.min(kv::MAX_VALUE_SIZE),
+		}
+	}
+}
+
+fn kv_compression(config: &rivet_config::Config, namespace: &str) -> Option<kv::Compression> {
+	let compression = config.pegboard().kv_compression(namespace)?;
+	let algorithm = match compression.algorithm {
+		KvCompressionAlgorithm::None => return None,
+		KvCompressionAlgorithm::Gzip => kv::CompressionAlgorithm::Gzip,
+		KvCompressionAlgorithm::Zstd => kv::CompressionAlgorithm::Zstd,
+	};
+
+	Some(kv::Compression {
+		algorithm,
+		min_size: compression.min_size(),
+		max_entropy: compression.max_entropy(),
+	})
+}
+
+/// Returns an error if the given KV request has more keys than allowed per request.
+fn check_kv_keys_limit(ctx: &StandaloneCtx, data: &KvRequestData) -> Option<KvErrorResponse> {
+	let keys = match data {
+		KvRequestData::KvGetRequest(body) => body.keys.len(),
+		KvRequestData::KvPutRequest(body) => body.keys.len(),
+		KvRequestData::KvDeleteRequest(body) => body.keys.len(),
+		KvRequestData::KvListRequest(_) | KvRequestData::KvDropRequest => return None,
+	};
+	let max_keys = max_kv_keys_per_request(ctx.config());
+
+	(keys > max_keys).then(|| KvErrorResponse {
+		message: format!("too many keys in kv request ({keys}, max {max_keys})"),
+		code: Some(KV_KEYS_LIMIT_EXCEEDED_CODE.to_string()),
+		retryable: Some(false),
+	})
+}
+
+/// Returns an error if a key or value of the given KV request is larger than allowed for the connection's
+/// namespace.
+fn check_kv_size_limits(conn: &Connection, data: &KvRequestData) -> Option<KvErrorResponse> {
+	let limits = conn.kv_size_limits;
+	let (keys, values): (Vec<&KvKey>, &[KvValue]) = match data {
+		KvRequestData::KvGetRequest(body) => (body.keys.iter().collect(), &[]),
+		KvRequestData::KvPutRequest(body) => (body.keys.iter().collect(), body.values.as_slice()),
+		KvRequestData::KvDeleteRequest(body) => (body.keys.iter().collect(), &[]),
+		KvRequestData::KvListRequest(body) => match &body.query {
+			KvListQuery::KvListAllQuery => (Vec::new(), &[]),
+			KvListQuery::KvListRangeQuery(range) => (vec![&range.start, &range.end], &[]),
+			KvListQuery::KvListPrefixQuery(prefix) => (vec![&prefix.key], &[]),
+		},
+		KvRequestData::KvDropRequest => return None,
+	};
+
+	if let Some(key) = keys.iter().find(|key| key.len() > limits.max_key_size) {
+		return Some(KvErrorResponse {
+			message: format!(
+				"kv key too large ({} bytes, max {} bytes)",
+				key.len(),
+				limits.max_key_size
+			),
+			code: Some(KV_SIZE_LIMIT_EXCEEDED_CODE.to_string()),
+			retryable: Some(false),
+		});
+	}
+
+	let value = values
+		.iter()
+		.find(|value| value.len() > limits.max_value_size)?;
+
+	Some(KvErrorResponse {
+		message: format!(
+			"kv value too large ({} bytes, max {} bytes)",
+			value.len(),
+			limits.max_value_size
+		),
+		code: Some(KV_SIZE_LIMIT_EXCEEDED_CODE.to_string()),
+		retryable: Some(false),
+	})
+}
+
+/// Returns an error if KV is overloaded and the connection is best effort. Other connections are only
+/// asked to slow down, see `kv_pressure::thread`.
+fn check_kv_overloaded(state: &SharedState, conn: &Connection) -> Option<KvErrorResponse> {
+	if conn.priority != protocol::PriorityClass::BestEffort || !state.kv_pressure.is_overloaded() {
+		return None;
+	}
+
+	Some(KvErrorResponse {
+		message: "kv is overloaded, try again later".to_string(),
+		code: Some(KV_OVERLOADED_CODE.to_string()),
+		retryable: Some(true),
+	})
+}
+
+/// Rejects KV reads while the instance-wide KV response budget is exhausted, before touching the
+/// database.
+fn check_kv_budget(state: &SharedState, data: &KvRequestData) -> Option<KvErrorResponse> {
+	let is_read = matches!(data, KvRequestData::KvGetRequest(_) | KvRequestData::KvListRequest(_));
+	(is_read && state.kv_budget.is_exhausted()).then(kv_budget_exhausted_error)
+}
+
+fn kv_budget_exhausted_error() -> KvErrorResponse {
+	KvErrorResponse {
+		message: "kv response budget exhausted, try again later".to_string(),
+		code: Some(KV_BUDGET_EXHAUSTED_CODE.to_string()),
+		retryable: Some(true),
+	}
+}
+
+/// Sends the response of a KV read. The response is accounted against the instance-wide KV response
+/// budget until it is sent, responses that do not fit are dropped and the request is rejected instead.
+async fn send_kv_read_response(
+	state: &SharedState,
+	conn: &Connection,
+	request_id: u32,
+	data: KvResponseData,
+) -> Result<()> {
+	let Some(_reservation) = state.kv_budget.try_reserve(&data) else {
+		drop(data);
+		reject_kv_request(conn, request_id, "budget_exhausted", kv_budget_exhausted_error()).await;
+
+		return Ok(());
+	};
+
+	send_kv_response(conn, request_id, data).await
+}
+
+/// Sheds KV requests of non-system connections while KV is saturated.
+fn check_kv_saturated(state: &SharedState, conn: &Connection) -> Option<KvErrorResponse> {
+	if conn.priority == protocol::PriorityClass::System {
+		return None;
+	}
+
+	let retry_after_ms = state.kv_pressure.shed_retry_after_ms()?;
+
+	Some(KvErrorResponse {
+		message: format!("kv is saturated, retry after {retry_after_ms}ms"),
+		code: Some(KV_SATURATED_CODE.to_string()),
+		retryable: Some(true),
+	})
+}
+
+/// Sheds KV requests of non-system connections while the instance is under CPU or memory pressure, see
+/// `ResourcePressure::shed_kv`. Uses the saturated code so runners back off the same way.
+fn check_resource_pressure(state: &SharedState, conn: &Connection) -> Option<KvErrorResponse> {
+	if conn.priority == protocol::PriorityClass::System {
+		return None;
+	}
+
+	let retry_after_ms = state.resource_pressure.shed_kv_retry_after_ms()?;
+
+	Some(KvErrorResponse {
+		message: format!("server is under resource pressure, retry after {retry_after_ms}ms"),
+		code: Some(KV_SATURATED_CODE.to_string()),
+		retryable: Some(true),
+	})
+}
+
+/// Returns an error message if the given KV request writes to a key prefix that is read-only for the
+/// connection's namespace.
+fn check_kv_read_only(
+	ctx: &StandaloneCtx,
+	conn: &Connection,
+	collection: Option<&str>,
+	data: &KvRequestData,
+) -> Option<String> {
+	// Read-only prefixes only apply to the actor's flat key space
+	if collection.is_some() {
+		return None;
+	}
+
+	let prefixes = ctx
+		.config()
+		.pegboard()
+		.namespace(&conn.namespace_name)
+		.map(|ns| ns.kv_read_only_prefixes())
+		.unwrap_or_default();
+
+	if prefixes.is_empty() {
+		return None;
+	}
+
+	let keys = match data {
+		KvRequestData::KvPutRequest(body) => &body.keys,
+		KvRequestData::KvDeleteRequest(body) => &body.keys,
+		// Dropping would delete read-only keys
+		KvRequestData::KvDropRequest => {
+			return Some("cannot drop kv, namespace has read-only keys".to_string());
+		}
+		KvRequestData::KvGetRequest(_) | KvRequestData::KvListRequest(_) => return None,
+	};
+
+	keys.iter()
+		.find(|key| {
+			prefixes
+				.iter()
+				.any(|prefix| key.starts_with(prefix.as_bytes()))
+		})
+		.map(|_| "permission denied, key is read-only".to_string())
+}
+
+/// Returns the RTT to store for a ping sample, or `None` if the sample is dropped. Samples below the floor
+/// are suspicious (i.e. the runner's clock is ahead) and must not show up as a near zero RTT.
+fn sanitize_rtt(policy: SuspiciousRttPolicy, floor_ms: u64, rtt: i64) -> Option<u32> {
+	let floor_ms = u32::try_from(floor_ms).unwrap_or(u32::MAX);
+
+	match u32::try_from(rtt) {
+		Ok(rtt) if rtt >= floor_ms => Some(rtt),
+		// Too large to be a real RTT either way
+		Err(_) if rtt > 0 => Some(u32::MAX),
+		_ => match policy {
+			SuspiciousRttPolicy::Clamp => Some(floor_ms),
+			SuspiciousRttPolicy::Ignore | SuspiciousRttPolicy::Exclude => None,
+		},
+	}
+}
+
+/// Estimates the runner's clock skew from a ws pong sent in response to the ping after a `ToServerPing`.
+fn handle_pong(runner_id: Id, conn: &Connection, buf: &[u8]) {
+	let Ok(sent_at) = <[u8; 8]>::try_from(buf).map(i64::from_le_bytes) else {
+		tracing::debug!(?runner_id, "unexpected pong payload");
+		return;
+	};
+
+	let rtt = util::timestamp::now().saturating_sub(sent_at);
+	let clock_skew = conn
+		.last_ping_offset
+		.load(Ordering::Relaxed)
+		.saturating_sub(rtt / 2);
+	let prev_clock_skew = conn.clock_skew.swap(clock_skew, Ordering::Relaxed);
+
+	metrics::RUNNER_CLOCK_SKEW.record(clock_skew.unsigned_abs() as f64 / 1000.0, &[]);
+
+	// Only warn once when the threshold is crossed
+	if clock_skew.abs() > CLOCK_SKEW_WARN_THRESHOLD_MS
+		&& prev_clock_skew.abs() <= CLOCK_SKEW_WARN_THRESHOLD_MS
+	{
+		tracing::warn!(?runner_id, ?clock_skew, ?rtt, "runner clock skew exceeds threshold");
+	}
+}
+
 #[tracing::instrument(skip_all)]
-async fn update_ping_thread(ctx: &StandaloneCtx, conns: Arc<RwLock<Connections>>) {
+async fn update_ping_thread(
+	ctx: &StandaloneCtx,
+	conns: Arc<RwLock<Connections>>,
+	state: &SharedState,
+) {
 	loop {
-		match update_ping_thread_inner(ctx, conns.clone()).await {
+		let res = update_ping_thread_inner(
+			ctx,
+			conns.clone(),
+			&state.maintenance,
+			&state.connection_events,
+		)
+		.await;
+		match res {
 			Ok(_) => {
 				tracing::warn!("update ping thread thread exited early");
 			}
@@ -640,6 +2690,8 @@ async fn update_ping_thread(ctx: &StandaloneCtx, conns: Arc<RwLock<Connections>>
 			}
 		}
 
+		state.health.record_restart("update_ping");
+
 		tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 	}
 }
@@ -649,21 +2701,42 @@ async fn update_ping_thread(ctx: &StandaloneCtx, conns: Arc<RwLock<Connections>>
 async fn update_ping_thread_inner(
 	ctx: &StandaloneCtx,
 	conns: Arc<RwLock<Connections>>,
+	maintenance: &Maintenance,
+	connection_events: &ConnectionEvents,
 ) -> Result<()> {
+	let mut was_paused = false;
+
 	loop {
 		tokio::time::sleep(UPDATE_PING_INTERVAL).await;
 
+		// While paused, runners keep their eligibility. Eligible runners are still pinged so they don't
+		// become ineligible, ineligible runners are not pinged so they don't become eligible again.
+		// Pauses are bounded so that those are not lost before updates resume, see
+		// `Maintenance::set_ping_updates_paused`.
+		let paused = maintenance.ping_updates_paused();
+		metrics::PING_UPDATES_PAUSED.record(paused as u64, &[]);
+		if paused != was_paused {
+			tracing::info!(?paused, "ping updates paused state changed");
+			was_paused = paused;
+		}
+
 		let runners = {
-			let mut conns = conns.write().await;
+			let conns = conns.read().await;
 
 			// Select all runners that required a ping update
 			conns
-				.iter_mut()
+				.iter()
 				.map(|(runner_id, conn)| {
+					// Runners that opted out or have an unreliable clock still ping to stay alive, but their
+					// RTT is not written
+					let rtt = (!conn.exclude_rtt && !conn.unreliable_clock.load(Ordering::Relaxed))
+						.then(|| conn.last_rtt.load(Ordering::Relaxed));
+
 					(
 						*runner_id,
 						conn.workflow_id,
-						conn.last_rtt.load(Ordering::Relaxed),
+						rtt,
+						conn.last_load.load(Ordering::Relaxed),
 					)
 				})
 				.collect::<Vec<_>>()
@@ -677,7 +2750,7 @@ async fn update_ping_thread_inner(
 
 		// TODO: Parallelize
 		// Filter out dead wfs
-		for (runner_id, workflow_id, rtt) in runners {
+		for (runner_id, workflow_id, rtt, load) in runners {
 			let Some(wf) = ctx
 				.workflow::<pegboard::workflows::runner::Input>(workflow_id)
 				.get()
@@ -691,8 +2764,18 @@ async fn update_ping_thread_inner(
 			if wf.has_wake_condition {
 				runners2.push(pegboard::ops::runner::update_alloc_idx::Runner {
 					runner_id,
-					action: Action::UpdatePing { rtt },
+					action: Action::UpdatePing {
+						rtt,
+						load,
+						retain_eligibility: paused,
+					},
 				});
+			} else {
+				// Don't hold the lock while closing the socket
+				let conn = conns.read().await.get(&runner_id).cloned();
+				if let Some(conn) = conn {
+					end_workflow_connection(ctx, connection_events, runner_id, &conn).await;
+				}
 			}
 		}
 
@@ -705,6 +2788,13 @@ async fn update_ping_thread_inner(
 			.await?;
 
 		for notif in res.notifications {
+			if let Some(conn) = conns.read().await.get(&notif.runner_id) {
+				conn.eligible.store(
+					matches!(notif.eligibility, RunnerEligibility::ReEligible),
+					Ordering::Relaxed,
+				);
+			}
+
 			if let RunnerEligibility::ReEligible = notif.eligibility {
 				tracing::debug!(runner_id=?notif.runner_id, "runner has become eligible again");
 
@@ -718,9 +2808,18 @@ async fn update_ping_thread_inner(
 }
 
 #[tracing::instrument(skip_all)]
-async fn msg_thread(ctx: &StandaloneCtx, conns: Arc<RwLock<Connections>>) {
+async fn msg_thread(ctx: &StandaloneCtx, conns: Arc<RwLock<Connections>>, state: &SharedState) {
 	loop {
-		match msg_thread_inner(ctx, conns.clone()).await {
+		match msg_thread_inner(
+			ctx,
+			conns.clone(),
+			&state.instance_id,
+			&state.incompatible_messages,
+			&state.connection_events,
+			&state.health,
+		)
+		.await
+		{
 			Ok(_) => {
 				tracing::warn!("msg thread exited early");
 			}
@@ -729,72 +2828,293 @@ async fn msg_thread(ctx: &StandaloneCtx, conns: Arc<RwLock<Connections>>) {
 			}
 		}
 
+		// Failing to subscribe means the pubsub backend is unavailable, which recycling the instance would
+		// not fix. Only degrade health instead of counting towards the restart threshold.
+		if state.health.set_msg_delivery_down() {
+			state.health.record_restart("msg");
+		}
+
 		tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 	}
 }
 
 #[tracing::instrument(skip_all)]
-async fn msg_thread_inner(ctx: &StandaloneCtx, conns: Arc<RwLock<Connections>>) -> Result<()> {
-	// Listen for commands from runner workflows
+async fn msg_thread_inner(
+	ctx: &StandaloneCtx,
+	conns: Arc<RwLock<Connections>>,
+	instance_id: &str,
+	incompatible_messages: &IncompatibleMessages,
+	connection_events: &ConnectionEvents,
+	health: &Health,
+) -> Result<()> {
+	// Listen for commands from runner workflows.
+	//
+	// NOTE: Subscriptions cannot be filtered by the set of runners held by this instance. The pubsub subject
+	// only consists of the message name and tags are matched after the message is received and
+	// deserialized, so every instance receives every message. See `MSG_THREAD_MESSAGES` for how many are
+	// ignored.
 	let mut sub = ctx
 		.subscribe::<pegboard::workflows::runner::ToWs>(&json!({}))
 		.await?;
 	let mut close_sub = ctx
 		.subscribe::<pegboard::workflows::runner::CloseWs>(&json!({}))
 		.await?;
+	let mut query_sub = ctx
+		.subscribe::<pegboard::ops::runner::get_connection::ConnectionQuery>(&json!({}))
+		.await?;
+	let mut packet_capture_sub = ctx
+		.subscribe::<pegboard::ops::runner::get_packet_capture::PacketCaptureQuery>(&json!({}))
+		.await?;
+	let mut workflow_connections_sub = ctx
+		.subscribe::<pegboard::ops::runner::list_workflow_connections::WorkflowConnectionsQuery>(
+			&json!({}),
+		)
+		.await?;
+
+	health.set_msg_delivery_up();
 
 	loop {
 		tokio::select! {
 			msg = sub.next() => {
-				let msg = msg?.into_body();
-
-				{
-					let conns = conns.read().await;
+				let msg = msg?;
+				let msg_ts = msg.msg_ts();
+				health.record_msg_lag("to_ws", msg_ts);
+				let msg = msg.into_body();
+
+				// Don't hold the lock while sending, a slow socket would block all connection inserts and
+				// removals
+				let conn = conns.read().await.get(&msg.runner_id).cloned();
+				record_msg_thread_message("to_ws", conn.is_some());
+
+				// Send command to socket
+				if let Some(conn) = conn {
+					let message_type = incompatible_messages::message_type(&msg.inner);
+					let policy = incompatible_messages::policy(&msg.inner);
+					// Messages added after the runner's protocol version (i.e. `ShutdownAck` for v1
+					// runners) have no equivalent it can parse
+					let res = ToClient::try_from(msg.inner).and_then(|message| {
+						let protocol_version = conn.protocol_version;
+						if versioned::ToClient::is_representable(&message, protocol_version) {
+							Ok(message)
+						} else {
+							Err(anyhow!("message does not exist in protocol version"))
+						}
+					});
+					let mut message = match res {
+						Ok(message) => message,
+						// Only affects this runner, should not tear down the thread
+						Err(err) => {
+							tracing::warn!(
+								runner_id=?msg.runner_id,
+								protocol_version=?conn.protocol_version,
+								downgraded_from=?conn.downgraded_from,
+								%message_type,
+								?policy,
+								?err,
+								"message not representable in protocol version"
+							);
+							incompatible_messages.record(
+								msg.runner_id,
+								conn.protocol_version,
+								message_type,
+							);
+
+							if policy == IncompatiblePolicy::Reject {
+								reject_incompatible(&conn, message_type).await;
+							}
+
+							continue;
+						}
+					};
+
+					// Attach the instance affinity hint determined during the handshake and the limits
+					// enforced by the ws
+					if let ToClient::ToClientInit(init) = &mut message {
+						init.preferred_instance = conn.preferred_instance.clone();
+						init.max_kv_keys_per_request =
+							Some(max_kv_keys_per_request(ctx.config()) as u32);
+						init.max_kv_key_size = Some(conn.kv_size_limits.max_key_size as u32);
+						init.max_kv_value_size = Some(conn.kv_size_limits.max_value_size as u32);
+					}
 
-					// Send command to socket
-					if let Some(conn) = conns.get(&msg.runner_id) {
-						let buf = versioned::ToClient::serialize(
-							protocol::ToClient::from(msg.inner).try_into()?,
-							conn.protocol_version
-						)?;
-						conn.tx.lock().await.send(Message::Binary(buf.into())).await?;
+					// A broken connection should not tear down the thread for all other connections
+					if let Err(err) = conn.send(message).await {
+						tracing::warn!(
+							runner_id=?msg.runner_id,
+							?err,
+							"failed sending to runner, closing connection"
+						);
+						conn.close_broken();
 					} else {
+						record_command_delivery(message_type, msg_ts);
+					}
+				} else {
+					tracing::debug!(
+						runner_id=?msg.runner_id,
+						"received command for runner that isn't connected, ignoring"
+					);
+				}
+			}
+			msg = close_sub.next() => {
+				let msg = msg?;
+				health.record_msg_lag("close_ws", msg.msg_ts());
+
+				let conn = conns.read().await.get(&msg.runner_id).cloned();
+				record_msg_thread_message("close_ws", conn.is_some());
+
+				// Close socket
+				if let Some(conn) = conn {
+					tracing::info!(
+						runner_id = ?msg.runner_id,
+						reason = ?msg.reason,
+						"received close ws event, closing socket"
+					);
+
+					let _ = conn.disconnect_reason.set(DisconnectReason::Evicted);
+					conn.closed.cancel();
+
+					connection_events.publish(
+						ctx,
+						msg.runner_id,
+						conn.namespace_id,
+						RunnerConnectionEventKind::Evict,
+						Some(eviction_reason_str(msg.reason).to_string()),
+					);
+
+					let close_frame = err_to_close_frame(eviction_error(msg.reason));
+					let mut tx = conn.tx.lock().await;
+					if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
 						tracing::debug!(
 							runner_id=?msg.runner_id,
-							"received command for runner that isn't connected, ignoring"
+							?err,
+							"failed closing evicted socket"
 						);
 					}
+				} else {
+					tracing::debug!(
+						runner_id=?msg.runner_id,
+						"received close command for runner that isn't connected, ignoring"
+					);
 				}
 			}
-			msg = close_sub.next() => {
+			msg = query_sub.next() => {
 				let msg = msg?;
+				health.record_msg_lag("connection_query", msg.msg_ts());
 
-				{
+				let rtt = {
 					let conns = conns.read().await;
+					conns.get(&msg.runner_id).map(|conn| conn.last_rtt.load(Ordering::Relaxed))
+				};
+				record_msg_thread_message("connection_query", rtt.is_some());
 
-					// Close socket
-					if let Some(conn) = conns.get(&msg.runner_id) {
-						tracing::info!(runner_id = ?msg.runner_id, "received close ws event, closing socket");
+				// Only the instance holding the connection responds
+				if let Some(rtt) = rtt {
+					ctx.msg(pegboard::ops::runner::get_connection::ConnectionQueryResponse {
+						runner_id: msg.runner_id,
+						rtt,
+					})
+					.tag("request_id", msg.request_id)
+					.send()
+					.await?;
+				}
+			}
+			msg = packet_capture_sub.next() => {
+				let msg = msg?;
+				health.record_msg_lag("packet_capture_query", msg.msg_ts());
 
-						let close_frame = err_to_close_frame(WsError::Eviction.build());
-						conn.tx.lock().await.send(Message::Close(Some(close_frame))).await?;
-					} else {
-						tracing::debug!(
-							runner_id=?msg.runner_id,
-							"received close command for runner that isn't connected, ignoring"
-						);
-					}
+				let packets = {
+					let conns = conns.read().await;
+					conns
+						.get(&msg.runner_id)
+						.and_then(|conn| conn.packet_capture.as_ref())
+						.map(|packet_capture| packet_capture.dump())
+				};
+				record_msg_thread_message("packet_capture_query", packets.is_some());
+
+				// Only the instance holding the connection responds
+				if let Some(packets) = packets {
+					ctx.msg(pegboard::ops::runner::get_packet_capture::PacketCaptureQueryResponse {
+						packets,
+					})
+					.tag("request_id", msg.request_id)
+					.send()
+					.await?;
+				}
+			}
+			msg = workflow_connections_sub.next() => {
+				let msg = msg?;
+				health.record_msg_lag("workflow_connections_query", msg.msg_ts());
+
+				let runner_ids = {
+					let conns = conns.read().await;
+					conns
+						.iter()
+						.filter(|(_, conn)| conn.workflow_id == msg.workflow_id)
+						.map(|(runner_id, _)| *runner_id)
+						.collect::<Vec<_>>()
+				};
+				record_msg_thread_message("workflow_connections_query", !runner_ids.is_empty());
+
+				// Only instances holding a connection for the workflow respond
+				if !runner_ids.is_empty() {
+					ctx.msg(
+						pegboard::ops::runner::list_workflow_connections::WorkflowConnectionsQueryResponse {
+							instance_id: instance_id.to_string(),
+							runner_ids,
+						},
+					)
+					.tag("request_id", msg.request_id)
+					.send()
+					.await?;
 				}
 			}
 		}
 	}
 }
 
+/// Closes a connection that could not be sent a message the runner cannot recover without, see
+/// `incompatible_messages::policy`.
+async fn reject_incompatible(conn: &Connection, message_type: &'static str) {
+	let _ = conn.disconnect_reason.set(DisconnectReason::Error);
+	conn.closed.cancel();
+
+	let close_frame = err_to_close_frame(WsError::MessageNotRepresentable(message_type).build());
+	let mut tx = conn.tx.lock().await;
+	if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+		tracing::debug!(?err, "failed closing incompatible socket");
+	}
+}
+
+/// Counts a message received by the msg thread. Not handled if this instance does not hold the runner's
+/// connection.
+fn record_msg_thread_message(message: &'static str, handled: bool) {
+	metrics::MSG_THREAD_MESSAGES.add(
+		1,
+		&[
+			KeyValue::new("message", message),
+			KeyValue::new("handled", handled.to_string()),
+		],
+	);
+}
+
+/// Records the time from the runner workflow publishing a `ToWs` message to it being written to the
+/// runner's socket. Unlike `Health::record_msg_lag`, this includes the time spent looking up the
+/// connection and waiting for its socket lock.
+fn record_command_delivery(message_type: &'static str, msg_ts: i64) {
+	let delivery_ms = util::timestamp::now().saturating_sub(msg_ts).max(0);
+	metrics::COMMAND_DELIVERY_DURATION.record(
+		delivery_ms as f64 / 1000.0,
+		&[KeyValue::new("message_type", message_type)],
+	);
+}
+
 #[derive(Clone)]
 struct UrlData {
 	protocol_version: u16,
 	namespace: String,
 	runner_key: String,
+	init_compression: Option<InitCompression>,
+	kv_prefix: Option<String>,
 }
 
 fn parse_url(addr: SocketAddr, uri: hyper::Uri) -> Result<UrlData> {
@@ -822,13 +3142,105 @@ fn parse_url(addr: SocketAddr, uri: hyper::Uri) -> Result<UrlData> {
 		.context("missing `runner_key` query parameter")?
 		.to_string();
 
+	// Read init packet compression from query parameters (optional)
+	let init_compression = url
+		.query_pairs()
+		.find_map(|(n, v)| (n == "init_compression").then_some(v))
+		.map(|v| InitCompression::parse(&v))
+		.transpose()
+		.context("invalid `init_compression` query parameter")?;
+
+	// Read connection-scoped KV prefix from query parameters (optional)
+	let kv_prefix = url
+		.query_pairs()
+		.find_map(|(n, v)| (n == "kv_prefix").then_some(v))
+		.map(|v| {
+			kv::validate_prefix(&v)
+				.map(|_| v.to_string())
+				.context("invalid `kv_prefix` query parameter")
+		})
+		.transpose()?;
+
 	Ok(UrlData {
 		protocol_version,
 		namespace,
 		runner_key,
+		init_compression,
+		kv_prefix,
 	})
 }
 
+/// Tells the runner that its workflow ended and closes the connection, so the runner can stop its actors
+/// gracefully instead of treating the close as an error.
+async fn end_workflow_connection(
+	ctx: &StandaloneCtx,
+	connection_events: &ConnectionEvents,
+	runner_id: Id,
+	conn: &Connection,
+) {
+	// The connection is already closing
+	if conn.disconnect_reason.set(DisconnectReason::Evicted).is_err() {
+		return;
+	}
+
+	tracing::info!(
+		?runner_id,
+		workflow_id=?conn.workflow_id,
+		"runner workflow ended, closing socket"
+	);
+	metrics::WORKFLOW_ENDED_CLOSES.add(1, &[]);
+
+	if let Err(err) = conn.send(ToClient::ToClientWorkflowEnding).await {
+		tracing::debug!(?runner_id, ?err, "failed sending workflow ending");
+	}
+	conn.closed.cancel();
+
+	connection_events.publish(
+		ctx,
+		runner_id,
+		conn.namespace_id,
+		RunnerConnectionEventKind::Evict,
+		Some(eviction_reason_str(EvictionReason::Completed).to_string()),
+	);
+
+	let close_frame = err_to_close_frame(eviction_error(EvictionReason::Completed));
+	let mut tx = conn.tx.lock().await;
+	if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+		tracing::debug!(?runner_id, ?err, "failed closing socket of ended workflow");
+	}
+}
+
+fn eviction_error(reason: EvictionReason) -> anyhow::Error {
+	match reason {
+		EvictionReason::Completed => WsError::Eviction.build(),
+		EvictionReason::Decommission => WsError::EvictionDecommission.build(),
+		EvictionReason::Rebalance => WsError::EvictionRebalance.build(),
+		EvictionReason::PolicyViolation => WsError::EvictionPolicyViolation.build(),
+		EvictionReason::Manual => WsError::EvictionManual.build(),
+	}
+}
+
+fn eviction_reason_str(reason: EvictionReason) -> &'static str {
+	match reason {
+		EvictionReason::Completed => "completed",
+		EvictionReason::Decommission => "decommission",
+		EvictionReason::Rebalance => "rebalance",
+		EvictionReason::PolicyViolation => "policy_violation",
+		EvictionReason::Manual => "manual",
+	}
+}
+
+/// Returns the `group.code` of the error, `core.internal_error` if it is not a `RivetError`.
+fn err_code(err: &anyhow::Error) -> String {
+	let rivet_err = err
+		.chain()
+		.find_map(|x| x.downcast_ref::<RivetError>())
+		.cloned()
+		.unwrap_or_else(|| RivetError::from(&INTERNAL_ERROR));
+
+	format!("{}.{}", rivet_err.group(), rivet_err.code())
+}
+
 fn err_to_close_frame(err: anyhow::Error) -> CloseFrame {
 	let rivet_err = err
 		.chain()
@@ -838,16 +3250,647 @@ fn err_to_close_frame(err: anyhow::Error) -> CloseFrame {
 
 	let code = match (rivet_err.group(), rivet_err.code()) {
 		("ws", "connection_closed") => CloseCode::Normal,
+		("ws", "timed_out_waiting_for_init") => CloseCode::Library(INIT_TIMEOUT_CLOSE_CODE),
+		// Hint to reconnect elsewhere
+		("ws", "eviction_decommission" | "eviction_rebalance" | "namespace_draining") => {
+			CloseCode::Restart
+		}
+		// Hint to not reconnect
+		("ws", "eviction_policy_violation") => CloseCode::Policy,
 		_ => CloseCode::Error,
 	};
 
+	let mut reason = format!("{}.{}", rivet_err.group(), rivet_err.code());
+
+	// Include retry hint so runners know when to reconnect
+	if let Some(retry_after_ms) = rivet_err
+		.metadata()
+		.and_then(|meta| meta.get("retry_after_ms")?.as_u64())
+	{
+		reason.push_str(&format!(";retry_after_ms={retry_after_ms}"));
+	}
+
 	// NOTE: reason cannot be more than 123 bytes as per the WS protocol
-	let reason = util::safe_slice(
-		&format!("{}.{}", rivet_err.group(), rivet_err.code()),
-		0,
-		123,
-	)
-	.into();
+	let reason = util::safe_slice(&reason, 0, 123).into();
 
 	CloseFrame { code, reason }
 }
+
+/// Same as `err_to_close_frame` but appends the error message to the reason with
+/// `Pegboard::detailed_errors`, e.g. `core.internal_error;message=...`. Used where the error code alone
+/// does not describe the failure.
+fn err_to_detailed_close_frame(config: &rivet_config::Config, err: anyhow::Error) -> CloseFrame {
+	let message = config.pegboard().detailed_errors().then(|| err.to_string());
+	let mut close_frame = err_to_close_frame(err);
+
+	if let Some(message) = message {
+		let reason = format!("{};message={message}", close_frame.reason.as_str());
+
+		// NOTE: reason cannot be more than 123 bytes as per the WS protocol
+		close_frame.reason = util::safe_slice(&reason, 0, 123).into();
+	}
+
+	close_frame
+}
+
+/// How a runner ended its connection. Each is reported with a normal close code, the annotation lets
+/// logs and metrics tell a graceful close from an abrupt one.
+#[derive(Debug, Clone, Copy)]
+enum StreamEnd {
+	/// Runner sent a Close frame.
+	ClientClose,
+	/// Socket ended without a Close frame (i.e. the runner process died or the proxy dropped it).
+	StreamEnded,
+	/// Socket failed to read, with the disconnect reason derived from the error.
+	ReadError(DisconnectReason),
+}
+
+impl StreamEnd {
+	fn as_str(&self) -> &'static str {
+		match self {
+			StreamEnd::ClientClose => "client_close",
+			StreamEnd::StreamEnded => "stream_ended",
+			StreamEnd::ReadError(_) => "read_error",
+		}
+	}
+
+	fn disconnect_reason(&self) -> DisconnectReason {
+		match self {
+			StreamEnd::ClientClose | StreamEnd::StreamEnded => DisconnectReason::Normal,
+			StreamEnd::ReadError(reason) => *reason,
+		}
+	}
+}
+
+/// Reads the next message from the socket, or how the runner ended the connection.
+async fn next_message(
+	rx: &mut (impl Stream<Item = Result<Message, tungstenite::Error>> + Unpin),
+) -> Result<Message, StreamEnd> {
+	match rx.next().await {
+		Some(Ok(Message::Close(_))) => Err(StreamEnd::ClientClose),
+		Some(Ok(msg)) => Ok(msg),
+		None
+		| Some(Err(tungstenite::Error::Protocol(
+			ProtocolError::ResetWithoutClosingHandshake,
+		))) => Err(StreamEnd::StreamEnded),
+		Some(Err(err)) => {
+			let err = anyhow::Error::from(err);
+			tracing::debug!(?err, "failed reading from socket");
+
+			Err(StreamEnd::ReadError(err_to_disconnect_reason(&err)))
+		}
+	}
+}
+
+/// Read half of the runner's socket. Reads ahead while a packet is processed to notice the runner
+/// ending the stream, see `run_until_ended`.
+struct RunnerRx<'a> {
+	rx: &'a mut SplitStream<WebSocketStream<TcpStream>>,
+	/// Read while processing the previous packet, returned by the next `next` call.
+	read_ahead: Option<Result<Message, StreamEnd>>,
+}
+
+impl<'a> RunnerRx<'a> {
+	fn new(rx: &'a mut SplitStream<WebSocketStream<TcpStream>>) -> Self {
+		RunnerRx {
+			rx,
+			read_ahead: None,
+		}
+	}
+
+	async fn next(&mut self) -> Result<Message, StreamEnd> {
+		match self.read_ahead.take() {
+			Some(res) => res,
+			None => next_message(self.rx).await,
+		}
+	}
+
+	/// Runs `fut` unless the runner ends the stream first. Reads at most one message ahead (ws pings
+	/// aside), which is kept for `next` so packets are still processed in order.
+	async fn run_until_ended<F: Future>(&mut self, fut: F) -> Option<F::Output> {
+		tokio::pin!(fut);
+
+		while self.read_ahead.is_none() {
+			tokio::select! {
+				output = &mut fut => return Some(output),
+				res = next_message(self.rx) => {
+					// Ignored by `handle_messages` as well
+					if !matches!(res, Ok(Message::Ping(_))) {
+						self.read_ahead = Some(res);
+					}
+				}
+			}
+		}
+
+		if let Some(Err(_)) = self.read_ahead {
+			return None;
+		}
+
+		Some(fut.await)
+	}
+}
+
+/// Normal close frame annotated with how the runner ended the connection, e.g.
+/// `ws.connection_closed;end=stream_ended`.
+fn stream_end_close_frame(end: StreamEnd) -> CloseFrame {
+	let mut close_frame = err_to_close_frame(WsError::ConnectionClosed.build());
+	close_frame.reason = format!("{};end={}", close_frame.reason.as_str(), end.as_str()).into();
+
+	close_frame
+}
+
+/// Rejects slot counts the alloc idx cannot represent. Zero is valid and connects a standby runner
+/// that is never allocated actors, see `pegboard::workflows::runner::MAX_TOTAL_SLOTS`.
+fn validate_total_slots(total_slots: u32) -> Result<()> {
+	let max = pegboard::workflows::runner::MAX_TOTAL_SLOTS;
+	if total_slots > max {
+		return Err(WsError::InvalidTotalSlots { total_slots, max }.build());
+	}
+
+	Ok(())
+}
+
+/// Returns the runner requested in the init packet if it is owned by the connecting runner (same
+/// namespace, name and key) and still live. Ownership is checked strictly so a runner cannot take over
+/// another runner's id.
+async fn get_requested_runner(
+	ctx: &StandaloneCtx,
+	namespace_id: Id,
+	name: &str,
+	runner_key: &str,
+	requested_runner_id: Id,
+) -> Result<Option<rivet_types::runners::Runner>> {
+	let runner = ctx
+		.op(pegboard::ops::runner::get::Input {
+			runner_ids: vec![requested_runner_id],
+		})
+		.await?
+		.runners
+		.into_iter()
+		.next();
+
+	let res = requested_runner_rejection(runner.as_ref(), namespace_id, name, runner_key);
+	let rejection = match res {
+		Ok(rejection) => rejection,
+		Err(err) => {
+			tracing::warn!(
+				?requested_runner_id,
+				?namespace_id,
+				"requested runner belongs to a different namespace"
+			);
+			metrics::REQUESTED_RUNNER_ID.add(1, &[KeyValue::new("result", "namespace_mismatch")]);
+
+			return Err(err);
+		}
+	};
+
+	if let Some(rejection) = rejection {
+		tracing::debug!(
+			?requested_runner_id,
+			%rejection,
+			"ignoring requested runner id, falling back to lookup by key"
+		);
+	}
+	metrics::REQUESTED_RUNNER_ID.add(1, &[KeyValue::new("result", rejection.unwrap_or("rebound"))]);
+
+	Ok(runner.filter(|_| rejection.is_none()))
+}
+
+/// Returns why the requested runner cannot be rebound, `None` if it can. Fails if the runner belongs to
+/// another namespace, rebinding it would cross tenants.
+fn requested_runner_rejection(
+	runner: Option<&rivet_types::runners::Runner>,
+	namespace_id: Id,
+	name: &str,
+	runner_key: &str,
+) -> Result<Option<&'static str>> {
+	let Some(runner) = runner else {
+		return Ok(Some("not_found"));
+	};
+
+	ensure_runner_namespace(runner, namespace_id)?;
+
+	if runner.name != name || runner.key != runner_key {
+		Ok(Some("not_owned"))
+	} else if runner.drain_ts.is_some() || runner.stop_ts.is_some() {
+		Ok(Some("not_live"))
+	} else {
+		Ok(None)
+	}
+}
+
+/// Fails if the runner does not belong to the namespace the connection resolved to.
+fn ensure_runner_namespace(runner: &rivet_types::runners::Runner, namespace_id: Id) -> Result<()> {
+	if runner.namespace_id != namespace_id {
+		return Err(WsError::RunnerNamespaceMismatch.build());
+	}
+
+	Ok(())
+}
+
+fn err_to_disconnect_reason(err: &anyhow::Error) -> DisconnectReason {
+	let rivet_err = err.chain().find_map(|x| x.downcast_ref::<RivetError>());
+
+	match rivet_err.map(|err| (err.group(), err.code())) {
+		Some(("ws", "connection_closed")) => DisconnectReason::Normal,
+		Some(("ws", "new_runner_connected")) => DisconnectReason::Replaced,
+		Some((
+			"ws",
+			"eviction"
+			| "eviction_decommission"
+			| "eviction_rebalance"
+			| "eviction_policy_violation"
+			| "eviction_manual",
+		)) => DisconnectReason::Evicted,
+		_ if err.chain().any(|x| {
+			x.downcast_ref::<std::io::Error>()
+				.is_some_and(|err| err.kind() == std::io::ErrorKind::TimedOut)
+		}) =>
+		{
+			DisconnectReason::Timeout
+		}
+		_ => DisconnectReason::Error,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn slow_old_connection_does_not_block_registration() {
+		let conns = RwLock::new(Connections::new());
+		let runner_id = Id::new_v1(1);
+		let policy = DuplicateConnectionPolicy::LastWriterWins;
+
+		let old_conn = test_connection().await;
+		assert!(register_connection(&conns, runner_id, &old_conn, policy).await);
+
+		// Simulates a send stuck on the old socket
+		let stuck_tx = old_conn.tx.lock().await;
+
+		let new_conn = test_connection().await;
+		tokio::time::timeout(
+			Duration::from_secs(1),
+			register_connection(&conns, runner_id, &new_conn, policy),
+		)
+		.await
+		.expect("registration blocked by old connection");
+
+		// Other runners can still register while the old connection is closing
+		let other_conn = test_connection().await;
+		tokio::time::timeout(
+			Duration::from_secs(1),
+			register_connection(&conns, Id::new_v1(1), &other_conn, policy),
+		)
+		.await
+		.expect("registration blocked by old connection");
+
+		assert!(old_conn.closed.is_cancelled());
+		assert!(Arc::ptr_eq(&conns.read().await[&runner_id], &new_conn));
+
+		drop(stuck_tx);
+	}
+
+	#[test]
+	fn reports_missing_keys_in_request_order() {
+		let requested = vec![b"c".to_vec(), b"a".to_vec(), b"b".to_vec(), b"d".to_vec()];
+		let found = vec![b"a".to_vec(), b"b".to_vec()];
+
+		assert_eq!(
+			missing_keys(requested, &found),
+			vec![b"c".to_vec(), b"d".to_vec()]
+		);
+	}
+
+	#[test]
+	fn strips_kv_fields_unsupported_by_runner() {
+		let response = || {
+			KvResponseData::KvGetResponse(KvGetResponse {
+				keys: vec![b"a".to_vec()],
+				values: vec![b"1".to_vec()],
+				metadata: vec![KvMetadata {
+					version: Vec::new(),
+					create_ts: 0,
+					content_type: None,
+				}],
+				missing_keys: Some(vec![b"b".to_vec()]),
+			})
+		};
+
+		let mut data = response();
+		tailor_kv_response(protocol::KvCapabilities::default(), &mut data);
+		let KvResponseData::KvGetResponse(res) = data else {
+			unreachable!();
+		};
+		assert_eq!(res.metadata.len(), 1);
+		assert!(res.missing_keys.is_some());
+
+		let mut data = response();
+		let capabilities = protocol::KvCapabilities {
+			list: true,
+			metadata: false,
+			missing_keys: false,
+		};
+		tailor_kv_response(capabilities, &mut data);
+		let KvResponseData::KvGetResponse(res) = data else {
+			unreachable!();
+		};
+		assert_eq!(res.values.len(), 1);
+		assert!(res.metadata.is_empty());
+		assert!(res.missing_keys.is_none());
+	}
+
+	#[test]
+	fn init_timeout_has_distinct_close_code() {
+		let frame = err_to_close_frame(WsError::TimedOutWaitingForInit { retry_after_ms: 0 }.build());
+		assert_eq!(frame.code, CloseCode::Library(INIT_TIMEOUT_CLOSE_CODE));
+		assert_eq!(
+			frame.reason.as_str(),
+			"ws.timed_out_waiting_for_init;retry_after_ms=0"
+		);
+
+		let frame = err_to_close_frame(WsError::RateLimited.build());
+		assert_eq!(frame.code, CloseCode::Error);
+	}
+
+	#[test]
+	fn eviction_reasons_have_distinct_close_codes() {
+		let frame = err_to_close_frame(eviction_error(EvictionReason::Completed));
+		assert_eq!(frame.code, CloseCode::Error);
+		assert_eq!(frame.reason.as_str(), "ws.eviction");
+
+		let frame = err_to_close_frame(eviction_error(EvictionReason::Rebalance));
+		assert_eq!(frame.code, CloseCode::Restart);
+		assert_eq!(frame.reason.as_str(), "ws.eviction_rebalance");
+
+		let frame = err_to_close_frame(eviction_error(EvictionReason::PolicyViolation));
+		assert_eq!(frame.code, CloseCode::Policy);
+	}
+
+	#[test]
+	fn kv_size_limits_resolve_per_namespace() {
+		let mut root = rivet_config::config::Root::default();
+		root.pegboard = Some(rivet_config::config::Pegboard {
+			max_kv_value_size: Some(1024),
+			namespaces: Some(HashMap::from([(
+				"premium".to_string(),
+				rivet_config::config::pegboard::PegboardNamespace {
+					max_kv_value_size: Some(usize::MAX),
+					..Default::default()
+				},
+			)])),
+			..Default::default()
+		});
+		let config = rivet_config::Config::from_root(root);
+
+		let limits = KvSizeLimits::new(&config, "default");
+		assert_eq!(limits.max_key_size, kv::MAX_KEY_SIZE);
+		assert_eq!(limits.max_value_size, 1024);
+
+		// Overrides can raise the limit, but never above the KV store's own limit
+		let limits = KvSizeLimits::new(&config, "premium");
+		assert_eq!(limits.max_value_size, kv::MAX_VALUE_SIZE);
+	}
+
+	/// Builds a connection over a loopback socket.
+	async fn test_connection() -> Arc<Connection> {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let (_client, (stream, _)) = tokio::try_join!(
+			TcpStream::connect(listener.local_addr().unwrap()),
+			listener.accept(),
+		)
+		.unwrap();
+		let (tx, _rx) = WebSocketStream::from_raw_socket(
+			stream,
+			tokio_tungstenite::tungstenite::protocol::Role::Server,
+			None,
+		)
+		.await
+		.split();
+
+		Arc::new(Connection {
+			workflow_id: Id::new_v1(1),
+			namespace_id: Id::new_v1(1),
+			namespace_name: "default".to_string(),
+			runner_key: "test".to_string(),
+			preferred_instance: None,
+			protocol_version: PROTOCOL_VERSION,
+			downgraded_from: None,
+			tx: Mutex::new(tx),
+			last_rtt: AtomicU32::new(0),
+			last_load: AtomicU32::new(0),
+			last_ping_offset: AtomicI64::new(0),
+			clock_skew: AtomicI64::new(0),
+			last_seq: AtomicU64::new(0),
+			last_acked_seq: AtomicU64::new(0),
+			closed: CancellationToken::new(),
+			packet_capture: None,
+			kv_throttled: AtomicBool::new(false),
+			disconnect_reason: OnceLock::new(),
+			metrics_snapshots: false,
+			kv_prefix: None,
+			kv_enabled: true,
+			kv_compression: None,
+			kv_size_limits: KvSizeLimits {
+				max_key_size: kv::MAX_KEY_SIZE,
+				max_value_size: kv::MAX_VALUE_SIZE,
+			},
+			priority: protocol::PriorityClass::Normal,
+			allowed_kv_operations: None,
+			kv_capabilities: protocol::KvCapabilities::default(),
+			exclude_rtt: false,
+			unreliable_clock: AtomicBool::new(false),
+			eligible: AtomicBool::new(true),
+			kv_latency_us: AtomicU64::new(0),
+			kv_requests: AtomicU64::new(0),
+			kv_queue_wait_us: AtomicU64::new(0),
+			synthetic_load: SyntheticLoad::default(),
+			kv_stats: KvStats::default(),
+		})
+	}
+
+	/// Returns a raw client socket and the server side of the connection.
+	async fn socket_pair() -> (TcpStream, WebSocketStream<TcpStream>) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let (client, (stream, _)) = tokio::try_join!(
+			TcpStream::connect(listener.local_addr().unwrap()),
+			listener.accept(),
+		)
+		.unwrap();
+		let server =
+			WebSocketStream::from_raw_socket(stream, tungstenite::protocol::Role::Server, None)
+				.await;
+
+		(client, server)
+	}
+
+	#[tokio::test]
+	async fn client_close_frame_ends_stream() {
+		let (client, mut server) = socket_pair().await;
+		let mut client =
+			WebSocketStream::from_raw_socket(client, tungstenite::protocol::Role::Client, None)
+				.await;
+
+		client.send(Message::Close(None)).await.unwrap();
+
+		let end = next_message(&mut server).await.unwrap_err();
+		assert!(matches!(end, StreamEnd::ClientClose));
+		assert_eq!(end.disconnect_reason(), DisconnectReason::Normal);
+
+		let close_frame = stream_end_close_frame(end);
+		assert_eq!(close_frame.code, CloseCode::Normal);
+		assert_eq!(close_frame.reason.as_str(), "ws.connection_closed;end=client_close");
+	}
+
+	#[tokio::test]
+	async fn dropped_socket_ends_stream() {
+		let (client, mut server) = socket_pair().await;
+
+		drop(client);
+
+		let end = next_message(&mut server).await.unwrap_err();
+		assert!(matches!(end, StreamEnd::StreamEnded));
+		assert_eq!(end.disconnect_reason(), DisconnectReason::Normal);
+
+		let close_frame = stream_end_close_frame(end);
+		assert_eq!(close_frame.code, CloseCode::Normal);
+		assert_eq!(close_frame.reason.as_str(), "ws.connection_closed;end=stream_ended");
+	}
+
+	#[tokio::test]
+	async fn invalid_frame_ends_stream_with_read_error() {
+		let (mut client, mut server) = socket_pair().await;
+
+		// Unmasked binary frame, clients must mask all frames
+		tokio::io::AsyncWriteExt::write_all(&mut client, &[0x82, 0x01, 0x00])
+			.await
+			.unwrap();
+
+		let end = next_message(&mut server).await.unwrap_err();
+		assert!(matches!(end, StreamEnd::ReadError(_)));
+		assert_eq!(end.disconnect_reason(), DisconnectReason::Error);
+
+		let close_frame = stream_end_close_frame(end);
+		assert_eq!(close_frame.code, CloseCode::Normal);
+		assert_eq!(close_frame.reason.as_str(), "ws.connection_closed;end=read_error");
+	}
+
+	#[tokio::test]
+	async fn runner_ending_stream_abandons_kv_op() {
+		let (client, server) = socket_pair().await;
+		let (_tx, mut rx) = server.split();
+		let mut rx = RunnerRx::new(&mut rx);
+		let in_flight = Arc::new(());
+
+		let (res, _) = tokio::join!(
+			rx.run_until_ended({
+				let in_flight = in_flight.clone();
+
+				async move {
+					// Slow KV operation
+					tokio::time::sleep(Duration::from_secs(60)).await;
+					drop(in_flight);
+				}
+			}),
+			async move {
+				tokio::time::sleep(Duration::from_millis(10)).await;
+				drop(client);
+			},
+		);
+
+		// The operation did not complete and was dropped instead of running to completion
+		assert!(res.is_none());
+		assert_eq!(Arc::strong_count(&in_flight), 1);
+		assert!(matches!(rx.next().await, Err(StreamEnd::StreamEnded)));
+	}
+
+	#[tokio::test]
+	async fn packet_received_during_kv_op_is_kept() {
+		let (client, server) = socket_pair().await;
+		let mut client =
+			WebSocketStream::from_raw_socket(client, tungstenite::protocol::Role::Client, None)
+				.await;
+		let (_tx, mut rx) = server.split();
+		let mut rx = RunnerRx::new(&mut rx);
+
+		client.send(Message::Binary(vec![1].into())).await.unwrap();
+		let res = rx
+			.run_until_ended(tokio::time::sleep(Duration::from_millis(50)))
+			.await;
+		assert!(res.is_some());
+
+		// Processed after the operation, in order
+		client.send(Message::Binary(vec![2].into())).await.unwrap();
+		assert_eq!(rx.next().await.unwrap(), Message::Binary(vec![1].into()));
+		assert_eq!(rx.next().await.unwrap(), Message::Binary(vec![2].into()));
+	}
+
+	#[test]
+	fn suspicious_rtt_is_never_zero() {
+		assert_eq!(sanitize_rtt(SuspiciousRttPolicy::Ignore, 1, 25), Some(25));
+		assert_eq!(sanitize_rtt(SuspiciousRttPolicy::Ignore, 1, -40), None);
+		assert_eq!(sanitize_rtt(SuspiciousRttPolicy::Exclude, 1, 0), None);
+		assert_eq!(sanitize_rtt(SuspiciousRttPolicy::Clamp, 5, -40), Some(5));
+		assert_eq!(sanitize_rtt(SuspiciousRttPolicy::Clamp, 5, 3), Some(5));
+	}
+
+	#[test]
+	fn close_frame_errors_are_unique() {
+		let errors = close_frame_errors();
+		let codes = errors.iter().map(|error| error.code).collect::<HashSet<_>>();
+
+		assert_eq!(codes.len(), errors.len());
+		assert!(errors.iter().all(|error| error.group == "ws"));
+		assert!(codes.contains("new_runner_connected"));
+		assert!(codes.contains("namespace_draining"));
+	}
+
+	#[test]
+	fn total_slots_are_validated() {
+		let max = pegboard::workflows::runner::MAX_TOTAL_SLOTS;
+
+		// Standby runner
+		assert!(validate_total_slots(0).is_ok());
+		assert!(validate_total_slots(max).is_ok());
+
+		let err = validate_total_slots(max + 1).unwrap_err();
+		assert_eq!(err_code(&err), "ws.invalid_total_slots");
+
+		// Negative counts from clients that serialize slots as signed integers wrap to huge values
+		let err = validate_total_slots(-1i32 as u32).unwrap_err();
+		assert_eq!(err_code(&err), "ws.invalid_total_slots");
+	}
+
+	#[test]
+	fn requested_runner_of_other_namespace_is_rejected() {
+		let namespace_id = Id::new_v1(1);
+		let runner = rivet_types::runners::Runner {
+			runner_id: Id::new_v1(1),
+			namespace_id,
+			datacenter: "test".to_string(),
+			name: "runner".to_string(),
+			key: "key".to_string(),
+			version: 1,
+			total_slots: 1,
+			remaining_slots: 1,
+			create_ts: 0,
+			drain_ts: None,
+			stop_ts: None,
+			last_ping_ts: 0,
+			last_connected_ts: None,
+			last_rtt: 0,
+			metadata: None,
+		};
+
+		let rejection = requested_runner_rejection(Some(&runner), namespace_id, "runner", "key");
+		assert_eq!(rejection.unwrap(), None);
+		let rejection = requested_runner_rejection(Some(&runner), namespace_id, "runner", "other");
+		assert_eq!(rejection.unwrap(), Some("not_owned"));
+
+		// Reconnecting with the same key to another namespace must not rebind the runner
+		let err = requested_runner_rejection(Some(&runner), Id::new_v1(1), "runner", "key")
+			.unwrap_err();
+		assert_eq!(err_code(&err), "ws.runner_namespace_mismatch");
+	}
+}
@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+
+use gas::prelude::*;
+use pegboard::keys::runner::WsMaintenance;
+use rivet_types::msgs::pegboard::{SetRunnerWsMaintenanceMode, SetRunnerWsPingUpdatesPaused};
+
+/// Maintenance mode rejects new runner connections while keeping existing ones alive.
+///
+/// Maintenance mode is persisted (see `pegboard::ops::runner::set_ws_maintenance_mode`) and read
+/// when the instance starts, on every new connection and after `SetRunnerWsMaintenanceMode` is
+/// published. The last known state is kept in memory in case the database cannot be read.
+///
+/// Ping updates can be paused separately, see `SetRunnerWsPingUpdatesPaused`. Pauses are not
+/// persisted.
+pub struct Maintenance {
+	/// Set by `pegboard.maintenance_mode`, enabled regardless of the persisted state.
+	config_enabled: bool,
+	enabled: AtomicBool,
+	retry_after_ms: AtomicU64,
+	default_retry_after_ms: u64,
+	/// Ping updates are paused until this timestamp. 0 if not paused.
+	ping_updates_paused_until_ts: AtomicI64,
+	max_ping_updates_pause_ms: i64,
+}
+
+impl Maintenance {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		Maintenance {
+			config_enabled: config.pegboard().maintenance_mode(),
+			enabled: AtomicBool::new(config.pegboard().maintenance_mode()),
+			retry_after_ms: AtomicU64::new(config.pegboard().maintenance_retry_after_ms()),
+			default_retry_after_ms: config.pegboard().maintenance_retry_after_ms(),
+			ping_updates_paused_until_ts: AtomicI64::new(0),
+			max_ping_updates_pause_ms: config.pegboard().max_ping_updates_pause_ms(),
+		}
+	}
+
+	/// Whether the update ping thread should keep the eligibility of runners unchanged.
+	pub fn ping_updates_paused(&self) -> bool {
+		util::timestamp::now() < self.ping_updates_paused_until_ts.load(Ordering::Acquire)
+	}
+
+	/// Pauses are bounded by `max_ping_updates_pause_ms` so runners that are not pinged during a pause
+	/// (ineligible ones) are never considered lost because of it.
+	fn set_ping_updates_paused(&self, paused: bool, duration_ms: Option<i64>) {
+		let paused_until_ts = if paused {
+			let duration_ms = duration_ms
+				.unwrap_or(self.max_ping_updates_pause_ms)
+				.min(self.max_ping_updates_pause_ms);
+
+			util::timestamp::now() + duration_ms
+		} else {
+			0
+		};
+
+		self.ping_updates_paused_until_ts
+			.store(paused_until_ts, Ordering::Release);
+	}
+
+	/// Returns the retry after hint (in ms) if maintenance mode is enabled. Falls back to the last
+	/// known state if the persisted state cannot be read.
+	pub async fn retry_after_ms(&self, ctx: &StandaloneCtx) -> Option<u64> {
+		if let Err(err) = self.reload(ctx).await {
+			tracing::warn!(?err, "failed reading maintenance mode");
+		}
+
+		self.enabled
+			.load(Ordering::Acquire)
+			.then(|| self.retry_after_ms.load(Ordering::Relaxed))
+	}
+
+	async fn reload(&self, ctx: &StandaloneCtx) -> Result<()> {
+		let res = ctx
+			.op(pegboard::ops::runner::get_ws_maintenance_mode::Input {})
+			.await?;
+		self.set(res.maintenance);
+
+		Ok(())
+	}
+
+	fn set(&self, maintenance: Option<WsMaintenance>) {
+		let retry_after_ms = maintenance
+			.and_then(|maintenance| maintenance.retry_after_ms)
+			.unwrap_or(self.default_retry_after_ms);
+		self.retry_after_ms.store(retry_after_ms, Ordering::Relaxed);
+
+		let enabled = self.config_enabled || maintenance.is_some();
+		if self.enabled.swap(enabled, Ordering::AcqRel) != enabled {
+			tracing::info!(%enabled, %retry_after_ms, "maintenance mode changed");
+		}
+	}
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn thread(ctx: &StandaloneCtx, maintenance: &Maintenance) {
+	loop {
+		match thread_inner(ctx, maintenance).await {
+			Ok(_) => {
+				tracing::warn!("maintenance thread exited early");
+			}
+			Err(err) => {
+				tracing::error!(?err, "maintenance thread error");
+			}
+		}
+
+		tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+	}
+}
+
+#[tracing::instrument(skip_all)]
+async fn thread_inner(ctx: &StandaloneCtx, maintenance: &Maintenance) -> Result<()> {
+	let mut sub = ctx
+		.subscribe::<SetRunnerWsMaintenanceMode>(&serde_json::json!({}))
+		.await?;
+	let mut ping_sub = ctx
+		.subscribe::<SetRunnerWsPingUpdatesPaused>(&serde_json::json!({}))
+		.await?;
+
+	// Read after subscribing so changes made in between are not missed (i.e. while starting)
+	maintenance.reload(ctx).await?;
+
+	loop {
+		tokio::select! {
+			msg = sub.next() => {
+				msg?;
+				maintenance.reload(ctx).await?;
+			}
+			msg = ping_sub.next() => {
+				let msg = msg?.into_body();
+
+				tracing::info!(
+					paused = msg.paused,
+					duration_ms = ?msg.duration_ms,
+					"setting ping updates paused"
+				);
+
+				maintenance.set_ping_updates_paused(msg.paused, msg.duration_ms);
+			}
+		}
+	}
+}
@@ -0,0 +1,381 @@
+use rivet_metrics::{
+	BUCKETS, MICRO_BUCKETS,
+	otel::{global::*, metrics::*},
+};
+
+lazy_static::lazy_static! {
+	static ref METER: Meter = meter("rivet-pegboard-runner-ws");
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_NAMESPACE_RESOLVE_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_handshake_namespace_resolve_duration")
+		.with_description("Duration to resolve the namespace of a new runner connection.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_INIT_WAIT_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_handshake_init_wait_duration")
+		.with_description("Duration spent waiting for the runner to send the init packet.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_RUNNER_LOOKUP_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_handshake_runner_lookup_duration")
+		.with_description("Duration to look up an existing runner by key.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_PING_UPDATE_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_handshake_ping_update_duration")
+		.with_description("Duration to update the ping of an existing runner.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_WORKFLOW_DISPATCH_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_handshake_workflow_dispatch_duration")
+		.with_description("Duration to dispatch (or find) the runner workflow.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_WORKFLOW_DISPATCH_CONFLICT: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_handshake_workflow_dispatch_conflict")
+		.with_description("Runner workflow dispatches that failed but resolved to a workflow created by a concurrent connection.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_SIGNAL_SEND_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_handshake_signal_send_duration")
+		.with_description("Duration to forward the init packet to the runner workflow.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Expected attributes: "runner_reused"
+	pub static ref HANDSHAKE_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_handshake_duration")
+		.with_description("Total duration to establish a runner connection.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref RUNNER_CLOCK_SKEW: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_runner_clock_skew")
+		.with_description("Absolute estimated clock skew between runners and the server in seconds.")
+		.with_boundaries(BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref KV_REQUEST_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_kv_request_duration")
+		.with_description("Duration of KV requests from runners, including sending the response.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref KV_IN_FLIGHT_REQUESTS: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_kv_in_flight_requests")
+		.with_description("Number of KV requests currently being processed.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_PENDING: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_handshake_pending")
+		.with_description("Number of connections that have not completed their handshake.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_SILENT_CLIENT_CLOSED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_handshake_silent_client_closed")
+		.with_description("Connections closed early for not sending their init packet while handshakes were under pressure.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref RUNNER_QUARANTINED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_runner_quarantined")
+		.with_description("Runner keys quarantined after repeatedly sending invalid packets.")
+		.build();
+
+	/// Expected attributes: "op"
+	pub static ref KV_UDB_RETRIES: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_kv_udb_retries")
+		.with_description("UDB transaction retries of KV requests, caused by transaction conflicts.")
+		.build();
+
+	/// Expected attributes: "policy"
+	pub static ref DUPLICATE_CONNECTION: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_duplicate_connection")
+		.with_description("Connections for runners that already had an open connection, by duplicate connection policy.")
+		.build();
+
+	/// Expected attributes: "message", "handled"
+	pub static ref MSG_THREAD_MESSAGES: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_msg_thread_messages")
+		.with_description("Messages received by the msg thread, not handled if the runner is not connected to this instance.")
+		.build();
+
+	/// Expected attributes: "thread"
+	pub static ref THREAD_RESTARTS: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_thread_restarts")
+		.with_description("Restarts of background threads after exiting or erroring.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref PING_UPDATES_PAUSED: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_ping_updates_paused")
+		.with_description("1 if runner ping updates are paused on this instance.")
+		.build();
+
+	/// Expected attributes: "prior_reason"
+	pub static ref RECONNECT_CHURN: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_reconnect_churn")
+		.with_description("Reconnects of recently disconnected runners, by the reason of the previous disconnect.")
+		.build();
+
+	/// Expected attributes: "prior_reason"
+	pub static ref RECONNECT_GAP_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_reconnect_gap_duration")
+		.with_description("Time between a runner disconnecting and reconnecting.")
+		.with_boundaries(BUCKETS.to_vec())
+		.build();
+
+	/// Expected attributes: "result"
+	pub static ref REQUESTED_RUNNER_ID: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_requested_runner_id")
+		.with_description("Runner ids requested in init packets, by whether the connection was rebound to the requested runner.")
+		.build();
+
+	/// Expected attributes: "reason", "response_sent"
+	pub static ref KV_REQUEST_REJECTED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_kv_request_rejected")
+		.with_description("KV requests rejected because of client errors. Not sent if the socket broke while responding, in which case the connection is closed.")
+		.build();
+
+	/// Expected attributes: "protocol_version", "message_type"
+	pub static ref INCOMPATIBLE_MESSAGES: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_incompatible_messages")
+		.with_description("Messages dropped because they cannot be represented in the runner's protocol version.")
+		.build();
+
+	/// Expected attributes: "protocol_version", "message_type"
+	pub static ref INCOMPATIBLE_RUNNERS: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_incompatible_runners")
+		.with_description("Distinct runners that could not receive a message type because of their protocol version, since this instance started. Capped at 10,000.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref CONNS_LOCK_WAIT_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_conns_lock_wait_duration")
+		.with_description("Time the watchdog waited to acquire the connections lock.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Expected attributes: "result"
+	pub static ref CONNECTION_EXPORT: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_connection_export")
+		.with_description("Connections exported on shutdown.")
+		.build();
+	/// Has no expected attributes
+	pub static ref CONNECTION_IMPORT: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_connection_import")
+		.with_description("Connections exported by the previous instance read on startup.")
+		.build();
+
+	/// Expected attributes: "reason"
+	pub static ref NAMESPACE_RESOLVE_FAILED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_namespace_resolve_failed")
+		.with_description("Connections closed because their namespace could not be resolved in time.")
+		.build();
+
+	/// Expected attributes: "kind", "result"
+	pub static ref CONNECTION_EVENTS: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_connection_events")
+		.with_description("Connection lifecycle events published, see `pegboard.publish_connection_events`.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref KV_SHED_CONNECTIONS: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_kv_shed_connections")
+		.with_description("New connections rejected because KV is saturated.")
+		.build();
+
+	/// Expected attributes: "from", "to"
+	pub static ref PROTOCOL_DOWNGRADES: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_protocol_downgrades")
+		.with_description("Runners that connected with a lower protocol version than they previously used.")
+		.build();
+
+	/// Expected attributes: "op"
+	pub static ref SYNTHETIC_KV_REQUEST_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_synthetic_kv_request_duration")
+		.with_description("Duration of KV requests sent as synthetic load test traffic. Only recorded in load test builds.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref RUNNER_VERSION_UNSUPPORTED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_runner_version_unsupported")
+		.with_description("Connections rejected because the runner version is outside the allowed range.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref MSG_DELIVERY_DOWN: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_msg_delivery_down")
+		.with_description("1 while the msg thread is not subscribed to workflow messages and commands cannot be delivered to runners.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref WORKFLOW_DISPATCH_QUEUE_DEPTH: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_workflow_dispatch_queue_depth")
+		.with_description("Connections waiting for a runner workflow dispatch slot, see `pegboard.workflow_dispatch_rate`.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref WORKFLOW_DISPATCH_REJECTED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_workflow_dispatch_rejected")
+		.with_description("Connections rejected because the workflow dispatch queue was full.")
+		.build();
+
+	/// Expected attributes: "reason"
+	pub static ref DISCONNECTS: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_disconnects")
+		.with_description("Runner disconnects by reason.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref BOOT_EPOCH: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_boot_epoch")
+		.with_description("When this instance started (ms since epoch), to correlate disconnect spikes with deploys.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref KV_RESPONSE_BYTES_IN_FLIGHT: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_kv_response_bytes_in_flight")
+		.with_description("KV response bytes buffered but not yet sent, see `pegboard.kv_response_budget_bytes`.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref NAMESPACES_DRAINING: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_namespaces_draining")
+		.with_description("Number of namespaces being drained on this instance, see `SetRunnerWsNamespaceDraining`.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref DRAINED_CONNECTIONS: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_drained_connections")
+		.with_description("Connections closed because their namespace is being drained.")
+		.build();
+
+	/// Expected attributes: "end"
+	pub static ref CONNECTION_ENDS: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_connection_ends")
+		.with_description("Connections ended by the runner, by how the socket ended (client_close, stream_ended, read_error).")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref SUSPICIOUS_RTT_SAMPLES: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_suspicious_rtt_samples")
+		.with_description("Pings with an RTT below `pegboard.suspicious_rtt_floor_ms`, usually from runners whose clock is ahead.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref HANDSHAKE_PREPROVISIONED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_handshake_preprovisioned")
+		.with_description("Connections that bound to a pre-provisioned runner workflow instead of dispatching one.")
+		.build();
+
+	/// Expected attributes: "direction", "stage"
+	pub static ref KV_COMPRESSION_BYTES: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_kv_compression_bytes")
+		.with_description("Size of KV values before (stage=uncompressed) and after (stage=compressed) compression. Inbound are values written by runners, outbound are compressed values read by runners.")
+		.build();
+
+	/// Expected attributes: "direction"
+	pub static ref KV_COMPRESSION_RATIO: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_kv_compression_ratio")
+		.with_description("Compressed size divided by uncompressed size of the values of a single KV request.")
+		.with_boundaries(vec![0.05, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 1.0])
+		.build();
+
+	/// Expected attributes: "reason"
+	pub static ref KV_COMPRESSION_SKIPPED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_kv_compression_skipped")
+		.with_description("KV values written uncompressed because they looked incompressible, by declared content type (reason=content_type) or sampled entropy (reason=entropy). See `pegboard.kv_compression.max_entropy`.")
+		.build();
+
+	/// Expected attributes: "subscription"
+	pub static ref MSG_THREAD_LAG: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_msg_thread_lag")
+		.with_description("Age of messages when the msg thread processes them. A growing lag means a growing backlog in the pubsub backend.")
+		.with_boundaries(BUCKETS.to_vec())
+		.build();
+
+	/// Expected attributes: "subscription"
+	pub static ref MSG_THREAD_CURRENT_LAG: Gauge<f64> = METER.f64_gauge("rivet_pegboard_runner_ws_msg_thread_current_lag")
+		.with_description("Age of the last message processed by the msg thread, see `pegboard.msg_lag_threshold_ms`.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref DB_REACHABLE: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_db_reachable")
+		.with_description("Whether the last database probe succeeded. The instance reports not ready while 0.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref DB_PROBE_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_db_probe_duration")
+		.with_description("Duration of database probes, see `pegboard.db_probe_interval_ms`.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Expected attributes: "rank"
+	pub static ref KV_HOT_KEY_ACCESSES: Gauge<f64> = METER.f64_gauge("rivet_pegboard_runner_ws_kv_hot_key_accesses")
+		.with_description("Decayed access count of the hottest KV keys by rank (1 is the hottest), see `pegboard.kv_hot_key_half_life_ms`.")
+		.build();
+
+	/// Expected attributes: "rank"
+	pub static ref KV_HOT_KEY_CONFLICTS: Gauge<f64> = METER.f64_gauge("rivet_pegboard_runner_ws_kv_hot_key_conflicts")
+		.with_description("Decayed transaction conflict count of the hottest KV keys by rank.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref KV_COALESCED_GETS: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_kv_coalesced_gets")
+		.with_description("KV gets that joined an in-flight get of the same hot keys instead of reading the database.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref UPGRADES_ACCEPTED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_upgrades_accepted")
+		.with_description("Connections that got an upgrade slot, see `pegboard.max_pending_upgrades`.")
+		.build();
+
+	/// Expected attributes: "reason"
+	pub static ref UPGRADES_SHED: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_upgrades_shed")
+		.with_description("Connections rejected before the websocket upgrade because no upgrade slot was free (reason=capacity) or freed up in time (reason=queue_timeout).")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref UPGRADES_TIMED_OUT: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_upgrades_timed_out")
+		.with_description("Connections closed because their websocket upgrade did not complete within the upgrade timeout.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref UPGRADES_PENDING: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_upgrades_pending")
+		.with_description("Websocket upgrades and init handshakes holding an upgrade slot.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref KV_NAMESPACE_IN_FLIGHT: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_kv_namespace_in_flight")
+		.with_description("KV operations in flight of a namespace when one of its operations starts, see `pegboard.max_kv_in_flight_per_namespace`.")
+		.with_boundaries(vec![1.0, 4.0, 16.0, 64.0, 256.0, 1024.0, 4096.0])
+		.build();
+
+	/// Has no expected attributes
+	pub static ref KV_NAMESPACES_IN_FLIGHT: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_kv_namespaces_in_flight")
+		.with_description("Namespaces with KV operations in flight while the per-namespace limit is enabled.")
+		.build();
+
+	/// Expected attributes: "priority"
+	pub static ref KV_SCHEDULER_WAIT_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_kv_scheduler_wait_duration")
+		.with_description("Time KV operations spent queued for a slot, see `pegboard.max_kv_concurrency`.")
+		.with_boundaries(MICRO_BUCKETS.to_vec())
+		.build();
+
+	/// Has no expected attributes
+	pub static ref KV_SCHEDULER_QUEUE_DEPTH: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_kv_scheduler_queue_depth")
+		.with_description("KV operations queued for a slot.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref WORKFLOW_ENDED_CLOSES: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_workflow_ended_closes")
+		.with_description("Connections closed after `ToClientWorkflowEnding` because the runner workflow ended without the runner being evicted.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref RESOURCE_CPU_USAGE: Gauge<f64> = METER.f64_gauge("rivet_pegboard_runner_ws_resource_cpu_usage")
+		.with_description("Fraction of CPU time in use, sampled while `pegboard.resource_pressure` is enabled.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref RESOURCE_MEMORY_USAGE: Gauge<f64> = METER.f64_gauge("rivet_pegboard_runner_ws_resource_memory_usage")
+		.with_description("Fraction of memory in use, sampled while `pegboard.resource_pressure` is enabled.")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref RESOURCE_PRESSURE_SHEDDING: Gauge<u64> = METER.u64_gauge("rivet_pegboard_runner_ws_resource_pressure_shedding")
+		.with_description("Whether new work is shed because of CPU or memory pressure (1 if shedding, 0 otherwise).")
+		.build();
+
+	/// Has no expected attributes
+	pub static ref RESOURCE_PRESSURE_SHED_CONNECTIONS: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_resource_pressure_shed_connections")
+		.with_description("New connections rejected because of CPU or memory pressure.")
+		.build();
+
+	/// Expected attributes: "protocol_version", "compressed"
+	pub static ref INIT_PACKET_SIZE: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_init_packet_size")
+		.with_description("Size of init packets in bytes, after decompression.")
+		.with_boundaries(vec![256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0, 67108864.0])
+		.build();
+
+	/// Expected attributes: "protocol_version", "field"
+	pub static ref INIT_PACKET_FIELDS: Counter<u64> = METER.u64_counter("rivet_pegboard_runner_ws_init_packet_fields")
+		.with_description("Init packets with the given optional field set. Compare to the count of `init_packet_size` for the adoption rate.")
+		.build();
+
+	/// Expected attributes: "message_type"
+	pub static ref COMMAND_DELIVERY_DURATION: Histogram<f64> = METER.f64_histogram("rivet_pegboard_runner_ws_command_delivery_duration")
+		.with_description("Time from a runner workflow publishing a message to it being sent over the runner's socket. Measured against the publisher's clock with millisecond precision.")
+		.with_boundaries(BUCKETS.to_vec())
+		.build();
+}
@@ -0,0 +1,54 @@
+use std::sync::{Arc, atomic::Ordering};
+
+use rivet_runner_protocol::*;
+use tokio::sync::RwLock;
+
+use crate::{Connection, Connections};
+
+/// Periodically sends the server's view of each connection's health to runners that opted in to metrics
+/// snapshots during the init handshake.
+#[tracing::instrument(skip_all)]
+pub async fn thread(config: &rivet_config::Config, conns: Arc<RwLock<Connections>>) {
+	let mut interval = tokio::time::interval(config.pegboard().metrics_snapshot_interval());
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+	loop {
+		interval.tick().await;
+
+		let conns = conns
+			.read()
+			.await
+			.iter()
+			.filter(|(_, conn)| conn.metrics_snapshots)
+			.map(|(runner_id, conn)| (*runner_id, conn.clone()))
+			.collect::<Vec<_>>();
+
+		for (runner_id, conn) in conns {
+			if let Err(err) = conn
+				.send(ToClient::ToClientMetricsSnapshot(snapshot(&conn)))
+				.await
+			{
+				tracing::debug!(?runner_id, ?err, "failed sending metrics snapshot");
+			}
+		}
+	}
+}
+
+/// Builds a snapshot from the connection's tracked data and resets the KV counters.
+fn snapshot(conn: &Connection) -> ToClientMetricsSnapshot {
+	let kv_latency_us = conn.kv_latency_us.swap(0, Ordering::Relaxed);
+	let kv_requests = conn.kv_requests.swap(0, Ordering::Relaxed);
+	let kv_queue_wait_us = conn.kv_queue_wait_us.swap(0, Ordering::Relaxed);
+
+	ToClientMetricsSnapshot {
+		rtt: conn.last_rtt.load(Ordering::Relaxed),
+		clock_skew: conn.clock_skew.load(Ordering::Relaxed),
+		kv_latency: (kv_requests != 0)
+			.then(|| (kv_latency_us / kv_requests / 1000).try_into().unwrap_or(u32::MAX)),
+		kv_requests: kv_requests.try_into().unwrap_or(u32::MAX),
+		kv_queue_wait: (kv_requests != 0)
+			.then(|| (kv_queue_wait_us / kv_requests / 1000).try_into().unwrap_or(u32::MAX)),
+		kv_throttled: conn.kv_throttled.load(Ordering::Acquire),
+		eligible: conn.eligible.load(Ordering::Relaxed),
+	}
+}
@@ -0,0 +1,179 @@
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use futures_util::SinkExt;
+use gas::prelude::*;
+use pegboard::workflows::runner::DisconnectReason;
+use rivet_types::msgs::pegboard::SetRunnerWsNamespaceDraining;
+use tokio::sync::RwLock;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{Connections, WsError, err_to_close_frame, metrics};
+
+/// Drains the runners of single namespaces, see `SetRunnerWsNamespaceDraining`.
+///
+/// Drains are persisted (see `pegboard::ops::runner::set_ws_namespace_draining`). All drains are
+/// read when the instance starts, and a namespace's drain is read on every new connection of the
+/// namespace and after `SetRunnerWsNamespaceDraining` is published for it. The last known state is
+/// kept in memory in case the database cannot be read.
+pub struct NamespaceDrain {
+	/// Retry hint of each draining namespace.
+	namespaces: Mutex<HashMap<Id, u64>>,
+	default_retry_after_ms: u64,
+}
+
+impl NamespaceDrain {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		NamespaceDrain {
+			namespaces: Mutex::new(HashMap::new()),
+			default_retry_after_ms: config.pegboard().maintenance_retry_after_ms(),
+		}
+	}
+
+	/// Returns the retry after hint (in ms) if the namespace is draining. Falls back to the last known
+	/// state if the persisted state cannot be read.
+	pub async fn retry_after_ms(&self, ctx: &StandaloneCtx, namespace_id: Id) -> Option<u64> {
+		if let Err(err) = self.reload(ctx, namespace_id).await {
+			tracing::warn!(?namespace_id, ?err, "failed reading namespace drain");
+		}
+
+		self.namespaces
+			.lock()
+			.expect("poisoned")
+			.get(&namespace_id)
+			.copied()
+	}
+
+	/// Reads the persisted drain of the namespace. Returns the retry after hint if it is draining.
+	async fn reload(&self, ctx: &StandaloneCtx, namespace_id: Id) -> Result<Option<u64>> {
+		let res = ctx
+			.op(pegboard::ops::runner::get_ws_namespace_drain::Input { namespace_id })
+			.await?;
+		let retry_after_ms = res
+			.drain
+			.map(|drain| drain.retry_after_ms.unwrap_or(self.default_retry_after_ms));
+		self.set(namespace_id, retry_after_ms);
+
+		Ok(retry_after_ms)
+	}
+
+	/// Reads all persisted drains. Returns the draining namespaces.
+	async fn reload_all(&self, ctx: &StandaloneCtx) -> Result<Vec<(Id, u64)>> {
+		let res = ctx
+			.op(pegboard::ops::runner::list_ws_namespace_drains::Input {})
+			.await?;
+		let draining = res
+			.namespaces
+			.into_iter()
+			.map(|drain| {
+				(
+					drain.namespace_id,
+					drain.retry_after_ms.unwrap_or(self.default_retry_after_ms),
+				)
+			})
+			.collect::<Vec<_>>();
+
+		let mut namespaces = self.namespaces.lock().expect("poisoned");
+		*namespaces = draining.iter().copied().collect();
+		metrics::NAMESPACES_DRAINING.record(namespaces.len() as u64, &[]);
+
+		Ok(draining)
+	}
+
+	fn set(&self, namespace_id: Id, retry_after_ms: Option<u64>) {
+		let mut namespaces = self.namespaces.lock().expect("poisoned");
+
+		if let Some(retry_after_ms) = retry_after_ms {
+			namespaces.insert(namespace_id, retry_after_ms);
+		} else {
+			namespaces.remove(&namespace_id);
+		}
+
+		metrics::NAMESPACES_DRAINING.record(namespaces.len() as u64, &[]);
+	}
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn thread(ctx: &StandaloneCtx, conns: Arc<RwLock<Connections>>, drain: &NamespaceDrain) {
+	loop {
+		match thread_inner(ctx, &conns, drain).await {
+			Ok(_) => {
+				tracing::warn!("namespace drain thread exited early");
+			}
+			Err(err) => {
+				tracing::error!(?err, "namespace drain thread error");
+			}
+		}
+
+		tokio::time::sleep(Duration::from_secs(2)).await;
+	}
+}
+
+#[tracing::instrument(skip_all)]
+async fn thread_inner(
+	ctx: &StandaloneCtx,
+	conns: &RwLock<Connections>,
+	drain: &NamespaceDrain,
+) -> Result<()> {
+	let mut sub = ctx
+		.subscribe::<SetRunnerWsNamespaceDraining>(&serde_json::json!({}))
+		.await?;
+
+	// Read after subscribing so drains started in between are not missed (i.e. while starting).
+	// Connections accepted while the drain was missed are closed.
+	for (namespace_id, retry_after_ms) in drain.reload_all(ctx).await? {
+		close_namespace(conns, namespace_id, retry_after_ms).await;
+	}
+
+	loop {
+		let msg = sub.next().await?.into_body();
+
+		// Set before closing existing connections so their reconnects are rejected
+		let retry_after_ms = drain.reload(ctx, msg.namespace_id).await?;
+
+		tracing::info!(
+			namespace_id = ?msg.namespace_id,
+			draining = retry_after_ms.is_some(),
+			?retry_after_ms,
+			"namespace draining changed"
+		);
+
+		if let Some(retry_after_ms) = retry_after_ms {
+			close_namespace(conns, msg.namespace_id, retry_after_ms).await;
+		}
+	}
+}
+
+/// Closes all connections of the namespace with a hint to reconnect elsewhere.
+async fn close_namespace(conns: &RwLock<Connections>, namespace_id: Id, retry_after_ms: u64) {
+	// Don't hold the lock while sending, a slow socket would block all connection inserts and removals
+	let drained = conns
+		.read()
+		.await
+		.iter()
+		.filter(|(_, conn)| conn.namespace_id == namespace_id)
+		.map(|(runner_id, conn)| (*runner_id, conn.clone()))
+		.collect::<Vec<_>>();
+
+	tracing::info!(?namespace_id, count = drained.len(), "draining namespace connections");
+
+	for (runner_id, conn) in drained {
+		let _ = conn.disconnect_reason.set(DisconnectReason::Drained);
+		conn.closed.cancel();
+		metrics::DRAINED_CONNECTIONS.add(1, &[]);
+
+		// Sent in the background so a slow socket does not hold up the rest of the namespace
+		tokio::spawn(async move {
+			let close_frame =
+				err_to_close_frame(WsError::NamespaceDraining { retry_after_ms }.build());
+			let mut tx = conn.tx.lock().await;
+
+			if let Err(err) = tx.send(Message::Close(Some(close_frame))).await {
+				tracing::debug!(?runner_id, ?err, "failed closing drained socket");
+			}
+		});
+	}
+}
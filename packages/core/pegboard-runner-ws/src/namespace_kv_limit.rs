@@ -0,0 +1,101 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use gas::prelude::*;
+
+use crate::metrics;
+
+/// Limits the KV operations in flight per namespace on this instance, see
+/// `Pegboard::max_kv_in_flight_per_namespace`.
+///
+/// Applies on top of the per-connection limit (one KV request at a time) and the instance-wide limits
+/// (throttling, shedding and the response budget), so the runners of a single namespace cannot take
+/// up all of the instance's KV capacity.
+pub struct NamespaceKvLimiter {
+	limit: Option<usize>,
+	in_flight: Mutex<HashMap<Id, usize>>,
+}
+
+impl NamespaceKvLimiter {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		NamespaceKvLimiter {
+			limit: config.pegboard().max_kv_in_flight_per_namespace(),
+			in_flight: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Tracks a KV operation of the namespace until the returned guard is dropped. Returns `None`
+	/// if the namespace is at its limit.
+	pub fn try_acquire(&self, namespace_id: Id) -> Option<NamespaceKvGuard<'_>> {
+		let Some(limit) = self.limit else {
+			return Some(NamespaceKvGuard {
+				limiter: self,
+				namespace_id: None,
+			});
+		};
+
+		let mut in_flight = self.in_flight.lock().expect("poisoned");
+		if in_flight.get(&namespace_id).copied().unwrap_or_default() >= limit {
+			return None;
+		}
+
+		let count = in_flight.entry(namespace_id).or_default();
+		*count += 1;
+		metrics::KV_NAMESPACE_IN_FLIGHT.record(*count as f64, &[]);
+		metrics::KV_NAMESPACES_IN_FLIGHT.record(in_flight.len() as u64, &[]);
+
+		Some(NamespaceKvGuard {
+			limiter: self,
+			namespace_id: Some(namespace_id),
+		})
+	}
+}
+
+pub struct NamespaceKvGuard<'a> {
+	limiter: &'a NamespaceKvLimiter,
+	/// Not set if the limit is disabled.
+	namespace_id: Option<Id>,
+}
+
+impl Drop for NamespaceKvGuard<'_> {
+	fn drop(&mut self) {
+		let Some(namespace_id) = self.namespace_id else {
+			return;
+		};
+
+		let mut in_flight = self.limiter.in_flight.lock().expect("poisoned");
+		if let Some(count) = in_flight.get_mut(&namespace_id) {
+			*count -= 1;
+
+			// Bounds the map to namespaces with operations in flight
+			if *count == 0 {
+				in_flight.remove(&namespace_id);
+			}
+		}
+		metrics::KV_NAMESPACES_IN_FLIGHT.record(in_flight.len() as u64, &[]);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn limits_in_flight_per_namespace() {
+		let limiter = NamespaceKvLimiter {
+			limit: Some(2),
+			in_flight: Mutex::new(HashMap::new()),
+		};
+		let namespace_id = Id::new_v1(1);
+
+		// Requests of two connections of the namespace (one request in flight each)
+		let first = limiter.try_acquire(namespace_id).unwrap();
+		let _second = limiter.try_acquire(namespace_id).unwrap();
+		assert!(limiter.try_acquire(namespace_id).is_none());
+
+		// Other namespaces are unaffected
+		assert!(limiter.try_acquire(Id::new_v1(1)).is_some());
+
+		drop(first);
+		assert!(limiter.try_acquire(namespace_id).is_some());
+	}
+}
@@ -0,0 +1,131 @@
+use std::{
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use gas::prelude::*;
+use namespace::types::Namespace;
+use rivet_metrics::KeyValue;
+
+use crate::{WsError, metrics};
+
+/// Resolves namespaces of connecting runners with a timeout, so a degraded namespace service doesn't
+/// hang handshakes. After repeated consecutive timeouts, connections fail fast without resolving the
+/// namespace until the cooldown passes.
+pub struct NamespaceResolver {
+	timeout: Duration,
+	breaker_threshold: usize,
+	breaker_cooldown: Duration,
+	breaker: Mutex<Breaker>,
+}
+
+#[derive(Default)]
+struct Breaker {
+	consecutive_timeouts: usize,
+	open_until: Option<Instant>,
+}
+
+impl NamespaceResolver {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		let (breaker_threshold, breaker_cooldown) = config.pegboard().namespace_resolve_breaker();
+
+		Self::with_config(
+			config.pegboard().namespace_resolve_timeout(),
+			breaker_threshold,
+			breaker_cooldown,
+		)
+	}
+
+	fn with_config(timeout: Duration, breaker_threshold: usize, breaker_cooldown: Duration) -> Self {
+		NamespaceResolver {
+			timeout,
+			breaker_threshold,
+			breaker_cooldown,
+			breaker: Mutex::new(Breaker::default()),
+		}
+	}
+
+	pub async fn resolve(&self, ctx: &StandaloneCtx, name: String) -> Result<Option<Namespace>> {
+		if let Some(retry_after) = self.open_remaining() {
+			metrics::NAMESPACE_RESOLVE_FAILED.add(1, &[KeyValue::new("reason", "breaker_open")]);
+
+			return Err(WsError::NamespaceResolutionTimeout {
+				retry_after_ms: retry_after.as_millis().try_into().unwrap_or(u64::MAX),
+			}
+			.build());
+		}
+
+		let res = tokio::time::timeout(
+			self.timeout,
+			ctx.op(namespace::ops::resolve_for_name_global::Input { name }),
+		)
+		.await;
+
+		match res {
+			Ok(res) => {
+				self.record_completed();
+				res
+			}
+			Err(_) => {
+				metrics::NAMESPACE_RESOLVE_FAILED.add(1, &[KeyValue::new("reason", "timeout")]);
+				self.record_timeout();
+
+				Err(WsError::NamespaceResolutionTimeout { retry_after_ms: 0 }.build())
+			}
+		}
+	}
+
+	/// Returns how long the breaker stays open, if open.
+	fn open_remaining(&self) -> Option<Duration> {
+		let breaker = self.breaker.lock().expect("poisoned");
+
+		breaker
+			.open_until
+			.and_then(|open_until| open_until.checked_duration_since(Instant::now()))
+	}
+
+	fn record_completed(&self) {
+		let mut breaker = self.breaker.lock().expect("poisoned");
+		breaker.consecutive_timeouts = 0;
+		breaker.open_until = None;
+	}
+
+	fn record_timeout(&self) {
+		let mut breaker = self.breaker.lock().expect("poisoned");
+		breaker.consecutive_timeouts += 1;
+
+		// Also reopens the breaker if the first attempt after the cooldown times out
+		if breaker.consecutive_timeouts >= self.breaker_threshold {
+			tracing::warn!(
+				consecutive_timeouts=%breaker.consecutive_timeouts,
+				cooldown=?self.breaker_cooldown,
+				"namespace resolution repeatedly timed out, failing connections fast"
+			);
+			breaker.open_until = Some(Instant::now() + self.breaker_cooldown);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn opens_after_consecutive_timeouts() {
+		let resolver =
+			NamespaceResolver::with_config(Duration::from_secs(1), 2, Duration::from_secs(60));
+
+		resolver.record_timeout();
+		assert!(resolver.open_remaining().is_none());
+
+		resolver.record_timeout();
+		assert!(resolver.open_remaining().is_some());
+
+		resolver.record_completed();
+		assert!(resolver.open_remaining().is_none());
+
+		// Only consecutive timeouts count
+		resolver.record_timeout();
+		assert!(resolver.open_remaining().is_none());
+	}
+}
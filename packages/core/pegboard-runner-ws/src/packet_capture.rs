@@ -0,0 +1,75 @@
+use std::{collections::VecDeque, fmt::Debug, sync::Mutex};
+
+use gas::prelude::*;
+use pegboard::ops::runner::get_packet_capture::{CapturedPacket, PacketDirection};
+
+use crate::redact;
+
+/// Keeps the most recent packets of a connection for debugging protocol issues.
+pub struct PacketCapture {
+	config: rivet_config::Config,
+	capacity: usize,
+	packets: Mutex<VecDeque<CapturedPacket>>,
+}
+
+impl PacketCapture {
+	/// Returns `None` if packet capture is disabled for the given namespace.
+	pub fn new(config: &rivet_config::Config, namespace_name: &str) -> Option<Self> {
+		let capacity = config.pegboard().packet_capture_size(namespace_name);
+
+		(capacity != 0).then(|| PacketCapture {
+			config: config.clone(),
+			capacity,
+			packets: Mutex::new(VecDeque::with_capacity(capacity)),
+		})
+	}
+
+	pub fn push(&self, direction: PacketDirection, packet: &impl Debug) {
+		let packet = CapturedPacket {
+			ts: util::timestamp::now(),
+			direction,
+			packet: redact::summary(&self.config, packet),
+		};
+
+		let mut packets = self.packets.lock().expect("poisoned");
+		if packets.len() == self.capacity {
+			packets.pop_front();
+		}
+		packets.push_back(packet);
+	}
+
+	/// Returns all captured packets, oldest first.
+	pub fn dump(&self) -> Vec<CapturedPacket> {
+		self.packets
+			.lock()
+			.expect("poisoned")
+			.iter()
+			.cloned()
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn evicts_oldest_packets() {
+		let mut root = rivet_config::config::Root::default();
+		root.pegboard = Some(rivet_config::config::Pegboard {
+			packet_capture_size: Some(2),
+			..Default::default()
+		});
+		let config = rivet_config::Config::from_root(root);
+
+		let capture = PacketCapture::new(&config, "default").unwrap();
+		for i in 0..3 {
+			capture.push(PacketDirection::ToServer, &i);
+		}
+
+		let packets = capture.dump();
+		assert_eq!(packets.len(), 2);
+		assert_eq!(packets[0].packet, "1");
+		assert_eq!(packets[1].packet, "2");
+	}
+}
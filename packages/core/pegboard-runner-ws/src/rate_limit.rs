@@ -0,0 +1,97 @@
+use std::{
+	net::IpAddr,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use moka::future::Cache;
+use tokio::sync::Mutex;
+
+/// Number of connections a likely abusive connection counts as.
+const ABUSE_PENALTY: u64 = 5;
+
+struct RateLimiter {
+	requests_remaining: u64,
+	reset_time: Instant,
+	requests_limit: u64,
+	period: Duration,
+}
+
+impl RateLimiter {
+	fn new(requests: u64, period: Duration) -> Self {
+		Self {
+			requests_remaining: requests,
+			reset_time: Instant::now() + period,
+			requests_limit: requests,
+			period,
+		}
+	}
+
+	fn try_acquire(&mut self) -> bool {
+		let now = Instant::now();
+
+		// Check if we need to reset the counter
+		if now >= self.reset_time {
+			self.requests_remaining = self.requests_limit;
+			self.reset_time = now + self.period;
+		}
+
+		// Try to consume a request
+		if self.requests_remaining > 0 {
+			self.requests_remaining -= 1;
+			true
+		} else {
+			false
+		}
+	}
+}
+
+/// Limits the rate of new connections per client address.
+pub struct SourceRateLimiter {
+	limit: Option<(u64, Duration)>,
+	limiters: Cache<IpAddr, Arc<Mutex<RateLimiter>>>,
+}
+
+impl SourceRateLimiter {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		let limit = config.pegboard().connection_rate_limit();
+
+		SourceRateLimiter {
+			limit,
+			limiters: Cache::builder()
+				.max_capacity(100_000)
+				.time_to_idle(
+					limit
+						.map(|(_, period)| period)
+						.unwrap_or(Duration::from_secs(60)),
+				)
+				.build(),
+		}
+	}
+
+	/// Returns false if the given client address has exceeded its connection rate.
+	pub async fn try_acquire(&self, client_addr: IpAddr) -> bool {
+		let Some((requests, period)) = self.limit else {
+			// Rate limiting disabled
+			return true;
+		};
+
+		let limiter = self
+			.limiters
+			.get_with(client_addr, async move {
+				Arc::new(Mutex::new(RateLimiter::new(requests, period)))
+			})
+			.await;
+
+		let mut limiter = limiter.lock().await;
+		limiter.try_acquire()
+	}
+
+	/// Counts a likely abusive connection (i.e. a silent client) against the client address' rate limit.
+	pub async fn penalize(&self, client_addr: IpAddr) {
+		if let Some(limiter) = self.limiters.get(&client_addr).await {
+			let mut limiter = limiter.lock().await;
+			limiter.requests_remaining = limiter.requests_remaining.saturating_sub(ABUSE_PENALTY);
+		}
+	}
+}
@@ -0,0 +1,124 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	sync::Mutex,
+};
+
+use gas::prelude::*;
+use pegboard::workflows::runner::DisconnectReason;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecentDisconnect {
+	pub disconnect_ts: i64,
+	pub reason: DisconnectReason,
+}
+
+/// Bounded LRU of recently disconnected runners, used to annotate reconnects with why and how long
+/// ago the runner last disconnected (i.e. to diagnose flapping runners).
+pub struct RecentDisconnects {
+	capacity: usize,
+	inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+	next_seq: u64,
+	entries: HashMap<Id, (u64, RecentDisconnect)>,
+	/// Insertion order of entries. Stale items (overwritten or taken entries) are skipped on eviction.
+	order: VecDeque<(Id, u64)>,
+}
+
+impl RecentDisconnects {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		Self::with_capacity(config.pegboard().recent_disconnects_capacity())
+	}
+
+	fn with_capacity(capacity: usize) -> Self {
+		RecentDisconnects {
+			capacity,
+			inner: Mutex::new(Inner::default()),
+		}
+	}
+
+	pub fn record(&self, runner_id: Id, reason: DisconnectReason) {
+		if self.capacity == 0 {
+			return;
+		}
+
+		let mut inner = self.inner.lock().expect("poisoned");
+		let seq = inner.next_seq;
+		inner.next_seq += 1;
+
+		inner.entries.insert(
+			runner_id,
+			(
+				seq,
+				RecentDisconnect {
+					disconnect_ts: util::timestamp::now(),
+					reason,
+				},
+			),
+		);
+		inner.order.push_back((runner_id, seq));
+
+		// Evict least recently disconnected runners. Also bounds the stale items in `order`.
+		while inner.entries.len() > self.capacity || inner.order.len() > self.capacity * 2 {
+			let Some((runner_id, seq)) = inner.order.pop_front() else {
+				break;
+			};
+
+			if inner
+				.entries
+				.get(&runner_id)
+				.is_some_and(|(entry_seq, _)| *entry_seq == seq)
+			{
+				inner.entries.remove(&runner_id);
+			}
+		}
+	}
+
+	/// Removes and returns the last disconnect of the given runner.
+	pub fn take(&self, runner_id: Id) -> Option<RecentDisconnect> {
+		self.inner
+			.lock()
+			.expect("poisoned")
+			.entries
+			.remove(&runner_id)
+			.map(|(_, disconnect)| disconnect)
+	}
+}
+
+pub fn reason_str(reason: DisconnectReason) -> &'static str {
+	match reason {
+		DisconnectReason::Normal => "normal",
+		DisconnectReason::Replaced => "replaced",
+		DisconnectReason::Evicted => "evicted",
+		DisconnectReason::Timeout => "timeout",
+		DisconnectReason::Error => "error",
+		DisconnectReason::Drained => "drained",
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn evicts_least_recent_disconnects() {
+		let recent_disconnects = RecentDisconnects::with_capacity(2);
+		let runner_ids = (0..3).map(|_| Id::new_v1(1)).collect::<Vec<_>>();
+
+		recent_disconnects.record(runner_ids[0], DisconnectReason::Normal);
+		recent_disconnects.record(runner_ids[1], DisconnectReason::Timeout);
+		// Refreshes the first runner
+		recent_disconnects.record(runner_ids[0], DisconnectReason::Error);
+		recent_disconnects.record(runner_ids[2], DisconnectReason::Normal);
+
+		assert!(recent_disconnects.take(runner_ids[1]).is_none());
+		assert_eq!(
+			recent_disconnects.take(runner_ids[0]).map(|x| x.reason),
+			Some(DisconnectReason::Error)
+		);
+		assert!(recent_disconnects.take(runner_ids[0]).is_none());
+		assert!(recent_disconnects.take(runner_ids[2]).is_some());
+	}
+}
@@ -0,0 +1,71 @@
+use std::fmt::Debug;
+
+/// Query parameters with values that are masked in logs.
+const SENSITIVE_QUERY_PARAMS: &[&str] = &["runner_key", "token", "api_token"];
+const REDACTED: &str = "[redacted]";
+
+fn enabled(config: &rivet_config::Config) -> bool {
+	!config.pegboard().unredacted_logs()
+}
+
+/// Formats the uri for logging with the values of sensitive query parameters masked.
+pub fn uri(config: &rivet_config::Config, uri: &hyper::Uri) -> String {
+	let Some(query) = uri.query().filter(|_| enabled(config)) else {
+		return uri.to_string();
+	};
+
+	let query = query
+		.split('&')
+		.map(|pair| match pair.split_once('=') {
+			Some((name, _)) if SENSITIVE_QUERY_PARAMS.contains(&name) => {
+				format!("{name}={REDACTED}")
+			}
+			_ => pair.to_string(),
+		})
+		.collect::<Vec<_>>()
+		.join("&");
+
+	format!("{}?{query}", uri.path())
+}
+
+/// Formats a value that may contain sensitive data (i.e. raw messages or packets) for logging.
+pub fn debug(config: &rivet_config::Config, value: &impl Debug) -> String {
+	if enabled(config) {
+		REDACTED.to_string()
+	} else {
+		format!("{value:?}")
+	}
+}
+
+/// Formats a packet for debugging. Only the type (i.e. enum variant) is kept if redaction is enabled.
+pub fn summary(config: &rivet_config::Config, value: &impl Debug) -> String {
+	let formatted = format!("{value:?}");
+
+	if enabled(config) {
+		formatted
+			.split(|c: char| !c.is_alphanumeric() && c != '_')
+			.next()
+			.unwrap_or_default()
+			.to_string()
+	} else {
+		formatted
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn masks_sensitive_query_params() {
+		let config = rivet_config::Config::from_root(Default::default());
+		let uri = "/?protocol_version=1&namespace=default&runner_key=secret"
+			.parse()
+			.unwrap();
+
+		assert_eq!(
+			super::uri(&config, &uri),
+			"/?protocol_version=1&namespace=default&runner_key=[redacted]"
+		);
+	}
+}
@@ -0,0 +1,278 @@
+use std::{
+	sync::atomic::{AtomicBool, Ordering},
+	time::Duration,
+};
+
+use crate::metrics;
+
+/// Tracks the CPU and memory usage of the host (or container) to shed new work while the instance is
+/// overloaded, see `Pegboard::resource_pressure`.
+///
+/// Shedding starts once either usage crosses its threshold and stops once both drop below 90% of
+/// it to prevent flapping. Usage is sampled from procfs and the cgroup filesystem, so shedding is
+/// never enabled on platforms other than Linux.
+pub struct ResourcePressure {
+	cpu_threshold: Option<f64>,
+	memory_threshold: Option<f64>,
+	shed_kv: bool,
+	retry_after_ms: u64,
+	sample_interval: Duration,
+	shedding: AtomicBool,
+}
+
+/// Usage as a fraction (0 to 1). Not set if it could not be sampled.
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+	cpu: Option<f64>,
+	memory: Option<f64>,
+}
+
+impl ResourcePressure {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		let pressure = config.pegboard().resource_pressure();
+
+		ResourcePressure {
+			cpu_threshold: pressure.and_then(|pressure| pressure.cpu_threshold),
+			memory_threshold: pressure.and_then(|pressure| pressure.memory_threshold),
+			shed_kv: pressure.is_some_and(|pressure| pressure.shed_kv()),
+			retry_after_ms: pressure
+				.map(|pressure| pressure.retry_after_ms())
+				.unwrap_or_default(),
+			sample_interval: pressure
+				.map(|pressure| pressure.sample_interval())
+				.unwrap_or_default(),
+			shedding: AtomicBool::new(false),
+		}
+	}
+
+	fn is_enabled(&self) -> bool {
+		self.cpu_threshold.is_some() || self.memory_threshold.is_some()
+	}
+
+	/// Returns the retry after hint (in ms) if new connections should be shed.
+	pub fn shed_retry_after_ms(&self) -> Option<u64> {
+		self.shedding
+			.load(Ordering::Acquire)
+			.then_some(self.retry_after_ms)
+	}
+
+	/// Returns the retry after hint (in ms) if KV requests of non-system runners should be shed.
+	pub fn shed_kv_retry_after_ms(&self) -> Option<u64> {
+		self.shed_kv.then(|| self.shed_retry_after_ms()).flatten()
+	}
+
+	/// Re-evaluates the shedding state from the latest usage sample.
+	fn update(&self, usage: Usage) {
+		let was_shedding = self.shedding.load(Ordering::Acquire);
+		let shedding = exceeded(self.cpu_threshold, usage.cpu, was_shedding)
+			|| exceeded(self.memory_threshold, usage.memory, was_shedding);
+		if shedding != was_shedding {
+			tracing::warn!(
+				?shedding,
+				cpu=?usage.cpu,
+				memory=?usage.memory,
+				"resource pressure shedding changed"
+			);
+		}
+
+		self.shedding.store(shedding, Ordering::Release);
+		metrics::RESOURCE_PRESSURE_SHEDDING.record(shedding as u64, &[]);
+	}
+}
+
+/// Uses a lower threshold for leaving the shedding state than for entering it to prevent flapping.
+fn exceeded(threshold: Option<f64>, usage: Option<f64>, shedding: bool) -> bool {
+	let (Some(threshold), Some(usage)) = (threshold, usage) else {
+		return false;
+	};
+	let factor = if shedding { 0.9 } else { 1.0 };
+
+	usage > threshold * factor
+}
+
+/// Cumulative CPU time in clock ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CpuTimes {
+	busy: u64,
+	total: u64,
+}
+
+/// Parses the aggregate `cpu` line of `/proc/stat`. Idle and iowait time count as idle.
+fn parse_cpu_times(stat: &str) -> Option<CpuTimes> {
+	let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+	let times = line
+		.split_whitespace()
+		.skip(1)
+		.map(|time| time.parse::<u64>().ok())
+		.collect::<Option<Vec<_>>>()?;
+
+	// Guest time is already included in user time
+	let total = times.iter().take(8).sum::<u64>();
+	let idle = times.get(3)? + times.get(4).copied().unwrap_or_default();
+
+	Some(CpuTimes {
+		busy: total.saturating_sub(idle),
+		total,
+	})
+}
+
+/// Fraction of CPU time in use between two samples.
+fn cpu_usage(prev: CpuTimes, cur: CpuTimes) -> Option<f64> {
+	let total = cur
+		.total
+		.checked_sub(prev.total)
+		.filter(|total| *total != 0)?;
+
+	Some(cur.busy.saturating_sub(prev.busy) as f64 / total as f64)
+}
+
+/// Parses the fraction of memory in use from `/proc/meminfo`.
+fn parse_meminfo(meminfo: &str) -> Option<f64> {
+	let field = |name: &str| {
+		meminfo.lines().find_map(|line| {
+			line.strip_prefix(name)?
+				.trim()
+				.strip_suffix("kB")?
+				.trim()
+				.parse::<u64>()
+				.ok()
+		})
+	};
+	let total = field("MemTotal:").filter(|total| *total != 0)?;
+	let available = field("MemAvailable:")?;
+
+	Some(1.0 - available as f64 / total as f64)
+}
+
+/// Fraction of memory in use relative to the cgroup (v2) memory limit, or to total system memory if the
+/// cgroup has no limit.
+async fn memory_usage() -> Option<f64> {
+	let cgroup = tokio::try_join!(
+		tokio::fs::read_to_string("/sys/fs/cgroup/memory.current"),
+		tokio::fs::read_to_string("/sys/fs/cgroup/memory.max"),
+	);
+	// The limit is `max` if the cgroup has no limit
+	if let Ok((current, max)) = cgroup {
+		let current = current.trim().parse::<u64>();
+		let max = max.trim().parse::<u64>();
+
+		if let (Ok(current), Ok(max)) = (current, max)
+			&& max != 0
+		{
+			return Some(current as f64 / max as f64);
+		}
+	}
+
+	match tokio::fs::read_to_string("/proc/meminfo").await {
+		Ok(meminfo) => parse_meminfo(&meminfo),
+		Err(err) => {
+			tracing::debug!(?err, "failed reading memory usage");
+			None
+		}
+	}
+}
+
+async fn cpu_times() -> Option<CpuTimes> {
+	match tokio::fs::read_to_string("/proc/stat").await {
+		Ok(stat) => parse_cpu_times(&stat),
+		Err(err) => {
+			tracing::debug!(?err, "failed reading cpu usage");
+			None
+		}
+	}
+}
+
+/// Periodically samples resource usage and updates the shedding state. Usage that cannot be sampled
+/// never causes shedding. Exits immediately if resource pressure shedding is disabled.
+#[tracing::instrument(skip_all)]
+pub async fn thread(pressure: &ResourcePressure) {
+	if !pressure.is_enabled() {
+		tracing::debug!("resource pressure shedding disabled");
+		return;
+	}
+
+	if !cfg!(target_os = "linux") {
+		tracing::warn!("resource pressure shedding is only supported on linux");
+		return;
+	}
+
+	let mut interval = tokio::time::interval(pressure.sample_interval);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+	let mut prev_cpu_times = None;
+
+	loop {
+		interval.tick().await;
+
+		let cur_cpu_times = cpu_times().await;
+		let usage = Usage {
+			cpu: prev_cpu_times
+				.zip(cur_cpu_times)
+				.and_then(|(prev, cur)| cpu_usage(prev, cur)),
+			memory: memory_usage().await,
+		};
+		prev_cpu_times = cur_cpu_times;
+
+		if let Some(cpu) = usage.cpu {
+			metrics::RESOURCE_CPU_USAGE.record(cpu, &[]);
+		}
+		if let Some(memory) = usage.memory {
+			metrics::RESOURCE_MEMORY_USAGE.record(memory, &[]);
+		}
+
+		pressure.update(usage);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sheds_until_usage_drops_below_threshold() {
+		let pressure = ResourcePressure {
+			cpu_threshold: Some(0.8),
+			memory_threshold: None,
+			shed_kv: false,
+			retry_after_ms: 1_000,
+			sample_interval: Duration::from_secs(1),
+			shedding: AtomicBool::new(false),
+		};
+		let usage = |cpu| Usage {
+			cpu: Some(cpu),
+			memory: Some(0.99),
+		};
+
+		pressure.update(usage(0.85));
+		assert_eq!(pressure.shed_retry_after_ms(), Some(1_000));
+		assert_eq!(pressure.shed_kv_retry_after_ms(), None);
+
+		// Still above the lower threshold for leaving the shedding state
+		pressure.update(usage(0.75));
+		assert!(pressure.shed_retry_after_ms().is_some());
+
+		pressure.update(usage(0.7));
+		assert!(pressure.shed_retry_after_ms().is_none());
+
+		// Usage that could not be sampled does not shed
+		pressure.update(Usage::default());
+		assert!(pressure.shed_retry_after_ms().is_none());
+	}
+
+	#[test]
+	fn parses_procfs_usage() {
+		let prev = parse_cpu_times("cpu  100 0 100 700 100 0 0 0 0 0\ncpu0 1 2 3 4 5 6 7 8 9 10\n");
+		let cur = parse_cpu_times("cpu  250 0 250 750 150 0 0 0 0 0\n");
+		assert_eq!(
+			prev,
+			Some(CpuTimes {
+				busy: 200,
+				total: 1000
+			})
+		);
+		assert_eq!(cpu_usage(prev.unwrap(), cur.unwrap()), Some(0.75));
+
+		let meminfo = "MemTotal:       1000 kB\nMemFree:         100 kB\nMemAvailable:    250 kB\n";
+		assert_eq!(parse_meminfo(meminfo), Some(0.75));
+	}
+}
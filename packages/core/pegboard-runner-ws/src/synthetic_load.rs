@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rivet_runner_protocol::*;
+
+/// Marks a connection's KV requests as synthetic load test traffic, see `ToServerSyntheticLoad`. Can only
+/// be enabled in builds with the `synthetic-load` feature, other builds reject the message.
+///
+/// Synthetic requests go through the same validation, dispatch and serialization as real requests but
+/// are not counted towards KV pressure or the regular KV request metrics.
+#[derive(Default)]
+pub struct SyntheticLoad {
+	enabled: AtomicBool,
+	skip_writes: AtomicBool,
+}
+
+impl SyntheticLoad {
+	#[cfg(feature = "synthetic-load")]
+	pub fn enable(&self, skip_writes: bool) {
+		self.skip_writes.store(skip_writes, Ordering::Release);
+		self.enabled.store(true, Ordering::Release);
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.enabled.load(Ordering::Acquire)
+	}
+
+	/// Returns the response to send instead of running the request if it is a write that should be
+	/// skipped.
+	pub fn skipped_write_response(&self, data: &KvRequestData) -> Option<KvResponseData> {
+		if !self.is_enabled() || !self.skip_writes.load(Ordering::Acquire) {
+			return None;
+		}
+
+		match data {
+			KvRequestData::KvPutRequest(_) => Some(KvResponseData::KvPutResponse),
+			KvRequestData::KvDeleteRequest(_) => Some(KvResponseData::KvDeleteResponse),
+			KvRequestData::KvDropRequest => Some(KvResponseData::KvDropResponse),
+			KvRequestData::KvGetRequest(_) | KvRequestData::KvListRequest(_) => None,
+		}
+	}
+}
@@ -0,0 +1,201 @@
+use std::{future::Future, time::Duration};
+
+use gas::prelude::*;
+use rivet_metrics::KeyValue;
+use tokio::{
+	io::AsyncWriteExt,
+	net::TcpStream,
+	sync::{Semaphore, SemaphorePermit},
+};
+
+use crate::metrics;
+
+/// Retry hint sent with shed upgrades.
+const SHED_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Limits the websocket upgrades and handshakes in progress at once, see
+/// `Pegboard::max_pending_upgrades`.
+///
+/// Each connection holds a slot from before its websocket upgrade until its init handshake completes.
+/// During bursts, new connections wait up to the queue timeout for a free slot and are then rejected
+/// with a plain 503 before the upgrade, which is cheaper than completing the upgrade only to close the
+/// socket. Shed connections are not counted against the client's rate limit, which applies after the
+/// upgrade. Upgrades are bounded by the upgrade timeout so clients stalling mid-request (i.e.
+/// slowloris) cannot hold slots forever.
+pub struct UpgradeLimiter {
+	limit: usize,
+	semaphore: Option<Semaphore>,
+	queue_timeout: Duration,
+	upgrade_timeout: Duration,
+}
+
+impl UpgradeLimiter {
+	pub fn new(config: &rivet_config::Config) -> Self {
+		let limit = config
+			.pegboard()
+			.max_pending_upgrades()
+			.map(|limit| limit.min(Semaphore::MAX_PERMITS));
+
+		UpgradeLimiter {
+			limit: limit.unwrap_or_default(),
+			semaphore: limit.map(Semaphore::new),
+			queue_timeout: config.pegboard().upgrade_queue_timeout(),
+			upgrade_timeout: config.pegboard().upgrade_timeout(),
+		}
+	}
+
+	/// Waits for a free slot, held until the returned guard is dropped. Returns the reason the upgrade
+	/// should be shed instead if no slot frees up within the queue timeout.
+	pub async fn acquire(&self) -> Result<UpgradeSlot<'_>, &'static str> {
+		let Some(semaphore) = &self.semaphore else {
+			metrics::UPGRADES_ACCEPTED.add(1, &[]);
+
+			return Ok(UpgradeSlot {
+				limiter: self,
+				permit: None,
+			});
+		};
+
+		let permit = if let Ok(permit) = semaphore.try_acquire() {
+			permit
+		} else if self.queue_timeout.is_zero() {
+			return Err(self.shed("capacity"));
+		} else {
+			match tokio::time::timeout(self.queue_timeout, semaphore.acquire()).await {
+				Ok(Ok(permit)) => permit,
+				Ok(Err(_)) | Err(_) => return Err(self.shed("queue_timeout")),
+			}
+		};
+
+		metrics::UPGRADES_ACCEPTED.add(1, &[]);
+		let slot = UpgradeSlot {
+			limiter: self,
+			permit: Some(permit),
+		};
+		self.record_pending();
+
+		Ok(slot)
+	}
+
+	/// Runs a websocket upgrade, failing if it does not complete within the upgrade timeout. The
+	/// caller drops its slot on failure.
+	pub async fn with_upgrade_timeout<T>(
+		&self,
+		upgrade: impl Future<Output = Result<T>>,
+	) -> Result<T> {
+		match tokio::time::timeout(self.upgrade_timeout, upgrade).await {
+			Ok(res) => res,
+			Err(_) => {
+				metrics::UPGRADES_TIMED_OUT.add(1, &[]);
+				bail!(
+					"websocket upgrade timed out after {:?}",
+					self.upgrade_timeout
+				)
+			}
+		}
+	}
+
+	fn shed(&self, reason: &'static str) -> &'static str {
+		metrics::UPGRADES_SHED.add(1, &[KeyValue::new("reason", reason)]);
+
+		reason
+	}
+
+	fn record_pending(&self) {
+		if let Some(semaphore) = &self.semaphore {
+			let pending = self.limit - semaphore.available_permits();
+			metrics::UPGRADES_PENDING.record(pending as u64, &[]);
+		}
+	}
+}
+
+pub struct UpgradeSlot<'a> {
+	limiter: &'a UpgradeLimiter,
+	permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for UpgradeSlot<'_> {
+	fn drop(&mut self) {
+		if let Some(permit) = self.permit.take() {
+			drop(permit);
+			self.limiter.record_pending();
+		}
+	}
+}
+
+/// Rejects an upgrade request with a 503 and a retry hint, without completing the websocket handshake.
+pub async fn reject(mut stream: TcpStream) -> Result<()> {
+	let body = r#"{"status":"overloaded"}"#;
+	let res = format!(
+		"HTTP/1.1 503 Service Unavailable\r\nretry-after: {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+		SHED_RETRY_AFTER.as_secs(),
+		body.len()
+	);
+	stream.write_all(res.as_bytes()).await?;
+	stream.shutdown().await?;
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::net::TcpListener;
+
+	use super::*;
+
+	fn limiter(queue_timeout: Duration, upgrade_timeout: Duration) -> UpgradeLimiter {
+		UpgradeLimiter {
+			limit: 1,
+			semaphore: Some(Semaphore::new(1)),
+			queue_timeout,
+			upgrade_timeout,
+		}
+	}
+
+	/// Same as `run_connection`, holds a slot for the duration of the upgrade.
+	async fn upgrade(limiter: &UpgradeLimiter, stream: TcpStream) -> Result<()> {
+		let _slot = limiter.acquire().await.map_err(|reason| anyhow!(reason))?;
+		limiter
+			.with_upgrade_timeout(async { Ok(tokio_tungstenite::accept_async(stream).await?) })
+			.await?;
+
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn sheds_after_queue_timeout() {
+		let limiter = limiter(Duration::from_millis(10), Duration::from_secs(5));
+
+		let slot = limiter.acquire().await.unwrap();
+		assert_eq!(limiter.acquire().await.err(), Some("queue_timeout"));
+
+		// Queued upgrades get the slot once it frees up
+		let (second, _) = tokio::join!(limiter.acquire(), async { drop(slot) });
+		assert!(second.is_ok());
+	}
+
+	#[tokio::test]
+	async fn stalled_upgrade_does_not_starve_later_clients() {
+		let limiter = limiter(Duration::from_secs(1), Duration::from_millis(100));
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		// Sends the first byte of its upgrade request and nothing else
+		let mut stalled_client = TcpStream::connect(addr).await.unwrap();
+		stalled_client.write_all(b"G").await.unwrap();
+		let (stalled, _) = listener.accept().await.unwrap();
+
+		let client = tokio::spawn(async move {
+			let stream = TcpStream::connect(addr).await.unwrap();
+			tokio_tungstenite::client_async(format!("ws://{addr}/"), stream).await
+		});
+		let (later, _) = listener.accept().await.unwrap();
+
+		// The later client queues behind the stalled one and gets the slot once it times out
+		let (stalled_res, later_res) =
+			tokio::join!(upgrade(&limiter, stalled), upgrade(&limiter, later));
+		assert!(stalled_res.is_err());
+		later_res.unwrap();
+		client.await.unwrap().unwrap();
+	}
+}
@@ -23,8 +23,7 @@ async fn test_tunnel_bidirectional_forwarding() -> Result<()> {
 	let mut root = rivet_config::config::Root::default();
 	root.pegboard = Some(rivet_config::config::pegboard::Pegboard {
 		port: Some(test_port),
-		host: None,
-		lan_host: None,
+		..Default::default()
 	});
 
 	let config = Config::from_root(root);
@@ -0,0 +1,33 @@
+mod common;
+
+#[test]
+fn runner_dupe_key_race() {
+	common::run(common::TestOpts::new(1), |ctx| async move {
+		let (namespace, _) = common::setup_test_namespace(ctx.leader_dc().guard_port()).await;
+
+		// Connect both runners concurrently so their handshakes race
+		let (runner1, runner2) = tokio::join!(
+			common::setup_runner(ctx.leader_dc(), &namespace, "key-1", 1, 1),
+			common::setup_runner(ctx.leader_dc(), &namespace, "key-1", 1, 1),
+		);
+
+		let res = ctx
+			.leader_dc()
+			.workflow_ctx
+			.op(pegboard::ops::runner::get::Input {
+				runner_ids: vec![runner1.runner_id, runner2.runner_id],
+			})
+			.await
+			.unwrap();
+
+		if runner1.runner_id == runner2.runner_id {
+			// Both connections converged on the same runner
+			assert_eq!(res.runners.len(), 1, "expected a single runner");
+		} else {
+			// The key was claimed by both, exactly one of the runners must be draining
+			let draining = res.runners.iter().filter(|r| r.drain_ts.is_some()).count();
+			assert_eq!(res.runners.len(), 2, "expected two runners");
+			assert_eq!(draining, 1, "expected exactly one draining runner");
+		}
+	});
+}
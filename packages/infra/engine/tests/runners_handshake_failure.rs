@@ -0,0 +1,76 @@
+mod common;
+
+use std::time::Duration;
+
+use pegboard::workflows::runner::{DisconnectReason, Disconnected};
+
+#[test]
+fn runner_handshake_failure_after_dispatch() {
+	common::run(common::TestOpts::new(1), |ctx| async move {
+		let (_, namespace_id) = common::setup_test_namespace(ctx.leader_dc().guard_port()).await;
+		let workflow_ctx = &ctx.leader_dc().workflow_ctx;
+		let runner_id = rivet_util::Id::new_v1(workflow_ctx.config().dc_label());
+
+		// Dispatch the runner workflow like the ws does during the handshake
+		let workflow_id = workflow_ctx
+			.workflow(pegboard::workflows::runner::Input {
+				runner_id,
+				namespace_id,
+				name: "test-runner".to_string(),
+				key: "key-1".to_string(),
+				version: 1,
+				total_slots: 1,
+				preprovision_ttl_ms: None,
+			})
+			.tag("runner_id", runner_id)
+			.unique()
+			.dispatch()
+			.await
+			.unwrap();
+
+		// The handshake fails before the init packet is forwarded, the ws informs the workflow instead
+		workflow_ctx
+			.signal(Disconnected {
+				reason: DisconnectReason::Error,
+			})
+			.to_workflow_id(workflow_id)
+			.send()
+			.await
+			.unwrap();
+
+		// The workflow completes instead of lingering until the runner lost threshold
+		tokio::time::timeout(Duration::from_secs(10), async {
+			loop {
+				let workflow = workflow_ctx
+					.get_workflows(vec![workflow_id])
+					.await
+					.unwrap()
+					.into_iter()
+					.next()
+					.expect("workflow not found");
+
+				if workflow
+					.parse_output::<pegboard::workflows::runner::Workflow>()
+					.unwrap()
+					.is_some()
+				{
+					break;
+				}
+
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+		})
+		.await
+		.expect("orphaned runner workflow did not complete");
+
+		let res = workflow_ctx
+			.op(pegboard::ops::runner::get_by_key::Input {
+				namespace_id,
+				name: "test-runner".to_string(),
+				key: "key-1".to_string(),
+			})
+			.await
+			.unwrap();
+		assert!(res.runner.is_none(), "runner key still claimed");
+	});
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use rivet_runner_protocol::{self as rp, versioned};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use versioned_data_util::OwnedVersionedData;
+
+#[test]
+fn runner_packet_order() {
+	common::run(common::TestOpts::new(1), |ctx| async move {
+		let (namespace, _) = common::setup_test_namespace(ctx.leader_dc().guard_port()).await;
+
+		let url = format!(
+			"ws://127.0.0.1:{}/?protocol_version={}&namespace={namespace}&runner_key=key-1",
+			ctx.leader_dc().test_deps.pegboard_port(),
+			rp::PROTOCOL_VERSION,
+		);
+		let (mut socket, _) = connect_async(url).await.expect("failed to connect");
+
+		send(
+			&mut socket,
+			rp::ToServer::ToServerInit(rp::ToServerInit {
+				name: "test-runner".to_string(),
+				version: 1,
+				total_slots: 1,
+				last_command_idx: None,
+				prepopulate_actor_names: None,
+				metadata: None,
+				metrics_snapshots: None,
+				runner_id: None,
+				priority: None,
+				kv_capabilities: None,
+				exclude_rtt: None,
+			}),
+		)
+		.await;
+
+		// Pipeline KV requests alternating between one rejected before touching the database
+		// (invalid actor id) and one rejected after a database read (actor not owned by the
+		// runner). The fast ones would overtake the slow ones if packets were not processed in
+		// order.
+		let request_count = 20;
+		for request_id in 0..request_count {
+			let actor_id = if request_id % 2 == 0 {
+				rivet_util::Id::new_v1(1).to_string()
+			} else {
+				"invalid".to_string()
+			};
+
+			send(
+				&mut socket,
+				rp::ToServer::ToServerKvRequest(rp::ToServerKvRequest {
+					actor_id,
+					request_id,
+					collection: None,
+					data: rp::KvRequestData::KvGetRequest(rp::KvGetRequest {
+						keys: vec![b"key".to_vec()],
+					}),
+				}),
+			)
+			.await;
+		}
+
+		let response_ids = tokio::time::timeout(Duration::from_secs(10), async {
+			let mut response_ids = Vec::new();
+
+			while response_ids.len() < request_count as usize {
+				let Message::Binary(buf) = socket.next().await.expect("socket closed").unwrap()
+				else {
+					continue;
+				};
+
+				let packet = <versioned::ToClient as OwnedVersionedData>::deserialize(
+					&buf,
+					rp::PROTOCOL_VERSION,
+				)
+				.unwrap();
+				if let rp::ToClient::ToClientKvResponse(res) = packet.message {
+					response_ids.push(res.request_id);
+				}
+			}
+
+			response_ids
+		})
+		.await
+		.expect("missing kv responses");
+
+		assert_eq!(response_ids, (0..request_count).collect::<Vec<_>>());
+	});
+}
+
+async fn send<S>(socket: &mut S, packet: rp::ToServer)
+where
+	S: SinkExt<Message> + Unpin,
+	S::Error: std::fmt::Debug,
+{
+	let buf = <versioned::ToServer as OwnedVersionedData>::serialize(
+		versioned::ToServer::latest(packet),
+		rp::PROTOCOL_VERSION,
+	)
+	.unwrap();
+	socket.send(Message::Binary(buf.into())).await.unwrap();
+}
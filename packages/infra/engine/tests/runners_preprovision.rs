@@ -0,0 +1,96 @@
+mod common;
+
+use std::time::Duration;
+
+#[test]
+fn preprovisioned_runner_expires_without_connection() {
+	common::run(common::TestOpts::new(1), |ctx| async move {
+		let (_, namespace_id) = common::setup_test_namespace(ctx.leader_dc().guard_port()).await;
+		let workflow_ctx = &ctx.leader_dc().workflow_ctx;
+
+		let res = workflow_ctx
+			.op(pegboard::ops::runner::preprovision::Input {
+				namespace_id,
+				name: "test-runner".to_string(),
+				key: "key-1".to_string(),
+				ttl_ms: Some(2_000),
+			})
+			.await
+			.unwrap();
+		assert!(res.created);
+
+		// The workflow claims the key once it starts, connections for the key bind to it from then on
+		let preprovisioned = tokio::time::timeout(Duration::from_secs(10), async {
+			loop {
+				let runner = workflow_ctx
+					.op(pegboard::ops::runner::get_preprovisioned::Input {
+						namespace_id,
+						name: "test-runner".to_string(),
+						key: "key-1".to_string(),
+					})
+					.await
+					.unwrap()
+					.runner;
+
+				if let Some(runner) = runner {
+					break runner;
+				}
+
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+		})
+		.await
+		.expect("pre-provisioned runner never claimed its key");
+		assert_eq!(preprovisioned.runner_id, res.runner_id);
+		assert_eq!(preprovisioned.workflow_id, res.workflow_id);
+
+		// Pre-provisioning the same key again returns the existing workflow
+		let res2 = workflow_ctx
+			.op(pegboard::ops::runner::preprovision::Input {
+				namespace_id,
+				name: "test-runner".to_string(),
+				key: "key-1".to_string(),
+				ttl_ms: None,
+			})
+			.await
+			.unwrap();
+		assert!(!res2.created);
+		assert_eq!(res2.workflow_id, res.workflow_id);
+
+		// No runner connects within the TTL, the workflow completes and releases the key
+		tokio::time::timeout(Duration::from_secs(10), async {
+			loop {
+				let workflow = workflow_ctx
+					.get_workflows(vec![res.workflow_id])
+					.await
+					.unwrap()
+					.into_iter()
+					.next()
+					.expect("workflow not found");
+
+				if workflow
+					.parse_output::<pegboard::workflows::runner::Workflow>()
+					.unwrap()
+					.is_some()
+				{
+					break;
+				}
+
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+		})
+		.await
+		.expect("pre-provisioned runner workflow did not expire");
+
+		let runner = workflow_ctx
+			.op(pegboard::ops::runner::get_preprovisioned::Input {
+				namespace_id,
+				name: "test-runner".to_string(),
+				key: "key-1".to_string(),
+			})
+			.await
+			.unwrap()
+			.runner;
+		assert!(runner.is_none(), "runner key still pre-provisioned");
+	});
+}
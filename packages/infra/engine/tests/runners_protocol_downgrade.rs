@@ -0,0 +1,116 @@
+mod common;
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use rivet_runner_protocol::{self as rp, versioned};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message};
+use versioned_data_util::OwnedVersionedData;
+
+type Socket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+#[test]
+fn runner_protocol_downgrade() {
+	common::run(common::TestOpts::new(1), |ctx| async move {
+		let (namespace, namespace_id) =
+			common::setup_test_namespace(ctx.leader_dc().guard_port()).await;
+		let dc = ctx.leader_dc();
+
+		// Connect with the latest protocol version
+		let mut socket = connect(dc, &namespace, rp::PROTOCOL_VERSION).await;
+		let runner_id = recv_init(&mut socket, rp::PROTOCOL_VERSION).await;
+
+		// Wait for the runner to be inserted so the next connection binds to it by key
+		tokio::time::timeout(Duration::from_secs(10), async {
+			loop {
+				let runner = dc
+					.workflow_ctx
+					.op(pegboard::ops::runner::get_by_key::Input {
+						namespace_id,
+						name: "test-runner".to_string(),
+						key: "key-1".to_string(),
+					})
+					.await
+					.unwrap()
+					.runner;
+
+				if runner.is_some() {
+					break;
+				}
+
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+		})
+		.await
+		.expect("runner was never inserted");
+
+		socket.close(None).await.unwrap();
+
+		// Reconnect the same runner with the previous protocol version (i.e. after a rollback). The
+		// init is sent and received in the v1 format.
+		let mut socket = connect(dc, &namespace, rp::PROTOCOL_VERSION - 1).await;
+		let downgraded_runner_id = recv_init(&mut socket, rp::PROTOCOL_VERSION - 1).await;
+		assert_eq!(downgraded_runner_id, runner_id);
+
+		// The highest version is kept, so the downgrade is still reported
+		let res = dc
+			.workflow_ctx
+			.op(pegboard::ops::runner::record_protocol_version::Input {
+				runner_id: rivet_util::Id::parse(&runner_id).unwrap(),
+				protocol_version: rp::PROTOCOL_VERSION - 1,
+			})
+			.await
+			.unwrap();
+		assert_eq!(res.downgraded_from, Some(rp::PROTOCOL_VERSION));
+	});
+}
+
+async fn connect(dc: &common::TestDatacenter, namespace: &str, protocol_version: u16) -> Socket {
+	let url = format!(
+		"ws://127.0.0.1:{}/?protocol_version={protocol_version}&namespace={namespace}&runner_key=key-1",
+		dc.test_deps.pegboard_port(),
+	);
+	let (mut socket, _) = connect_async(url).await.expect("failed to connect");
+
+	let init = rp::ToServer::ToServerInit(rp::ToServerInit {
+		name: "test-runner".to_string(),
+		version: 1,
+		total_slots: 1,
+		last_command_idx: None,
+		prepopulate_actor_names: None,
+		metadata: None,
+		metrics_snapshots: None,
+		runner_id: None,
+		priority: None,
+		kv_capabilities: None,
+		exclude_rtt: None,
+	});
+	let buf = <versioned::ToServer as OwnedVersionedData>::serialize(
+		versioned::ToServer::latest(init),
+		protocol_version,
+	)
+	.unwrap();
+	socket.send(Message::Binary(buf.into())).await.unwrap();
+
+	socket
+}
+
+/// Returns the runner id from the init sent by the server.
+async fn recv_init(socket: &mut Socket, protocol_version: u16) -> String {
+	tokio::time::timeout(Duration::from_secs(10), async {
+		loop {
+			let Message::Binary(buf) = socket.next().await.expect("socket closed").unwrap() else {
+				continue;
+			};
+
+			let packet =
+				<versioned::ToClient as OwnedVersionedData>::deserialize(&buf, protocol_version)
+					.unwrap();
+			if let rp::ToClient::ToClientInit(init) = packet.message {
+				break init.runner_id;
+			}
+		}
+	})
+	.await
+	.expect("no init received")
+}
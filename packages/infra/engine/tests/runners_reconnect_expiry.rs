@@ -0,0 +1,141 @@
+mod common;
+
+use std::time::Duration;
+
+use futures_util::{StreamExt, TryStreamExt};
+use rivet_util::Id;
+use universaldb::options::StreamingMode;
+use universaldb::utils::IsolationLevel::*;
+
+#[test]
+fn runner_reconnect_reuses_runner_id() {
+	common::run(common::TestOpts::new(1), |ctx| async move {
+		let (namespace, namespace_id) =
+			common::setup_test_namespace(ctx.leader_dc().guard_port()).await;
+
+		let runner1 = common::setup_runner(ctx.leader_dc(), &namespace, "key-1", 1, 1).await;
+		let runner_id = runner1.runner_id;
+		wait_for_alloc_idx(ctx.leader_dc(), namespace_id, &[runner_id]).await;
+
+		// Disconnecting evicts the runner from the alloc idx (no grace period by default)
+		drop(runner1);
+		wait_for_alloc_idx(ctx.leader_dc(), namespace_id, &[]).await;
+
+		// The workflow has not expired, the reconnecting runner binds to it
+		let runner2 = common::setup_runner(ctx.leader_dc(), &namespace, "key-1", 1, 1).await;
+		assert_eq!(runner2.runner_id, runner_id, "runner id not reused");
+
+		wait_for_alloc_idx(ctx.leader_dc(), namespace_id, &[runner_id]).await;
+	});
+}
+
+#[test]
+fn runner_reconnect_after_expiry_gets_new_runner_id() {
+	common::run(common::TestOpts::new(1), |ctx| async move {
+		let (namespace, namespace_id) =
+			common::setup_test_namespace(ctx.leader_dc().guard_port()).await;
+
+		let runner1 = common::setup_runner(ctx.leader_dc(), &namespace, "key-1", 1, 1).await;
+		let old_runner_id = runner1.runner_id;
+		wait_for_alloc_idx(ctx.leader_dc(), namespace_id, &[old_runner_id]).await;
+
+		drop(runner1);
+		wait_for_alloc_idx(ctx.leader_dc(), namespace_id, &[]).await;
+
+		// Mark the runner expired like the workflow does once the runner lost threshold has passed
+		ctx.leader_dc()
+			.workflow_ctx
+			.udb()
+			.unwrap()
+			.run(|tx| async move {
+				let tx = tx.with_subspace(pegboard::keys::subspace());
+				tx.write(
+					&pegboard::keys::runner::ExpiredTsKey::new(old_runner_id),
+					rivet_util::timestamp::now(),
+				)?;
+
+				Ok(())
+			})
+			.await
+			.unwrap();
+
+		// The ping update during the handshake reports the runner as expired, so a new runner id is used
+		// instead of signaling the expiring workflow
+		let runner2 = common::setup_runner(ctx.leader_dc(), &namespace, "key-1", 1, 1).await;
+		assert_ne!(runner2.runner_id, old_runner_id, "expired runner id reused");
+
+		// Only the new runner is eligible for allocation
+		wait_for_alloc_idx(ctx.leader_dc(), namespace_id, &[runner2.runner_id]).await;
+
+		let res = ctx
+			.leader_dc()
+			.workflow_ctx
+			.op(pegboard::ops::runner::update_alloc_idx::Input {
+				runners: vec![pegboard::ops::runner::update_alloc_idx::Runner {
+					runner_id: old_runner_id,
+					action: pegboard::ops::runner::update_alloc_idx::Action::AddIdx,
+				}],
+			})
+			.await
+			.unwrap();
+		assert!(
+			matches!(
+				res.notifications.first().map(|notif| &notif.eligibility),
+				Some(pegboard::ops::runner::update_alloc_idx::RunnerEligibility::Expired)
+			),
+			"old runner not expired"
+		);
+
+		// Expired runners are never re-added to the alloc idx
+		wait_for_alloc_idx(ctx.leader_dc(), namespace_id, &[runner2.runner_id]).await;
+	});
+}
+
+/// Waits until the alloc idx of the test runner name contains exactly the given runners.
+async fn wait_for_alloc_idx(dc: &common::TestDatacenter, namespace_id: Id, expected: &[Id]) {
+	let mut expected = expected.to_vec();
+	expected.sort();
+
+	loop {
+		let mut runner_ids = alloc_idx_runner_ids(dc, namespace_id).await;
+		runner_ids.sort();
+
+		if runner_ids == expected {
+			break;
+		}
+
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	}
+}
+
+async fn alloc_idx_runner_ids(dc: &common::TestDatacenter, namespace_id: Id) -> Vec<Id> {
+	dc.workflow_ctx
+		.udb()
+		.unwrap()
+		.run(|tx| async move {
+			let tx = tx.with_subspace(pegboard::keys::subspace());
+			let runner_alloc_subspace = pegboard::keys::subspace().subspace(
+				&pegboard::keys::ns::RunnerAllocIdxKey::subspace(
+					namespace_id,
+					"test-runner".to_string(),
+				),
+			);
+
+			tx.get_ranges_keyvalues(
+				universaldb::RangeOption {
+					mode: StreamingMode::WantAll,
+					..(&runner_alloc_subspace).into()
+				},
+				Snapshot,
+			)
+			.map(|res| {
+				let (key, _) = tx.read_entry::<pegboard::keys::ns::RunnerAllocIdxKey>(&res?)?;
+
+				Ok(key.runner_id)
+			})
+			.try_collect::<Vec<_>>()
+			.await
+		})
+		.await
+		.unwrap()
+}
@@ -147,6 +147,52 @@ impl<'de> TupleUnpack<'de> for CreateTsKey {
 	}
 }
 
+#[derive(Debug)]
+pub struct StatusKey {
+	namespace_id: Id,
+}
+
+impl StatusKey {
+	pub fn new(namespace_id: Id) -> Self {
+		StatusKey { namespace_id }
+	}
+}
+
+impl FormalKey for StatusKey {
+	type Value = crate::types::NamespaceStatus;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		let repr = *raw.first().context("empty status")?;
+
+		crate::types::NamespaceStatus::from_repr(repr as usize)
+			.with_context(|| format!("invalid namespace status: {repr}"))
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		Ok(vec![value as u8])
+	}
+}
+
+impl TuplePack for StatusKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (DATA, self.namespace_id, STATUS);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for StatusKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (_, namespace_id, _)) = <(usize, Id, usize)>::unpack(input, tuple_depth)?;
+		let v = StatusKey { namespace_id };
+
+		Ok((input, v))
+	}
+}
+
 #[derive(Debug)]
 pub struct ByNameKey {
 	name: String,
@@ -44,11 +44,13 @@ pub(crate) async fn get_inner(
 	let name_key = keys::NameKey::new(namespace_id);
 	let display_name_key = keys::DisplayNameKey::new(namespace_id);
 	let create_ts_key = keys::CreateTsKey::new(namespace_id);
+	let status_key = keys::StatusKey::new(namespace_id);
 
-	let (name, display_name, create_ts) = tokio::try_join!(
+	let (name, display_name, create_ts, status) = tokio::try_join!(
 		tx.read_opt(&name_key, Serializable),
 		tx.read_opt(&display_name_key, Serializable),
 		tx.read_opt(&create_ts_key, Serializable),
+		tx.read_opt(&status_key, Serializable),
 	)?;
 
 	// Namespace not found
@@ -64,5 +66,7 @@ pub(crate) async fn get_inner(
 		name,
 		display_name,
 		create_ts,
+		// Namespaces created before statuses existed have no status key
+		status: status.unwrap_or_default(),
 	}))
 }
@@ -9,6 +9,37 @@ pub struct Namespace {
 	pub name: String,
 	pub display_name: String,
 	pub create_ts: i64,
+	#[serde(default)]
+	pub status: NamespaceStatus,
+}
+
+#[derive(
+	Debug,
+	Default,
+	Clone,
+	Copy,
+	PartialEq,
+	Eq,
+	Hash,
+	Serialize,
+	Deserialize,
+	strum::FromRepr,
+	ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum NamespaceStatus {
+	#[default]
+	Active = 0,
+	// Temporarily disabled, new runners cannot connect
+	Suspended = 1,
+	// Permanently disabled, new runners cannot connect
+	Disabled = 2,
+}
+
+impl NamespaceStatus {
+	pub fn is_active(&self) -> bool {
+		matches!(self, NamespaceStatus::Active)
+	}
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, ToSchema)]
@@ -3,7 +3,7 @@ use gas::prelude::*;
 use serde::{Deserialize, Serialize};
 use universaldb::utils::IsolationLevel::*;
 
-use crate::{errors, keys};
+use crate::{errors, keys, types::NamespaceStatus};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Input {
@@ -55,10 +55,19 @@ pub async fn namespace(ctx: &mut WorkflowCtx, input: &Input) -> Result<()> {
 		.send()
 		.await?;
 
-	// Does nothing yet
 	ctx.repeat(|ctx| {
+		let namespace_id = input.namespace_id;
+
 		async move {
-			ctx.listen::<Update>().await?;
+			let update = ctx.listen::<Update>().await?;
+
+			if let Some(status) = update.status {
+				ctx.activity(UpdateStatusInput {
+					namespace_id,
+					status,
+				})
+				.await?;
+			}
 
 			Ok(Loop::<()>::Continue)
 		}
@@ -78,7 +87,9 @@ pub struct Failed {
 }
 
 #[signal("namespace_update")]
-pub struct Update {}
+pub struct Update {
+	pub status: Option<NamespaceStatus>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash)]
 pub struct ValidateInput {
@@ -176,3 +187,29 @@ async fn insert_db(
 		.await
 		.map_err(Into::into)
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+struct UpdateStatusInput {
+	namespace_id: Id,
+	status: NamespaceStatus,
+}
+
+#[activity(UpdateStatus)]
+async fn update_status(ctx: &ActivityCtx, input: &UpdateStatusInput) -> Result<()> {
+	ctx.udb()?
+		.run(|tx| {
+			let namespace_id = input.namespace_id;
+			let status = input.status;
+
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+
+				tx.write(&keys::StatusKey::new(namespace_id), status)?;
+
+				Ok(())
+			}
+		})
+		.custom_instrument(tracing::info_span!("namespace_update_status_tx"))
+		.await
+		.map_err(Into::into)
+}
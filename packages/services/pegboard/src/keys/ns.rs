@@ -1365,3 +1365,254 @@ impl TuplePack for RunnerNameSubspaceKey {
 		t.pack(w, tuple_depth)
 	}
 }
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RunnerQuarantine {
+	/// Protocol violations counted towards the current quarantine.
+	pub violations: u32,
+	pub last_violation_ts: i64,
+	/// Not set if the runner has not (yet) been quarantined.
+	pub quarantined_until_ts: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct RunnerQuarantineKey {
+	namespace_id: Id,
+	pub runner_key: String,
+}
+
+impl RunnerQuarantineKey {
+	pub fn new(namespace_id: Id, runner_key: String) -> Self {
+		RunnerQuarantineKey {
+			namespace_id,
+			runner_key,
+		}
+	}
+
+	pub fn subspace(namespace_id: Id) -> RunnerQuarantineSubspaceKey {
+		RunnerQuarantineSubspaceKey::new(namespace_id)
+	}
+}
+
+impl FormalKey for RunnerQuarantineKey {
+	type Value = RunnerQuarantine;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		ensure!(raw.len() == 20, "invalid runner quarantine length");
+
+		let quarantined_until_ts = i64::from_be_bytes(raw[12..20].try_into()?);
+
+		Ok(RunnerQuarantine {
+			violations: u32::from_be_bytes(raw[0..4].try_into()?),
+			last_violation_ts: i64::from_be_bytes(raw[4..12].try_into()?),
+			quarantined_until_ts: (quarantined_until_ts != 0).then_some(quarantined_until_ts),
+		})
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		let mut buf = Vec::with_capacity(20);
+		buf.extend_from_slice(&value.violations.to_be_bytes());
+		buf.extend_from_slice(&value.last_violation_ts.to_be_bytes());
+		buf.extend_from_slice(&value.quarantined_until_ts.unwrap_or_default().to_be_bytes());
+
+		Ok(buf)
+	}
+}
+
+impl TuplePack for RunnerQuarantineKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (NAMESPACE, self.namespace_id, RUNNER, QUARANTINE, &self.runner_key);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for RunnerQuarantineKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (_, namespace_id, _, _, runner_key)) =
+			<(usize, Id, usize, usize, String)>::unpack(input, tuple_depth)?;
+		let v = RunnerQuarantineKey {
+			namespace_id,
+			runner_key,
+		};
+
+		Ok((input, v))
+	}
+}
+
+pub struct RunnerQuarantineSubspaceKey {
+	namespace_id: Id,
+}
+
+impl RunnerQuarantineSubspaceKey {
+	pub fn new(namespace_id: Id) -> Self {
+		RunnerQuarantineSubspaceKey { namespace_id }
+	}
+}
+
+impl TuplePack for RunnerQuarantineSubspaceKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (NAMESPACE, self.namespace_id, RUNNER, QUARANTINE);
+		t.pack(w, tuple_depth)
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunnerLastInstance {
+	pub instance_id: String,
+	/// Last time the instance served a connection for the runner key.
+	pub last_connected_ts: i64,
+}
+
+#[derive(Debug)]
+pub struct RunnerLastInstanceKey {
+	namespace_id: Id,
+	pub runner_key: String,
+}
+
+impl RunnerLastInstanceKey {
+	pub fn new(namespace_id: Id, runner_key: String) -> Self {
+		RunnerLastInstanceKey {
+			namespace_id,
+			runner_key,
+		}
+	}
+}
+
+impl FormalKey for RunnerLastInstanceKey {
+	type Value = RunnerLastInstance;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		let (last_connected_ts, instance_id) = raw
+			.split_first_chunk::<8>()
+			.context("invalid runner last instance length")?;
+
+		Ok(RunnerLastInstance {
+			instance_id: String::from_utf8(instance_id.to_vec())?,
+			last_connected_ts: i64::from_be_bytes(*last_connected_ts),
+		})
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		let mut buf = Vec::with_capacity(8 + value.instance_id.len());
+		buf.extend_from_slice(&value.last_connected_ts.to_be_bytes());
+		buf.extend_from_slice(value.instance_id.as_bytes());
+
+		Ok(buf)
+	}
+}
+
+impl TuplePack for RunnerLastInstanceKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (NAMESPACE, self.namespace_id, RUNNER, LAST_INSTANCE, &self.runner_key);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for RunnerLastInstanceKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (_, namespace_id, _, _, runner_key)) =
+			<(usize, Id, usize, usize, String)>::unpack(input, tuple_depth)?;
+		let v = RunnerLastInstanceKey {
+			namespace_id,
+			runner_key,
+		};
+
+		Ok((input, v))
+	}
+}
+
+/// Runner ws drain of a namespace, set while draining. See `SetRunnerWsNamespaceDraining`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RunnerWsDrain {
+	/// Hint sent to rejected runners for how long to wait before reconnecting. Defaults to
+	/// `pegboard.maintenance_retry_after_ms`.
+	pub retry_after_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct RunnerWsDrainKey {
+	pub namespace_id: Id,
+}
+
+impl RunnerWsDrainKey {
+	pub fn new(namespace_id: Id) -> Self {
+		RunnerWsDrainKey { namespace_id }
+	}
+
+	pub fn entire_subspace() -> RunnerWsDrainSubspaceKey {
+		RunnerWsDrainSubspaceKey::new()
+	}
+}
+
+impl FormalKey for RunnerWsDrainKey {
+	type Value = RunnerWsDrain;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		let retry_after_ms = if raw.is_empty() {
+			None
+		} else {
+			Some(u64::from_be_bytes(raw.try_into()?))
+		};
+
+		Ok(RunnerWsDrain { retry_after_ms })
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		Ok(value
+			.retry_after_ms
+			.map(|x| x.to_be_bytes().to_vec())
+			.unwrap_or_default())
+	}
+}
+
+impl TuplePack for RunnerWsDrainKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (NAMESPACE, DRAIN, self.namespace_id);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for RunnerWsDrainKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (_, _, namespace_id)) = <(usize, usize, Id)>::unpack(input, tuple_depth)?;
+		let v = RunnerWsDrainKey { namespace_id };
+
+		Ok((input, v))
+	}
+}
+
+#[derive(Default)]
+pub struct RunnerWsDrainSubspaceKey {}
+
+impl RunnerWsDrainSubspaceKey {
+	pub fn new() -> Self {
+		RunnerWsDrainSubspaceKey {}
+	}
+}
+
+impl TuplePack for RunnerWsDrainSubspaceKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (NAMESPACE, DRAIN);
+		t.pack(w, tuple_depth)
+	}
+}
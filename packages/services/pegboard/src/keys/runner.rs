@@ -642,6 +642,96 @@ impl<'de> TupleUnpack<'de> for LastRttKey {
 	}
 }
 
+#[derive(Debug)]
+pub struct LastLoadKey {
+	runner_id: Id,
+}
+
+impl LastLoadKey {
+	pub fn new(runner_id: Id) -> Self {
+		LastLoadKey { runner_id }
+	}
+}
+
+impl FormalKey for LastLoadKey {
+	/// Load score in thousandths (0 = idle, 1000 = fully loaded).
+	type Value = u32;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		Ok(u32::from_be_bytes(raw.try_into()?))
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		Ok(value.to_be_bytes().to_vec())
+	}
+}
+
+impl TuplePack for LastLoadKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (RUNNER, DATA, self.runner_id, LAST_LOAD);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for LastLoadKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (_, _, runner_id, _)) =
+			<(usize, usize, Id, usize)>::unpack(input, tuple_depth)?;
+		let v = LastLoadKey { runner_id };
+
+		Ok((input, v))
+	}
+}
+
+#[derive(Debug)]
+pub struct ProtocolVersionKey {
+	runner_id: Id,
+}
+
+impl ProtocolVersionKey {
+	pub fn new(runner_id: Id) -> Self {
+		ProtocolVersionKey { runner_id }
+	}
+}
+
+impl FormalKey for ProtocolVersionKey {
+	/// Highest protocol version the runner has connected with.
+	type Value = u16;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		Ok(u16::from_be_bytes(raw.try_into()?))
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		Ok(value.to_be_bytes().to_vec())
+	}
+}
+
+impl TuplePack for ProtocolVersionKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (RUNNER, DATA, self.runner_id, PROTOCOL_VERSION);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for ProtocolVersionKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (_, _, runner_id, _)) =
+			<(usize, usize, Id, usize)>::unpack(input, tuple_depth)?;
+		let v = ProtocolVersionKey { runner_id };
+
+		Ok((input, v))
+	}
+}
+
 #[derive(Debug)]
 pub struct ConnectedTsKey {
 	runner_id: Id,
@@ -732,6 +822,53 @@ impl<'de> TupleUnpack<'de> for ExpiredTsKey {
 	}
 }
 
+/// Set while the runner's workflow is pre-provisioned and waiting for the runner to connect, see
+/// `ops::runner::preprovision`.
+#[derive(Debug)]
+pub struct PreprovisionExpireTsKey {
+	runner_id: Id,
+}
+
+impl PreprovisionExpireTsKey {
+	pub fn new(runner_id: Id) -> Self {
+		PreprovisionExpireTsKey { runner_id }
+	}
+}
+
+impl FormalKey for PreprovisionExpireTsKey {
+	// Timestamp.
+	type Value = i64;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		Ok(i64::from_be_bytes(raw.try_into()?))
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		Ok(value.to_be_bytes().to_vec())
+	}
+}
+
+impl TuplePack for PreprovisionExpireTsKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (RUNNER, DATA, self.runner_id, PREPROVISION_EXPIRE_TS);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for PreprovisionExpireTsKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (_, _, runner_id, _)) =
+			<(usize, usize, Id, usize)>::unpack(input, tuple_depth)?;
+		let v = PreprovisionExpireTsKey { runner_id };
+
+		Ok((input, v))
+	}
+}
+
 pub struct MetadataKey {
 	runner_id: Id,
 }
@@ -815,3 +952,145 @@ impl<'de> TupleUnpack<'de> for MetadataChunkKey {
 		Ok((input, v))
 	}
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedConnection {
+	pub workflow_id: Id,
+	pub namespace_id: Id,
+	pub namespace_name: String,
+	pub runner_key: String,
+	pub export_ts: i64,
+}
+
+#[derive(Debug)]
+pub struct ExportedConnectionKey {
+	instance_id: String,
+	pub runner_id: Id,
+}
+
+impl ExportedConnectionKey {
+	pub fn new(instance_id: String, runner_id: Id) -> Self {
+		ExportedConnectionKey {
+			instance_id,
+			runner_id,
+		}
+	}
+
+	pub fn subspace(instance_id: String) -> ExportedConnectionSubspaceKey {
+		ExportedConnectionSubspaceKey::new(instance_id)
+	}
+}
+
+impl FormalKey for ExportedConnectionKey {
+	type Value = ExportedConnection;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		serde_json::from_slice(raw).map_err(Into::into)
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		serde_json::to_vec(&value).map_err(Into::into)
+	}
+}
+
+impl TuplePack for ExportedConnectionKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (RUNNER, CONNECTION_EXPORT, &self.instance_id, self.runner_id);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for ExportedConnectionKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (_, _, instance_id, runner_id)) =
+			<(usize, usize, String, Id)>::unpack(input, tuple_depth)?;
+		let v = ExportedConnectionKey {
+			instance_id,
+			runner_id,
+		};
+
+		Ok((input, v))
+	}
+}
+
+pub struct ExportedConnectionSubspaceKey {
+	instance_id: String,
+}
+
+impl ExportedConnectionSubspaceKey {
+	pub fn new(instance_id: String) -> Self {
+		ExportedConnectionSubspaceKey { instance_id }
+	}
+}
+
+impl TuplePack for ExportedConnectionSubspaceKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (RUNNER, CONNECTION_EXPORT, &self.instance_id);
+		t.pack(w, tuple_depth)
+	}
+}
+
+/// Runner ws maintenance mode, set while enabled. See `SetRunnerWsMaintenanceMode`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct WsMaintenance {
+	/// Hint sent to rejected runners for how long to wait before reconnecting.
+	pub retry_after_ms: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct WsMaintenanceKey {}
+
+impl WsMaintenanceKey {
+	pub fn new() -> Self {
+		WsMaintenanceKey {}
+	}
+}
+
+impl FormalKey for WsMaintenanceKey {
+	type Value = WsMaintenance;
+
+	fn deserialize(&self, raw: &[u8]) -> Result<Self::Value> {
+		let retry_after_ms = if raw.is_empty() {
+			None
+		} else {
+			Some(u64::from_be_bytes(raw.try_into()?))
+		};
+
+		Ok(WsMaintenance { retry_after_ms })
+	}
+
+	fn serialize(&self, value: Self::Value) -> Result<Vec<u8>> {
+		Ok(value
+			.retry_after_ms
+			.map(|x| x.to_be_bytes().to_vec())
+			.unwrap_or_default())
+	}
+}
+
+impl TuplePack for WsMaintenanceKey {
+	fn pack<W: std::io::Write>(
+		&self,
+		w: &mut W,
+		tuple_depth: TupleDepth,
+	) -> std::io::Result<VersionstampOffset> {
+		let t = (RUNNER, MAINTENANCE);
+		t.pack(w, tuple_depth)
+	}
+}
+
+impl<'de> TupleUnpack<'de> for WsMaintenanceKey {
+	fn unpack(input: &[u8], tuple_depth: TupleDepth) -> PackResult<(&[u8], Self)> {
+		let (input, (_, _)) = <(usize, usize)>::unpack(input, tuple_depth)?;
+		let v = WsMaintenanceKey {};
+
+		Ok((input, v))
+	}
+}
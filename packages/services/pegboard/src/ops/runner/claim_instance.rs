@@ -0,0 +1,65 @@
+use anyhow::Result;
+use gas::prelude::*;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+/// How long an instance keeps its claim on a runner key after last serving it. Once expired, the next
+/// instance to serve the runner key takes over.
+const INSTANCE_AFFINITY_TTL_MS: i64 = util::duration::minutes(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+	pub runner_key: String,
+	pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	/// Set if another instance recently served the runner key. Runners should prefer that instance on
+	/// their next connection attempt since it holds the relevant cached state.
+	pub preferred_instance_id: Option<String>,
+}
+
+/// Records that the given instance is serving a runner key, unless another instance served it recently.
+#[operation]
+pub async fn pegboard_runner_claim_instance(ctx: &OperationCtx, input: &Input) -> Result<Output> {
+	let preferred_instance_id = ctx
+		.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+
+				let now = util::timestamp::now();
+				let last_instance_key =
+					keys::ns::RunnerLastInstanceKey::new(input.namespace_id, input.runner_key);
+
+				if let Some(last_instance) = tx.read_opt(&last_instance_key, Serializable).await? {
+					if last_instance.instance_id != input.instance_id
+						&& now.saturating_sub(last_instance.last_connected_ts)
+							< INSTANCE_AFFINITY_TTL_MS
+					{
+						return Ok(Some(last_instance.instance_id));
+					}
+				}
+
+				tx.write(
+					&last_instance_key,
+					keys::ns::RunnerLastInstance {
+						instance_id: input.instance_id,
+						last_connected_ts: now,
+					},
+				)?;
+
+				Ok(None)
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_claim_instance_tx"))
+		.await?;
+
+	Ok(Output {
+		preferred_instance_id,
+	})
+}
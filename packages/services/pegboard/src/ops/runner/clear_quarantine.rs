@@ -0,0 +1,33 @@
+use anyhow::Result;
+use gas::prelude::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+	pub runner_key: String,
+}
+
+/// Lifts the quarantine of a runner key and resets its protocol violations.
+#[operation]
+pub async fn pegboard_runner_clear_quarantine(ctx: &OperationCtx, input: &Input) -> Result<()> {
+	ctx.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+
+				tx.delete(&keys::ns::RunnerQuarantineKey::new(
+					input.namespace_id,
+					input.runner_key,
+				));
+
+				Ok(())
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_clear_quarantine_tx"))
+		.await?;
+
+	Ok(())
+}
@@ -0,0 +1,60 @@
+use anyhow::Result;
+use gas::prelude::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub instance_id: String,
+	pub connections: Vec<Connection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Connection {
+	pub runner_id: Id,
+	pub workflow_id: Id,
+	pub namespace_id: Id,
+	pub namespace_name: String,
+	pub runner_key: String,
+}
+
+/// Replaces the exported connection registry of a runner ws instance, read back with
+/// `take_exported_connections` by the instance replacing it.
+#[operation]
+pub async fn pegboard_runner_export_connections(ctx: &OperationCtx, input: &Input) -> Result<()> {
+	ctx.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let export_subspace = keys::subspace().subspace(
+					&keys::runner::ExportedConnectionKey::subspace(input.instance_id.clone()),
+				);
+				tx.clear_subspace_range(&export_subspace);
+
+				let tx = tx.with_subspace(keys::subspace());
+				let export_ts = util::timestamp::now();
+
+				for conn in input.connections {
+					tx.write(
+						&keys::runner::ExportedConnectionKey::new(
+							input.instance_id.clone(),
+							conn.runner_id,
+						),
+						keys::runner::ExportedConnection {
+							workflow_id: conn.workflow_id,
+							namespace_id: conn.namespace_id,
+							namespace_name: conn.namespace_name,
+							runner_key: conn.runner_key,
+							export_ts,
+						},
+					)?;
+				}
+
+				Ok(())
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_export_connections_tx"))
+		.await?;
+
+	Ok(())
+}
@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use gas::prelude::*;
+
+/// How long to wait for a runner ws instance to report a live connection.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+	pub name: String,
+	pub key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	/// Set if any runner ws instance in this datacenter holds a live connection for the runner.
+	pub connection: Option<Connection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Connection {
+	pub runner_id: Id,
+	/// Last measured round trip time in ms.
+	pub rtt: u32,
+}
+
+/// Sent to all runner ws instances. Only the instance holding the connection responds.
+#[message("pegboard_runner_connection_query")]
+pub struct ConnectionQuery {
+	pub request_id: Id,
+	pub runner_id: Id,
+}
+
+#[message("pegboard_runner_connection_query_response")]
+pub struct ConnectionQueryResponse {
+	pub runner_id: Id,
+	pub rtt: u32,
+}
+
+#[operation]
+pub async fn pegboard_runner_get_connection(ctx: &OperationCtx, input: &Input) -> Result<Output> {
+	let runner_res = ctx
+		.op(super::get_by_key::Input {
+			namespace_id: input.namespace_id,
+			name: input.name.clone(),
+			key: input.key.clone(),
+		})
+		.await?;
+
+	let Some(runner) = runner_res.runner else {
+		return Ok(Output { connection: None });
+	};
+
+	let request_id = Id::new_v1(ctx.config().dc_label());
+
+	// Set up subscription before sending the query
+	let mut sub = ctx
+		.subscribe::<ConnectionQueryResponse>(("request_id", request_id))
+		.await?;
+
+	ctx.msg(ConnectionQuery {
+		request_id,
+		runner_id: runner.runner_id,
+	})
+	.send()
+	.await?;
+
+	// Instances without a connection do not respond, so a timeout means the runner is not connected
+	let connection = match tokio::time::timeout(QUERY_TIMEOUT, sub.next()).await {
+		Ok(msg) => {
+			let msg = msg?.into_body();
+
+			Some(Connection {
+				runner_id: msg.runner_id,
+				rtt: msg.rtt,
+			})
+		}
+		Err(_) => None,
+	};
+
+	Ok(Output { connection })
+}
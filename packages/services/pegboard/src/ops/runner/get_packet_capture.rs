@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use gas::prelude::*;
+
+/// How long to wait for the runner ws instance holding the connection to respond.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub runner_id: Id,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	/// Recent packets of the runner's connection, oldest first. Not set if the runner is not connected or
+	/// packet capture is disabled for its namespace.
+	pub packets: Option<Vec<CapturedPacket>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapturedPacket {
+	pub ts: i64,
+	pub direction: PacketDirection,
+	/// Debug representation of the packet. Only contains the packet type if log redaction is enabled.
+	pub packet: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketDirection {
+	ToServer,
+	ToClient,
+}
+
+/// Sent to all runner ws instances. Only the instance holding the connection responds.
+#[message("pegboard_runner_packet_capture_query")]
+pub struct PacketCaptureQuery {
+	pub request_id: Id,
+	pub runner_id: Id,
+}
+
+#[message("pegboard_runner_packet_capture_query_response")]
+pub struct PacketCaptureQueryResponse {
+	pub packets: Vec<CapturedPacket>,
+}
+
+#[operation]
+pub async fn pegboard_runner_get_packet_capture(
+	ctx: &OperationCtx,
+	input: &Input,
+) -> Result<Output> {
+	let request_id = Id::new_v1(ctx.config().dc_label());
+
+	// Set up subscription before sending the query
+	let mut sub = ctx
+		.subscribe::<PacketCaptureQueryResponse>(("request_id", request_id))
+		.await?;
+
+	ctx.msg(PacketCaptureQuery {
+		request_id,
+		runner_id: input.runner_id,
+	})
+	.send()
+	.await?;
+
+	let packets = match tokio::time::timeout(QUERY_TIMEOUT, sub.next()).await {
+		Ok(msg) => Some(msg?.into_body().packets),
+		Err(_) => None,
+	};
+
+	Ok(Output { packets })
+}
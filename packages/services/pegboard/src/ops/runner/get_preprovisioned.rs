@@ -0,0 +1,67 @@
+use anyhow::Result;
+use gas::prelude::*;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+	pub name: String,
+	pub key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	pub runner: Option<PreprovisionedRunner>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreprovisionedRunner {
+	pub runner_id: Id,
+	pub workflow_id: Id,
+}
+
+/// Returns the unexpired pre-provisioned workflow for the key, if any. See `preprovision`.
+#[operation]
+pub async fn pegboard_runner_get_preprovisioned(
+	ctx: &OperationCtx,
+	input: &Input,
+) -> Result<Output> {
+	let runner = ctx
+		.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+
+				let Some(runner_by_key) = tx
+					.read_opt(
+						&keys::ns::RunnerByKeyKey::new(input.namespace_id, input.name, input.key),
+						Serializable,
+					)
+					.await?
+				else {
+					return Ok(None);
+				};
+
+				let expire_ts = tx
+					.read_opt(
+						&keys::runner::PreprovisionExpireTsKey::new(runner_by_key.runner_id),
+						Serializable,
+					)
+					.await?;
+
+				Ok(expire_ts
+					.filter(|expire_ts| *expire_ts > util::timestamp::now())
+					.map(|_| PreprovisionedRunner {
+						runner_id: runner_by_key.runner_id,
+						workflow_id: runner_by_key.workflow_id,
+					}))
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_get_preprovisioned_tx"))
+		.await?;
+
+	Ok(Output { runner })
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+use gas::prelude::*;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+	pub runner_key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	/// Set if the runner key is currently quarantined.
+	pub quarantined_until_ts: Option<i64>,
+}
+
+#[operation]
+pub async fn pegboard_runner_get_quarantine(ctx: &OperationCtx, input: &Input) -> Result<Output> {
+	let quarantine = ctx
+		.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+
+				tx.read_opt(
+					&keys::ns::RunnerQuarantineKey::new(input.namespace_id, input.runner_key),
+					Serializable,
+				)
+				.await
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_get_quarantine_tx"))
+		.await?;
+
+	let now = util::timestamp::now();
+
+	Ok(Output {
+		quarantined_until_ts: quarantine
+			.and_then(|quarantine| quarantine.quarantined_until_ts)
+			.filter(|until_ts| *until_ts > now),
+	})
+}
@@ -0,0 +1,33 @@
+use anyhow::Result;
+use gas::prelude::*;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	/// Set while maintenance mode is enabled.
+	pub maintenance: Option<keys::runner::WsMaintenance>,
+}
+
+#[operation]
+pub async fn pegboard_runner_get_ws_maintenance_mode(
+	ctx: &OperationCtx,
+	_input: &Input,
+) -> Result<Output> {
+	let maintenance = ctx
+		.udb()?
+		.run(|tx| async move {
+			let tx = tx.with_subspace(keys::subspace());
+
+			tx.read_opt(&keys::runner::WsMaintenanceKey::new(), Serializable)
+				.await
+		})
+		.custom_instrument(tracing::info_span!("runner_get_ws_maintenance_mode_tx"))
+		.await?;
+
+	Ok(Output { maintenance })
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use gas::prelude::*;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	/// Set while the namespace is draining.
+	pub drain: Option<keys::ns::RunnerWsDrain>,
+}
+
+#[operation]
+pub async fn pegboard_runner_get_ws_namespace_drain(
+	ctx: &OperationCtx,
+	input: &Input,
+) -> Result<Output> {
+	let drain = ctx
+		.udb()?
+		.run(|tx| async move {
+			let tx = tx.with_subspace(keys::subspace());
+
+			tx.read_opt(
+				&keys::ns::RunnerWsDrainKey::new(input.namespace_id),
+				Serializable,
+			)
+			.await
+		})
+		.custom_instrument(tracing::info_span!("runner_get_ws_namespace_drain_tx"))
+		.await?;
+
+	Ok(Output { drain })
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use futures_util::TryStreamExt;
+use gas::prelude::*;
+use universaldb::options::StreamingMode;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	pub runners: Vec<QuarantinedRunner>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedRunner {
+	pub runner_key: String,
+	pub violations: u32,
+	pub last_violation_ts: i64,
+	pub quarantined_until_ts: i64,
+}
+
+/// Lists runner keys of the namespace that are currently quarantined.
+#[operation]
+pub async fn pegboard_runner_list_quarantined(ctx: &OperationCtx, input: &Input) -> Result<Output> {
+	let now = util::timestamp::now();
+
+	let runners = ctx
+		.udb()?
+		.run(|tx| async move {
+			let tx = tx.with_subspace(keys::subspace());
+			let mut results = Vec::new();
+
+			let quarantine_subspace = keys::subspace()
+				.subspace(&keys::ns::RunnerQuarantineKey::subspace(input.namespace_id));
+
+			let mut stream = tx.get_ranges_keyvalues(
+				universaldb::RangeOption {
+					mode: StreamingMode::WantAll,
+					..(&quarantine_subspace).into()
+				},
+				// NOTE: Does not have to be serializable because we are listing, stale data does not matter
+				Snapshot,
+			);
+
+			while let Some(entry) = stream.try_next().await? {
+				let (quarantine_key, quarantine) =
+					tx.read_entry::<keys::ns::RunnerQuarantineKey>(&entry)?;
+
+				// Only include active quarantines
+				let Some(quarantined_until_ts) = quarantine
+					.quarantined_until_ts
+					.filter(|until_ts| *until_ts > now)
+				else {
+					continue;
+				};
+
+				results.push(QuarantinedRunner {
+					runner_key: quarantine_key.runner_key,
+					violations: quarantine.violations,
+					last_violation_ts: quarantine.last_violation_ts,
+					quarantined_until_ts,
+				});
+			}
+
+			Ok(results)
+		})
+		.custom_instrument(tracing::info_span!("runner_list_quarantined_tx"))
+		.await?;
+
+	Ok(Output { runners })
+}
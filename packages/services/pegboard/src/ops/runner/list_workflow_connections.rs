@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use gas::prelude::*;
+
+/// How long to collect responses from runner ws instances.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub workflow_id: Id,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	/// Live connections serving the workflow across all runner ws instances in this datacenter. Usually
+	/// at most one, more while a connection is being replaced.
+	pub connections: Vec<Connection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Connection {
+	/// Identity of the runner ws instance holding the connection.
+	pub instance_id: String,
+	pub runner_id: Id,
+}
+
+/// Sent to all runner ws instances. Only instances holding a connection for the workflow respond.
+#[message("pegboard_runner_workflow_connections_query")]
+pub struct WorkflowConnectionsQuery {
+	pub request_id: Id,
+	pub workflow_id: Id,
+}
+
+#[message("pegboard_runner_workflow_connections_query_response")]
+pub struct WorkflowConnectionsQueryResponse {
+	pub instance_id: String,
+	pub runner_ids: Vec<Id>,
+}
+
+#[operation]
+pub async fn pegboard_runner_list_workflow_connections(
+	ctx: &OperationCtx,
+	input: &Input,
+) -> Result<Output> {
+	let request_id = Id::new_v1(ctx.config().dc_label());
+
+	// Set up subscription before sending the query
+	let mut sub = ctx
+		.subscribe::<WorkflowConnectionsQueryResponse>(("request_id", request_id))
+		.await?;
+
+	ctx.msg(WorkflowConnectionsQuery {
+		request_id,
+		workflow_id: input.workflow_id,
+	})
+	.send()
+	.await?;
+
+	// The number of responding instances is unknown, collect responses until the timeout
+	let deadline = tokio::time::Instant::now() + QUERY_TIMEOUT;
+	let mut connections = Vec::new();
+	while let Ok(msg) = tokio::time::timeout_at(deadline, sub.next()).await {
+		let msg = msg?.into_body();
+
+		connections.extend(msg.runner_ids.into_iter().map(|runner_id| Connection {
+			instance_id: msg.instance_id.clone(),
+			runner_id,
+		}));
+	}
+
+	Ok(Output { connections })
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use futures_util::TryStreamExt;
+use gas::prelude::*;
+use universaldb::options::StreamingMode;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	pub namespaces: Vec<NamespaceDrain>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamespaceDrain {
+	pub namespace_id: Id,
+	pub retry_after_ms: Option<u64>,
+}
+
+/// Lists all namespaces whose runners are being drained.
+#[operation]
+pub async fn pegboard_runner_list_ws_namespace_drains(
+	ctx: &OperationCtx,
+	_input: &Input,
+) -> Result<Output> {
+	let namespaces = ctx
+		.udb()?
+		.run(|tx| async move {
+			let tx = tx.with_subspace(keys::subspace());
+			let mut results = Vec::new();
+
+			let drain_subspace =
+				keys::subspace().subspace(&keys::ns::RunnerWsDrainKey::entire_subspace());
+
+			let mut stream = tx.get_ranges_keyvalues(
+				universaldb::RangeOption {
+					mode: StreamingMode::WantAll,
+					..(&drain_subspace).into()
+				},
+				Serializable,
+			);
+
+			while let Some(entry) = stream.try_next().await? {
+				let (drain_key, drain) = tx.read_entry::<keys::ns::RunnerWsDrainKey>(&entry)?;
+
+				results.push(NamespaceDrain {
+					namespace_id: drain_key.namespace_id,
+					retry_after_ms: drain.retry_after_ms,
+				});
+			}
+
+			Ok(results)
+		})
+		.custom_instrument(tracing::info_span!("runner_list_ws_namespace_drains_tx"))
+		.await?;
+
+	Ok(Output { namespaces })
+}
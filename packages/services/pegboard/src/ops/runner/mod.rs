@@ -1,5 +1,24 @@
+pub mod claim_instance;
+pub mod clear_quarantine;
+pub mod export_connections;
 pub mod get;
 pub mod get_by_key;
+pub mod get_connection;
+pub mod get_packet_capture;
+pub mod get_preprovisioned;
+pub mod get_quarantine;
+pub mod get_ws_maintenance_mode;
+pub mod get_ws_namespace_drain;
 pub mod list_for_ns;
 pub mod list_names;
+pub mod list_quarantined;
+pub mod list_workflow_connections;
+pub mod list_ws_namespace_drains;
+pub mod preprovision;
+pub mod probe_db;
+pub mod record_protocol_version;
+pub mod record_violation;
+pub mod set_ws_maintenance_mode;
+pub mod set_ws_namespace_draining;
+pub mod take_exported_connections;
 pub mod update_alloc_idx;
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use gas::prelude::*;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+/// How long a pre-provisioned workflow waits for its runner to connect by default.
+const DEFAULT_TTL_MS: i64 = util::duration::minutes(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+	pub name: String,
+	pub key: String,
+	/// How long to wait for the runner to connect before the workflow expires. Defaults to 5 minutes.
+	pub ttl_ms: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	pub runner_id: Id,
+	pub workflow_id: Id,
+	/// False if the key already had a runner, which is returned instead.
+	pub created: bool,
+}
+
+/// Creates a runner workflow before the runner connects, so its connection binds to the workflow without
+/// dispatching one (see `get_preprovisioned`). Reduces connect latency for anticipated scale-ups.
+#[operation]
+pub async fn pegboard_runner_preprovision(ctx: &OperationCtx, input: &Input) -> Result<Output> {
+	let runner_id = Id::new_v1(ctx.config().dc_label());
+	let ttl_ms = input.ttl_ms.unwrap_or(DEFAULT_TTL_MS);
+
+	let existing = ctx
+		.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+
+				// Never evict a runner that already holds the key. The workflow backs off if a runner
+				// claims the key before it starts.
+				let existing = tx
+					.read_opt(
+						&keys::ns::RunnerByKeyKey::new(input.namespace_id, input.name, input.key),
+						Serializable,
+					)
+					.await?;
+
+				if existing.is_none() {
+					tx.write(
+						&keys::runner::PreprovisionExpireTsKey::new(runner_id),
+						util::timestamp::now() + ttl_ms,
+					)?;
+				}
+
+				Ok(existing)
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_preprovision_tx"))
+		.await?;
+
+	if let Some(existing) = existing {
+		return Ok(Output {
+			runner_id: existing.runner_id,
+			workflow_id: existing.workflow_id,
+			created: false,
+		});
+	}
+
+	let workflow_id = ctx
+		.workflow(crate::workflows::runner::Input {
+			runner_id,
+			namespace_id: input.namespace_id,
+			name: input.name.clone(),
+			key: input.key.clone(),
+			// Replaced by the values in the runner's init packet
+			version: 0,
+			total_slots: 0,
+			preprovision_ttl_ms: Some(ttl_ms),
+		})
+		.tag("runner_id", runner_id)
+		.unique()
+		.dispatch()
+		.await?;
+
+	Ok(Output {
+		runner_id,
+		workflow_id,
+		created: true,
+	})
+}
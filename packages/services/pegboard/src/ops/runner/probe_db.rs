@@ -0,0 +1,29 @@
+use anyhow::Result;
+use gas::prelude::*;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {}
+
+/// Performs a lightweight read to check that the database is reachable. Used by the runner ws readiness
+/// probe.
+#[operation]
+pub async fn pegboard_runner_probe_db(ctx: &OperationCtx, _input: &Input) -> Result<Output> {
+	ctx.udb()?
+		.run(|tx| async move {
+			let tx = tx.with_subspace(keys::subspace());
+
+			// Any read confirms the database is reachable, the key does not need to exist
+			tx.exists(&keys::runner::WorkflowIdKey::new(Id::nil()), Snapshot)
+				.await
+		})
+		.custom_instrument(tracing::info_span!("runner_probe_db_tx"))
+		.await?;
+
+	Ok(Output {})
+}
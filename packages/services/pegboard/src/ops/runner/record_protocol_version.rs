@@ -0,0 +1,54 @@
+use anyhow::Result;
+use gas::prelude::*;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub runner_id: Id,
+	pub protocol_version: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	/// Highest protocol version the runner connected with before, if it is higher than the given
+	/// version (i.e. the runner was rolled back).
+	pub downgraded_from: Option<u16>,
+}
+
+/// Records the protocol version a runner connected with. The highest version is kept so that every
+/// connection after a rollback is detected as a downgrade, not just the first.
+#[operation]
+pub async fn pegboard_runner_record_protocol_version(
+	ctx: &OperationCtx,
+	input: &Input,
+) -> Result<Output> {
+	let downgraded_from = ctx
+		.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+
+				let protocol_version_key = keys::runner::ProtocolVersionKey::new(input.runner_id);
+
+				if let Some(prev) = tx.read_opt(&protocol_version_key, Serializable).await? {
+					if prev > input.protocol_version {
+						return Ok(Some(prev));
+					}
+					if prev == input.protocol_version {
+						return Ok(None);
+					}
+				}
+
+				tx.write(&protocol_version_key, input.protocol_version)?;
+
+				Ok(None)
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_record_protocol_version_tx"))
+		.await?;
+
+	Ok(Output { downgraded_from })
+}
@@ -0,0 +1,82 @@
+use anyhow::Result;
+use gas::prelude::*;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+/// Number of protocol violations before a runner key is quarantined.
+const QUARANTINE_THRESHOLD: u32 = 3;
+/// Violations are forgotten after this long without a new violation (counted from the end of the last
+/// quarantine).
+const VIOLATION_WINDOW_MS: i64 = util::duration::minutes(5);
+/// Duration of the first quarantine, doubled for every further violation.
+const BASE_QUARANTINE_MS: i64 = util::duration::seconds(10);
+const MAX_QUARANTINE_MS: i64 = util::duration::hours(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+	pub runner_key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	/// Set if the runner key is now quarantined.
+	pub quarantined_until_ts: Option<i64>,
+}
+
+/// Records a protocol violation (i.e. an invalid packet) of a runner. Runner keys that repeatedly send
+/// invalid packets are quarantined with an exponentially increasing duration, which breaks reconnect loops
+/// of broken runner builds.
+#[operation]
+pub async fn pegboard_runner_record_violation(ctx: &OperationCtx, input: &Input) -> Result<Output> {
+	let quarantined_until_ts = ctx
+		.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+
+				let now = util::timestamp::now();
+				let quarantine_key =
+					keys::ns::RunnerQuarantineKey::new(input.namespace_id, input.runner_key);
+
+				let violations = tx
+					.read_opt(&quarantine_key, Serializable)
+					.await?
+					.filter(|quarantine| {
+						let last_ts = quarantine
+							.last_violation_ts
+							.max(quarantine.quarantined_until_ts.unwrap_or_default());
+
+						now.saturating_sub(last_ts) < VIOLATION_WINDOW_MS
+					})
+					.map_or(0, |quarantine| quarantine.violations)
+					+ 1;
+
+				let quarantined_until_ts = (violations >= QUARANTINE_THRESHOLD).then(|| {
+					let exp = (violations - QUARANTINE_THRESHOLD).min(16);
+					now + BASE_QUARANTINE_MS
+						.saturating_mul(1 << exp)
+						.min(MAX_QUARANTINE_MS)
+				});
+
+				tx.write(
+					&quarantine_key,
+					keys::ns::RunnerQuarantine {
+						violations,
+						last_violation_ts: now,
+						quarantined_until_ts,
+					},
+				)?;
+
+				Ok(quarantined_until_ts)
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_record_violation_tx"))
+		.await?;
+
+	Ok(Output {
+		quarantined_until_ts,
+	})
+}
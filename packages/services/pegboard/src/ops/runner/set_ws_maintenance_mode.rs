@@ -0,0 +1,44 @@
+use anyhow::Result;
+use gas::prelude::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub enabled: bool,
+	pub retry_after_ms: Option<u64>,
+}
+
+/// Persists runner ws maintenance mode. Instances pick it up when they start, on every new connection
+/// and after `SetRunnerWsMaintenanceMode` is published.
+#[operation]
+pub async fn pegboard_runner_set_ws_maintenance_mode(
+	ctx: &OperationCtx,
+	input: &Input,
+) -> Result<()> {
+	ctx.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+				let maintenance_key = keys::runner::WsMaintenanceKey::new();
+
+				if input.enabled {
+					tx.write(
+						&maintenance_key,
+						keys::runner::WsMaintenance {
+							retry_after_ms: input.retry_after_ms,
+						},
+					)?;
+				} else {
+					tx.delete(&maintenance_key);
+				}
+
+				Ok(())
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_set_ws_maintenance_mode_tx"))
+		.await?;
+
+	Ok(())
+}
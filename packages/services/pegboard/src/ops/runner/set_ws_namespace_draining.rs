@@ -0,0 +1,45 @@
+use anyhow::Result;
+use gas::prelude::*;
+
+use crate::keys;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub namespace_id: Id,
+	pub draining: bool,
+	pub retry_after_ms: Option<u64>,
+}
+
+/// Persists the runner ws drain of a namespace. Instances pick it up when they start, on every new
+/// connection of the namespace and after `SetRunnerWsNamespaceDraining` is published.
+#[operation]
+pub async fn pegboard_runner_set_ws_namespace_draining(
+	ctx: &OperationCtx,
+	input: &Input,
+) -> Result<()> {
+	ctx.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let tx = tx.with_subspace(keys::subspace());
+				let drain_key = keys::ns::RunnerWsDrainKey::new(input.namespace_id);
+
+				if input.draining {
+					tx.write(
+						&drain_key,
+						keys::ns::RunnerWsDrain {
+							retry_after_ms: input.retry_after_ms,
+						},
+					)?;
+				} else {
+					tx.delete(&drain_key);
+				}
+
+				Ok(())
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_set_ws_namespace_draining_tx"))
+		.await?;
+
+	Ok(())
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use futures_util::TryStreamExt;
+use gas::prelude::*;
+use universaldb::options::StreamingMode;
+use universaldb::utils::IsolationLevel::*;
+
+use crate::keys;
+
+/// Exports older than this are ignored, the runners have long reconnected elsewhere.
+const EXPORT_TTL_MS: i64 = util::duration::minutes(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+	pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Output {
+	pub connections: Vec<Connection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Connection {
+	pub runner_id: Id,
+	pub workflow_id: Id,
+	pub namespace_id: Id,
+	pub namespace_name: String,
+	pub runner_key: String,
+}
+
+/// Reads and clears the connection registry exported by a previous runner ws instance with the same
+/// identity, see `export_connections`.
+#[operation]
+pub async fn pegboard_runner_take_exported_connections(
+	ctx: &OperationCtx,
+	input: &Input,
+) -> Result<Output> {
+	let now = util::timestamp::now();
+
+	let connections = ctx
+		.udb()?
+		.run(|tx| {
+			let input = input.clone();
+			async move {
+				let export_subspace = keys::subspace().subspace(
+					&keys::runner::ExportedConnectionKey::subspace(input.instance_id),
+				);
+				let mut results = Vec::new();
+
+				{
+					let tx = tx.with_subspace(keys::subspace());
+					let mut stream = tx.get_ranges_keyvalues(
+						universaldb::RangeOption {
+							mode: StreamingMode::WantAll,
+							..(&export_subspace).into()
+						},
+						Serializable,
+					);
+
+					while let Some(entry) = stream.try_next().await? {
+						let (export_key, conn) =
+							tx.read_entry::<keys::runner::ExportedConnectionKey>(&entry)?;
+
+						if now.saturating_sub(conn.export_ts) > EXPORT_TTL_MS {
+							continue;
+						}
+
+						results.push(Connection {
+							runner_id: export_key.runner_id,
+							workflow_id: conn.workflow_id,
+							namespace_id: conn.namespace_id,
+							namespace_name: conn.namespace_name,
+							runner_key: conn.runner_key,
+						});
+					}
+				}
+
+				tx.clear_subspace_range(&export_subspace);
+
+				Ok(results)
+			}
+		})
+		.custom_instrument(tracing::info_span!("runner_take_exported_connections_tx"))
+		.await?;
+
+	Ok(Output { connections })
+}
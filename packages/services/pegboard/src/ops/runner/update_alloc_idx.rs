@@ -19,7 +19,39 @@ pub struct Runner {
 pub enum Action {
 	ClearIdx,
 	AddIdx,
-	UpdatePing { rtt: u32 },
+	UpdatePing {
+		/// `None` leaves the last RTT untouched, for runners that opted out of RTT reporting.
+		rtt: Option<u32>,
+		/// Load score in thousandths reported by the runner, see `RunnerLoad::score`.
+		load: u32,
+		/// Skips runners that are currently ineligible instead of making them eligible again.
+		retain_eligibility: bool,
+	},
+}
+
+/// Load reported by the runner in its pings. Runners that don't report load are treated as idle.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RunnerLoad {
+	/// CPU utilization in thousandths.
+	pub cpu: u32,
+	/// Memory utilization in thousandths.
+	pub memory: u32,
+	/// Number of actors waiting to be started by the runner.
+	pub queue_depth: u32,
+}
+
+impl RunnerLoad {
+	/// How much each queued actor adds to the load score (in thousandths).
+	const QUEUE_DEPTH_WEIGHT: u32 = 50;
+
+	/// Composite load score in thousandths (0 = idle, 1000 = fully loaded). The most utilized resource
+	/// determines the score, with queued actors adding to it.
+	pub fn score(&self) -> u32 {
+		self.cpu
+			.max(self.memory)
+			.saturating_add(self.queue_depth.saturating_mul(Self::QUEUE_DEPTH_WEIGHT))
+			.min(1000)
+	}
 }
 
 #[derive(Debug)]
@@ -119,7 +151,9 @@ pub async fn pegboard_runner_update_alloc_idx(ctx: &OperationCtx, input: &Input)
 						continue;
 					}
 
-					let remaining_millislots = (remaining_slots * 1000) / total_slots;
+					// Standby runners (zero slots) are never in the idx, the key below does not exist
+					let remaining_millislots =
+						(remaining_slots * 1000).checked_div(total_slots).unwrap_or_default();
 
 					let old_alloc_key = keys::ns::RunnerAllocIdxKey::new(
 						namespace_id,
@@ -137,6 +171,8 @@ pub async fn pegboard_runner_update_alloc_idx(ctx: &OperationCtx, input: &Input)
 						Action::ClearIdx => {
 							tx.delete(&old_alloc_key);
 						}
+						// Standby runners are never made eligible
+						Action::AddIdx if total_slots == 0 => {}
 						Action::AddIdx => {
 							tx.write(
 								&old_alloc_key,
@@ -147,14 +183,31 @@ pub async fn pegboard_runner_update_alloc_idx(ctx: &OperationCtx, input: &Input)
 								},
 							)?;
 						}
-						Action::UpdatePing { rtt } => {
+						Action::UpdatePing {
+							rtt,
+							load,
+							retain_eligibility,
+						} => {
 							let last_ping_ts = util::timestamp::now();
 
+							// Writing the ping of an ineligible runner would make it eligible again
+							if retain_eligibility
+								&& last_ping_ts.saturating_sub(old_last_ping_ts)
+									> RUNNER_ELIGIBLE_THRESHOLD_MS
+							{
+								continue;
+							}
+
 							// Write new ping
 							tx.write(&last_ping_ts_key, last_ping_ts)?;
 
-							let last_rtt_key = keys::runner::LastRttKey::new(runner.runner_id);
-							tx.write(&last_rtt_key, rtt)?;
+							if let Some(rtt) = rtt {
+								let last_rtt_key = keys::runner::LastRttKey::new(runner.runner_id);
+								tx.write(&last_rtt_key, rtt)?;
+							}
+
+							let last_load_key = keys::runner::LastLoadKey::new(runner.runner_id);
+							tx.write(&last_load_key, load)?;
 
 							// Only update allocation idx if it existed before
 							if tx.exists(&old_alloc_key, Serializable).await? {
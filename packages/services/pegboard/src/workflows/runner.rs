@@ -9,9 +9,19 @@ use crate::{keys, workflows::actor::Allocate};
 
 /// How long after last ping before considering a runner ineligible for allocation.
 pub const RUNNER_ELIGIBLE_THRESHOLD_MS: i64 = util::duration::seconds(10);
+/// Max slots a runner can declare. The alloc idx stores remaining slots as millislots (thousandths of
+/// the total), which must fit in a u32. Runners declaring zero slots are standby runners, they stay
+/// connected but are never added to the alloc idx.
+pub const MAX_TOTAL_SLOTS: u32 = u32::MAX / 1000;
 /// How long to wait after last ping before forcibly removing a runner from the database and deleting its
 /// workflow, evicting all actors. Note that the runner may still be running and can reconnect.
 const RUNNER_LOST_THRESHOLD_MS: i64 = util::duration::minutes(2);
+/// How long a new runner workflow waits for the init packet of the connection that dispatched it before
+/// completing. Bounds the lifetime of workflows whose handshake failed after they were dispatched.
+const RUNNER_INIT_TIMEOUT_MS: i64 = util::duration::seconds(30);
+/// How long to wait for actors to stop after a runner requests a graceful shutdown. Remaining actors are
+/// set as lost after this timeout.
+const GRACEFUL_SHUTDOWN_TIMEOUT_MS: i64 = util::duration::minutes(5);
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Input {
@@ -21,6 +31,10 @@ pub struct Input {
 	pub key: String,
 	pub version: u32,
 	pub total_slots: u32,
+	/// Set if the workflow was created before its runner connected (see `ops::runner::preprovision`).
+	/// Replaces `RUNNER_INIT_TIMEOUT_MS`.
+	#[serde(default)]
+	pub preprovision_ttl_ms: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -62,9 +76,23 @@ pub async fn pegboard_runner(ctx: &mut WorkflowCtx, input: &Input) -> Result<()>
 			key: input.key.clone(),
 			namespace_id: input.namespace_id,
 			create_ts: ctx.create_ts(),
+			preprovisioned: input.preprovision_ttl_ms.is_some(),
 		})
 		.await?;
 
+	// A runner claimed the key between pre-provisioning and this workflow starting
+	if init_res.key_taken {
+		ctx.activity(ClearDbInput {
+			runner_id: input.runner_id,
+			name: input.name.clone(),
+			key: input.key.clone(),
+			update_state: RunnerState::Stopped,
+		})
+		.await?;
+
+		return Ok(());
+	}
+
 	// Evict other workflow if there was a key conflict
 	if let Some(evict_workflow_id) = init_res.evict_workflow_id {
 		ctx.signal(protocol::ToServer::Stopping)
@@ -77,18 +105,41 @@ pub async fn pegboard_runner(ctx: &mut WorkflowCtx, input: &Input) -> Result<()>
 		let input = input.clone();
 
 		async move {
-			match ctx
-				.listen_with_timeout::<Main>(RUNNER_LOST_THRESHOLD_MS)
+			let sig = if let Some(shutdown_deadline_ts) = state.shutdown_deadline_ts {
+				ctx.listen_until::<Main>(shutdown_deadline_ts).await?
+			} else if state.awaiting_init {
+				ctx.listen_with_timeout::<Main>(
+					input.preprovision_ttl_ms.unwrap_or(RUNNER_INIT_TIMEOUT_MS),
+				)
 				.await?
-			{
+			} else {
+				ctx.listen_with_timeout::<Main>(RUNNER_LOST_THRESHOLD_MS)
+					.await?
+			};
+
+			match sig {
 				Some(Main::Forward(sig)) => {
 					match sig {
 						protocol::ToServer::Init {
+							version,
+							total_slots,
 							last_command_idx,
 							prepopulate_actor_names,
 							metadata,
 							..
 						} => {
+							// Pre-provisioned workflows learn the version and slots from the first init
+							// packet. Later reconnects keep them, the alloc idx entry is keyed by the
+							// inserted values.
+							if state.awaiting_init && input.preprovision_ttl_ms.is_some() {
+								state.preprovisioned_runner = Some((version, total_slots));
+							}
+							let (version, total_slots) = state
+								.preprovisioned_runner
+								.unwrap_or((input.version, input.total_slots));
+
+							state.awaiting_init = false;
+
 							let init_data = ctx
 								.activity(ProcessInitInput {
 									runner_id: input.runner_id,
@@ -129,8 +180,8 @@ pub async fn pegboard_runner(ctx: &mut WorkflowCtx, input: &Input) -> Result<()>
 									namespace_id: input.namespace_id,
 									name: input.name.clone(),
 									key: input.key.clone(),
-									version: input.version,
-									total_slots: input.total_slots,
+									version,
+									total_slots,
 									create_ts: ctx.create_ts(),
 								})
 								.await?;
@@ -159,6 +210,18 @@ pub async fn pegboard_runner(ctx: &mut WorkflowCtx, input: &Input) -> Result<()>
 							// Forward to actor workflows
 							for event in events {
 								let actor_id = event.inner.actor_id();
+
+								if let protocol::Event::ActorStateUpdate {
+									generation,
+									state: protocol::ActorState::Stopped { .. },
+									..
+								} = &event.inner
+								{
+									state
+										.shutdown_remaining_actors
+										.retain(|x| *x != (actor_id, *generation));
+								}
+
 								let res = ctx
 									.signal(event.inner)
 									.to_workflow::<crate::workflows::actor::Workflow>()
@@ -263,6 +326,36 @@ pub async fn pegboard_runner(ctx: &mut WorkflowCtx, input: &Input) -> Result<()>
 								.await?;
 							}
 						}
+						protocol::ToServer::GracefulShutdown => {
+							if state.shutdown_deadline_ts.is_none() {
+								tracing::debug!(
+									runner_id=?input.runner_id,
+									"runner graceful shutdown started"
+								);
+
+								// Stop allocating new actors to this runner but let existing actors finish. The
+								// shutdown is completed below once all actors have stopped.
+								state.draining = true;
+								state.shutdown_deadline_ts =
+									Some(util::timestamp::now() + GRACEFUL_SHUTDOWN_TIMEOUT_MS);
+
+								ctx.activity(ClearDbInput {
+									runner_id: input.runner_id,
+									name: input.name.clone(),
+									key: input.key.clone(),
+									update_state: RunnerState::Draining,
+								})
+								.await?;
+
+								// No new actors can be allocated after clearing the runner from the alloc
+								// idx. The shutdown waits for a stopped event from each of these.
+								state.shutdown_remaining_actors = ctx
+									.activity(FetchRemainingActorsInput {
+										runner_id: input.runner_id,
+									})
+									.await?;
+							}
+						}
 					}
 				}
 				Some(Main::Command(command)) => {
@@ -336,19 +429,77 @@ pub async fn pegboard_runner(ctx: &mut WorkflowCtx, input: &Input) -> Result<()>
 							.await?;
 					}
 				}
-				None => {
+				Some(Main::Disconnected(sig)) => {
+					tracing::debug!(
+						runner_id=?input.runner_id,
+						reason=?sig.reason,
+						"runner disconnected"
+					);
+
+					// The handshake failed after this workflow was dispatched, the runner never connected
+					if state.awaiting_init {
+						tracing::debug!(
+							runner_id=?input.runner_id,
+							"runner never completed its handshake"
+						);
+						return Ok(Loop::Break(()));
+					}
+
+					// A draining runner that closed its connection on its own is not coming back, no need to
+					// wait for it to expire. Other reasons wait for a reconnect.
 					if state.draining
-						|| ctx
-							.activity(CheckExpiredInput {
-								runner_id: input.runner_id,
-							})
-							.await?
+						&& state.shutdown_deadline_ts.is_none()
+						&& sig.reason == DisconnectReason::Normal
+					{
+						return Ok(Loop::Break(()));
+					}
+				}
+				None => {
+					if state.shutdown_deadline_ts.is_some() {
+						tracing::warn!(
+							runner_id=?input.runner_id,
+							remaining_actors=?state.shutdown_remaining_actors.len(),
+							"runner graceful shutdown timed out, remaining actors will be lost"
+						);
+
+						state.shutdown_remaining_actors.clear();
+					}
+
+					if state.awaiting_init {
+						tracing::debug!(
+							runner_id=?input.runner_id,
+							"timed out waiting for runner init"
+						);
+						return Ok(Loop::Break(()));
+					}
+
+					// Graceful shutdowns are completed below
+					if state.shutdown_deadline_ts.is_none()
+						&& (state.draining
+							|| ctx
+								.activity(CheckExpiredInput {
+									runner_id: input.runner_id,
+								})
+								.await?)
 					{
 						return Ok(Loop::Break(()));
 					}
 				}
 			}
 
+			// All actors have stopped or the graceful shutdown timed out
+			if state.shutdown_deadline_ts.is_some() && state.shutdown_remaining_actors.is_empty() {
+				// Inform the runner that it can safely exit
+				ctx.msg(ToWs {
+					runner_id: input.runner_id,
+					inner: protocol::ToClient::ShutdownAck,
+				})
+				.send()
+				.await?;
+
+				return Ok(Loop::Break(()));
+			}
+
 			Ok(Loop::Continue)
 		}
 		.boxed()
@@ -395,6 +546,7 @@ pub async fn pegboard_runner(ctx: &mut WorkflowCtx, input: &Input) -> Result<()>
 	// Close websocket connection (its unlikely to be open)
 	ctx.msg(CloseWs {
 		runner_id: input.runner_id,
+		reason: EvictionReason::Completed,
 	})
 	.send()
 	.await?;
@@ -406,6 +558,19 @@ pub async fn pegboard_runner(ctx: &mut WorkflowCtx, input: &Input) -> Result<()>
 struct LifecycleState {
 	draining: bool,
 	last_event_ack_idx: i64,
+	/// Set when the runner requested a graceful shutdown.
+	#[serde(default)]
+	shutdown_deadline_ts: Option<i64>,
+	/// Actors that have not sent a stopped event since the graceful shutdown started.
+	#[serde(default)]
+	shutdown_remaining_actors: Vec<(Id, u32)>,
+	/// Set until the first init packet is forwarded. Defaults to false for workflows started before this
+	/// field existed, they have already been initialized.
+	#[serde(default)]
+	awaiting_init: bool,
+	/// Version and total slots from the first init packet of a pre-provisioned workflow.
+	#[serde(default)]
+	preprovisioned_runner: Option<(u32, u32)>,
 }
 
 impl LifecycleState {
@@ -413,6 +578,10 @@ impl LifecycleState {
 		LifecycleState {
 			draining: false,
 			last_event_ack_idx: -1,
+			shutdown_deadline_ts: None,
+			shutdown_remaining_actors: Vec::new(),
+			awaiting_init: true,
+			preprovisioned_runner: None,
 		}
 	}
 }
@@ -424,12 +593,18 @@ struct InitInput {
 	key: String,
 	namespace_id: Id,
 	create_ts: i64,
+	/// Pre-provisioned workflows never evict a runner that holds the key.
+	#[serde(default)]
+	preprovisioned: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct InitOutput {
 	/// The workflow id of another runner that has the same key.
 	evict_workflow_id: Option<Id>,
+	/// Set if the workflow is pre-provisioned and another runner holds the key.
+	#[serde(default)]
+	key_taken: bool,
 }
 
 #[activity(Init)]
@@ -438,7 +613,7 @@ async fn init(ctx: &ActivityCtx, input: &InitInput) -> Result<InitOutput> {
 
 	*state = Some(State::new(input.namespace_id, input.create_ts));
 
-	let evict_workflow_id = ctx
+	let output = ctx
 		.udb()?
 		.run(|tx| async move {
 			let tx = tx.with_subspace(keys::subspace());
@@ -455,6 +630,13 @@ async fn init(ctx: &ActivityCtx, input: &InitInput) -> Result<InitOutput> {
 				.await?
 				.map(|x| x.workflow_id);
 
+			if input.preprovisioned && evict_workflow_id.is_some() {
+				return Ok(InitOutput {
+					evict_workflow_id: None,
+					key_taken: true,
+				});
+			}
+
 			// Allocate self
 			tx.write(
 				&runner_by_key_key,
@@ -464,11 +646,14 @@ async fn init(ctx: &ActivityCtx, input: &InitInput) -> Result<InitOutput> {
 				},
 			)?;
 
-			Ok(evict_workflow_id)
+			Ok(InitOutput {
+				evict_workflow_id,
+				key_taken: false,
+			})
 		})
 		.await?;
 
-	Ok(InitOutput { evict_workflow_id })
+	Ok(output)
 }
 
 #[derive(Debug, Serialize, Deserialize, Hash)]
@@ -597,6 +782,11 @@ async fn insert_db(ctx: &ActivityCtx, input: &InsertDbInput) -> Result<()> {
 			// Set last connect ts
 			tx.write(&keys::runner::ConnectedTsKey::new(input.runner_id), now)?;
 
+			// Standby runners are never eligible for allocation
+			if input.total_slots == 0 {
+				return Ok(());
+			}
+
 			let remaining_millislots = (remaining_slots * 1000) / input.total_slots;
 
 			// Insert into index (same as the `update_alloc_idx` op with `AddIdx`)
@@ -661,6 +851,9 @@ async fn clear_db(ctx: &ActivityCtx, input: &ClearDbInput) -> Result<()> {
 				tx.delete(&runner_by_key_key);
 			}
 
+			// Pre-provisioned workflows that expire never get an init packet
+			tx.delete(&keys::runner::PreprovisionExpireTsKey::new(input.runner_id));
+
 			match input.update_state {
 				RunnerState::Draining => {
 					tx.write(&keys::runner::DrainTsKey::new(input.runner_id), now)?;
@@ -758,6 +951,9 @@ async fn process_init(ctx: &ActivityCtx, input: &ProcessInitInput) -> Result<Pro
 				}
 			}
 
+			// The runner connected, no longer pre-provisioned
+			tx.delete(&keys::runner::PreprovisionExpireTsKey::new(input.runner_id));
+
 			Ok(())
 		})
 		.custom_instrument(tracing::info_span!("runner_populate_actor_names_tx"))
@@ -1105,6 +1301,50 @@ pub struct CheckQueue {}
 #[message("pegboard_runner_close_ws")]
 pub struct CloseWs {
 	pub runner_id: Id,
+	/// Determines the close code and reason sent to the runner.
+	#[serde(default)]
+	pub reason: EvictionReason,
+}
+
+/// Why a runner's connection is closed with `CloseWs`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+	/// The runner workflow completed. The runner should not reconnect with the same runner id.
+	#[default]
+	Completed,
+	/// The instance holding the connection is being decommissioned. The runner should reconnect to
+	/// another instance.
+	Decommission,
+	/// The connection is moved to balance load. The runner should reconnect to another instance.
+	Rebalance,
+	/// The runner violated a policy and should not reconnect.
+	PolicyViolation,
+	/// Evicted by an operator.
+	Manual,
+}
+
+/// Sent by the ws when the runner's connection ends.
+#[signal("pegboard_runner_disconnected")]
+pub struct Disconnected {
+	pub reason: DisconnectReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+	/// The runner closed the connection.
+	Normal,
+	/// A newer connection for the same runner replaced this one.
+	Replaced,
+	/// The connection was closed by this workflow (see `CloseWs`).
+	Evicted,
+	Timeout,
+	/// Protocol or transport error.
+	Error,
+	/// The runner's namespace is being drained (see `SetRunnerWsNamespaceDraining`). The runner is
+	/// expected to reconnect to another instance.
+	Drained,
 }
 
 join_signal!(Main {
@@ -1112,4 +1352,5 @@ join_signal!(Main {
 	// Forwarded from the ws to this workflow
 	Forward(protocol::ToServer),
 	CheckQueue,
+	Disconnected,
 });
@@ -13,6 +13,7 @@ pub enum ToClient {
 	AckEvents {
 		last_event_idx: i64,
 	},
+	ShutdownAck,
 }
 
 #[signal("pegboard_to_server")]
@@ -27,12 +28,59 @@ pub enum ToServer {
 		last_command_idx: Option<i64>,
 		prepopulate_actor_names: Option<util::serde::HashableMap<String, ActorName>>,
 		metadata: Option<String>,
+		/// Opts in to periodic metrics snapshots. Handled at the websocket level.
+		#[serde(default)]
+		metrics_snapshots: bool,
+		/// Runner id to rebind to, if still owned by this runner. Handled at the websocket level.
+		#[serde(default)]
+		runner_id: Option<Id>,
+		/// Handled at the websocket level.
+		#[serde(default)]
+		priority: PriorityClass,
+		/// Handled at the websocket level.
+		#[serde(default)]
+		kv_capabilities: KvCapabilities,
+		/// Keeps the runner's RTT out of the alloc index. Handled at the websocket level.
+		#[serde(default)]
+		exclude_rtt: bool,
 	},
 	Events(Vec<EventWrapper>),
 	AckCommands {
 		last_command_idx: i64,
 	},
 	Stopping,
+	GracefulShutdown,
+}
+
+/// How a connection is treated while the server is under load.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PriorityClass {
+	/// KV requests are rejected while KV is overloaded.
+	BestEffort,
+	/// Asked to slow down KV requests while KV is overloaded.
+	#[default]
+	Normal,
+	/// Never asked to slow down KV requests.
+	System,
+}
+
+/// KV features the runner can handle. Assumes full support by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct KvCapabilities {
+	pub list: bool,
+	pub metadata: bool,
+	pub missing_keys: bool,
+}
+
+impl Default for KvCapabilities {
+	fn default() -> Self {
+		KvCapabilities {
+			list: true,
+			metadata: true,
+			missing_keys: true,
+		}
+	}
 }
 
 #[derive(Debug, Serialize, Deserialize, Hash)]
@@ -3,22 +3,22 @@ use base64::prelude::*;
 use gas::prelude::*;
 use versioned_data_util::OwnedVersionedData;
 
-use crate::{PROTOCOL_VERSION, generated::v1, protocol};
+use crate::{PROTOCOL_VERSION, generated::v1, generated::v2, protocol};
 
 pub enum ToClient {
 	V1(v1::ToClient),
+	V2(v2::ToClientPacket),
 }
 
 impl OwnedVersionedData for ToClient {
-	type Latest = v1::ToClient;
+	type Latest = v2::ToClientPacket;
 
-	fn latest(latest: v1::ToClient) -> Self {
-		ToClient::V1(latest)
+	fn latest(latest: v2::ToClientPacket) -> Self {
+		ToClient::V2(latest)
 	}
 
 	fn into_latest(self) -> Result<Self::Latest> {
-		#[allow(irrefutable_let_patterns)]
-		if let ToClient::V1(data) = self {
+		if let ToClient::V2(data) = self {
 			Ok(data)
 		} else {
 			bail!("version not latest");
@@ -28,6 +28,7 @@ impl OwnedVersionedData for ToClient {
 	fn deserialize_version(payload: &[u8], version: u16) -> Result<Self> {
 		match version {
 			1 => Ok(ToClient::V1(serde_bare::from_slice(payload)?)),
+			2 => Ok(ToClient::V2(serde_bare::from_slice(payload)?)),
 			_ => bail!("invalid version: {version}"),
 		}
 	}
@@ -35,29 +36,79 @@ impl OwnedVersionedData for ToClient {
 	fn serialize_version(self, _version: u16) -> Result<Vec<u8>> {
 		match self {
 			ToClient::V1(data) => serde_bare::to_vec(&data).map_err(Into::into),
+			ToClient::V2(data) => serde_bare::to_vec(&data).map_err(Into::into),
 		}
 	}
+
+	fn deserialize_converters() -> Vec<impl Fn(Self) -> Result<Self>> {
+		vec![Self::v1_to_v2]
+	}
+
+	fn serialize_converters() -> Vec<impl Fn(Self) -> Result<Self>> {
+		vec![Self::v2_to_v1]
+	}
 }
 
 impl ToClient {
-	pub fn deserialize(buf: &[u8]) -> Result<v1::ToClient> {
+	pub fn deserialize(buf: &[u8]) -> Result<v2::ToClientPacket> {
 		<Self as OwnedVersionedData>::deserialize(buf, PROTOCOL_VERSION)
 	}
+
+	/// Whether the message can be sent to a runner connected with the given protocol version.
+	/// Messages added after that version have no equivalent and must not be sent.
+	pub fn is_representable(message: &v2::ToClient, version: u16) -> bool {
+		version >= 2
+			|| !matches!(
+				message,
+				v2::ToClient::ToClientShutdownAck
+					| v2::ToClient::ToClientKvThrottle
+					| v2::ToClient::ToClientKvResume
+					| v2::ToClient::ToClientMetricsSnapshot(_)
+					| v2::ToClient::ToClientKvStats(_)
+					| v2::ToClient::ToClientWorkflowEnding
+			)
+	}
+
+	/// v1 has no packet envelope, the sequence number is not known.
+	fn v1_to_v2(self) -> Result<Self> {
+		if let ToClient::V1(message) = self {
+			Ok(ToClient::V2(v2::ToClientPacket {
+				seq: 0,
+				message: message.into(),
+			}))
+		} else {
+			bail!("unexpected version");
+		}
+	}
+
+	/// Strips the packet envelope, v1 runners receive bare messages.
+	fn v2_to_v1(self) -> Result<Self> {
+		if let ToClient::V2(packet) = self {
+			Ok(ToClient::V1(packet.message.try_into()?))
+		} else {
+			bail!("unexpected version");
+		}
+	}
 }
 
-impl TryFrom<protocol::ToClient> for ToClient {
+impl TryFrom<protocol::ToClient> for v2::ToClient {
 	type Error = anyhow::Error;
 
 	fn try_from(value: protocol::ToClient) -> Result<Self> {
-		Ok(ToClient::V1(match value {
+		Ok(match value {
 			protocol::ToClient::Init {
 				runner_id,
 				last_event_idx,
 				metadata,
-			} => v1::ToClient::ToClientInit(v1::ToClientInit {
+			} => v2::ToClient::ToClientInit(v2::ToClientInit {
 				runner_id: runner_id.to_string(),
 				last_event_idx,
 				metadata: metadata.try_into()?,
+				// Set by the ws
+				preferred_instance: None,
+				max_kv_keys_per_request: None,
+				max_kv_key_size: None,
+				max_kv_value_size: None,
 			}),
 			protocol::ToClient::Commands(commands) => {
 				let commands = commands
@@ -65,37 +116,38 @@ impl TryFrom<protocol::ToClient> for ToClient {
 					.map(|c| c.try_into())
 					.collect::<Result<_>>()?;
 
-				v1::ToClient::ToClientCommands(commands)
+				v2::ToClient::ToClientCommands(commands)
 			}
 			protocol::ToClient::AckEvents { last_event_idx } => {
-				v1::ToClient::ToClientAckEvents(v1::ToClientAckEvents { last_event_idx })
+				v2::ToClient::ToClientAckEvents(v2::ToClientAckEvents { last_event_idx })
 			}
-		}))
+			protocol::ToClient::ShutdownAck => v2::ToClient::ToClientShutdownAck,
+		})
 	}
 }
 
-impl TryFrom<protocol::ProtocolMetadata> for v1::ProtocolMetadata {
+impl TryFrom<protocol::ProtocolMetadata> for v2::ProtocolMetadata {
 	type Error = anyhow::Error;
 
 	fn try_from(value: protocol::ProtocolMetadata) -> Result<Self> {
-		Ok(v1::ProtocolMetadata {
+		Ok(v2::ProtocolMetadata {
 			runner_lost_threshold: value.runner_lost_threshold,
 		})
 	}
 }
 
-impl TryFrom<protocol::CommandWrapper> for v1::CommandWrapper {
+impl TryFrom<protocol::CommandWrapper> for v2::CommandWrapper {
 	type Error = anyhow::Error;
 
 	fn try_from(value: protocol::CommandWrapper) -> Result<Self> {
-		Ok(v1::CommandWrapper {
+		Ok(v2::CommandWrapper {
 			index: value.index,
 			inner: value.inner.try_into()?,
 		})
 	}
 }
 
-impl TryFrom<protocol::Command> for v1::Command {
+impl TryFrom<protocol::Command> for v2::Command {
 	type Error = anyhow::Error;
 
 	fn try_from(value: protocol::Command) -> Result<Self> {
@@ -104,7 +156,7 @@ impl TryFrom<protocol::Command> for v1::Command {
 				actor_id,
 				generation,
 				config,
-			} => Ok(v1::Command::CommandStartActor(v1::CommandStartActor {
+			} => Ok(v2::Command::CommandStartActor(v2::CommandStartActor {
 				actor_id: actor_id.to_string(),
 				generation,
 				config: (*config).try_into()?,
@@ -112,7 +164,7 @@ impl TryFrom<protocol::Command> for v1::Command {
 			protocol::Command::StopActor {
 				actor_id,
 				generation,
-			} => Ok(v1::Command::CommandStopActor(v1::CommandStopActor {
+			} => Ok(v2::Command::CommandStopActor(v2::CommandStopActor {
 				actor_id: actor_id.to_string(),
 				generation,
 			})),
@@ -120,11 +172,11 @@ impl TryFrom<protocol::Command> for v1::Command {
 	}
 }
 
-impl TryFrom<protocol::ActorConfig> for v1::ActorConfig {
+impl TryFrom<protocol::ActorConfig> for v2::ActorConfig {
 	type Error = anyhow::Error;
 
 	fn try_from(value: protocol::ActorConfig) -> Result<Self> {
-		Ok(v1::ActorConfig {
+		Ok(v2::ActorConfig {
 			name: value.name,
 			key: value.key,
 			create_ts: value.create_ts,
@@ -135,18 +187,18 @@ impl TryFrom<protocol::ActorConfig> for v1::ActorConfig {
 
 pub enum ToServer {
 	V1(v1::ToServer),
+	V2(v2::ToServer),
 }
 
 impl OwnedVersionedData for ToServer {
-	type Latest = v1::ToServer;
+	type Latest = v2::ToServer;
 
-	fn latest(latest: v1::ToServer) -> Self {
-		ToServer::V1(latest)
+	fn latest(latest: v2::ToServer) -> Self {
+		ToServer::V2(latest)
 	}
 
 	fn into_latest(self) -> Result<Self::Latest> {
-		#[allow(irrefutable_let_patterns)]
-		if let ToServer::V1(data) = self {
+		if let ToServer::V2(data) = self {
 			Ok(data)
 		} else {
 			bail!("version not latest");
@@ -156,6 +208,7 @@ impl OwnedVersionedData for ToServer {
 	fn deserialize_version(payload: &[u8], version: u16) -> Result<Self> {
 		match version {
 			1 => Ok(ToServer::V1(serde_bare::from_slice(payload)?)),
+			2 => Ok(ToServer::V2(serde_bare::from_slice(payload)?)),
 			_ => bail!("invalid version: {version}"),
 		}
 	}
@@ -163,28 +216,53 @@ impl OwnedVersionedData for ToServer {
 	fn serialize_version(self, _version: u16) -> Result<Vec<u8>> {
 		match self {
 			ToServer::V1(data) => serde_bare::to_vec(&data).map_err(Into::into),
+			ToServer::V2(data) => serde_bare::to_vec(&data).map_err(Into::into),
 		}
 	}
+
+	fn deserialize_converters() -> Vec<impl Fn(Self) -> Result<Self>> {
+		vec![Self::v1_to_v2]
+	}
+
+	fn serialize_converters() -> Vec<impl Fn(Self) -> Result<Self>> {
+		vec![Self::v2_to_v1]
+	}
 }
 
 impl ToServer {
 	pub fn serialize(self) -> Result<Vec<u8>> {
 		<Self as OwnedVersionedData>::serialize(self, PROTOCOL_VERSION)
 	}
+
+	fn v1_to_v2(self) -> Result<Self> {
+		if let ToServer::V1(message) = self {
+			Ok(ToServer::V2(message.into()))
+		} else {
+			bail!("unexpected version");
+		}
+	}
+
+	fn v2_to_v1(self) -> Result<Self> {
+		if let ToServer::V2(message) = self {
+			Ok(ToServer::V1(message.try_into()?))
+		} else {
+			bail!("unexpected version");
+		}
+	}
 }
 
-impl From<v1::ActorName> for protocol::ActorName {
-	fn from(value: v1::ActorName) -> Self {
+impl From<v2::ActorName> for protocol::ActorName {
+	fn from(value: v2::ActorName) -> Self {
 		protocol::ActorName {
 			metadata: value.metadata,
 		}
 	}
 }
 
-impl TryFrom<v1::EventWrapper> for protocol::EventWrapper {
+impl TryFrom<v2::EventWrapper> for protocol::EventWrapper {
 	type Error = anyhow::Error;
 
-	fn try_from(value: v1::EventWrapper) -> Result<Self> {
+	fn try_from(value: v2::EventWrapper) -> Result<Self> {
 		Ok(protocol::EventWrapper {
 			index: value.index,
 			inner: value.inner.try_into()?,
@@ -192,22 +270,22 @@ impl TryFrom<v1::EventWrapper> for protocol::EventWrapper {
 	}
 }
 
-impl TryFrom<v1::Event> for protocol::Event {
+impl TryFrom<v2::Event> for protocol::Event {
 	type Error = anyhow::Error;
 
-	fn try_from(value: v1::Event) -> Result<Self> {
+	fn try_from(value: v2::Event) -> Result<Self> {
 		match value {
-			v1::Event::EventActorIntent(event) => Ok(protocol::Event::ActorIntent {
+			v2::Event::EventActorIntent(event) => Ok(protocol::Event::ActorIntent {
 				actor_id: util::Id::parse(&event.actor_id)?,
 				generation: event.generation,
 				intent: event.intent.try_into()?,
 			}),
-			v1::Event::EventActorStateUpdate(event) => Ok(protocol::Event::ActorStateUpdate {
+			v2::Event::EventActorStateUpdate(event) => Ok(protocol::Event::ActorStateUpdate {
 				actor_id: util::Id::parse(&event.actor_id)?,
 				generation: event.generation,
 				state: event.state.try_into()?,
 			}),
-			v1::Event::EventActorSetAlarm(event) => Ok(protocol::Event::ActorSetAlarm {
+			v2::Event::EventActorSetAlarm(event) => Ok(protocol::Event::ActorSetAlarm {
 				actor_id: util::Id::parse(&event.actor_id)?,
 				generation: event.generation,
 				alarm_ts: event.alarm_ts,
@@ -216,24 +294,24 @@ impl TryFrom<v1::Event> for protocol::Event {
 	}
 }
 
-impl TryFrom<v1::ActorIntent> for protocol::ActorIntent {
+impl TryFrom<v2::ActorIntent> for protocol::ActorIntent {
 	type Error = anyhow::Error;
 
-	fn try_from(value: v1::ActorIntent) -> Result<Self> {
+	fn try_from(value: v2::ActorIntent) -> Result<Self> {
 		match value {
-			v1::ActorIntent::ActorIntentSleep => Ok(protocol::ActorIntent::Sleep),
-			v1::ActorIntent::ActorIntentStop => Ok(protocol::ActorIntent::Stop),
+			v2::ActorIntent::ActorIntentSleep => Ok(protocol::ActorIntent::Sleep),
+			v2::ActorIntent::ActorIntentStop => Ok(protocol::ActorIntent::Stop),
 		}
 	}
 }
 
-impl TryFrom<v1::ActorState> for protocol::ActorState {
+impl TryFrom<v2::ActorState> for protocol::ActorState {
 	type Error = anyhow::Error;
 
-	fn try_from(value: v1::ActorState) -> Result<Self> {
+	fn try_from(value: v2::ActorState) -> Result<Self> {
 		match value {
-			v1::ActorState::ActorStateRunning => Ok(protocol::ActorState::Running),
-			v1::ActorState::ActorStateStopped(stopped) => Ok(protocol::ActorState::Stopped {
+			v2::ActorState::ActorStateRunning => Ok(protocol::ActorState::Running),
+			v2::ActorState::ActorStateStopped(stopped) => Ok(protocol::ActorState::Stopped {
 				code: stopped.code.try_into()?,
 				message: stopped.message,
 			}),
@@ -241,23 +319,45 @@ impl TryFrom<v1::ActorState> for protocol::ActorState {
 	}
 }
 
-impl TryFrom<v1::StopCode> for protocol::StopCode {
+impl TryFrom<v2::StopCode> for protocol::StopCode {
 	type Error = anyhow::Error;
 
-	fn try_from(value: v1::StopCode) -> Result<Self> {
+	fn try_from(value: v2::StopCode) -> Result<Self> {
 		match value {
-			v1::StopCode::Ok => Ok(protocol::StopCode::Ok),
-			v1::StopCode::Error => Ok(protocol::StopCode::Error),
+			v2::StopCode::Ok => Ok(protocol::StopCode::Ok),
+			v2::StopCode::Error => Ok(protocol::StopCode::Error),
 		}
 	}
 }
 
-impl TryFrom<v1::ToServer> for protocol::ToServer {
+impl TryFrom<v2::PriorityClass> for protocol::PriorityClass {
 	type Error = anyhow::Error;
 
-	fn try_from(value: v1::ToServer) -> Result<Self> {
+	fn try_from(value: v2::PriorityClass) -> Result<Self> {
 		match value {
-			v1::ToServer::ToServerInit(init) => Ok(protocol::ToServer::Init {
+			v2::PriorityClass::BestEffort => Ok(protocol::PriorityClass::BestEffort),
+			v2::PriorityClass::Normal => Ok(protocol::PriorityClass::Normal),
+			v2::PriorityClass::System => Ok(protocol::PriorityClass::System),
+		}
+	}
+}
+
+impl From<v2::KvCapabilities> for protocol::KvCapabilities {
+	fn from(value: v2::KvCapabilities) -> Self {
+		protocol::KvCapabilities {
+			list: value.list,
+			metadata: value.metadata,
+			missing_keys: value.missing_keys,
+		}
+	}
+}
+
+impl TryFrom<v2::ToServer> for protocol::ToServer {
+	type Error = anyhow::Error;
+
+	fn try_from(value: v2::ToServer) -> Result<Self> {
+		match value {
+			v2::ToServer::ToServerInit(init) => Ok(protocol::ToServer::Init {
 				name: init.name,
 				version: init.version,
 				total_slots: init.total_slots,
@@ -266,25 +366,712 @@ impl TryFrom<v1::ToServer> for protocol::ToServer {
 					.prepopulate_actor_names
 					.map(|x| x.into_iter().map(|(k, v)| (k, v.into())).collect()),
 				metadata: init.metadata,
+				metrics_snapshots: init.metrics_snapshots.unwrap_or_default(),
+				runner_id: init.runner_id.as_deref().map(util::Id::parse).transpose()?,
+				priority: init
+					.priority
+					.map(TryInto::try_into)
+					.transpose()?
+					.unwrap_or_default(),
+				kv_capabilities: init.kv_capabilities.map(Into::into).unwrap_or_default(),
+				exclude_rtt: init.exclude_rtt.unwrap_or_default(),
 			}),
-			v1::ToServer::ToServerEvents(events) => Ok(protocol::ToServer::Events(
+			v2::ToServer::ToServerEvents(events) => Ok(protocol::ToServer::Events(
 				events
 					.into_iter()
 					.map(|e| e.try_into())
 					.collect::<Result<_>>()?,
 			)),
-			v1::ToServer::ToServerAckCommands(ack) => Ok(protocol::ToServer::AckCommands {
+			v2::ToServer::ToServerAckCommands(ack) => Ok(protocol::ToServer::AckCommands {
 				last_command_idx: ack.last_command_idx,
 			}),
-			v1::ToServer::ToServerStopping => Ok(protocol::ToServer::Stopping),
-			v1::ToServer::ToServerPing(_) => {
+			v2::ToServer::ToServerStopping => Ok(protocol::ToServer::Stopping),
+			v2::ToServer::ToServerGracefulShutdown => Ok(protocol::ToServer::GracefulShutdown),
+			v2::ToServer::ToServerPing(_) => {
 				// NOTE: Ping is handled at the websocket level and never reaches the workflow.
 				bail!("Ping variant should not be converted")
 			}
-			v1::ToServer::ToServerKvRequest(_) => {
+			v2::ToServer::ToServerKvRequest(_) => {
 				// NOTE: KV is handled at the websocket level and never reaches the workflow.
 				bail!("KV variant should not be converted")
 			}
+			v2::ToServer::ToServerAckPackets(_) => {
+				// NOTE: Packet acks are handled at the websocket level and never reach the workflow.
+				bail!("AckPackets variant should not be converted")
+			}
+			v2::ToServer::ToServerSyntheticLoad(_) => {
+				// NOTE: Synthetic load is handled at the websocket level and never reaches the workflow.
+				bail!("SyntheticLoad variant should not be converted")
+			}
+			v2::ToServer::ToServerGetKvStats => {
+				// NOTE: KV stats are handled at the websocket level and never reach the workflow.
+				bail!("GetKvStats variant should not be converted")
+			}
 		}
 	}
 }
+
+// Conversions between v1 and v2. Fields added in v2 are dropped when converting to v1 and are not
+// set when converting from v1. Messages added in v2 cannot be converted to v1.
+
+impl From<v1::ToServer> for v2::ToServer {
+	fn from(value: v1::ToServer) -> Self {
+		match value {
+			v1::ToServer::ToServerInit(init) => v2::ToServer::ToServerInit(init.into()),
+			v1::ToServer::ToServerEvents(events) => {
+				v2::ToServer::ToServerEvents(events.into_iter().map(Into::into).collect())
+			}
+			v1::ToServer::ToServerAckCommands(ack) => {
+				v2::ToServer::ToServerAckCommands(v2::ToServerAckCommands {
+					last_command_idx: ack.last_command_idx,
+				})
+			}
+			v1::ToServer::ToServerStopping => v2::ToServer::ToServerStopping,
+			v1::ToServer::ToServerPing(ping) => v2::ToServer::ToServerPing(v2::ToServerPing {
+				ts: ping.ts,
+				load: None,
+			}),
+			v1::ToServer::ToServerKvRequest(req) => v2::ToServer::ToServerKvRequest(req.into()),
+		}
+	}
+}
+
+impl TryFrom<v2::ToServer> for v1::ToServer {
+	type Error = anyhow::Error;
+
+	fn try_from(value: v2::ToServer) -> Result<Self> {
+		Ok(match value {
+			v2::ToServer::ToServerInit(init) => v1::ToServer::ToServerInit(init.into()),
+			v2::ToServer::ToServerEvents(events) => {
+				v1::ToServer::ToServerEvents(events.into_iter().map(Into::into).collect())
+			}
+			v2::ToServer::ToServerAckCommands(ack) => {
+				v1::ToServer::ToServerAckCommands(v1::ToServerAckCommands {
+					last_command_idx: ack.last_command_idx,
+				})
+			}
+			v2::ToServer::ToServerStopping => v1::ToServer::ToServerStopping,
+			v2::ToServer::ToServerPing(ping) => {
+				v1::ToServer::ToServerPing(v1::ToServerPing { ts: ping.ts })
+			}
+			v2::ToServer::ToServerKvRequest(req) => {
+				v1::ToServer::ToServerKvRequest(req.try_into()?)
+			}
+			v2::ToServer::ToServerAckPackets(_) => bail!("AckPackets does not exist in v1"),
+			v2::ToServer::ToServerGracefulShutdown => {
+				bail!("GracefulShutdown does not exist in v1")
+			}
+			v2::ToServer::ToServerSyntheticLoad(_) => bail!("SyntheticLoad does not exist in v1"),
+			v2::ToServer::ToServerGetKvStats => bail!("GetKvStats does not exist in v1"),
+		})
+	}
+}
+
+impl From<v1::ToServerInit> for v2::ToServerInit {
+	fn from(value: v1::ToServerInit) -> Self {
+		v2::ToServerInit {
+			name: value.name,
+			version: value.version,
+			total_slots: value.total_slots,
+			last_command_idx: value.last_command_idx,
+			prepopulate_actor_names: value
+				.prepopulate_actor_names
+				.map(|x| x.into_iter().map(|(k, v)| (k, v.into())).collect()),
+			metadata: value.metadata,
+			metrics_snapshots: None,
+			runner_id: None,
+			priority: None,
+			kv_capabilities: None,
+			exclude_rtt: None,
+		}
+	}
+}
+
+impl From<v2::ToServerInit> for v1::ToServerInit {
+	fn from(value: v2::ToServerInit) -> Self {
+		v1::ToServerInit {
+			name: value.name,
+			version: value.version,
+			total_slots: value.total_slots,
+			last_command_idx: value.last_command_idx,
+			prepopulate_actor_names: value
+				.prepopulate_actor_names
+				.map(|x| x.into_iter().map(|(k, v)| (k, v.into())).collect()),
+			metadata: value.metadata,
+		}
+	}
+}
+
+impl From<v1::ActorName> for v2::ActorName {
+	fn from(value: v1::ActorName) -> Self {
+		v2::ActorName {
+			metadata: value.metadata,
+		}
+	}
+}
+
+impl From<v2::ActorName> for v1::ActorName {
+	fn from(value: v2::ActorName) -> Self {
+		v1::ActorName {
+			metadata: value.metadata,
+		}
+	}
+}
+
+impl From<v1::EventWrapper> for v2::EventWrapper {
+	fn from(value: v1::EventWrapper) -> Self {
+		v2::EventWrapper {
+			index: value.index,
+			inner: match value.inner {
+				v1::Event::EventActorIntent(event) => {
+					v2::Event::EventActorIntent(v2::EventActorIntent {
+						actor_id: event.actor_id,
+						generation: event.generation,
+						intent: match event.intent {
+							v1::ActorIntent::ActorIntentSleep => v2::ActorIntent::ActorIntentSleep,
+							v1::ActorIntent::ActorIntentStop => v2::ActorIntent::ActorIntentStop,
+						},
+					})
+				}
+				v1::Event::EventActorStateUpdate(event) => {
+					v2::Event::EventActorStateUpdate(v2::EventActorStateUpdate {
+						actor_id: event.actor_id,
+						generation: event.generation,
+						state: match event.state {
+							v1::ActorState::ActorStateRunning => v2::ActorState::ActorStateRunning,
+							v1::ActorState::ActorStateStopped(stopped) => {
+								v2::ActorState::ActorStateStopped(v2::ActorStateStopped {
+									code: match stopped.code {
+										v1::StopCode::Ok => v2::StopCode::Ok,
+										v1::StopCode::Error => v2::StopCode::Error,
+									},
+									message: stopped.message,
+								})
+							}
+						},
+					})
+				}
+				v1::Event::EventActorSetAlarm(event) => {
+					v2::Event::EventActorSetAlarm(v2::EventActorSetAlarm {
+						actor_id: event.actor_id,
+						generation: event.generation,
+						alarm_ts: event.alarm_ts,
+					})
+				}
+			},
+		}
+	}
+}
+
+impl From<v2::EventWrapper> for v1::EventWrapper {
+	fn from(value: v2::EventWrapper) -> Self {
+		v1::EventWrapper {
+			index: value.index,
+			inner: match value.inner {
+				v2::Event::EventActorIntent(event) => {
+					v1::Event::EventActorIntent(v1::EventActorIntent {
+						actor_id: event.actor_id,
+						generation: event.generation,
+						intent: match event.intent {
+							v2::ActorIntent::ActorIntentSleep => v1::ActorIntent::ActorIntentSleep,
+							v2::ActorIntent::ActorIntentStop => v1::ActorIntent::ActorIntentStop,
+						},
+					})
+				}
+				v2::Event::EventActorStateUpdate(event) => {
+					v1::Event::EventActorStateUpdate(v1::EventActorStateUpdate {
+						actor_id: event.actor_id,
+						generation: event.generation,
+						state: match event.state {
+							v2::ActorState::ActorStateRunning => v1::ActorState::ActorStateRunning,
+							v2::ActorState::ActorStateStopped(stopped) => {
+								v1::ActorState::ActorStateStopped(v1::ActorStateStopped {
+									code: match stopped.code {
+										v2::StopCode::Ok => v1::StopCode::Ok,
+										v2::StopCode::Error => v1::StopCode::Error,
+									},
+									message: stopped.message,
+								})
+							}
+						},
+					})
+				}
+				v2::Event::EventActorSetAlarm(event) => {
+					v1::Event::EventActorSetAlarm(v1::EventActorSetAlarm {
+						actor_id: event.actor_id,
+						generation: event.generation,
+						alarm_ts: event.alarm_ts,
+					})
+				}
+			},
+		}
+	}
+}
+
+impl From<v1::ToServerKvRequest> for v2::ToServerKvRequest {
+	fn from(value: v1::ToServerKvRequest) -> Self {
+		v2::ToServerKvRequest {
+			actor_id: value.actor_id,
+			request_id: value.request_id,
+			// v1 runners only use the actor's flat key space
+			collection: None,
+			data: match value.data {
+				v1::KvRequestData::KvGetRequest(req) => {
+					v2::KvRequestData::KvGetRequest(v2::KvGetRequest { keys: req.keys })
+				}
+				v1::KvRequestData::KvListRequest(req) => {
+					v2::KvRequestData::KvListRequest(v2::KvListRequest {
+						query: req.query.into(),
+						reverse: req.reverse,
+						limit: req.limit,
+					})
+				}
+				v1::KvRequestData::KvPutRequest(req) => {
+					v2::KvRequestData::KvPutRequest(v2::KvPutRequest {
+						keys: req.keys,
+						values: req.values,
+						content_types: None,
+					})
+				}
+				v1::KvRequestData::KvDeleteRequest(req) => {
+					v2::KvRequestData::KvDeleteRequest(v2::KvDeleteRequest { keys: req.keys })
+				}
+				v1::KvRequestData::KvDropRequest => v2::KvRequestData::KvDropRequest,
+			},
+		}
+	}
+}
+
+impl TryFrom<v2::ToServerKvRequest> for v1::ToServerKvRequest {
+	type Error = anyhow::Error;
+
+	fn try_from(value: v2::ToServerKvRequest) -> Result<Self> {
+		// Dropping the collection would apply the request to the actor's flat key space instead
+		if value.collection.is_some() {
+			bail!("KV collections do not exist in v1");
+		}
+
+		Ok(v1::ToServerKvRequest {
+			actor_id: value.actor_id,
+			request_id: value.request_id,
+			data: match value.data {
+				v2::KvRequestData::KvGetRequest(req) => {
+					v1::KvRequestData::KvGetRequest(v1::KvGetRequest { keys: req.keys })
+				}
+				v2::KvRequestData::KvListRequest(req) => {
+					v1::KvRequestData::KvListRequest(v1::KvListRequest {
+						query: req.query.into(),
+						reverse: req.reverse,
+						limit: req.limit,
+					})
+				}
+				v2::KvRequestData::KvPutRequest(req) => {
+					v1::KvRequestData::KvPutRequest(v1::KvPutRequest {
+						keys: req.keys,
+						values: req.values,
+					})
+				}
+				v2::KvRequestData::KvDeleteRequest(req) => {
+					v1::KvRequestData::KvDeleteRequest(v1::KvDeleteRequest { keys: req.keys })
+				}
+				v2::KvRequestData::KvDropRequest => v1::KvRequestData::KvDropRequest,
+			},
+		})
+	}
+}
+
+impl From<v1::KvListQuery> for v2::KvListQuery {
+	fn from(value: v1::KvListQuery) -> Self {
+		match value {
+			v1::KvListQuery::KvListAllQuery => v2::KvListQuery::KvListAllQuery,
+			v1::KvListQuery::KvListRangeQuery(query) => {
+				v2::KvListQuery::KvListRangeQuery(v2::KvListRangeQuery {
+					start: query.start,
+					end: query.end,
+					exclusive: query.exclusive,
+				})
+			}
+			v1::KvListQuery::KvListPrefixQuery(query) => {
+				v2::KvListQuery::KvListPrefixQuery(v2::KvListPrefixQuery { key: query.key })
+			}
+		}
+	}
+}
+
+impl From<v2::KvListQuery> for v1::KvListQuery {
+	fn from(value: v2::KvListQuery) -> Self {
+		match value {
+			v2::KvListQuery::KvListAllQuery => v1::KvListQuery::KvListAllQuery,
+			v2::KvListQuery::KvListRangeQuery(query) => {
+				v1::KvListQuery::KvListRangeQuery(v1::KvListRangeQuery {
+					start: query.start,
+					end: query.end,
+					exclusive: query.exclusive,
+				})
+			}
+			v2::KvListQuery::KvListPrefixQuery(query) => {
+				v1::KvListQuery::KvListPrefixQuery(v1::KvListPrefixQuery { key: query.key })
+			}
+		}
+	}
+}
+
+impl From<v1::ToClient> for v2::ToClient {
+	fn from(value: v1::ToClient) -> Self {
+		match value {
+			v1::ToClient::ToClientInit(init) => v2::ToClient::ToClientInit(v2::ToClientInit {
+				runner_id: init.runner_id,
+				last_event_idx: init.last_event_idx,
+				metadata: v2::ProtocolMetadata {
+					runner_lost_threshold: init.metadata.runner_lost_threshold,
+				},
+				preferred_instance: None,
+				max_kv_keys_per_request: None,
+				max_kv_key_size: None,
+				max_kv_value_size: None,
+			}),
+			v1::ToClient::ToClientCommands(commands) => {
+				v2::ToClient::ToClientCommands(commands.into_iter().map(Into::into).collect())
+			}
+			v1::ToClient::ToClientAckEvents(ack) => {
+				v2::ToClient::ToClientAckEvents(v2::ToClientAckEvents {
+					last_event_idx: ack.last_event_idx,
+				})
+			}
+			v1::ToClient::ToClientKvResponse(res) => {
+				v2::ToClient::ToClientKvResponse(v2::ToClientKvResponse {
+					request_id: res.request_id,
+					data: res.data.into(),
+				})
+			}
+		}
+	}
+}
+
+impl TryFrom<v2::ToClient> for v1::ToClient {
+	type Error = anyhow::Error;
+
+	fn try_from(value: v2::ToClient) -> Result<Self> {
+		Ok(match value {
+			v2::ToClient::ToClientInit(init) => v1::ToClient::ToClientInit(v1::ToClientInit {
+				runner_id: init.runner_id,
+				last_event_idx: init.last_event_idx,
+				metadata: v1::ProtocolMetadata {
+					runner_lost_threshold: init.metadata.runner_lost_threshold,
+				},
+			}),
+			v2::ToClient::ToClientCommands(commands) => {
+				v1::ToClient::ToClientCommands(commands.into_iter().map(Into::into).collect())
+			}
+			v2::ToClient::ToClientAckEvents(ack) => {
+				v1::ToClient::ToClientAckEvents(v1::ToClientAckEvents {
+					last_event_idx: ack.last_event_idx,
+				})
+			}
+			v2::ToClient::ToClientKvResponse(res) => {
+				v1::ToClient::ToClientKvResponse(v1::ToClientKvResponse {
+					request_id: res.request_id,
+					data: res.data.into(),
+				})
+			}
+			v2::ToClient::ToClientShutdownAck => bail!("ShutdownAck does not exist in v1"),
+			v2::ToClient::ToClientKvThrottle => bail!("KvThrottle does not exist in v1"),
+			v2::ToClient::ToClientKvResume => bail!("KvResume does not exist in v1"),
+			v2::ToClient::ToClientMetricsSnapshot(_) => {
+				bail!("MetricsSnapshot does not exist in v1")
+			}
+			v2::ToClient::ToClientKvStats(_) => bail!("KvStats does not exist in v1"),
+			v2::ToClient::ToClientWorkflowEnding => bail!("WorkflowEnding does not exist in v1"),
+		})
+	}
+}
+
+impl From<v1::CommandWrapper> for v2::CommandWrapper {
+	fn from(value: v1::CommandWrapper) -> Self {
+		v2::CommandWrapper {
+			index: value.index,
+			inner: match value.inner {
+				v1::Command::CommandStartActor(cmd) => {
+					v2::Command::CommandStartActor(v2::CommandStartActor {
+						actor_id: cmd.actor_id,
+						generation: cmd.generation,
+						config: v2::ActorConfig {
+							name: cmd.config.name,
+							key: cmd.config.key,
+							create_ts: cmd.config.create_ts,
+							input: cmd.config.input,
+						},
+					})
+				}
+				v1::Command::CommandStopActor(cmd) => {
+					v2::Command::CommandStopActor(v2::CommandStopActor {
+						actor_id: cmd.actor_id,
+						generation: cmd.generation,
+					})
+				}
+			},
+		}
+	}
+}
+
+impl From<v2::CommandWrapper> for v1::CommandWrapper {
+	fn from(value: v2::CommandWrapper) -> Self {
+		v1::CommandWrapper {
+			index: value.index,
+			inner: match value.inner {
+				v2::Command::CommandStartActor(cmd) => {
+					v1::Command::CommandStartActor(v1::CommandStartActor {
+						actor_id: cmd.actor_id,
+						generation: cmd.generation,
+						config: v1::ActorConfig {
+							name: cmd.config.name,
+							key: cmd.config.key,
+							create_ts: cmd.config.create_ts,
+							input: cmd.config.input,
+						},
+					})
+				}
+				v2::Command::CommandStopActor(cmd) => {
+					v1::Command::CommandStopActor(v1::CommandStopActor {
+						actor_id: cmd.actor_id,
+						generation: cmd.generation,
+					})
+				}
+			},
+		}
+	}
+}
+
+impl From<v1::KvResponseData> for v2::KvResponseData {
+	fn from(value: v1::KvResponseData) -> Self {
+		match value {
+			v1::KvResponseData::KvErrorResponse(res) => {
+				v2::KvResponseData::KvErrorResponse(v2::KvErrorResponse {
+					message: res.message,
+					code: None,
+					retryable: None,
+				})
+			}
+			v1::KvResponseData::KvGetResponse(res) => {
+				v2::KvResponseData::KvGetResponse(v2::KvGetResponse {
+					keys: res.keys,
+					values: res.values,
+					metadata: res.metadata.into_iter().map(Into::into).collect(),
+					missing_keys: None,
+				})
+			}
+			v1::KvResponseData::KvListResponse(res) => {
+				v2::KvResponseData::KvListResponse(v2::KvListResponse {
+					keys: res.keys,
+					values: res.values,
+					metadata: res.metadata.into_iter().map(Into::into).collect(),
+				})
+			}
+			v1::KvResponseData::KvPutResponse => v2::KvResponseData::KvPutResponse,
+			v1::KvResponseData::KvDeleteResponse => v2::KvResponseData::KvDeleteResponse,
+			v1::KvResponseData::KvDropResponse => v2::KvResponseData::KvDropResponse,
+		}
+	}
+}
+
+impl From<v2::KvResponseData> for v1::KvResponseData {
+	fn from(value: v2::KvResponseData) -> Self {
+		match value {
+			v2::KvResponseData::KvErrorResponse(res) => {
+				v1::KvResponseData::KvErrorResponse(v1::KvErrorResponse {
+					message: res.message,
+				})
+			}
+			v2::KvResponseData::KvGetResponse(res) => {
+				v1::KvResponseData::KvGetResponse(v1::KvGetResponse {
+					keys: res.keys,
+					values: res.values,
+					metadata: res.metadata.into_iter().map(Into::into).collect(),
+				})
+			}
+			v2::KvResponseData::KvListResponse(res) => {
+				v1::KvResponseData::KvListResponse(v1::KvListResponse {
+					keys: res.keys,
+					values: res.values,
+					metadata: res.metadata.into_iter().map(Into::into).collect(),
+				})
+			}
+			v2::KvResponseData::KvPutResponse => v1::KvResponseData::KvPutResponse,
+			v2::KvResponseData::KvDeleteResponse => v1::KvResponseData::KvDeleteResponse,
+			v2::KvResponseData::KvDropResponse => v1::KvResponseData::KvDropResponse,
+		}
+	}
+}
+
+impl From<v1::KvMetadata> for v2::KvMetadata {
+	fn from(value: v1::KvMetadata) -> Self {
+		v2::KvMetadata {
+			version: value.version,
+			create_ts: value.create_ts,
+			content_type: None,
+		}
+	}
+}
+
+impl From<v2::KvMetadata> for v1::KvMetadata {
+	fn from(value: v2::KvMetadata) -> Self {
+		v1::KvMetadata {
+			version: value.version,
+			create_ts: value.create_ts,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn v1_kv_request_uses_flat_key_space() {
+		let req = v1::ToServer::ToServerKvRequest(v1::ToServerKvRequest {
+			actor_id: "actor".to_string(),
+			request_id: 1,
+			data: v1::KvRequestData::KvDropRequest,
+		});
+		let buf = serde_bare::to_vec(&req).unwrap();
+
+		let req = <ToServer as OwnedVersionedData>::deserialize(&buf, 1).unwrap();
+		let v2::ToServer::ToServerKvRequest(req) = req else {
+			panic!("expected kv request");
+		};
+		assert_eq!(req.request_id, 1);
+		assert!(req.collection.is_none());
+		assert!(matches!(req.data, v2::KvRequestData::KvDropRequest));
+
+		// Dropping the collection would drop the actor's entire KV
+		let req = v2::ToServerKvRequest {
+			collection: Some("collection".to_string()),
+			..req
+		};
+		assert!(v1::ToServerKvRequest::try_from(req).is_err());
+	}
+
+	// Golden bytes below are encoded by hand from the v1 schema as deployed runners send and expect
+	// them. They must never change.
+
+	#[test]
+	fn decodes_v1_init() {
+		let buf = [
+			0x00, // ToServerInit
+			0x06, b'r', b'u', b'n', b'n', b'e', b'r', // name
+			0x01, 0x00, 0x00, 0x00, // version
+			0x04, 0x00, 0x00, 0x00, // totalSlots
+			0x01, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // lastCommandIdx
+			0x00, // prepopulateActorNames
+			0x00, // metadata
+		];
+
+		let packet = <ToServer as OwnedVersionedData>::deserialize(&buf, 1).unwrap();
+		let protocol::ToServer::Init {
+			name,
+			version,
+			total_slots,
+			last_command_idx,
+			prepopulate_actor_names,
+			metadata,
+			metrics_snapshots,
+			runner_id,
+			priority,
+			exclude_rtt,
+			..
+		} = packet.try_into().unwrap()
+		else {
+			panic!("expected init");
+		};
+		assert_eq!(name, "runner");
+		assert_eq!(version, 1);
+		assert_eq!(total_slots, 4);
+		assert_eq!(last_command_idx, Some(5));
+		assert!(prepopulate_actor_names.is_none());
+		assert!(metadata.is_none());
+		assert!(!metrics_snapshots);
+		assert!(runner_id.is_none());
+		assert_eq!(priority, protocol::PriorityClass::Normal);
+		assert!(!exclude_rtt);
+	}
+
+	#[test]
+	fn decodes_v1_kv_request() {
+		let buf = [
+			0x05, // ToServerKvRequest
+			0x01, b'a', // actorId
+			0x07, 0x00, 0x00, 0x00, // requestId
+			0x02, // KvPutRequest
+			0x01, 0x02, 0x01, 0x02, // keys
+			0x01, 0x01, 0x03, // values
+		];
+
+		let packet = <ToServer as OwnedVersionedData>::deserialize(&buf, 1).unwrap();
+		let v2::ToServer::ToServerKvRequest(req) = packet else {
+			panic!("expected kv request");
+		};
+		assert_eq!(req.actor_id, "a");
+		assert_eq!(req.request_id, 7);
+		assert!(req.collection.is_none());
+		let v2::KvRequestData::KvPutRequest(put) = req.data else {
+			panic!("expected put request");
+		};
+		assert_eq!(put.keys, vec![vec![1, 2]]);
+		assert_eq!(put.values, vec![vec![3]]);
+		assert!(put.content_types.is_none());
+	}
+
+	#[test]
+	fn encodes_v1_to_client() {
+		let init = v2::ToClientPacket {
+			seq: 1,
+			message: v2::ToClient::ToClientInit(v2::ToClientInit {
+				runner_id: "r".to_string(),
+				last_event_idx: 2,
+				metadata: v2::ProtocolMetadata {
+					runner_lost_threshold: 1000,
+				},
+				preferred_instance: Some("instance".to_string()),
+				max_kv_keys_per_request: Some(128),
+				max_kv_key_size: None,
+				max_kv_value_size: None,
+			}),
+		};
+		assert_eq!(
+			ToClient::latest(init).serialize(1).unwrap(),
+			[
+				0x00, // ToClientInit
+				0x01, b'r', // runnerId
+				0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // lastEventIdx
+				0xe8, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // runnerLostThreshold
+			],
+		);
+
+		let error = v2::ToClientPacket {
+			seq: 2,
+			message: v2::ToClient::ToClientKvResponse(v2::ToClientKvResponse {
+				request_id: 7,
+				data: v2::KvResponseData::KvErrorResponse(v2::KvErrorResponse {
+					message: "x".to_string(),
+					code: Some("kv_internal_error".to_string()),
+					retryable: Some(true),
+				}),
+			}),
+		};
+		assert_eq!(
+			ToClient::latest(error).serialize(1).unwrap(),
+			[
+				0x03, // ToClientKvResponse
+				0x07, 0x00, 0x00, 0x00, // requestId
+				0x00, // KvErrorResponse
+				0x01, b'x', // message
+			],
+		);
+
+		let ack = v2::ToClientPacket {
+			seq: 3,
+			message: v2::ToClient::ToClientShutdownAck,
+		};
+		assert!(ToClient::latest(ack).serialize(1).is_err());
+	}
+}
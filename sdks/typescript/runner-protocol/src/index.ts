@@ -49,18 +49,24 @@ export function writeKvValue(bc: bare.ByteCursor, x: KvValue): void {
 export type KvMetadata = {
     readonly version: ArrayBuffer
     readonly createTs: i64
+    /**
+     * Content type given when the value was put. Not set for opaque values.
+     */
+    readonly contentType: string | null
 }
 
 export function readKvMetadata(bc: bare.ByteCursor): KvMetadata {
     return {
         version: bare.readData(bc),
         createTs: bare.readI64(bc),
+        contentType: read0(bc),
     }
 }
 
 export function writeKvMetadata(bc: bare.ByteCursor, x: KvMetadata): void {
     bare.writeData(bc, x.version)
     bare.writeI64(bc, x.createTs)
+    write0(bc, x.contentType)
 }
 
 export type KvListAllQuery = null
@@ -583,13 +589,170 @@ function write5(bc: bare.ByteCursor, x: Json | null): void {
     }
 }
 
+/**
+ * How a connection is treated while the server is under load.
+ */
+export enum PriorityClass {
+    /**
+     * KV requests are rejected while KV is overloaded.
+     */
+    BestEffort = "BestEffort",
+    /**
+     * Asked to slow down KV requests while KV is overloaded (see `ToClientKvThrottle`).
+     */
+    Normal = "Normal",
+    /**
+     * Control plane runners. Never asked to slow down KV requests.
+     */
+    System = "System",
+}
+
+export function readPriorityClass(bc: bare.ByteCursor): PriorityClass {
+    const offset = bc.offset
+    const tag = bare.readU8(bc)
+    switch (tag) {
+        case 0:
+            return PriorityClass.BestEffort
+        case 1:
+            return PriorityClass.Normal
+        case 2:
+            return PriorityClass.System
+        default: {
+            bc.offset = offset
+            throw new bare.BareError(offset, "invalid tag")
+        }
+    }
+}
+
+export function writePriorityClass(bc: bare.ByteCursor, x: PriorityClass): void {
+    switch (x) {
+        case PriorityClass.BestEffort: {
+            bare.writeU8(bc, 0)
+            break
+        }
+        case PriorityClass.Normal: {
+            bare.writeU8(bc, 1)
+            break
+        }
+        case PriorityClass.System: {
+            bare.writeU8(bc, 2)
+            break
+        }
+    }
+}
+
+/**
+ * KV features the runner can handle. Responses are tailored so the runner is never sent fields it cannot
+ * parse.
+ */
+export type KvCapabilities = {
+    /**
+     * Whether the runner sends `KvListRequest`s. List requests are rejected with `kv_list_unsupported` if
+     * not set.
+     */
+    readonly list: boolean
+    /**
+     * Whether the runner parses `KvMetadata`. Get and list responses have empty `metadata` if not set.
+     */
+    readonly metadata: boolean
+    /**
+     * Whether the runner parses `KvGetResponse.missingKeys`. Never set if not supported.
+     */
+    readonly missingKeys: boolean
+}
+
+export function readKvCapabilities(bc: bare.ByteCursor): KvCapabilities {
+    return {
+        list: bare.readBool(bc),
+        metadata: bare.readBool(bc),
+        missingKeys: bare.readBool(bc),
+    }
+}
+
+export function writeKvCapabilities(bc: bare.ByteCursor, x: KvCapabilities): void {
+    bare.writeBool(bc, x.list)
+    bare.writeBool(bc, x.metadata)
+    bare.writeBool(bc, x.missingKeys)
+}
+
+function read16(bc: bare.ByteCursor): Id | null {
+    return bare.readBool(bc) ? readId(bc) : null
+}
+
+function write16(bc: bare.ByteCursor, x: Id | null): void {
+    bare.writeBool(bc, x != null)
+    if (x != null) {
+        writeId(bc, x)
+    }
+}
+
+function read17(bc: bare.ByteCursor): PriorityClass | null {
+    return bare.readBool(bc) ? readPriorityClass(bc) : null
+}
+
+function write17(bc: bare.ByteCursor, x: PriorityClass | null): void {
+    bare.writeBool(bc, x != null)
+    if (x != null) {
+        writePriorityClass(bc, x)
+    }
+}
+
+function read18(bc: bare.ByteCursor): KvCapabilities | null {
+    return bare.readBool(bc) ? readKvCapabilities(bc) : null
+}
+
+function write18(bc: bare.ByteCursor, x: KvCapabilities | null): void {
+    bare.writeBool(bc, x != null)
+    if (x != null) {
+        writeKvCapabilities(bc, x)
+    }
+}
+
+function read19(bc: bare.ByteCursor): boolean | null {
+    return bare.readBool(bc) ? bare.readBool(bc) : null
+}
+
+function write19(bc: bare.ByteCursor, x: boolean | null): void {
+    bare.writeBool(bc, x != null)
+    if (x != null) {
+        bare.writeBool(bc, x)
+    }
+}
+
 export type ToServerInit = {
     readonly name: string
     readonly version: u32
+    /**
+     * Max concurrent actors, at most 4294967. Zero connects a standby runner that stays connected
+     * (and keeps pinging) but is never allocated actors.
+     */
     readonly totalSlots: u32
     readonly lastCommandIdx: i64 | null
     readonly prepopulateActorNames: ReadonlyMap<string, ActorName> | null
     readonly metadata: Json | null
+    /**
+     * Opts in to periodic `ToClientMetricsSnapshot` messages. Defaults to false.
+     */
+    readonly metricsSnapshots: boolean | null
+    /**
+     * Runner id from a previous connection to rebind to (i.e. after the runner process restarted). Only
+     * honored if the runner is still live and has the same namespace, name and key, otherwise a runner id
+     * is assigned as usual.
+     */
+    readonly runnerId: Id | null
+    /**
+     * Defaults to `NORMAL`.
+     */
+    readonly priority: PriorityClass | null
+    /**
+     * Assumes full support if not set.
+     */
+    readonly kvCapabilities: KvCapabilities | null
+    /**
+     * Keeps the runner's RTT out of the allocation index (i.e. batch workers whose sporadic pings would
+     * report stale RTTs). Pings still keep the runner alive. Defaults to false.
+     */
+    readonly excludeRtt: boolean | null
 }
 
 export function readToServerInit(bc: bare.ByteCursor): ToServerInit {
@@ -600,6 +763,11 @@ export function readToServerInit(bc: bare.ByteCursor): ToServerInit {
         lastCommandIdx: read1(bc),
         prepopulateActorNames: read4(bc),
         metadata: read5(bc),
+        metricsSnapshots: read19(bc),
+        runnerId: read16(bc),
+        priority: read17(bc),
+        kvCapabilities: read18(bc),
+        excludeRtt: read19(bc),
     }
 }
 
@@ -610,6 +778,11 @@ export function writeToServerInit(bc: bare.ByteCursor, x: ToServerInit): void {
     write1(bc, x.lastCommandIdx)
     write4(bc, x.prepopulateActorNames)
     write5(bc, x.metadata)
+    write19(bc, x.metricsSnapshots)
+    write16(bc, x.runnerId)
+    write17(bc, x.priority)
+    write18(bc, x.kvCapabilities)
+    write19(bc, x.excludeRtt)
 }
 
 export type ToServerEvents = readonly EventWrapper[]
@@ -649,21 +822,65 @@ export function writeToServerAckCommands(bc: bare.ByteCursor, x: ToServerAckComm
 
 export type ToServerStopping = null
 
+/**
+ * Load reported by the runner. `cpu` and `memory` are utilization in thousandths (0 = idle, 1000 = fully
+ * loaded).
+ */
+export type RunnerLoad = {
+    readonly cpu: u32
+    readonly memory: u32
+    /**
+     * Number of actors waiting to be started by the runner.
+     */
+    readonly queueDepth: u32
+}
+
+export function readRunnerLoad(bc: bare.ByteCursor): RunnerLoad {
+    return {
+        cpu: bare.readU32(bc),
+        memory: bare.readU32(bc),
+        queueDepth: bare.readU32(bc),
+    }
+}
+
+export function writeRunnerLoad(bc: bare.ByteCursor, x: RunnerLoad): void {
+    bare.writeU32(bc, x.cpu)
+    bare.writeU32(bc, x.memory)
+    bare.writeU32(bc, x.queueDepth)
+}
+
+function read6(bc: bare.ByteCursor): RunnerLoad | null {
+    return bare.readBool(bc) ? readRunnerLoad(bc) : null
+}
+
+function write6(bc: bare.ByteCursor, x: RunnerLoad | null): void {
+    bare.writeBool(bc, x != null)
+    if (x != null) {
+        writeRunnerLoad(bc, x)
+    }
+}
+
 export type ToServerPing = {
     readonly ts: i64
+    /**
+     * Not set if the runner does not report load.
+     */
+    readonly load: RunnerLoad | null
 }
 
 export function readToServerPing(bc: bare.ByteCursor): ToServerPing {
     return {
         ts: bare.readI64(bc),
+        load: read6(bc),
     }
 }
 
 export function writeToServerPing(bc: bare.ByteCursor, x: ToServerPing): void {
     bare.writeI64(bc, x.ts)
+    write6(bc, x.load)
 }
 
-function read6(bc: bare.ByteCursor): readonly KvKey[] {
+function read7(bc: bare.ByteCursor): readonly KvKey[] {
     const len = bare.readUintSafe(bc)
     if (len === 0) {
         return []
@@ -675,7 +892,7 @@ function read6(bc: bare.ByteCursor): readonly KvKey[] {
     return result
 }
 
-function write6(bc: bare.ByteCursor, x: readonly KvKey[]): void {
+function write7(bc: bare.ByteCursor, x: readonly KvKey[]): void {
     bare.writeUintSafe(bc, x.length)
     for (let i = 0; i < x.length; i++) {
         writeKvKey(bc, x[i])
@@ -688,30 +905,30 @@ export type KvGetRequest = {
 
 export function readKvGetRequest(bc: bare.ByteCursor): KvGetRequest {
     return {
-        keys: read6(bc),
+        keys: read7(bc),
     }
 }
 
 export function writeKvGetRequest(bc: bare.ByteCursor, x: KvGetRequest): void {
-    write6(bc, x.keys)
+    write7(bc, x.keys)
 }
 
-function read7(bc: bare.ByteCursor): boolean | null {
+function read8(bc: bare.ByteCursor): boolean | null {
     return bare.readBool(bc) ? bare.readBool(bc) : null
 }
 
-function write7(bc: bare.ByteCursor, x: boolean | null): void {
+function write8(bc: bare.ByteCursor, x: boolean | null): void {
     bare.writeBool(bc, x != null)
     if (x != null) {
         bare.writeBool(bc, x)
     }
 }
 
-function read8(bc: bare.ByteCursor): u64 | null {
+function read9(bc: bare.ByteCursor): u64 | null {
     return bare.readBool(bc) ? bare.readU64(bc) : null
 }
 
-function write8(bc: bare.ByteCursor, x: u64 | null): void {
+function write9(bc: bare.ByteCursor, x: u64 | null): void {
     bare.writeBool(bc, x != null)
     if (x != null) {
         bare.writeU64(bc, x)
@@ -727,18 +944,18 @@ export type KvListRequest = {
 export function readKvListRequest(bc: bare.ByteCursor): KvListRequest {
     return {
         query: readKvListQuery(bc),
-        reverse: read7(bc),
-        limit: read8(bc),
+        reverse: read8(bc),
+        limit: read9(bc),
     }
 }
 
 export function writeKvListRequest(bc: bare.ByteCursor, x: KvListRequest): void {
     writeKvListQuery(bc, x.query)
-    write7(bc, x.reverse)
-    write8(bc, x.limit)
+    write8(bc, x.reverse)
+    write9(bc, x.limit)
 }
 
-function read9(bc: bare.ByteCursor): readonly KvValue[] {
+function read10(bc: bare.ByteCursor): readonly KvValue[] {
     const len = bare.readUintSafe(bc)
     if (len === 0) {
         return []
@@ -750,28 +967,65 @@ function read9(bc: bare.ByteCursor): readonly KvValue[] {
     return result
 }
 
-function write9(bc: bare.ByteCursor, x: readonly KvValue[]): void {
+function write10(bc: bare.ByteCursor, x: readonly KvValue[]): void {
     bare.writeUintSafe(bc, x.length)
     for (let i = 0; i < x.length; i++) {
         writeKvValue(bc, x[i])
     }
 }
 
+function read14(bc: bare.ByteCursor): readonly (string | null)[] {
+    const len = bare.readUintSafe(bc)
+    if (len === 0) {
+        return []
+    }
+    const result = [read0(bc)]
+    for (let i = 1; i < len; i++) {
+        result[i] = read0(bc)
+    }
+    return result
+}
+
+function write14(bc: bare.ByteCursor, x: readonly (string | null)[]): void {
+    bare.writeUintSafe(bc, x.length)
+    for (let i = 0; i < x.length; i++) {
+        write0(bc, x[i])
+    }
+}
+
+function read15(bc: bare.ByteCursor): readonly (string | null)[] | null {
+    return bare.readBool(bc) ? read14(bc) : null
+}
+
+function write15(bc: bare.ByteCursor, x: readonly (string | null)[] | null): void {
+    bare.writeBool(bc, x != null)
+    if (x != null) {
+        write14(bc, x)
+    }
+}
+
 export type KvPutRequest = {
     readonly keys: readonly KvKey[]
     readonly values: readonly KvValue[]
+    /**
+     * Content type of each value, in the same order as `values`. Stored alongside the value and returned
+     * unchanged in `KvMetadata`. Values without a content type are opaque.
+     */
+    readonly contentTypes: readonly (string | null)[] | null
 }
 
 export function readKvPutRequest(bc: bare.ByteCursor): KvPutRequest {
     return {
-        keys: read6(bc),
-        values: read9(bc),
+        keys: read7(bc),
+        values: read10(bc),
+        contentTypes: read15(bc),
     }
 }
 
 export function writeKvPutRequest(bc: bare.ByteCursor, x: KvPutRequest): void {
-    write6(bc, x.keys)
-    write9(bc, x.values)
+    write7(bc, x.keys)
+    write10(bc, x.values)
+    write15(bc, x.contentTypes)
 }
 
 export type KvDeleteRequest = {
@@ -780,12 +1034,12 @@ export type KvDeleteRequest = {
 
 export function readKvDeleteRequest(bc: bare.ByteCursor): KvDeleteRequest {
     return {
-        keys: read6(bc),
+        keys: read7(bc),
     }
 }
 
 export function writeKvDeleteRequest(bc: bare.ByteCursor, x: KvDeleteRequest): void {
-    write6(bc, x.keys)
+    write7(bc, x.keys)
 }
 
 export type KvDropRequest = null
@@ -850,6 +1104,11 @@ export function writeKvRequestData(bc: bare.ByteCursor, x: KvRequestData): void
 export type ToServerKvRequest = {
     readonly actorId: Id
     readonly requestId: u32
+    /**
+     * Scopes the request to a named collection within the actor's KV. Uses the actor's flat key space if
+     * not set.
+     */
+    readonly collection: string | null
     readonly data: KvRequestData
 }
 
@@ -857,6 +1116,7 @@ export function readToServerKvRequest(bc: bare.ByteCursor): ToServerKvRequest {
     return {
         actorId: readId(bc),
         requestId: bare.readU32(bc),
+        collection: read0(bc),
         data: readKvRequestData(bc),
     }
 }
@@ -864,9 +1124,65 @@ export function readToServerKvRequest(bc: bare.ByteCursor): ToServerKvRequest {
 export function writeToServerKvRequest(bc: bare.ByteCursor, x: ToServerKvRequest): void {
     writeId(bc, x.actorId)
     bare.writeU32(bc, x.requestId)
+    write0(bc, x.collection)
     writeKvRequestData(bc, x.data)
 }
 
+/**
+ * Acknowledges all `ToClientPacket`s up to and including the given sequence number.
+ */
+export type ToServerAckPackets = {
+    readonly lastSeq: u64
+}
+
+export function readToServerAckPackets(bc: bare.ByteCursor): ToServerAckPackets {
+    return {
+        lastSeq: bare.readU64(bc),
+    }
+}
+
+export function writeToServerAckPackets(bc: bare.ByteCursor, x: ToServerAckPackets): void {
+    bare.writeU64(bc, x.lastSeq)
+}
+
+/**
+ * Tells the server the runner is draining. The server stops allocating actors to the runner and waits for
+ * the remaining actors to stop (or a timeout) before responding with `ToClientShutdownAck`.
+ */
+export type ToServerGracefulShutdown = null
+
+/**
+ * Marks all following KV requests of the connection as synthetic load test traffic, recorded separately
+ * from real traffic. Only accepted by servers built with the `synthetic-load` feature, other servers close
+ * the connection.
+ */
+export type ToServerSyntheticLoad = {
+    /**
+     * Respond to writes (put, delete and drop) without writing to the database.
+     */
+    readonly skipWrites: boolean
+}
+
+export function readToServerSyntheticLoad(bc: bare.ByteCursor): ToServerSyntheticLoad {
+    return {
+        skipWrites: bare.readBool(bc),
+    }
+}
+
+export function writeToServerSyntheticLoad(bc: bare.ByteCursor, x: ToServerSyntheticLoad): void {
+    bare.writeBool(bc, x.skipWrites)
+}
+
+/**
+ * Requests the KV usage of this connection, answered with `ToClientKvStats`.
+ */
+export type ToServerGetKvStats = null
+
+/**
+ * Packets are processed strictly in the order they are sent, across all message types. A KV request is
+ * applied before any packet sent after it (i.e. events), and its response is sent before those packets are
+ * processed.
+ */
 export type ToServer =
     | { readonly tag: "ToServerInit"; readonly val: ToServerInit }
     | { readonly tag: "ToServerEvents"; readonly val: ToServerEvents }
@@ -874,6 +1190,10 @@ export type ToServer =
     | { readonly tag: "ToServerStopping"; readonly val: ToServerStopping }
     | { readonly tag: "ToServerPing"; readonly val: ToServerPing }
     | { readonly tag: "ToServerKvRequest"; readonly val: ToServerKvRequest }
+    | { readonly tag: "ToServerAckPackets"; readonly val: ToServerAckPackets }
+    | { readonly tag: "ToServerGracefulShutdown"; readonly val: ToServerGracefulShutdown }
+    | { readonly tag: "ToServerSyntheticLoad"; readonly val: ToServerSyntheticLoad }
+    | { readonly tag: "ToServerGetKvStats"; readonly val: ToServerGetKvStats }
 
 export function readToServer(bc: bare.ByteCursor): ToServer {
     const offset = bc.offset
@@ -891,6 +1211,14 @@ export function readToServer(bc: bare.ByteCursor): ToServer {
             return { tag: "ToServerPing", val: readToServerPing(bc) }
         case 5:
             return { tag: "ToServerKvRequest", val: readToServerKvRequest(bc) }
+        case 6:
+            return { tag: "ToServerAckPackets", val: readToServerAckPackets(bc) }
+        case 7:
+            return { tag: "ToServerGracefulShutdown", val: null }
+        case 8:
+            return { tag: "ToServerSyntheticLoad", val: readToServerSyntheticLoad(bc) }
+        case 9:
+            return { tag: "ToServerGetKvStats", val: null }
         default: {
             bc.offset = offset
             throw new bare.BareError(offset, "invalid tag")
@@ -929,6 +1257,24 @@ export function writeToServer(bc: bare.ByteCursor, x: ToServer): void {
             writeToServerKvRequest(bc, x.val)
             break
         }
+        case "ToServerAckPackets": {
+            bare.writeU8(bc, 6)
+            writeToServerAckPackets(bc, x.val)
+            break
+        }
+        case "ToServerGracefulShutdown": {
+            bare.writeU8(bc, 7)
+            break
+        }
+        case "ToServerSyntheticLoad": {
+            bare.writeU8(bc, 8)
+            writeToServerSyntheticLoad(bc, x.val)
+            break
+        }
+        case "ToServerGetKvStats": {
+            bare.writeU8(bc, 9)
+            break
+        }
     }
 }
 
@@ -969,6 +1315,25 @@ export type ToClientInit = {
     readonly runnerId: Id
     readonly lastEventIdx: i64
     readonly metadata: ProtocolMetadata
+    /**
+     * Advisory identity of the instance that recently served this runner key and holds its cached state.
+     * Runners can pass it as the `preferred_instance` query parameter on their next connection attempt so
+     * load balancers can route to it. Not set if this instance is the preferred one.
+     */
+    readonly preferredInstance: string | null
+    /**
+     * Maximum number of keys in a single KV get, put or delete request. Larger requests must be split into
+     * batches.
+     */
+    readonly maxKvKeysPerRequest: u32 | null
+    /**
+     * Largest KV key in bytes. Requests with larger keys are rejected with `kv_size_limit_exceeded`.
+     */
+    readonly maxKvKeySize: u32 | null
+    /**
+     * Largest KV value in bytes. Puts with larger values are rejected with `kv_size_limit_exceeded`.
+     */
+    readonly maxKvValueSize: u32 | null
 }
 
 export function readToClientInit(bc: bare.ByteCursor): ToClientInit {
@@ -976,6 +1341,10 @@ export function readToClientInit(bc: bare.ByteCursor): ToClientInit {
         runnerId: readId(bc),
         lastEventIdx: bare.readI64(bc),
         metadata: readProtocolMetadata(bc),
+        preferredInstance: read0(bc),
+        maxKvKeysPerRequest: read13(bc),
+        maxKvKeySize: read13(bc),
+        maxKvValueSize: read13(bc),
     }
 }
 
@@ -983,6 +1352,10 @@ export function writeToClientInit(bc: bare.ByteCursor, x: ToClientInit): void {
     writeId(bc, x.runnerId)
     bare.writeI64(bc, x.lastEventIdx)
     writeProtocolMetadata(bc, x.metadata)
+    write0(bc, x.preferredInstance)
+    write13(bc, x.maxKvKeysPerRequest)
+    write13(bc, x.maxKvKeySize)
+    write13(bc, x.maxKvValueSize)
 }
 
 export type ToClientCommands = readonly CommandWrapper[]
@@ -1022,19 +1395,47 @@ export function writeToClientAckEvents(bc: bare.ByteCursor, x: ToClientAckEvents
 
 export type KvErrorResponse = {
     readonly message: string
+    /**
+     * Machine readable error code. Not set for generic errors.
+     *
+     * - `kv_keys_limit_exceeded`: The request has more keys than `ToClientInit.maxKvKeysPerRequest`.
+     * - `kv_overloaded`: KV is overloaded and the connection's priority class is `BEST_EFFORT`.
+     * - `kv_disabled`: KV is disabled for the runner's namespace.
+     * - `kv_list_unsupported`: The runner did not declare list support in `KvCapabilities`.
+     * - `kv_budget_exhausted`: Too much KV data is being sent by the server. Retryable.
+     * - `kv_namespace_busy`: The runner's namespace has too many KV operations in flight. Retryable.
+     * - `kv_size_limit_exceeded`: A key or value is larger than `ToClientInit.maxKvKeySize` or
+     *   `ToClientInit.maxKvValueSize`.
+     * - `kv_invalid_request`: The request is invalid (i.e. an invalid key or a full storage quota).
+     * - `kv_internal_error`: The operation failed on the server.
+     *
+     * The message of the last two only contains the underlying error if the server has detailed errors
+     * enabled.
+     */
+    readonly code: string | null
+    /**
+     * Whether the same request can succeed if retried (i.e. after a transaction conflict or while the
+     * server is overloaded). Errors caused by the request itself (i.e. invalid keys or a full storage
+     * quota) are not retryable. Not set by servers that do not classify errors.
+     */
+    readonly retryable: boolean | null
 }
 
 export function readKvErrorResponse(bc: bare.ByteCursor): KvErrorResponse {
     return {
         message: bare.readString(bc),
+        code: read0(bc),
+        retryable: read8(bc),
     }
 }
 
 export function writeKvErrorResponse(bc: bare.ByteCursor, x: KvErrorResponse): void {
     bare.writeString(bc, x.message)
+    write0(bc, x.code)
+    write8(bc, x.retryable)
 }
 
-function read10(bc: bare.ByteCursor): readonly KvMetadata[] {
+function read11(bc: bare.ByteCursor): readonly KvMetadata[] {
     const len = bare.readUintSafe(bc)
     if (len === 0) {
         return []
@@ -1046,31 +1447,49 @@ function read10(bc: bare.ByteCursor): readonly KvMetadata[] {
     return result
 }
 
-function write10(bc: bare.ByteCursor, x: readonly KvMetadata[]): void {
+function write11(bc: bare.ByteCursor, x: readonly KvMetadata[]): void {
     bare.writeUintSafe(bc, x.length)
     for (let i = 0; i < x.length; i++) {
         writeKvMetadata(bc, x[i])
     }
 }
 
+function read12(bc: bare.ByteCursor): readonly KvKey[] | null {
+    return bare.readBool(bc) ? read7(bc) : null
+}
+
+function write12(bc: bare.ByteCursor, x: readonly KvKey[] | null): void {
+    bare.writeBool(bc, x != null)
+    if (x != null) {
+        write7(bc, x)
+    }
+}
+
 export type KvGetResponse = {
     readonly keys: readonly KvKey[]
     readonly values: readonly KvValue[]
     readonly metadata: readonly KvMetadata[]
+    /**
+     * Requested keys that do not exist, in request order. Not set by servers that do not report missing
+     * keys, in which case absence must be inferred from `keys`.
+     */
+    readonly missingKeys: readonly KvKey[] | null
 }
 
 export function readKvGetResponse(bc: bare.ByteCursor): KvGetResponse {
     return {
-        keys: read6(bc),
-        values: read9(bc),
-        metadata: read10(bc),
+        keys: read7(bc),
+        values: read10(bc),
+        metadata: read11(bc),
+        missingKeys: read12(bc),
     }
 }
 
 export function writeKvGetResponse(bc: bare.ByteCursor, x: KvGetResponse): void {
-    write6(bc, x.keys)
-    write9(bc, x.values)
-    write10(bc, x.metadata)
+    write7(bc, x.keys)
+    write10(bc, x.values)
+    write11(bc, x.metadata)
+    write12(bc, x.missingKeys)
 }
 
 export type KvListResponse = {
@@ -1081,16 +1500,16 @@ export type KvListResponse = {
 
 export function readKvListResponse(bc: bare.ByteCursor): KvListResponse {
     return {
-        keys: read6(bc),
-        values: read9(bc),
-        metadata: read10(bc),
+        keys: read7(bc),
+        values: read10(bc),
+        metadata: read11(bc),
     }
 }
 
 export function writeKvListResponse(bc: bare.ByteCursor, x: KvListResponse): void {
-    write6(bc, x.keys)
-    write9(bc, x.values)
-    write10(bc, x.metadata)
+    write7(bc, x.keys)
+    write10(bc, x.values)
+    write11(bc, x.metadata)
 }
 
 export type KvPutResponse = null
@@ -1179,11 +1598,199 @@ export function writeToClientKvResponse(bc: bare.ByteCursor, x: ToClientKvRespon
     writeKvResponseData(bc, x.data)
 }
 
+/**
+ * Sent in response to `ToServerGracefulShutdown` once it is safe for the runner to exit.
+ */
+export type ToClientShutdownAck = null
+
+/**
+ * Sent when the server's KV backend is overloaded. Runners should reduce their KV request rate until
+ * `ToClientKvResume` is received.
+ */
+export type ToClientKvThrottle = null
+
+export type ToClientKvResume = null
+
+function read13(bc: bare.ByteCursor): u32 | null {
+    return bare.readBool(bc) ? bare.readU32(bc) : null
+}
+
+function write13(bc: bare.ByteCursor, x: u32 | null): void {
+    bare.writeBool(bc, x != null)
+    if (x != null) {
+        bare.writeU32(bc, x)
+    }
+}
+
+/**
+ * The server's view of the connection's health. Only sent to runners that set `metricsSnapshots` in
+ * `ToServerInit`.
+ */
+export type ToClientMetricsSnapshot = {
+    /**
+     * Last measured round trip time in ms.
+     */
+    readonly rtt: u32
+    /**
+     * Estimated clock skew in ms, positive if the runner's clock is behind the server's.
+     */
+    readonly clockSkew: i64
+    /**
+     * Average KV request latency in ms since the last snapshot. Not set if no KV requests were made.
+     */
+    readonly kvLatency: u32 | null
+    /**
+     * Number of KV requests since the last snapshot.
+     */
+    readonly kvRequests: u32
+    readonly kvThrottled: boolean
+    /**
+     * Whether the runner is eligible for actor allocation.
+     */
+    readonly eligible: boolean
+    /**
+     * Average time in ms KV requests spent queued behind other namespaces' requests since the last
+     * snapshot (see `pegboard.max_kv_concurrency`). Not set if no KV requests were made.
+     */
+    readonly kvQueueWait: u32 | null
+}
+
+export function readToClientMetricsSnapshot(bc: bare.ByteCursor): ToClientMetricsSnapshot {
+    return {
+        rtt: bare.readU32(bc),
+        clockSkew: bare.readI64(bc),
+        kvLatency: read13(bc),
+        kvRequests: bare.readU32(bc),
+        kvThrottled: bare.readBool(bc),
+        eligible: bare.readBool(bc),
+        kvQueueWait: read13(bc),
+    }
+}
+
+export function writeToClientMetricsSnapshot(bc: bare.ByteCursor, x: ToClientMetricsSnapshot): void {
+    bare.writeU32(bc, x.rtt)
+    bare.writeI64(bc, x.clockSkew)
+    write13(bc, x.kvLatency)
+    bare.writeU32(bc, x.kvRequests)
+    bare.writeBool(bc, x.kvThrottled)
+    bare.writeBool(bc, x.eligible)
+    write13(bc, x.kvQueueWait)
+}
+
+/**
+ * Size of KV values before and after compression, see `ToClientKvStats.compression`.
+ */
+export type KvCompressionStats = {
+    /**
+     * Values written by puts.
+     */
+    readonly inboundUncompressed: u64
+    readonly inboundCompressed: u64
+    /**
+     * Compressed values returned by gets and lists.
+     */
+    readonly outboundUncompressed: u64
+    readonly outboundCompressed: u64
+}
+
+export function readKvCompressionStats(bc: bare.ByteCursor): KvCompressionStats {
+    return {
+        inboundUncompressed: bare.readU64(bc),
+        inboundCompressed: bare.readU64(bc),
+        outboundUncompressed: bare.readU64(bc),
+        outboundCompressed: bare.readU64(bc),
+    }
+}
+
+export function writeKvCompressionStats(bc: bare.ByteCursor, x: KvCompressionStats): void {
+    bare.writeU64(bc, x.inboundUncompressed)
+    bare.writeU64(bc, x.inboundCompressed)
+    bare.writeU64(bc, x.outboundUncompressed)
+    bare.writeU64(bc, x.outboundCompressed)
+}
+
+function read20(bc: bare.ByteCursor): KvCompressionStats | null {
+    return bare.readBool(bc) ? readKvCompressionStats(bc) : null
+}
+
+function write20(bc: bare.ByteCursor, x: KvCompressionStats | null): void {
+    bare.writeBool(bc, x != null)
+    if (x != null) {
+        writeKvCompressionStats(bc, x)
+    }
+}
+
+/**
+ * KV usage of all actors of this connection since it was established. Resets on reconnect.
+ */
+export type ToClientKvStats = {
+    readonly gets: u64
+    readonly lists: u64
+    readonly puts: u64
+    readonly deletes: u64
+    readonly drops: u64
+    /**
+     * Size of the keys and values returned by gets and lists.
+     */
+    readonly bytesRead: u64
+    /**
+     * Size of the keys and values sent with puts.
+     */
+    readonly bytesWritten: u64
+    /**
+     * Requests answered with `KvErrorResponse`.
+     */
+    readonly errors: u64
+    /**
+     * Not set if KV compression is disabled for the connection.
+     */
+    readonly compression: KvCompressionStats | null
+}
+
+export function readToClientKvStats(bc: bare.ByteCursor): ToClientKvStats {
+    return {
+        gets: bare.readU64(bc),
+        lists: bare.readU64(bc),
+        puts: bare.readU64(bc),
+        deletes: bare.readU64(bc),
+        drops: bare.readU64(bc),
+        bytesRead: bare.readU64(bc),
+        bytesWritten: bare.readU64(bc),
+        errors: bare.readU64(bc),
+        compression: read20(bc),
+    }
+}
+
+export function writeToClientKvStats(bc: bare.ByteCursor, x: ToClientKvStats): void {
+    bare.writeU64(bc, x.gets)
+    bare.writeU64(bc, x.lists)
+    bare.writeU64(bc, x.puts)
+    bare.writeU64(bc, x.deletes)
+    bare.writeU64(bc, x.drops)
+    bare.writeU64(bc, x.bytesRead)
+    bare.writeU64(bc, x.bytesWritten)
+    bare.writeU64(bc, x.errors)
+    write20(bc, x.compression)
+}
+
+/**
+ * Sent before the server closes the connection because the runner's workflow ended (i.e. it failed and will
+ * not be retried). The runner's actors are no longer tracked, so the runner should stop them and reconnect
+ * without its runner id to be assigned a new one.
+ */
+export type ToClientWorkflowEnding = null
+
 export type ToClient =
     | { readonly tag: "ToClientInit"; readonly val: ToClientInit }
     | { readonly tag: "ToClientCommands"; readonly val: ToClientCommands }
     | { readonly tag: "ToClientAckEvents"; readonly val: ToClientAckEvents }
     | { readonly tag: "ToClientKvResponse"; readonly val: ToClientKvResponse }
+    | { readonly tag: "ToClientShutdownAck"; readonly val: ToClientShutdownAck }
+    | { readonly tag: "ToClientKvThrottle"; readonly val: ToClientKvThrottle }
+    | { readonly tag: "ToClientKvResume"; readonly val: ToClientKvResume }
+    | { readonly tag: "ToClientMetricsSnapshot"; readonly val: ToClientMetricsSnapshot }
+    | { readonly tag: "ToClientKvStats"; readonly val: ToClientKvStats }
+    | { readonly tag: "ToClientWorkflowEnding"; readonly val: ToClientWorkflowEnding }
 
 export function readToClient(bc: bare.ByteCursor): ToClient {
     const offset = bc.offset
@@ -1197,6 +1804,18 @@ export function readToClient(bc: bare.ByteCursor): ToClient {
             return { tag: "ToClientAckEvents", val: readToClientAckEvents(bc) }
         case 3:
             return { tag: "ToClientKvResponse", val: readToClientKvResponse(bc) }
+        case 4:
+            return { tag: "ToClientShutdownAck", val: null }
+        case 5:
+            return { tag: "ToClientKvThrottle", val: null }
+        case 6:
+            return { tag: "ToClientKvResume", val: null }
+        case 7:
+            return { tag: "ToClientMetricsSnapshot", val: readToClientMetricsSnapshot(bc) }
+        case 8:
+            return { tag: "ToClientKvStats", val: readToClientKvStats(bc) }
+        case 9:
+            return { tag: "ToClientWorkflowEnding", val: null }
         default: {
             bc.offset = offset
             throw new bare.BareError(offset, "invalid tag")
@@ -1226,22 +1845,70 @@ export function writeToClient(bc: bare.ByteCursor, x: ToClient): void {
             writeToClientKvResponse(bc, x.val)
             break
         }
+        case "ToClientShutdownAck": {
+            bare.writeU8(bc, 4)
+            break
+        }
+        case "ToClientKvThrottle": {
+            bare.writeU8(bc, 5)
+            break
+        }
+        case "ToClientKvResume": {
+            bare.writeU8(bc, 6)
+            break
+        }
+        case "ToClientMetricsSnapshot": {
+            bare.writeU8(bc, 7)
+            writeToClientMetricsSnapshot(bc, x.val)
+            break
+        }
+        case "ToClientKvStats": {
+            bare.writeU8(bc, 8)
+            writeToClientKvStats(bc, x.val)
+            break
+        }
+        case "ToClientWorkflowEnding": {
+            bare.writeU8(bc, 9)
+            break
+        }
     }
 }
 
-export function encodeToClient(x: ToClient, config?: Partial<bare.Config>): Uint8Array {
+/**
+ * Every message sent to the runner is wrapped in a packet. The sequence number starts at 1 for each new
+ * connection and increments by 1 for every packet, so runners can detect dropped packets. Sequence numbers
+ * reset on reconnect.
+ */
+export type ToClientPacket = {
+    readonly seq: u64
+    readonly message: ToClient
+}
+
+export function readToClientPacket(bc: bare.ByteCursor): ToClientPacket {
+    return {
+        seq: bare.readU64(bc),
+        message: readToClient(bc),
+    }
+}
+
+export function writeToClientPacket(bc: bare.ByteCursor, x: ToClientPacket): void {
+    bare.writeU64(bc, x.seq)
+    writeToClient(bc, x.message)
+}
+
+export function encodeToClientPacket(x: ToClientPacket, config?: Partial<bare.Config>): Uint8Array {
     const fullConfig = config != null ? bare.Config(config) : DEFAULT_CONFIG
     const bc = new bare.ByteCursor(
         new Uint8Array(fullConfig.initialBufferLength),
         fullConfig,
     )
-    writeToClient(bc, x)
+    writeToClientPacket(bc, x)
     return new Uint8Array(bc.view.buffer, bc.view.byteOffset, bc.offset)
 }
 
-export function decodeToClient(bytes: Uint8Array): ToClient {
+export function decodeToClientPacket(bytes: Uint8Array): ToClientPacket {
     const bc = new bare.ByteCursor(bytes, DEFAULT_CONFIG)
-    const result = readToClient(bc)
+    const result = readToClientPacket(bc)
     if (bc.offset < bc.view.byteLength) {
         throw new bare.BareError(bc.offset, "remaining bytes")
     }
@@ -8,6 +8,14 @@ import type { Logger } from "pino";
 import { setLogger, logger } from "./log.js";
 
 const KV_EXPIRE: number = 30_000;
+/** Delay before sending KV requests while the server has throttled KV. */
+const KV_THROTTLE_DELAY: number = 250;
+/** Close code sent by the server when the init packet was not sent in time. */
+const INIT_TIMEOUT_CLOSE_CODE: number = 4008;
+/** Close code sent by the server when the runner should reconnect to another instance (i.e. rebalance). */
+const RECONNECT_ELSEWHERE_CLOSE_CODE: number = 1012;
+/** Close code sent by the server when the runner was evicted for a policy violation. */
+const POLICY_VIOLATION_CLOSE_CODE: number = 1008;
 
 export interface ActorInstance {
 	actorId: string;
@@ -48,15 +56,43 @@ export interface RunnerConfig {
 	) => Promise<void>;
 	onActorStop: (actorId: string, generation: number) => Promise<void>;
 	noAutoShutdown?: boolean;
+	/** Called with the server's view of the connection's health. Setting this opts in to snapshots. */
+	onMetricsSnapshot?: (snapshot: protocol.ToClientMetricsSnapshot) => void;
+	/** Isolates all KV data of this runner under the given prefix (i.e. for test runners). */
+	kvPrefix?: string;
+	/** Runner id from a previous run to rebind to, if it is still live. */
+	runnerId?: string;
+	/** How the connection is treated while the server is under load. Defaults to normal. */
+	priority?: protocol.PriorityClass;
+	/** Keeps this runner's RTT out of placement (i.e. for batch workers that ping sporadically). */
+	excludeRtt?: boolean;
 }
 
 export interface KvListOptions {
 	reverse?: boolean;
 	limit?: number;
+	/** Lists keys in the given collection instead of the actor's flat key space. */
+	collection?: string;
+}
+
+/** Error returned by the server for a KV request. */
+export class KvError extends Error {
+	/** Machine readable error code, see `KvErrorResponse.code`. */
+	readonly code: string | null;
+	/** Whether the same request can succeed if retried. Null if the server does not classify errors. */
+	readonly retryable: boolean | null;
+
+	constructor(error: protocol.KvErrorResponse) {
+		super(error.message || "Unknown KV error");
+		this.name = "KvError";
+		this.code = error.code;
+		this.retryable = error.retryable;
+	}
 }
 
 interface KvRequestEntry {
 	actorId: string;
+	collection: string | null;
 	data: protocol.KvRequestData;
 	resolve: (value: any) => void;
 	reject: (error: unknown) => void;
@@ -78,6 +114,7 @@ export class Runner {
 	#pegboardWebSocket?: WebSocket;
 	runnerId?: string;
 	#lastCommandIdx: number = -1;
+	#lastPacketSeq: bigint = 0n;
 	#pingLoop?: NodeJS.Timeout;
 	#nextEventIdx: bigint = 0n;
 	#started: boolean = false;
@@ -100,6 +137,20 @@ export class Runner {
 	#nextRequestId: number = 0;
 	#kvRequests: Map<number, KvRequestEntry> = new Map();
 	#kvCleanupInterval?: NodeJS.Timeout;
+	#kvThrottled: boolean = false;
+	#kvStatsRequests: {
+		resolve: (stats: protocol.ToClientKvStats) => void;
+		reject: (error: unknown) => void;
+	}[] = [];
+
+	// Instance to reconnect to, see `ToClientInit.preferredInstance`
+	#preferredInstance?: string;
+
+	// See `ToClientInit.maxKvKeysPerRequest`
+	#maxKvKeysPerRequest?: number;
+	// See `ToClientInit.maxKvKeySize` and `ToClientInit.maxKvValueSize`
+	#maxKvKeySize?: number;
+	#maxKvValueSize?: number;
 
 	// Tunnel for HTTP/WebSocket forwarding
 	#tunnel?: Tunnel;
@@ -160,10 +211,6 @@ export class Runner {
 	}
 
 	#stopAllActors() {
-		logger()?.info(
-			"stopping all actors due to runner lost threshold exceeded",
-		);
-
 		const actorIds = Array.from(this.#actors.keys());
 		for (const actorId of actorIds) {
 			this.stopActor(actorId);
@@ -308,6 +355,12 @@ export class Runner {
 			);
 		}
 		this.#kvRequests.clear();
+		for (const request of this.#kvStatsRequests) {
+			request.reject(
+				new Error("WebSocket connection closed during shutdown"),
+			);
+		}
+		this.#kvStatsRequests = [];
 
 		// Close WebSocket
 		if (
@@ -394,7 +447,28 @@ export class Runner {
 		const wsEndpoint = endpoint
 			.replace("http://", "ws://")
 			.replace("https://", "wss://");
-		return `${wsEndpoint}?protocol_version=1&namespace=${encodeURIComponent(this.#config.namespace)}&runner_key=${encodeURIComponent(this.#config.runnerKey)}`;
+		const preferredInstance = this.#preferredInstance
+			? `&preferred_instance=${encodeURIComponent(this.#preferredInstance)}`
+			: "";
+		const kvPrefix = this.#config.kvPrefix
+			? `&kv_prefix=${encodeURIComponent(this.#config.kvPrefix)}`
+			: "";
+		return `${wsEndpoint}?protocol_version=2&namespace=${encodeURIComponent(this.#config.namespace)}&runner_key=${encodeURIComponent(this.#config.runnerKey)}${preferredInstance}${kvPrefix}`;
+	}
+
+	/** Maximum number of keys in a single KV get, put or delete. Larger requests must be batched. */
+	get maxKvKeysPerRequest(): number | undefined {
+		return this.#maxKvKeysPerRequest;
+	}
+
+	/** Largest KV key in bytes allowed for this runner's namespace. */
+	get maxKvKeySize(): number | undefined {
+		return this.#maxKvKeySize;
+	}
+
+	/** Largest KV value in bytes allowed for this runner's namespace. */
+	get maxKvValueSize(): number | undefined {
+		return this.#maxKvValueSize;
 	}
 
 	get pegboardTunnelUrl() {
@@ -459,6 +533,12 @@ export class Runner {
 			// Reset reconnect attempt counter on successful connection
 			this.#reconnectAttempt = 0;
 
+			// Packet sequence numbers restart on every connection
+			this.#lastPacketSeq = 0n;
+
+			// KV throttling is tracked per connection by the server
+			this.#kvThrottled = false;
+
 			// Clear any pending reconnect timeout
 			if (this.#reconnectTimeout) {
 				clearTimeout(this.#reconnectTimeout);
@@ -489,6 +569,13 @@ export class Runner {
 					),
 				),
 				metadata: JSON.stringify(this.#config.metadata),
+				metricsSnapshots: this.#config.onMetricsSnapshot !== undefined,
+				// Rebind to the runner id of the previous connection if known
+				runnerId: this.runnerId ?? this.#config.runnerId ?? null,
+				priority: this.#config.priority ?? null,
+				// Supports all KV features
+				kvCapabilities: null,
+				excludeRtt: this.#config.excludeRtt ?? null,
 			};
 
 			this.#sendToServer({
@@ -507,6 +594,7 @@ export class Runner {
 						tag: "ToServerPing",
 						val: {
 							ts: BigInt(Date.now()),
+							load: null,
 						},
 					});
 				} else {
@@ -521,6 +609,7 @@ export class Runner {
 			const ackLoop = setInterval(() => {
 				if (ws.readyState === WebSocket.OPEN) {
 					this.#sendCommandAcknowledgment();
+					this.#sendPacketAcknowledgment();
 				} else {
 					clearInterval(ackLoop);
 					logger()?.info("WebSocket not open, stopping ack loop");
@@ -540,7 +629,18 @@ export class Runner {
 			}
 
 			// Parse message
-			const message = protocol.decodeToClient(buf);
+			const packet = protocol.decodeToClientPacket(buf);
+			const message = packet.message;
+
+			// Detect dropped packets
+			if (packet.seq !== this.#lastPacketSeq + 1n) {
+				logger()?.warn({
+					msg: "packet sequence gap",
+					expected: this.#lastPacketSeq + 1n,
+					received: packet.seq,
+				});
+			}
+			this.#lastPacketSeq = packet.seq;
 
 			// Handle message
 			if (message.tag === "ToClientInit") {
@@ -548,6 +648,11 @@ export class Runner {
 				const hadRunnerId = !!this.runnerId;
 				this.runnerId = init.runnerId;
 
+				this.#preferredInstance = init.preferredInstance ?? undefined;
+				this.#maxKvKeysPerRequest = init.maxKvKeysPerRequest ?? undefined;
+				this.#maxKvKeySize = init.maxKvKeySize ?? undefined;
+				this.#maxKvValueSize = init.maxKvValueSize ?? undefined;
+
 				// Store the runner lost threshold from metadata
 				this.#runnerLostThreshold = init.metadata?.runnerLostThreshold
 					? Number(init.metadata.runnerLostThreshold)
@@ -572,6 +677,32 @@ export class Runner {
 			} else if (message.tag === "ToClientKvResponse") {
 				const kvResponse = message.val;
 				this.#handleKvResponse(kvResponse);
+			} else if (message.tag === "ToClientKvThrottle") {
+				logger()?.warn("kv throttled by server");
+				this.#kvThrottled = true;
+			} else if (message.tag === "ToClientKvResume") {
+				logger()?.info("kv resumed by server");
+				this.#kvThrottled = false;
+			} else if (message.tag === "ToClientShutdownAck") {
+				// Only sent in response to ToServerGracefulShutdown, which this runner does not use yet
+				logger()?.info("received shutdown ack");
+			} else if (message.tag === "ToClientMetricsSnapshot") {
+				this.#config.onMetricsSnapshot?.(message.val);
+			} else if (message.tag === "ToClientKvStats") {
+				const requests = this.#kvStatsRequests;
+				this.#kvStatsRequests = [];
+				for (const request of requests) {
+					request.resolve(message.val);
+				}
+			} else if (message.tag === "ToClientWorkflowEnding") {
+				// The server closes the connection next, reconnect as a new runner
+				logger()?.warn({
+					msg: "runner workflow ended, stopping actors",
+					runnerId: this.runnerId,
+				});
+				this.runnerId = undefined;
+				this.#config.runnerId = undefined;
+				this.#stopAllActors();
 			}
 		});
 
@@ -586,6 +717,25 @@ export class Runner {
 				reason: ev.reason.toString(),
 			});
 
+			if (ev.code === INIT_TIMEOUT_CLOSE_CODE) {
+				logger()?.warn({
+					msg: "server timed out waiting for init packet, reconnecting",
+				});
+			} else if (ev.code === RECONNECT_ELSEWHERE_CLOSE_CODE) {
+				// The affinity hint points at the instance that closed the connection
+				this.#preferredInstance = undefined;
+			}
+
+			if (ev.reason.toString().startsWith("ws.runner_namespace_mismatch")) {
+				// The remembered runner belongs to another namespace, reconnect as a new runner
+				logger()?.warn({
+					msg: "runner id belongs to a different namespace, discarding it",
+					runnerId: this.runnerId ?? this.#config.runnerId,
+				});
+				this.runnerId = undefined;
+				this.#config.runnerId = undefined;
+			}
+
 			this.#config.onDisconnected();
 
 			// Clear ping loop on close
@@ -611,12 +761,22 @@ export class Runner {
 						seconds: this.#runnerLostThreshold / 1000,
 					});
 					this.#runnerLostTimeout = setTimeout(() => {
+						logger()?.info(
+							"stopping all actors due to runner lost threshold exceeded",
+						);
 						this.#stopAllActors();
 					}, this.#runnerLostThreshold);
 				}
 
 				// Attempt to reconnect if not stopped
-				this.#scheduleReconnect();
+				if (ev.code === POLICY_VIOLATION_CLOSE_CODE) {
+					logger()?.error({
+						msg: "evicted for a policy violation, not reconnecting",
+						reason: ev.reason.toString(),
+					});
+				} else {
+					this.#scheduleReconnect();
+				}
 			}
 		});
 	}
@@ -833,6 +993,19 @@ export class Runner {
 		});
 	}
 
+	#sendPacketAcknowledgment() {
+		if (this.#lastPacketSeq <= 0n) {
+			return;
+		}
+
+		this.#sendToServer({
+			tag: "ToServerAckPackets",
+			val: {
+				lastSeq: this.#lastPacketSeq,
+			},
+		});
+	}
+
 	#handleKvResponse(response: protocol.ToClientKvResponse) {
 		const requestId = response.requestId;
 		const request = this.#kvRequests.get(requestId);
@@ -850,9 +1023,7 @@ export class Runner {
 		this.#kvRequests.delete(requestId);
 
 		if (response.data.tag === "KvErrorResponse") {
-			request.reject(
-				new Error(response.data.val.message || "Unknown KV error"),
-			);
+			request.reject(new KvError(response.data.val));
 		} else {
 			request.resolve(response.data.val);
 		}
@@ -874,9 +1045,19 @@ export class Runner {
 			responseValues.push(new Uint8Array(value));
 		}
 
+		// Older servers do not report missing keys, absence is inferred from the response keys instead
+		const missingKeys = response.missingKeys?.map(
+			(key) => new Uint8Array(key),
+		);
+
 		// Map response back to requested key order
 		const result: (Uint8Array | null)[] = [];
 		for (const requestedKey of requestedKeys) {
+			if (missingKeys?.some((key) => this.#keysEqual(requestedKey, key))) {
+				result.push(null);
+				continue;
+			}
+
 			let found = false;
 			for (let i = 0; i < responseKeys.length; i++) {
 				if (this.#keysEqual(requestedKey, responseKeys[i])) {
@@ -970,6 +1151,7 @@ export class Runner {
 	async kvGet(
 		actorId: string,
 		keys: Uint8Array[],
+		collection?: string,
 	): Promise<(Uint8Array | null)[]> {
 		const kvKeys: protocol.KvKey[] = keys.map(
 			(key) =>
@@ -984,7 +1166,11 @@ export class Runner {
 			val: { keys: kvKeys },
 		};
 
-		const response = await this.#sendKvRequest(actorId, requestData);
+		const response = await this.#sendKvRequest(
+			actorId,
+			requestData,
+			collection,
+		);
 		return this.#parseGetResponseSimple(response, keys);
 	}
 
@@ -1002,7 +1188,11 @@ export class Runner {
 			},
 		};
 
-		const response = await this.#sendKvRequest(actorId, requestData);
+		const response = await this.#sendKvRequest(
+			actorId,
+			requestData,
+			options?.collection,
+		);
 		return this.#parseListResponseSimple(response);
 	}
 
@@ -1039,7 +1229,11 @@ export class Runner {
 			},
 		};
 
-		const response = await this.#sendKvRequest(actorId, requestData);
+		const response = await this.#sendKvRequest(
+			actorId,
+			requestData,
+			options?.collection,
+		);
 		return this.#parseListResponseSimple(response);
 	}
 
@@ -1066,13 +1260,19 @@ export class Runner {
 			},
 		};
 
-		const response = await this.#sendKvRequest(actorId, requestData);
+		const response = await this.#sendKvRequest(
+			actorId,
+			requestData,
+			options?.collection,
+		);
 		return this.#parseListResponseSimple(response);
 	}
 
 	async kvPut(
 		actorId: string,
 		entries: [Uint8Array, Uint8Array][],
+		collection?: string,
+		contentTypes?: (string | null)[],
 	): Promise<void> {
 		const keys: protocol.KvKey[] = entries.map(
 			([key, _value]) =>
@@ -1091,13 +1291,17 @@ export class Runner {
 
 		const requestData: protocol.KvRequestData = {
 			tag: "KvPutRequest",
-			val: { keys, values },
+			val: { keys, values, contentTypes: contentTypes ?? null },
 		};
 
-		await this.#sendKvRequest(actorId, requestData);
+		await this.#sendKvRequest(actorId, requestData, collection);
 	}
 
-	async kvDelete(actorId: string, keys: Uint8Array[]): Promise<void> {
+	async kvDelete(
+		actorId: string,
+		keys: Uint8Array[],
+		collection?: string,
+	): Promise<void> {
 		const kvKeys: protocol.KvKey[] = keys.map(
 			(key) =>
 				key.buffer.slice(
@@ -1111,16 +1315,25 @@ export class Runner {
 			val: { keys: kvKeys },
 		};
 
-		await this.#sendKvRequest(actorId, requestData);
+		await this.#sendKvRequest(actorId, requestData, collection);
 	}
 
-	async kvDrop(actorId: string): Promise<void> {
+	/** Drops all keys of the actor, or only the keys in `collection` if given. */
+	async kvDrop(actorId: string, collection?: string): Promise<void> {
 		const requestData: protocol.KvRequestData = {
 			tag: "KvDropRequest",
 			val: null,
 		};
 
-		await this.#sendKvRequest(actorId, requestData);
+		await this.#sendKvRequest(actorId, requestData, collection);
+	}
+
+	/** Returns the KV usage of this connection's actors. Resets when the runner reconnects. */
+	getKvStats(): Promise<protocol.ToClientKvStats> {
+		return new Promise((resolve, reject) => {
+			this.#kvStatsRequests.push({ resolve, reject });
+			this.#sendToServer({ tag: "ToServerGetKvStats", val: null });
+		});
 	}
 
 	// MARK: Alarm Operations
@@ -1167,6 +1380,7 @@ export class Runner {
 	#sendKvRequest(
 		actorId: string,
 		requestData: protocol.KvRequestData,
+		collection?: string,
 	): Promise<any> {
 		return new Promise((resolve, reject) => {
 			if (this.#shutdown) {
@@ -1182,6 +1396,7 @@ export class Runner {
 			// Store the request
 			const requestEntry = {
 				actorId,
+				collection: collection ?? null,
 				data: requestData,
 				resolve,
 				reject,
@@ -1192,8 +1407,16 @@ export class Runner {
 			this.#kvRequests.set(requestId, requestEntry);
 
 			if (isConnected) {
-				// Send immediately
-				this.#sendSingleKvRequest(requestId);
+				if (this.#kvThrottled) {
+					// Server asked us to slow down
+					setTimeout(
+						() => this.#sendSingleKvRequest(requestId),
+						KV_THROTTLE_DELAY,
+					);
+				} else {
+					// Send immediately
+					this.#sendSingleKvRequest(requestId);
+				}
 			}
 		});
 	}
@@ -1206,6 +1429,7 @@ export class Runner {
 			const kvRequest: protocol.ToServerKvRequest = {
 				actorId: request.actorId,
 				requestId,
+				collection: request.collection,
 				data: request.data,
 			};
 